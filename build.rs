@@ -0,0 +1,31 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+// git commit of the build, reported by the api
+const ENV_GIT_COMMIT: &str = "REALM_GIT_COMMIT";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed={}", ENV_GIT_COMMIT);
+    // a missing path would rerun this on every build
+    for path in [".git/HEAD", ".git/refs/heads", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    // given when building outside the repo, e.g. in a container
+    let commit = env::var(ENV_GIT_COMMIT).ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    });
+    if let Some(commit) = commit.map(|x| x.trim().to_string()).filter(|x| !x.is_empty()) {
+        println!("cargo:rustc-env={}={}", ENV_GIT_COMMIT, commit);
+    }
+}
@@ -0,0 +1,158 @@
+//! Access log of closed relays, one line for each.
+//!
+//! Lines are logged at info level with the [`TARGET`] target,
+//! so they go wherever the other logs go.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Serialize, Deserialize};
+
+use crate::monitor::ConnectionMetrics;
+
+/// Log target of access lines.
+pub const TARGET: &str = "access";
+
+static FORMAT: OnceCell<Format> = OnceCell::new();
+
+/// Format of access lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// Like the common log format:
+    /// `client - - [time] "proto rule remote" tx rx duration "error"`
+    Plain,
+    /// One json object per line.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(Format::Plain),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown access log format: {}", s)),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Plain => write!(f, "plain"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Enable the access log.
+///
+/// Only the first format takes effect.
+pub fn set_format(format: Format) {
+    let _ = FORMAT.set(format);
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    proto: &'a str,
+    rule: Option<&'a str>,
+    client: Option<String>,
+    remote: Option<String>,
+    tx_bytes: u64,
+    rx_bytes: u64,
+    duration_secs: f64,
+    error: Option<String>,
+}
+
+impl Format {
+    /// Format a closed relay as a line, without the trailing newline.
+    pub fn line(&self, proto: &str, metrics: &ConnectionMetrics, error: Option<&str>, now: DateTime<Utc>) -> String {
+        let remote = metrics.remote.map(|x| x.to_string()).or_else(|| metrics.raddr.clone());
+        let duration = metrics.start_time.elapsed().as_secs_f64();
+        match self {
+            Format::Plain => format!(
+                "{} - - [{}] \"{} {} {}\" {} {} {:.3} \"{}\"",
+                metrics.peer.map_or_else(|| String::from("-"), |x| x.ip().to_string()),
+                now.format("%d/%b/%Y:%H:%M:%S %z"),
+                proto,
+                metrics.rule_id.as_deref().unwrap_or("-"),
+                remote.as_deref().unwrap_or("-"),
+                metrics.traffic.tx_bytes,
+                metrics.traffic.rx_bytes,
+                duration,
+                // keep the line parseable
+                error.map_or_else(|| String::from("-"), |x| x.replace(['"', '\n'], "'")),
+            ),
+            Format::Json => serde_json::to_string(&Entry {
+                timestamp: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+                proto,
+                rule: metrics.rule_id.as_deref(),
+                client: metrics.peer.map(|x| x.to_string()),
+                remote,
+                tx_bytes: metrics.traffic.tx_bytes,
+                rx_bytes: metrics.traffic.rx_bytes,
+                duration_secs: duration,
+                error: error.map(String::from),
+            })
+            .unwrap(),
+        }
+    }
+}
+
+/// Log a closed relay, if the access log is enabled.
+pub fn log(proto: &str, metrics: &ConnectionMetrics, error: Option<&str>) {
+    if let Some(format) = FORMAT.get() {
+        log::info!(target: TARGET, "{}", format.line(proto, metrics, error, Utc::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> ConnectionMetrics {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.rule_id = Some("web".to_string());
+        metrics.peer = "1.2.3.4:5000".parse().ok();
+        metrics.remote = "10.0.0.2:443".parse().ok();
+        metrics.update_tx(100);
+        metrics.update_rx(200);
+        metrics
+    }
+
+    #[test]
+    fn access_plain() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T14:05:32Z").unwrap().to_utc();
+        let line = Format::Plain.line("tcp", &metrics(), None, now);
+        assert!(line.starts_with(r#"1.2.3.4 - - [01/May/2024:14:05:32 +0000] "tcp web 10.0.0.2:443" 100 200 "#));
+        assert!(line.ends_with(r#" "-""#));
+
+        let line = Format::Plain.line("tcp", &ConnectionMetrics::new(), Some("reset \"by\" peer"), now);
+        assert!(line.starts_with(r#"- - - [01/May/2024:14:05:32 +0000] "tcp - -" 0 0 "#));
+        assert!(line.ends_with(r#" "reset 'by' peer""#));
+    }
+
+    #[test]
+    fn access_json() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T14:05:32Z").unwrap().to_utc();
+        let line = Format::Json.line("tcp", &metrics(), Some("broken pipe"), now);
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["timestamp"], "2024-05-01T14:05:32.000Z");
+        assert_eq!(v["proto"], "tcp");
+        assert_eq!(v["rule"], "web");
+        assert_eq!(v["client"], "1.2.3.4:5000");
+        assert_eq!(v["remote"], "10.0.0.2:443");
+        assert_eq!(v["tx_bytes"], 100);
+        assert_eq!(v["rx_bytes"], 200);
+        assert!(v["duration_secs"].is_f64());
+        assert_eq!(v["error"], "broken pipe");
+
+        assert_eq!("JSON".parse::<Format>(), Ok(Format::Json));
+        assert!("clf".parse::<Format>().is_err());
+    }
+}
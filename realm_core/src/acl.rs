@@ -0,0 +1,88 @@
+//! Source ip access control.
+
+use std::net::{IpAddr, SocketAddr};
+
+use ipnet::IpNet;
+
+use crate::endpoint::AcceptFilter;
+
+/// Allow or deny clients by their source ip.
+///
+/// A client matching any of `deny` is refused. Otherwise it is
+/// accepted if `allow` is empty or it matches any of `allow`.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl Acl {
+    /// Parse cidrs like `10.0.0.0/8` or `2001:db8::/32`,
+    /// a bare ip is taken as a single host.
+    pub fn new<S: AsRef<str>>(allow: &[S], deny: &[S]) -> Result<Self, String> {
+        Ok(Self {
+            allow: parse_cidrs(allow)?,
+            deny: parse_cidrs(deny)?,
+        })
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // clients of a dual-stack listener come as ipv4-mapped addresses
+        let ip = ip.to_canonical();
+        let matched = |nets: &[IpNet]| nets.iter().any(|x| x.contains(&ip));
+        !matched(&self.deny) && (self.allow.is_empty() || matched(&self.allow))
+    }
+}
+
+fn parse_cidrs<S: AsRef<str>>(cidrs: &[S]) -> Result<Vec<IpNet>, String> {
+    cidrs
+        .iter()
+        .map(|x| {
+            let x = x.as_ref().trim();
+            x.parse::<IpNet>()
+                .or_else(|_| x.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("invalid cidr: {}", x))
+        })
+        .collect()
+}
+
+impl AcceptFilter for Acl {
+    fn allow(&self, peer: SocketAddr) -> bool {
+        self.allows(peer.ip())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn acl_parse() {
+        assert!(Acl::new(&["10.0.0.0/8", "2001:db8::/32", "1.1.1.1"], &[]).is_ok());
+        assert!(Acl::new(&["10.0.0.0/33"], &[]).is_err());
+        assert_eq!(Acl::new(&[], &["x"]).unwrap_err(), "invalid cidr: x");
+    }
+
+    #[test]
+    fn acl_match() {
+        // empty allows all
+        assert!(Acl::default().allows(ip("1.1.1.1")));
+
+        let acl = Acl::new(&["10.0.0.0/8", "2001:db8::/32"], &["10.1.0.0/16"]).unwrap();
+        assert!(acl.allows(ip("10.0.0.1")));
+        assert!(acl.allows(ip("2001:db8::1")));
+        assert!(!acl.allows(ip("11.0.0.1")));
+        // deny takes precedence
+        assert!(!acl.allows(ip("10.1.0.1")));
+        // ipv4-mapped
+        assert!(acl.allows(ip("::ffff:10.0.0.1")));
+
+        let acl = Acl::new(&[], &["1.1.1.1"]).unwrap();
+        assert!(!acl.allows(ip("1.1.1.1")));
+        assert!(acl.allows(ip("1.1.1.2")));
+    }
+}
@@ -0,0 +1,331 @@
+//! Webhook alerts on the traffic of rules.
+//!
+//! Thresholds are checked each time the metrics snapshot is refreshed.
+//! An alert is posted once a threshold is crossed, and fires again only
+//! after the value falls below it and the cooldown has passed.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Result, Error};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::Lazy;
+use reqwest::{Client, Url};
+use serde::Serialize;
+
+use crate::monitor::{MetricsSnapshot, RULE_METRICS};
+
+// a slow webhook is given up after this
+const POST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// redirects followed by a post
+const MAX_REDIRECTS: usize = 5;
+
+// shared by all webhooks, to reuse connections
+static CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .timeout(POST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+        .build()
+        .expect("failed to build webhook client")
+});
+
+/// An http or https url to post alerts to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook(Url);
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let url = Url::parse(s).map_err(|e| format!("invalid webhook url {}: {}", s, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("only http or https webhook is supported: {}", s));
+        }
+        if url.host_str().is_none_or(str::is_empty) {
+            return Err(format!("missing webhook host: {}", s));
+        }
+        Ok(Self(url))
+    }
+}
+
+impl Display for Webhook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Webhook {
+    /// Post a json body, fail if the final response is not 2xx.
+    pub async fn post(&self, body: &str) -> Result<()> {
+        let res = CLIENT
+            .post(self.0.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(Error::other)?;
+        let status = res.status();
+        // drain the body so that the connection could be reused
+        let _ = res.bytes().await;
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(Error::other(format!("unexpected response: {}", status)))
+        }
+    }
+}
+
+/// Thresholds of a rule, 0 to disable one.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub webhook: Webhook,
+    // tx + rx of the rule, including closed connections
+    pub bytes: u64,
+    // upload + download speed of live connections
+    pub speed_bps: u64,
+    pub cooldown: Duration,
+}
+
+/// Which threshold is crossed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Threshold {
+    Bytes,
+    Speed,
+}
+
+/// Traffic of a rule when checked.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Usage {
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub upload_speed_bps: f64,
+    pub download_speed_bps: f64,
+}
+
+/// Crossed thresholds of a rule.
+#[derive(Debug, Default)]
+pub struct AlertState {
+    // [bytes, speed]
+    tripped: [bool; 2],
+    fired_at: [Option<Instant>; 2],
+}
+
+impl AlertState {
+    /// Get thresholds newly crossed, which should be alerted.
+    pub fn check(&mut self, alert: &Alert, usage: &Usage, now: Instant) -> Vec<Threshold> {
+        let checks = [
+            (Threshold::Bytes, alert.bytes, usage.tx_bytes + usage.rx_bytes),
+            (
+                Threshold::Speed,
+                alert.speed_bps,
+                (usage.upload_speed_bps + usage.download_speed_bps) as u64,
+            ),
+        ];
+        let mut fired = Vec::new();
+        for (i, (threshold, limit, value)) in checks.into_iter().enumerate() {
+            if limit == 0 || value < limit {
+                self.tripped[i] = false;
+                continue;
+            }
+            if self.tripped[i] {
+                continue;
+            }
+            // crossed again within the cooldown, wait until it passes
+            if self.fired_at[i].is_some_and(|x| now.duration_since(x) < alert.cooldown) {
+                continue;
+            }
+            self.tripped[i] = true;
+            self.fired_at[i] = Some(now);
+            fired.push(threshold);
+        }
+        fired
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    rule: &'a str,
+    threshold: Threshold,
+    limit: u64,
+    #[serde(flatten)]
+    usage: &'a Usage,
+    timestamp: String,
+}
+
+/// Check the thresholds of all rules against the snapshot,
+/// and post alerts in the background.
+///
+/// This must be called inside a tokio runtime.
+pub fn check_all(snapshot: &MetricsSnapshot) {
+    let mut usages: HashMap<&str, Usage> = HashMap::new();
+    for metrics in snapshot.tcp.values().chain(snapshot.udp.values()) {
+        let Some(id) = metrics.rule_id.as_deref() else {
+            continue;
+        };
+        let usage = usages.entry(id).or_default();
+        usage.tx_bytes += metrics.traffic.tx_bytes;
+        usage.rx_bytes += metrics.traffic.rx_bytes;
+        usage.upload_speed_bps += metrics.upload_speed_bps;
+        usage.download_speed_bps += metrics.download_speed_bps;
+    }
+
+    let now = Instant::now();
+    for entry in RULE_METRICS.iter() {
+        let rule = entry.value();
+        let Some(alert) = rule.alert.load_full() else {
+            continue;
+        };
+        let mut usage = usages.remove(rule.id.as_str()).unwrap_or_default();
+        usage.tx_bytes += rule.lifetime_tx_bytes.load(Ordering::Relaxed);
+        usage.rx_bytes += rule.lifetime_rx_bytes.load(Ordering::Relaxed);
+
+        let Ok(mut state) = rule.alert_state.lock() else {
+            continue;
+        };
+        for threshold in state.check(&alert, &usage, now) {
+            let limit = match threshold {
+                Threshold::Bytes => alert.bytes,
+                Threshold::Speed => alert.speed_bps,
+            };
+            let payload = Payload {
+                rule: &rule.id,
+                threshold,
+                limit,
+                usage: &usage,
+                timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            };
+            let body = serde_json::to_string(&payload).unwrap();
+            log::info!("[alert]rule {} crossed {:?} threshold {}", rule.id, threshold, limit);
+
+            let alert = alert.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(POST_TIMEOUT, alert.webhook.post(&body)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::warn!("[alert]failed to post to {}: {}", alert.webhook, e),
+                    Err(_) => log::warn!("[alert]failed to post to {}: timeout", alert.webhook),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_webhook() {
+        let hook: Webhook = "http://a.b.c/alert?x=1".parse().unwrap();
+        assert_eq!(hook.to_string(), "http://a.b.c/alert?x=1");
+
+        let hook: Webhook = "https://[::1]:8000".parse().unwrap();
+        assert_eq!(hook.to_string(), "https://[::1]:8000/");
+
+        assert!("ftp://a.b.c/".parse::<Webhook>().is_err());
+        assert!("http://a.b.c:x/".parse::<Webhook>().is_err());
+        assert!("http:///".parse::<Webhook>().is_err());
+    }
+
+    #[test]
+    fn alert_debounce() {
+        let alert = Alert {
+            webhook: "http://127.0.0.1/".parse().unwrap(),
+            bytes: 100,
+            speed_bps: 0,
+            cooldown: Duration::from_secs(60),
+        };
+        let usage = |bytes| Usage {
+            tx_bytes: bytes,
+            upload_speed_bps: 1e9,
+            ..Default::default()
+        };
+        let mut state = AlertState::default();
+        let now = Instant::now();
+
+        assert!(state.check(&alert, &usage(99), now).is_empty());
+        // speed is disabled
+        assert_eq!(state.check(&alert, &usage(100), now), vec![Threshold::Bytes]);
+        assert!(state.check(&alert, &usage(200), now).is_empty());
+
+        // crossed again within the cooldown
+        assert!(state.check(&alert, &usage(0), now).is_empty());
+        assert!(state
+            .check(&alert, &usage(100), now + Duration::from_secs(30))
+            .is_empty());
+        assert!(state.check(&alert, &usage(0), now + Duration::from_secs(40)).is_empty());
+        let later = now + Duration::from_secs(61);
+        assert_eq!(state.check(&alert, &usage(100), later), vec![Threshold::Bytes]);
+    }
+
+    // reply each request with the next response, return the requests
+    async fn serve(responses: Vec<String>) -> (std::net::SocketAddr, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = lis.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = lis.accept().await.unwrap();
+                let mut buf = Vec::new();
+                // headers, and the body of a post
+                loop {
+                    let mut chunk = [0; 1024];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let s = String::from_utf8_lossy(&buf);
+                    if let Some((head, body)) = s.split_once("\r\n\r\n") {
+                        let len = head
+                            .lines()
+                            .find_map(|x| x.to_ascii_lowercase().strip_prefix("content-length: ").map(|x| x.parse().unwrap()))
+                            .unwrap_or(0);
+                        if body.len() >= len {
+                            break;
+                        }
+                    }
+                }
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+                requests.push(String::from_utf8_lossy(&buf).to_string());
+            }
+            requests
+        });
+        (addr, server)
+    }
+
+    #[tokio::test]
+    async fn post_webhook() {
+        let ok = "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n".to_string();
+        let (addr, server) = serve(vec![ok]).await;
+        let hook: Webhook = format!("http://{}/hook", addr).parse().unwrap();
+        hook.post(r#"{"rule":"a"}"#).await.unwrap();
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(requests[0].contains("content-length: 12\r\n"));
+        assert!(requests[0].ends_with(r#"{"rule":"a"}"#));
+
+        // the status of a chunked response
+        let chunked = "HTTP/1.1 500 Internal Server Error\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\noops!\r\n0\r\n\r\n";
+        let (addr, server) = serve(vec![chunked.to_string()]).await;
+        let hook: Webhook = format!("http://{}/hook", addr).parse().unwrap();
+        let e = hook.post("{}").await.unwrap_err();
+        assert_eq!(e.to_string(), "unexpected response: 500 Internal Server Error");
+        server.await.unwrap();
+
+        // posted again after a redirect
+        let moved = "HTTP/1.1 307 Temporary Redirect\r\nLocation: /moved\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+        let (addr, server) = serve(vec![moved.to_string(), ok.to_string()]).await;
+        let hook: Webhook = format!("http://{}/hook", addr).parse().unwrap();
+        hook.post(r#"{"rule":"a"}"#).await.unwrap();
+        let requests = server.await.unwrap();
+        assert!(requests[1].starts_with("POST /moved HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with(r#"{"rule":"a"}"#));
+    }
+}
@@ -1,9 +1,84 @@
-use actix_web::{get, web, HttpResponse, Responder}; // Removed App, HttpServer
-use crate::monitor::{ConnectionMetrics, TCP_CONNECTION_METRICS, UDP_ASSOCIATION_METRICS}; // Adjusted path
-use serde::Serialize;
-use std::net::SocketAddr;
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, Responder}; // Removed App, HttpServer
+use actix_web::ResponseError;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{EntityTag, ETag, IfNoneMatch};
+use crate::monitor::{ConnectionMetrics, MetricsSnapshot, RuleMetrics, RULE_METRICS, metrics_snapshot, reset_metrics}; // Adjusted path
+use crate::monitor::{lock_metrics, subscribe_snapshot};
+use crate::monitor::{Histogram, TCP_CONNECTION_HISTOGRAMS, UDP_ASSOCIATION_HISTOGRAMS, UDP_ASSOCIATION_METRICS};
+use serde::{Serialize, Deserialize};
+use chrono::SecondsFormat;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
 // use std::sync::{Arc, Mutex}; // Not strictly required here as ConnectionMetrics is Clone and fields are public
 
+/// Error of api handlers, replied as `{"error": code, "detail": message}`.
+///
+/// `error` is a stable code for programs, e.g. `not_found`,
+/// while `detail` is meant for humans and may change.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    detail: String,
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody<'a> {
+    error: &'a str,
+    detail: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn bad_request(code: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", detail)
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", detail)
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.detail)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ApiErrorBody {
+            error: self.code,
+            detail: &self.detail,
+        })
+    }
+}
+
 // Structs used for API responses can remain private to this module
 #[derive(Serialize, Debug)]
 struct TrafficStatsResponse {
@@ -11,7 +86,23 @@ struct TrafficStatsResponse {
     rx_bytes: u64,
     upload_speed_bps: f64,
     download_speed_bps: f64,
+    peak_upload_speed_bps: f64,
+    peak_download_speed_bps: f64,
+    avg_upload_speed_bps: f64,
+    avg_download_speed_bps: f64,
+    // udp only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx_packets: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rx_packets: Option<u64>,
     uptime_seconds: u64,
+    started_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
 }
 
 // Helper to create TrafficStatsResponse from ConnectionMetrics
@@ -22,89 +113,888 @@ fn create_traffic_stats_response(metrics: &ConnectionMetrics) -> TrafficStatsRes
         rx_bytes: metrics.traffic.rx_bytes,
         upload_speed_bps: metrics.upload_speed_bps,
         download_speed_bps: metrics.download_speed_bps,
+        peak_upload_speed_bps: metrics.peak_upload_speed_bps,
+        peak_download_speed_bps: metrics.peak_download_speed_bps,
+        avg_upload_speed_bps: metrics.avg_upload_speed_bps,
+        avg_download_speed_bps: metrics.avg_download_speed_bps,
+        tx_packets: None,
+        rx_packets: None,
         uptime_seconds: metrics.start_time.elapsed().as_secs(),
+        started_at: metrics.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        region: metrics.region.clone(),
+        trace_id: metrics.trace_id.clone(),
+        tags: metrics.tags.clone(),
+    }
+}
+
+// Along with packet counts
+fn create_udp_stats_response(metrics: &ConnectionMetrics) -> TrafficStatsResponse {
+    TrafficStatsResponse {
+        tx_packets: Some(metrics.traffic.tx_packets),
+        rx_packets: Some(metrics.traffic.rx_packets),
+        ..create_traffic_stats_response(metrics)
     }
 }
 
 #[derive(Serialize, Debug)]
 struct TcpConnectionInfo {
     id: String,
+    // client address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    // selected remote address, as configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote: Option<String>,
+    // resolved address of the remote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_addr: Option<String>,
+    // time taken to connect to the remote, including dns resolution
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connect_latency_ms: Option<u64>,
     stats: TrafficStatsResponse,
 }
 
+impl TcpConnectionInfo {
+    fn new(id: &str, metrics: &ConnectionMetrics) -> Self {
+        TcpConnectionInfo {
+            id: id.to_string(),
+            peer: metrics.peer.map(|x| x.to_string()),
+            remote: metrics.raddr.clone(),
+            remote_addr: metrics.remote.map(|x| x.to_string()),
+            connect_latency_ms: metrics.connect_latency_ms,
+            stats: create_traffic_stats_response(metrics),
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 struct UdpAssociationResponse {
     client_addr: String,
     stats: TrafficStatsResponse,
 }
 
-#[get("/rules/tcp")]
-pub async fn list_tcp_connections() -> impl Responder {
-    let mut conns = Vec::new();
-    for entry in TCP_CONNECTION_METRICS.iter() {
-        let key = entry.key();
-        let metrics_arc = entry.value();
-        if let Ok(metrics) = metrics_arc.lock() {
-            conns.push(TcpConnectionInfo {
-                id: key.clone(),
-                stats: create_traffic_stats_response(&metrics),
-            });
-        } else {
-            log::warn!("Failed to lock TCP metrics for API for key: {}", key);
-        }
+// Handlers below read the metrics snapshot instead of the live metrics,
+// which is refreshed along with speed calculation
+
+// reply 304 if the client already has the same snapshot, otherwise build
+// the response upon a 200 tagged with the snapshot digest, folded with
+// other states in the response if any
+fn conditional(
+    req: &HttpRequest,
+    digest: u64,
+    build: impl FnOnce(HttpResponseBuilder) -> HttpResponse,
+) -> HttpResponse {
+    let tag = EntityTag::new_weak(format!("{:016x}", digest));
+    let matched = match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(items)) => items.iter().any(|x| x.weak_eq(&tag)),
+        None => false,
+    };
+    if matched {
+        return HttpResponse::NotModified().insert_header(ETag(tag)).finish();
     }
-    HttpResponse::Ok().json(conns)
+    let mut ok = HttpResponse::Ok();
+    ok.insert_header(ETag(tag));
+    build(ok)
+}
+
+#[get("/rules/tcp")]
+pub async fn list_tcp_connections(req: HttpRequest) -> impl Responder {
+    let snapshot = metrics_snapshot();
+    let conns: Vec<_> = snapshot
+        .tcp
+        .iter()
+        .map(|(key, metrics)| TcpConnectionInfo::new(key, metrics))
+        .collect();
+    conditional(&req, snapshot.digest, |mut ok| ok.json(conns))
 }
 
 #[get("/rules/tcp/{conn_id}/stats")]
-pub async fn get_tcp_connection_stats(conn_id: web::Path<String>) -> impl Responder {
+pub async fn get_tcp_connection_stats(conn_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let conn_id_str = conn_id.into_inner();
-    if let Some(metrics_entry) = TCP_CONNECTION_METRICS.get(&conn_id_str) {
-        let metrics_arc = metrics_entry.value();
-        if let Ok(metrics) = metrics_arc.lock() {
-            HttpResponse::Ok().json(create_traffic_stats_response(&metrics))
-        } else {
-            HttpResponse::InternalServerError().body(format!("Failed to lock TCP metrics for conn_id: {}", conn_id_str))
-        }
+    if let Some(metrics) = metrics_snapshot().tcp.get(&conn_id_str) {
+        Ok(HttpResponse::Ok().json(create_traffic_stats_response(metrics)))
     } else {
-        HttpResponse::NotFound().body(format!("TCP Connection ID not found: {}", conn_id_str))
+        Err(ApiError::not_found(format!("TCP Connection ID not found: {}", conn_id_str)))
     }
 }
 
 #[get("/rules/udp")]
-pub async fn list_udp_associations() -> impl Responder {
-    let mut assocs = Vec::new();
-    for entry in UDP_ASSOCIATION_METRICS.iter() {
-        let client_socket_addr = entry.key();
-        let metrics_arc = entry.value();
-        if let Ok(metrics) = metrics_arc.lock() {
-            assocs.push(UdpAssociationResponse {
+pub async fn list_udp_associations(req: HttpRequest) -> impl Responder {
+    let snapshot = metrics_snapshot();
+    let assocs: Vec<_> = snapshot
+        .udp
+        .iter()
+        .map(|(client_socket_addr, metrics)| UdpAssociationResponse {
+            client_addr: client_socket_addr.to_string(),
+            stats: create_udp_stats_response(metrics),
+        })
+        .collect();
+    conditional(&req, snapshot.digest, |mut ok| ok.json(assocs))
+}
+
+#[derive(Serialize, Debug)]
+struct StatsStreamMessage {
+    tcp: Vec<TcpConnectionInfo>,
+    udp: Vec<UdpAssociationResponse>,
+}
+
+impl From<&MetricsSnapshot> for StatsStreamMessage {
+    fn from(snapshot: &MetricsSnapshot) -> Self {
+        let tcp = snapshot
+            .tcp
+            .iter()
+            .map(|(key, metrics)| TcpConnectionInfo::new(key, metrics))
+            .collect();
+        let udp = snapshot
+            .udp
+            .iter()
+            .map(|(client_socket_addr, metrics)| UdpAssociationResponse {
                 client_addr: client_socket_addr.to_string(),
-                stats: create_traffic_stats_response(&metrics),
-            });
-        } else {
-            log::warn!("Failed to lock UDP metrics for API for key: {:?}", client_socket_addr);
+                stats: create_udp_stats_response(metrics),
+            })
+            .collect();
+        StatsStreamMessage { tcp, udp }
+    }
+}
+
+/// Push stats of all tcp connections and udp associations over a websocket,
+/// each time the snapshot is refreshed. Identical snapshots are skipped.
+#[get("/rules/stream")]
+pub async fn stream_stats(req: HttpRequest, payload: web::Payload) -> actix_web::Result<HttpResponse> {
+    use actix_http::ws;
+    use actix_web::http::header;
+
+    // the key is present once verified
+    ws::verify_handshake(req.head())?;
+    let key = ws::hash_key(req.headers().get(header::SEC_WEBSOCKET_KEY).unwrap().as_bytes());
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    actix_web::rt::spawn(push_stats(payload, tx));
+
+    let frames = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|x| (Ok::<_, actix_web::Error>(x), rx))
+    });
+    Ok(HttpResponse::build(StatusCode::SWITCHING_PROTOCOLS)
+        .upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, &key[..]))
+        .streaming(frames))
+}
+
+// exit once the client closes the websocket or goes away,
+// so that the response stream ends as well
+async fn push_stats(mut payload: web::Payload, tx: tokio::sync::mpsc::Sender<web::Bytes>) {
+    use actix_codec::{Decoder, Encoder};
+    use actix_http::ws::{Codec, Frame, Message};
+    use futures::future::{select, Either};
+    use futures::StreamExt;
+    use std::pin::pin;
+
+    let mut codec = Codec::new();
+    let mut refreshed = subscribe_snapshot();
+    let mut digest = None;
+    let mut rbuf = web::BytesMut::new();
+    let mut wbuf = web::BytesMut::new();
+
+    macro_rules! send {
+        ($msg: expr) => {
+            if codec.encode($msg, &mut wbuf).is_err() || tx.send(wbuf.split().freeze()).await.is_err() {
+                return;
+            }
+        };
+    }
+
+    loop {
+        let snapshot = metrics_snapshot();
+        if digest != Some(snapshot.digest) {
+            digest = Some(snapshot.digest);
+            let text = serde_json::to_string(&StatsStreamMessage::from(&*snapshot)).unwrap();
+            send!(Message::Text(text.into()));
+        }
+
+        let chunk = match select(pin!(refreshed.changed()), payload.next()).await {
+            Either::Left((Ok(()), _)) => continue,
+            Either::Right((Some(Ok(chunk)), _)) => chunk,
+            _ => return,
+        };
+        rbuf.extend_from_slice(&chunk);
+
+        loop {
+            match codec.decode(&mut rbuf) {
+                Ok(Some(Frame::Ping(x))) => send!(Message::Pong(x)),
+                Ok(Some(Frame::Close(reason))) => {
+                    send!(Message::Close(reason));
+                    return;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(e) => {
+                    log::debug!("[api]stats stream closed: {}", e);
+                    return;
+                }
+            }
         }
     }
-    HttpResponse::Ok().json(assocs)
 }
 
 #[get("/rules/udp/{client_addr}/stats")]
-pub async fn get_udp_association_stats(client_addr_path: web::Path<String>) -> impl Responder {
+pub async fn get_udp_association_stats(client_addr_path: web::Path<String>) -> Result<HttpResponse, ApiError> {
     let client_addr_str = client_addr_path.into_inner();
     match client_addr_str.parse::<SocketAddr>() {
         Ok(client_addr) => {
-            if let Some(metrics_entry) = UDP_ASSOCIATION_METRICS.get(&client_addr) {
-                let metrics_arc = metrics_entry.value();
-                if let Ok(metrics) = metrics_arc.lock() {
-                    HttpResponse::Ok().json(create_traffic_stats_response(&metrics))
-                } else {
-                    HttpResponse::InternalServerError().body(format!("Failed to lock UDP metrics for client: {}", client_addr_str))
-                }
+            if let Some(metrics) = metrics_snapshot().udp.get(&client_addr) {
+                Ok(HttpResponse::Ok().json(create_udp_stats_response(metrics)))
             } else {
-                HttpResponse::NotFound().body(format!("UDP Association not found for client address: {}", client_addr_str))
+                Err(ApiError::not_found(format!(
+                    "UDP Association not found for client address: {}",
+                    client_addr_str
+                )))
             }
         }
-        Err(_) => HttpResponse::BadRequest().body(format!("Invalid client address format: {}", client_addr_str)),
+        Err(_) => Err(ApiError::bad_request(
+            "invalid_address",
+            format!("Invalid client address format: {}", client_addr_str),
+        )),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CloseResponse {
+    client_addr: SocketAddr,
+    closed: bool,
+}
+
+/// Close a udp association at once, e.g. when it is stuck.
+///
+/// The client associates again with its next packet.
+#[post("/rules/udp/{client_addr}/close")]
+pub async fn close_udp_association(client_addr_path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let client_addr_str = client_addr_path.into_inner();
+    let Ok(client_addr) = client_addr_str.parse::<SocketAddr>() else {
+        return Err(ApiError::bad_request(
+            "invalid_address",
+            format!("Invalid client address format: {}", client_addr_str),
+        ));
+    };
+    // live ones rather than the snapshot
+    let closer = UDP_ASSOCIATION_METRICS
+        .get(&client_addr)
+        .and_then(|x| lock_metrics(x.value()).closer.clone());
+    let Some(closer) = closer else {
+        return Err(ApiError::not_found(format!(
+            "UDP Association not found for client address: {}",
+            client_addr_str
+        )));
+    };
+
+    closer.notify_one();
+    log::info!("[api]udp association of {} closed", client_addr);
+    Ok(HttpResponse::Ok().json(CloseResponse {
+        client_addr,
+        closed: true,
+    }))
+}
+
+#[derive(Serialize, Debug)]
+struct SourceInfo {
+    ip: IpAddr,
+    connections: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct RuleSourcesResponse {
+    id: String,
+    rejected_per_ip: u64,
+    sources: Vec<SourceInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SourcesQuery {
+    limit: Option<usize>,
+}
+
+#[get("/rules/{id}/sources")]
+pub async fn get_rule_sources(
+    id: web::Path<String>,
+    query: web::Query<SourcesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let Some(rule) = RULE_METRICS.get(&id).map(|x| x.value().clone()) else {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    };
+
+    let sources = rule
+        .top_sources(query.limit.unwrap_or(10))
+        .into_iter()
+        .map(|(ip, connections)| SourceInfo { ip, connections })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(RuleSourcesResponse {
+        id,
+        rejected_per_ip: rule.rejected_per_ip.load(Ordering::Relaxed),
+        sources,
+    }))
+}
+
+#[derive(Serialize, Debug, Default)]
+struct RuleStatsResponse {
+    id: String,
+    active_connections: usize,
+    active_associations: usize,
+    tx_bytes: u64,
+    rx_bytes: u64,
+    // closed connections and associations
+    lifetime_tx_bytes: u64,
+    lifetime_rx_bytes: u64,
+    avg_upload_speed_bps: f64,
+    avg_download_speed_bps: f64,
+    // refused by max_connections
+    rejected_total: u64,
+    last_error: Option<LastErrorInfo>,
+}
+
+#[derive(Serialize, Debug)]
+struct LastErrorInfo {
+    message: String,
+    at: String,
+    count: u64,
+    // folded into the digest
+    #[serde(skip)]
+    at_millis: i64,
+}
+
+/// Traffic of the active connections and associations of a rule,
+/// and the lifetime traffic of closed ones.
+#[get("/rules/{id}/stats")]
+pub async fn get_rule_stats(req: HttpRequest, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let Some(rule) = RULE_METRICS.get(&id).map(|x| x.value().clone()) else {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    };
+
+    let snapshot = metrics_snapshot();
+    let stats = rule_stats(id, &snapshot, &rule);
+    let digest = snapshot
+        .digest
+        .wrapping_add(stats.rejected_total)
+        .wrapping_add(stats.lifetime_tx_bytes)
+        .wrapping_add(stats.lifetime_rx_bytes.rotate_left(32));
+    let digest = match &stats.last_error {
+        Some(e) => digest.wrapping_add(e.count.rotate_left(16) ^ e.at_millis as u64),
+        None => digest,
+    };
+    Ok(conditional(&req, digest, |mut ok| ok.json(stats)))
+}
+
+fn rule_stats(id: String, snapshot: &MetricsSnapshot, rule: &RuleMetrics) -> RuleStatsResponse {
+    let is_rule = |x: &&ConnectionMetrics| x.rule_id.as_deref() == Some(id.as_str());
+    let tcp: Vec<_> = snapshot.tcp.values().filter(is_rule).collect();
+    let udp: Vec<_> = snapshot.udp.values().filter(is_rule).collect();
+
+    let mut stats = RuleStatsResponse {
+        active_connections: tcp.len(),
+        active_associations: udp.len(),
+        lifetime_tx_bytes: rule.lifetime_tx_bytes.load(Ordering::Relaxed),
+        lifetime_rx_bytes: rule.lifetime_rx_bytes.load(Ordering::Relaxed),
+        rejected_total: rule.rejected_max_connections.load(Ordering::Relaxed),
+        last_error: rule.last_error.lock().ok().and_then(|x| x.clone()).map(|e| LastErrorInfo {
+            message: e.message,
+            at: e.at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            count: e.count,
+            at_millis: e.at.timestamp_millis(),
+        }),
+        ..Default::default()
+    };
+    for metrics in tcp.iter().chain(udp.iter()) {
+        stats.tx_bytes += metrics.traffic.tx_bytes;
+        stats.rx_bytes += metrics.traffic.rx_bytes;
+        stats.avg_upload_speed_bps += metrics.upload_speed_bps;
+        stats.avg_download_speed_bps += metrics.download_speed_bps;
+    }
+    let n = tcp.len() + udp.len();
+    if n != 0 {
+        stats.avg_upload_speed_bps /= n as f64;
+        stats.avg_download_speed_bps /= n as f64;
+    }
+    stats.id = id;
+    stats
+}
+
+#[derive(Serialize, Debug)]
+struct UnquarantineResponse {
+    id: String,
+    was_quarantined: bool,
+}
+
+#[post("/rules/{id}/unquarantine")]
+pub async fn unquarantine_rule(id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let Some(rule) = RULE_METRICS.get(&id).map(|x| x.value().clone()) else {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    };
+
+    let Ok(mut quarantine) = rule.quarantine.lock() else {
+        return Err(ApiError::internal(format!("Failed to lock quarantine state for rule: {}", id)));
+    };
+    let was_quarantined = quarantine.lift();
+    drop(quarantine);
+
+    if was_quarantined {
+        log::info!("[api]rule {} unquarantined", id);
+    }
+    Ok(HttpResponse::Ok().json(UnquarantineResponse { id, was_quarantined }))
+}
+
+#[derive(Serialize, Debug)]
+struct ResetResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    // live connections and associations
+    reset: usize,
+}
+
+/// Zero the traffic of a rule, connections are left alive.
+#[post("/rules/{id}/reset")]
+pub async fn reset_rule(id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    if !RULE_METRICS.contains_key(&id) {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    }
+    let reset = reset_metrics(Some(&id));
+    log::info!("[api]rule {} reset, {} connections", id, reset);
+    Ok(HttpResponse::Ok().json(ResetResponse { id: Some(id), reset }))
+}
+
+/// Zero the traffic of all rules.
+#[post("/rules/reset")]
+pub async fn reset_rules() -> impl Responder {
+    let reset = reset_metrics(None);
+    log::info!("[api]all rules reset, {} connections", reset);
+    HttpResponse::Ok().json(ResetResponse { id: None, reset })
+}
+
+#[cfg(feature = "balance")]
+#[derive(Deserialize, Debug)]
+pub struct WeightsRequest {
+    weights: Vec<u8>,
+    // restore the previous weights after some seconds
+    #[serde(default)]
+    revert_after: Option<u64>,
+}
+
+#[cfg(feature = "balance")]
+#[derive(Serialize, Debug)]
+struct WeightsResponse {
+    id: String,
+    strategy: String,
+    weights: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revert_after: Option<u64>,
+}
+
+#[cfg(feature = "balance")]
+#[actix_web::patch("/rules/{id}/balancer/weights")]
+pub async fn patch_balancer_weights(
+    id: web::Path<String>,
+    req: web::Json<WeightsRequest>,
+) -> Result<HttpResponse, ApiError> {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use realm_lb::{Balancer, Strategy};
+
+    let id = id.into_inner();
+    let WeightsRequest { weights, revert_after } = req.into_inner();
+    let Some(rule) = RULE_METRICS.get(&id).map(|x| x.value().clone()) else {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    };
+    let Some(balancer) = rule.balancer.load_full() else {
+        return Err(ApiError::not_found(format!("Balancer not found for rule: {}", id)));
+    };
+
+    // keep the strategy
+    let strategy = balancer.live.load().strategy();
+    if strategy == Strategy::Off {
+        return Err(ApiError::bad_request(
+            "balance_disabled",
+            format!("Balance is not enabled for rule: {}", id),
+        ));
+    }
+    if weights.len() != balancer.peers {
+        return Err(ApiError::bad_request(
+            "invalid_weights",
+            format!("Expect {} weights, got {}", balancer.peers, weights.len()),
+        ));
+    }
+
+    let new = Arc::new(Balancer::new(strategy, &weights));
+    let old = balancer.live.swap(new.clone());
+    log::info!("[api]rule {} balancer weights set to {:?}", id, weights);
+
+    if let Some(secs) = revert_after {
+        let live = balancer.live.clone();
+        let id = id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+            // skip if the weights have been changed again
+            let prev = live.compare_and_swap(&new, old);
+            if Arc::ptr_eq(&prev, &new) {
+                log::info!("[api]rule {} balancer weights reverted", id);
+            }
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(WeightsResponse {
+        id,
+        strategy: strategy.to_string(),
+        weights,
+        revert_after,
+    }))
+}
+
+#[cfg(feature = "balance")]
+#[derive(Serialize, Debug)]
+struct PeerHealthInfo {
+    remote: String,
+    // not disabled manually
+    enabled: bool,
+    // not marked down by active checks
+    up: bool,
+    // ejected by connect failures
+    ejected: bool,
+    healthy: bool,
+}
+
+#[cfg(feature = "balance")]
+#[derive(Serialize, Debug)]
+struct RuleHealthResponse {
+    id: String,
+    peers: Vec<PeerHealthInfo>,
+}
+
+/// Health state of the remote peers of a rule.
+#[cfg(feature = "balance")]
+#[get("/rules/{id}/health")]
+pub async fn get_rule_health(id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    use realm_lb::Token;
+
+    let id = id.into_inner();
+    let Some(rule) = RULE_METRICS.get(&id).map(|x| x.value().clone()) else {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    };
+    let Some(balancer) = rule.balancer.load_full() else {
+        return Err(ApiError::not_found(format!("Balancer not found for rule: {}", id)));
+    };
+
+    let health = &rule.peer_health;
+    let peers = balancer
+        .remotes
+        .iter()
+        .enumerate()
+        .map(|(idx, remote)| {
+            let token = Token(idx as u8);
+            PeerHealthInfo {
+                remote: remote.clone(),
+                enabled: health.is_enabled(token),
+                up: health.is_up(token),
+                ejected: health.is_ejected(token),
+                healthy: health.is_healthy(token),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(RuleHealthResponse { id, peers }))
+}
+
+#[cfg(feature = "balance")]
+#[derive(Serialize, Debug)]
+struct PeerEnabledResponse {
+    id: String,
+    index: usize,
+    remote: String,
+    enabled: bool,
+    changed: bool,
+}
+
+#[cfg(feature = "balance")]
+fn set_peer_enabled(id: String, index: usize, enabled: bool) -> Result<HttpResponse, ApiError> {
+    use realm_lb::{Strategy, Token};
+
+    let Some(rule) = RULE_METRICS.get(&id).map(|x| x.value().clone()) else {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    };
+    let Some(balancer) = rule.balancer.load_full() else {
+        return Err(ApiError::not_found(format!("Balancer not found for rule: {}", id)));
+    };
+    if balancer.live.load().strategy() == Strategy::Off {
+        return Err(ApiError::bad_request(
+            "balance_disabled",
+            format!("Balance is not enabled for rule: {}", id),
+        ));
+    }
+    let Some(remote) = balancer.remotes.get(index).cloned() else {
+        return Err(ApiError::not_found(format!("Peer not found: {}", index)));
+    };
+
+    let changed = rule.peer_health.set_enabled(Token(index as u8), enabled);
+    if changed {
+        let state = if enabled { "enabled" } else { "disabled" };
+        log::info!("[api]rule {} peer {} {}", id, remote, state);
+    }
+
+    Ok(HttpResponse::Ok().json(PeerEnabledResponse {
+        id,
+        index,
+        remote,
+        enabled,
+        changed,
+    }))
+}
+
+/// Skip a remote peer in the balancer, e.g. for maintenance.
+#[cfg(feature = "balance")]
+#[post("/rules/{id}/peers/{index}/disable")]
+pub async fn disable_peer(path: web::Path<(String, usize)>) -> Result<HttpResponse, ApiError> {
+    let (id, index) = path.into_inner();
+    set_peer_enabled(id, index, false)
+}
+
+#[cfg(feature = "balance")]
+#[post("/rules/{id}/peers/{index}/enable")]
+pub async fn enable_peer(path: web::Path<(String, usize)>) -> Result<HttpResponse, ApiError> {
+    let (id, index) = path.into_inner();
+    set_peer_enabled(id, index, true)
+}
+
+#[derive(Serialize, Debug)]
+struct ShutdownStatusResponse {
+    // running, draining or drained
+    state: &'static str,
+    active_connections: usize,
+    active_associations: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_seconds: Option<u64>,
+}
+
+#[get("/shutdown/status")]
+pub async fn get_shutdown_status() -> impl Responder {
+    use crate::shutdown::{active, drain_state};
+
+    let (active_connections, active_associations) = active();
+    let drain = drain_state();
+    let state = match drain {
+        None => "running",
+        Some(_) if active_connections + active_associations == 0 => "drained",
+        Some(_) => "draining",
+    };
+    HttpResponse::Ok().json(ShutdownStatusResponse {
+        state,
+        active_connections,
+        active_associations,
+        deadline: drain.map(|x| x.deadline_unix),
+        remaining_seconds: drain.map(|x| x.deadline.saturating_duration_since(std::time::Instant::now()).as_secs()),
+    })
+}
+
+/// Sort key of top connections.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TopBy {
+    #[default]
+    UploadSpeed,
+    DownloadSpeed,
+    TxBytes,
+    RxBytes,
+    Uptime,
+}
+
+impl TopBy {
+    fn key(self, metrics: &ConnectionMetrics) -> f64 {
+        match self {
+            TopBy::UploadSpeed => metrics.upload_speed_bps,
+            TopBy::DownloadSpeed => metrics.download_speed_bps,
+            TopBy::TxBytes => metrics.traffic.tx_bytes as f64,
+            TopBy::RxBytes => metrics.traffic.rx_bytes as f64,
+            TopBy::Uptime => metrics.start_time.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TopQuery {
+    #[serde(default)]
+    by: TopBy,
+    n: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct TopConnectionInfo {
+    protocol: &'static str,
+    // connection id, or client address of an association
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    stats: TrafficStatsResponse,
+}
+
+#[get("/stats/top")]
+pub async fn get_top_connections(req: HttpRequest, query: web::Query<TopQuery>) -> impl Responder {
+    let TopQuery { by, n } = query.into_inner();
+    let n = n.unwrap_or(20).min(1000);
+
+    let snapshot = metrics_snapshot();
+    conditional(&req, snapshot.digest, |mut ok| ok.json(top_connections(&snapshot, by, n)))
+}
+
+fn top_connections(snapshot: &MetricsSnapshot, by: TopBy, n: usize) -> Vec<TopConnectionInfo> {
+    let mut conns: Vec<_> = snapshot
+        .tcp
+        .iter()
+        .map(|(id, metrics)| ("tcp", id.clone(), metrics))
+        .chain(snapshot.udp.iter().map(|(addr, metrics)| ("udp", addr.to_string(), metrics)))
+        .map(|x| (by.key(x.2), x))
+        .collect();
+
+    // only sort the top n
+    let desc = |a: &(f64, _), b: &(f64, _)| b.0.total_cmp(&a.0);
+    if conns.len() > n && n != 0 {
+        conns.select_nth_unstable_by(n - 1, desc);
+    }
+    conns.truncate(n);
+    conns.sort_unstable_by(desc);
+
+    let conns: Vec<_> = conns
+        .into_iter()
+        .map(|(_, (protocol, id, metrics))| TopConnectionInfo {
+            protocol,
+            id,
+            rule: metrics.rule_id.clone(),
+            peer: metrics.peer.map(|x| x.to_string()),
+            stats: create_traffic_stats_response(metrics),
+        })
+        .collect();
+    conns
+}
+
+// label values may contain backslashes, quotes or newlines
+fn escape_label(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn render_histogram(out: &mut String, name: &str, proto: &str, histogram: &Histogram) {
+    use std::fmt::Write;
+    for (le, count) in histogram.cumulative() {
+        let le = if le.is_infinite() { "+Inf".to_string() } else { le.to_string() };
+        let _ = writeln!(out, "{}_bucket{{proto=\"{}\",le=\"{}\"}} {}", name, proto, le, count);
+    }
+    let _ = writeln!(out, "{}_sum{{proto=\"{}\"}} {}", name, proto, histogram.sum);
+    let _ = writeln!(out, "{}_count{{proto=\"{}\"}} {}", name, proto, histogram.count);
+}
+
+type MetricValue = fn(&ConnectionMetrics) -> f64;
+
+/// Render connection metrics in the prometheus text format.
+pub fn render_metrics() -> String {
+    use std::fmt::Write;
+
+    let snapshot = metrics_snapshot();
+    // (proto, label name, label value, metrics)
+    let conns: Vec<_> = snapshot
+        .tcp
+        .iter()
+        .map(|(id, metrics)| ("tcp", "conn_id", escape_label(id), metrics))
+        .chain(
+            snapshot
+                .udp
+                .iter()
+                .map(|(addr, metrics)| ("udp", "client_addr", escape_label(&addr.to_string()), metrics)),
+        )
+        .collect();
+
+    let mut out = String::new();
+    let families: [(&str, &str, &str, MetricValue); 5] = [
+        ("realm_tx_bytes_total", "counter", "Bytes sent to the remote peer.", |x| {
+            x.traffic.tx_bytes as f64
+        }),
+        ("realm_rx_bytes_total", "counter", "Bytes received from the remote peer.", |x| {
+            x.traffic.rx_bytes as f64
+        }),
+        ("realm_upload_speed_bps", "gauge", "Upload speed in bits per second.", |x| {
+            x.upload_speed_bps
+        }),
+        ("realm_download_speed_bps", "gauge", "Download speed in bits per second.", |x| {
+            x.download_speed_bps
+        }),
+        ("realm_connection_uptime_seconds", "gauge", "Time since the connection is established.", |x| {
+            x.start_time.elapsed().as_secs_f64()
+        }),
+    ];
+    for (name, kind, help, value) in families {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (proto, key, id, metrics) in &conns {
+            let rule = escape_label(metrics.rule_id.as_deref().unwrap_or(""));
+            let _ = writeln!(
+                out,
+                "{}{{proto=\"{}\",rule=\"{}\",{}=\"{}\"}} {}",
+                name,
+                proto,
+                rule,
+                key,
+                id,
+                value(metrics)
+            );
+        }
+    }
+
+    // distributions of closed connections
+    let histograms = [("tcp", &TCP_CONNECTION_HISTOGRAMS), ("udp", &UDP_ASSOCIATION_HISTOGRAMS)];
+    let histograms: Vec<_> = histograms
+        .into_iter()
+        .filter_map(|(proto, x)| x.lock().ok().map(|x| (proto, x.clone())))
+        .collect();
+    let _ = writeln!(out, "# HELP realm_connection_bytes Total bytes of closed connections.");
+    let _ = writeln!(out, "# TYPE realm_connection_bytes histogram");
+    for (proto, histograms) in &histograms {
+        render_histogram(&mut out, "realm_connection_bytes", proto, &histograms.bytes);
+    }
+    let _ = writeln!(out, "# HELP realm_connection_duration_seconds Duration of closed connections.");
+    let _ = writeln!(out, "# TYPE realm_connection_duration_seconds histogram");
+    for (proto, histograms) in &histograms {
+        render_histogram(&mut out, "realm_connection_duration_seconds", proto, &histograms.duration);
+    }
+
+    out
+}
+
+/// Connection metrics for prometheus.
+#[get("/metrics")]
+pub async fn get_metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render_metrics())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_label_value() {
+        assert_eq!(escape_label("127.0.0.1:80"), "127.0.0.1:80");
+        assert_eq!(escape_label("[fe80::1%eth0]:80"), "[fe80::1%eth0]:80");
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
     }
 }
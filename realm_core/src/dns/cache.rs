@@ -0,0 +1,188 @@
+//! Cache of resolved remote addresses.
+
+use std::io::Result;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use once_cell::sync::{Lazy, OnceCell};
+use tokio::sync::Mutex;
+
+/// Resolved addresses, shared by cache hits.
+pub type Addrs = Arc<[SocketAddr]>;
+
+#[derive(Debug, Default)]
+struct Slot {
+    // (addrs, expire at)
+    cached: Mutex<Option<(Addrs, Instant)>>,
+    // round-robin among addrs
+    turn: AtomicUsize,
+}
+
+static CACHE_TTL: OnceCell<Duration> = OnceCell::new();
+
+/// Shared by tcp and udp relays.
+pub(super) static CACHE: Lazy<Cache> = Lazy::new(Cache::default);
+
+/// Override the ttl of cached records, zero to disable the cache.
+/// Records are kept as long as their own ttl if not set.
+///
+/// Return false if it has been set.
+pub fn set_cache_ttl(ttl: Duration) -> bool {
+    CACHE_TTL.set(ttl).is_ok()
+}
+
+/// Get the overridden ttl of cached records.
+pub fn cache_ttl() -> Option<Duration> {
+    CACHE_TTL.get().copied()
+}
+
+/// Resolved addresses keyed by domain and port.
+///
+/// Concurrent lookups of the same key wait for a single query.
+#[derive(Debug, Default)]
+pub struct Cache {
+    slots: DashMap<(String, u16), Arc<Slot>>,
+}
+
+impl Cache {
+    fn slot(&self, host: &str, port: u16) -> Arc<Slot> {
+        self.slots.entry((host.to_string(), port)).or_default().value().clone()
+    }
+
+    /// Get the turn of next connection to this domain and port,
+    /// increased by one each time.
+    pub fn next_turn(&self, host: &str, port: u16) -> usize {
+        self.slot(host, port).turn.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Get cached addresses, or resolve and cache them if missing or expired.
+    ///
+    /// `resolve` returns the addresses and their valid time, which
+    /// is replaced by `ttl` if provided.
+    pub async fn get_or_resolve<F, Fut>(
+        &self,
+        host: &str,
+        port: u16,
+        ttl: Option<Duration>,
+        resolve: F,
+    ) -> Result<Addrs>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(Addrs, Instant)>>,
+    {
+        let slot = self.slot(host, port);
+
+        // held while resolving, others wait for the result
+        let mut slot = slot.cached.lock().await;
+        if let Some((addrs, expire)) = slot.as_ref() {
+            if Instant::now() < *expire {
+                return Ok(addrs.clone());
+            }
+        }
+
+        let (addrs, valid_until) = resolve().await?;
+        let expire = match ttl {
+            Some(ttl) => Instant::now() + ttl,
+            None => valid_until,
+        };
+        *slot = Some((addrs.clone(), expire));
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Addrs {
+        Arc::from(["127.0.0.1:80".parse().unwrap()])
+    }
+
+    #[tokio::test]
+    async fn cache_hit() {
+        let cache = Cache::default();
+        let queries = AtomicUsize::new(0);
+        let resolve = || async {
+            queries.fetch_add(1, Ordering::Relaxed);
+            Ok((addrs(), Instant::now() + Duration::from_secs(60)))
+        };
+
+        for _ in 0..8 {
+            let x = cache.get_or_resolve("a.b.c", 80, None, resolve).await.unwrap();
+            assert_eq!(&*x, &*addrs());
+        }
+        assert_eq!(queries.load(Ordering::Relaxed), 1);
+
+        // another port
+        cache.get_or_resolve("a.b.c", 443, None, resolve).await.unwrap();
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn cache_expire() {
+        let cache = Cache::default();
+        let queries = AtomicUsize::new(0);
+        let resolve = || async {
+            queries.fetch_add(1, Ordering::Relaxed);
+            Ok((addrs(), Instant::now() + Duration::from_millis(50)))
+        };
+
+        cache.get_or_resolve("a.b.c", 80, None, resolve).await.unwrap();
+        cache.get_or_resolve("a.b.c", 80, None, resolve).await.unwrap();
+        assert_eq!(queries.load(Ordering::Relaxed), 1);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cache.get_or_resolve("a.b.c", 80, None, resolve).await.unwrap();
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+
+        // ttl overridden, zero never caches
+        let ttl = Some(Duration::ZERO);
+        cache.get_or_resolve("d.e.f", 80, ttl, resolve).await.unwrap();
+        cache.get_or_resolve("d.e.f", 80, ttl, resolve).await.unwrap();
+        assert_eq!(queries.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn cache_coalesce() {
+        let cache = Cache::default();
+        let queries = AtomicUsize::new(0);
+        let resolve = || async {
+            queries.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok((addrs(), Instant::now() + Duration::from_secs(60)))
+        };
+
+        let lookups = (0..8).map(|_| cache.get_or_resolve("a.b.c", 80, None, resolve));
+        for x in futures::future::join_all(lookups).await {
+            assert_eq!(&*x.unwrap(), &*addrs());
+        }
+        assert_eq!(queries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn cache_turn() {
+        let cache = Cache::default();
+        for i in 0..4 {
+            assert_eq!(cache.next_turn("a.b.c", 80), i);
+        }
+        assert_eq!(cache.next_turn("a.b.c", 443), 0);
+        assert_eq!(cache.next_turn("d.e.f", 80), 0);
+    }
+
+    #[tokio::test]
+    async fn cache_error() {
+        use std::io::Error;
+
+        let cache = Cache::default();
+        let failed = || async { Err(Error::other("no record")) };
+        assert!(cache.get_or_resolve("a.b.c", 80, None, failed).await.is_err());
+
+        // errors are not cached
+        let resolve = || async { Ok((addrs(), Instant::now() + Duration::from_secs(60))) };
+        assert!(cache.get_or_resolve("a.b.c", 80, None, resolve).await.is_ok());
+    }
+}
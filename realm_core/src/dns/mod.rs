@@ -8,7 +8,7 @@ use std::net::SocketAddr;
 use hickory_resolver as resolver;
 use resolver::TokioAsyncResolver;
 use resolver::system_conf::read_system_conf;
-use resolver::lookup_ip::{LookupIp, LookupIpIter};
+use resolver::lookup_ip::LookupIp;
 pub use resolver::config;
 use config::{ResolverOpts, ResolverConfig};
 
@@ -20,6 +20,10 @@ use once_cell::{unsync::OnceCell, sync::Lazy};
 
 use crate::endpoint::RemoteAddr;
 
+mod cache;
+pub use cache::{Cache, Addrs, set_cache_ttl, cache_ttl};
+use cache::CACHE;
+
 /// Dns config.
 #[derive(Debug, Clone)]
 pub struct DnsConf {
@@ -85,33 +89,69 @@ pub async fn resolve_ip(ip: &str) -> Result<LookupIp> {
     unsafe {
         DNS.lookup_ip(ip)
             .await
-            .map_or_else(|e| Err(Error::new(ErrorKind::Other, e)), Ok)
+            .map_or_else(|e| Err(Error::other(e)), Ok)
     }
 }
 
 /// Lookup socketaddr with global dns resolver.
+///
+/// Results are cached as long as their ttl, or the one set by [`set_cache_ttl`].
 pub async fn resolve_addr(addr: &RemoteAddr) -> Result<LookupRemoteAddr<'_>> {
     use RemoteAddr::*;
     use LookupRemoteAddr::*;
     match addr {
         SocketAddr(addr) => Ok(NoLookup(addr)),
-        DomainName(ip, port) => resolve_ip(ip).await.map(|ip| Dolookup(ip, *port)),
+        DomainName(host, port) => {
+            let resolve = || async {
+                let ip = resolve_ip(host).await?;
+                let addrs: Addrs = ip.iter().map(|ip| std::net::SocketAddr::new(ip, *port)).collect();
+                Ok((addrs, ip.valid_until()))
+            };
+            CACHE.get_or_resolve(host, *port, cache_ttl(), resolve).await.map(Dolookup)
+        }
+        #[cfg(unix)]
+        UnixSocket(path) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unix socket {} has no socket address", path.display()),
+        )),
+    }
+}
+
+/// Get the turn of next connection to a domain name, which
+/// is increased by one each time. Always 0 for a socket address or unix socket.
+///
+/// Used to round-robin among resolved addresses with [`LookupRemoteAddr::iter_from`].
+pub fn next_turn(addr: &RemoteAddr) -> usize {
+    match addr {
+        RemoteAddr::SocketAddr(_) => 0,
+        RemoteAddr::DomainName(host, port) => CACHE.next_turn(host, *port),
+        #[cfg(unix)]
+        RemoteAddr::UnixSocket(_) => 0,
     }
 }
 
 /// Resolved result.
 pub enum LookupRemoteAddr<'a> {
     NoLookup(&'a SocketAddr),
-    Dolookup(LookupIp, u16),
+    Dolookup(Addrs),
 }
 
 impl LookupRemoteAddr<'_> {
     /// Get view of resolved result.
-    pub fn iter(&self) -> LookupRemoteAddrIter {
+    pub fn iter(&self) -> LookupRemoteAddrIter<'_> {
+        self.iter_from(0)
+    }
+
+    /// Get view of resolved result, starting from the `turn`-th
+    /// address and wrapping around.
+    pub fn iter_from(&self, turn: usize) -> LookupRemoteAddrIter<'_> {
         use LookupRemoteAddr::*;
         match self {
             NoLookup(addr) => LookupRemoteAddrIter::NoLookup(std::iter::once(addr)),
-            Dolookup(ip, port) => LookupRemoteAddrIter::DoLookup(ip.iter(), *port),
+            Dolookup(addrs) => {
+                let (back, front) = addrs.split_at(turn % addrs.len().max(1));
+                LookupRemoteAddrIter::DoLookup(front.iter().chain(back.iter()))
+            }
         }
     }
 }
@@ -119,7 +159,7 @@ impl LookupRemoteAddr<'_> {
 /// View of resolved result.
 pub enum LookupRemoteAddrIter<'a> {
     NoLookup(std::iter::Once<&'a SocketAddr>),
-    DoLookup(LookupIpIter<'a>, u16),
+    DoLookup(std::iter::Chain<std::slice::Iter<'a, SocketAddr>, std::slice::Iter<'a, SocketAddr>>),
 }
 
 impl Iterator for LookupRemoteAddrIter<'_> {
@@ -129,7 +169,32 @@ impl Iterator for LookupRemoteAddrIter<'_> {
         use LookupRemoteAddrIter::*;
         match self {
             NoLookup(addr) => addr.next().copied(),
-            DoLookup(ip, port) => ip.next().map(|ip| SocketAddr::new(ip, *port)),
+            DoLookup(addrs) => addrs.next().copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter_round_robin() {
+        let addrs: Addrs = ["1.1.1.1:80", "2.2.2.2:80", "3.3.3.3:80"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        let lookup = LookupRemoteAddr::Dolookup(addrs.clone());
+
+        for turn in 0..6 {
+            let rotated: Vec<_> = lookup.iter_from(turn).collect();
+            assert_eq!(rotated.len(), addrs.len());
+            assert_eq!(rotated[0], addrs[turn % addrs.len()]);
+            assert_eq!(rotated[1], addrs[(turn + 1) % addrs.len()]);
         }
+
+        let addr = "127.0.0.1:80".parse().unwrap();
+        let lookup = LookupRemoteAddr::NoLookup(&addr);
+        assert_eq!(lookup.iter_from(5).collect::<Vec<_>>(), vec![addr]);
     }
 }
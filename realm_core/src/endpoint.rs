@@ -1,7 +1,13 @@
 //! Relay endpoint.
 
 use std::fmt::{Display, Formatter};
-use std::net::SocketAddr;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::str::FromStr;
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
 
 #[cfg(feature = "transport")]
 use kaminari::mix::{MixAccept, MixConnect};
@@ -9,21 +15,207 @@ use kaminari::mix::{MixAccept, MixConnect};
 #[cfg(feature = "balance")]
 use realm_lb::Balancer;
 
+/// Balancer shared with the relay, which can be replaced at runtime.
+#[cfg(feature = "balance")]
+pub type SharedBalancer = std::sync::Arc<arc_swap::ArcSwap<Balancer>>;
+
+/// Transport shared with the relay, which can be replaced at runtime,
+/// e.g. to reload tls certificates.
+#[cfg(feature = "transport")]
+pub type SharedTransport = std::sync::Arc<arc_swap::ArcSwap<(MixAccept, MixConnect)>>;
+
+#[cfg(feature = "geoip")]
+use crate::geoip::GeoRouter;
+
+#[cfg(feature = "alert")]
+use crate::alert::Alert;
+
+/// Decide whether to accept a tcp connection or udp packets from a peer,
+/// before any relay logic.
+///
+/// Unlike the pre-connect hook, this is synchronous and
+/// only sees the peer address.
+pub trait AcceptFilter: Send + Sync {
+    fn allow(&self, peer: SocketAddr) -> bool;
+}
+
+impl<F> AcceptFilter for F
+where
+    F: Fn(SocketAddr) -> bool + Send + Sync,
+{
+    fn allow(&self, peer: SocketAddr) -> bool {
+        self(peer)
+    }
+}
+
+/// Accept filter shared by the relays of an endpoint.
+#[derive(Clone)]
+pub struct SharedAcceptFilter(pub std::sync::Arc<dyn AcceptFilter>);
+
+impl SharedAcceptFilter {
+    pub fn new<F: AcceptFilter + 'static>(filter: F) -> Self {
+        Self(std::sync::Arc::new(filter))
+    }
+
+    #[inline]
+    pub fn allow(&self, peer: SocketAddr) -> bool {
+        self.0.allow(peer)
+    }
+
+    /// Allow a peer only if both filters allow it.
+    pub fn and(self, other: SharedAcceptFilter) -> Self {
+        Self::new(move |peer| self.allow(peer) && other.allow(peer))
+    }
+}
+
+impl std::fmt::Debug for SharedAcceptFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedAcceptFilter")
+    }
+}
+
+/// Prefix of a unix socket path in a listen or remote address.
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Seconds before an idle udp association is dropped, if not set.
+pub const DEFAULT_ASSOCIATE_TIMEOUT: usize = 30;
+
 /// Remote address.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RemoteAddr {
     SocketAddr(SocketAddr),
     DomainName(String, u16),
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
+}
+
+impl RemoteAddr {
+    #[inline]
+    pub const fn is_unix(&self) -> bool {
+        #[cfg(unix)]
+        if let RemoteAddr::UnixSocket(_) = self {
+            return true;
+        }
+        false
+    }
+
+    /// Replace the port, a unix socket has none.
+    pub fn set_port(&mut self, port: u16) {
+        match self {
+            RemoteAddr::SocketAddr(addr) => addr.set_port(port),
+            RemoteAddr::DomainName(_, x) => *x = port,
+            #[cfg(unix)]
+            RemoteAddr::UnixSocket(_) => {}
+        }
+    }
+}
+
+impl FromStr for RemoteAddr {
+    type Err = String;
+
+    /// Parse a socket address, a domain name with port, or a unix socket
+    /// path prefixed with `unix:`. An ipv6 address may carry a zone,
+    /// e.g. `[fe80::1%eth0]:443` or `[fe80::1%2]:443`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix(UNIX_SOCKET_PREFIX) {
+            #[cfg(unix)]
+            return match path {
+                "" => Err(String::from("empty unix socket path")),
+                _ => Ok(RemoteAddr::UnixSocket(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!("unix socket is not supported: {}", path));
+        }
+        if let Some(addr) = parse_scoped(s)? {
+            return Ok(RemoteAddr::SocketAddr(addr));
+        }
+        if let Ok(addr) = s.parse() {
+            return Ok(RemoteAddr::SocketAddr(addr));
+        }
+        let (host, port) = s.rsplit_once(':').ok_or_else(|| format!("missing port: {}", s))?;
+        let port = port.parse().map_err(|_| format!("invalid port: {}", s))?;
+        Ok(RemoteAddr::DomainName(host.to_string(), port))
+    }
+}
+
+// std does not accept the zone of an ipv6 address, which is kept as
+// the scope id so that the connect goes through that interface
+fn parse_scoped(s: &str) -> Result<Option<SocketAddr>, String> {
+    let Some((host, port)) = s.strip_prefix('[').and_then(|x| x.rsplit_once("]:")) else {
+        return Ok(None);
+    };
+    let Some((ip, zone)) = host.split_once('%') else {
+        return Ok(None);
+    };
+    let ip: Ipv6Addr = ip.parse().map_err(|_| format!("invalid ipv6 address: {}", s))?;
+    let port = port.parse().map_err(|_| format!("invalid port: {}", s))?;
+    let scope_id = match zone.parse() {
+        Ok(x) => x,
+        #[cfg(unix)]
+        Err(_) => realm_syscall::interface_index(zone).map_err(|e| format!("invalid zone {}: {}", zone, e))?,
+        #[cfg(not(unix))]
+        Err(_) => return Err(format!("invalid zone: {}", zone)),
+    };
+    Ok(Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id))))
+}
+
+/// Listen address, a unix socket only accepts tcp connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalAddr {
+    SocketAddr(SocketAddr),
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
+}
+
+impl LocalAddr {
+    #[inline]
+    pub const fn is_unix(&self) -> bool {
+        #[cfg(unix)]
+        if let LocalAddr::UnixSocket(_) = self {
+            return true;
+        }
+        false
+    }
+}
+
+impl From<SocketAddr> for LocalAddr {
+    fn from(addr: SocketAddr) -> Self {
+        LocalAddr::SocketAddr(addr)
+    }
+}
+
+impl FromStr for LocalAddr {
+    type Err = String;
+
+    /// Parse a socket address, or a unix socket path prefixed with `unix:`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix(UNIX_SOCKET_PREFIX) {
+            #[cfg(unix)]
+            return match path {
+                "" => Err(String::from("empty unix socket path")),
+                _ => Ok(LocalAddr::UnixSocket(PathBuf::from(path))),
+            };
+            #[cfg(not(unix))]
+            return Err(format!("unix socket is not supported: {}", path));
+        }
+        s.parse()
+            .map(LocalAddr::SocketAddr)
+            .map_err(|e| format!("invalid local address {}: {}", s, e))
+    }
 }
 
 /// Proxy protocol options.
 #[cfg(feature = "proxy")]
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone)]
 pub struct ProxyOpts {
     pub send_proxy: bool,
     pub accept_proxy: bool,
     pub send_proxy_version: usize,
     pub accept_proxy_timeout: usize,
+    // appended to sent v2 headers
+    pub send_proxy_tlvs: Vec<ProxyTlv>,
+    // expose received tlvs as tags of the connection
+    pub accept_proxy_tlvs: bool,
 }
 
 #[cfg(feature = "proxy")]
@@ -34,40 +226,627 @@ impl ProxyOpts {
     }
 }
 
+/// Type-length-value field of a PROXY protocol v2 header.
+///
+/// Written as `type=value`, where type is a decimal or `0x` prefixed hex byte,
+/// and value is a string, or hex bytes if prefixed with `hex:`.
+#[cfg(feature = "proxy")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyTlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+#[cfg(feature = "proxy")]
+impl ProxyTlv {
+    /// The value as a string if printable, otherwise hex bytes prefixed with `hex:`.
+    pub fn value_string(&self) -> String {
+        match std::str::from_utf8(&self.value) {
+            Ok(x) if !x.starts_with("hex:") && x.chars().all(|c| c.is_ascii_graphic() || c == ' ') => x.to_string(),
+            _ => {
+                let hex: String = self.value.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("hex:{}", hex)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "proxy")]
+impl FromStr for ProxyTlv {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once('=').ok_or_else(|| format!("invalid proxy tlv: {}", s))?;
+        let kind = kind.trim();
+        let kind = match kind.strip_prefix("0x") {
+            Some(x) => u8::from_str_radix(x, 16),
+            None => kind.parse(),
+        }
+        .map_err(|_| format!("invalid proxy tlv type: {}", kind))?;
+
+        let value = match value.strip_prefix("hex:") {
+            Some(x) if x.len() % 2 == 0 => (0..x.len())
+                .step_by(2)
+                .map(|i| x.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("invalid proxy tlv value: {}", value))?,
+            Some(_) => return Err(format!("invalid proxy tlv value: {}", value)),
+            None => value.as_bytes().to_vec(),
+        };
+        if value.len() > u16::MAX as usize {
+            return Err(format!("proxy tlv value is too long: {} bytes", value.len()));
+        }
+
+        Ok(Self { kind, value })
+    }
+}
+
+#[cfg(feature = "proxy")]
+impl Display for ProxyTlv {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#04x}={}", self.kind, self.value_string())
+    }
+}
+
+/// What to do with a connection if the pre-connect hook
+/// fails to make a decision.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookFailMode {
+    /// Drop the connection.
+    #[default]
+    Closed,
+    /// Relay to the default remote peer.
+    Open,
+}
+
+impl FromStr for HookFailMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "closed" => Ok(HookFailMode::Closed),
+            "open" => Ok(HookFailMode::Open),
+            _ => Err(format!("unknown hook fail mode: {}", s)),
+        }
+    }
+}
+
+impl Display for HookFailMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookFailMode::Closed => write!(f, "closed"),
+            HookFailMode::Open => write!(f, "open"),
+        }
+    }
+}
+
+/// What to do with the client once the remote peer closes its write side.
+///
+/// The default follows the `brutal-shutdown` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamEof {
+    /// Pass the EOF to the client, and keep relaying
+    /// the client's pending bytes until it also finishes.
+    #[cfg_attr(not(feature = "brutal-shutdown"), default)]
+    Drain,
+    /// Pass the EOF to the client, then close both sides.
+    #[cfg_attr(feature = "brutal-shutdown", default)]
+    Close,
+}
+
+impl FromStr for UpstreamEof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "drain" => Ok(UpstreamEof::Drain),
+            "close" => Ok(UpstreamEof::Close),
+            _ => Err(format!("unknown upstream eof behavior: {}", s)),
+        }
+    }
+}
+
+impl Display for UpstreamEof {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamEof::Drain => write!(f, "drain"),
+            UpstreamEof::Close => write!(f, "close"),
+        }
+    }
+}
+
+/// A socks5 proxy to connect to the remote through, tcp only.
+///
+/// Written as `[username:password@]ip:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    // username and password
+    pub auth: Option<(String, String)>,
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (auth, addr) = match s.rsplit_once('@') {
+            Some((auth, addr)) => {
+                let (username, password) = auth
+                    .split_once(':')
+                    .ok_or_else(|| format!("missing socks5 password: {}", s))?;
+                // a length of one byte each
+                if !(1..=255).contains(&username.len()) || !(1..=255).contains(&password.len()) {
+                    return Err(format!("socks5 username and password must be 1-255 bytes: {}", s));
+                }
+                (Some((username.to_string(), password.to_string())), addr)
+            }
+            None => (None, s),
+        };
+        let addr = addr
+            .parse()
+            .map_err(|_| format!("invalid socks5 proxy address: {}", addr))?;
+        Ok(Self { addr, auth })
+    }
+}
+
+impl Display for Socks5Proxy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.auth {
+            // keep the password out of logs
+            Some((username, _)) => write!(f, "{}:***@{}", username, self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// Which bytes of a tcp connection are counted as its traffic.
+///
+/// They only differ with a transport, a plain relay
+/// copies the bytes as they are.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteCount {
+    /// Bytes of the relayed stream, after the handshakes,
+    /// without the framing or encryption of the transports.
+    #[default]
+    Payload,
+    /// Bytes received from the client and the remote peer on the sockets,
+    /// including the handshakes and the overhead of the transports.
+    Wire,
+}
+
+impl FromStr for ByteCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "payload" => Ok(ByteCount::Payload),
+            "wire" => Ok(ByteCount::Wire),
+            _ => Err(format!("unknown byte count: {}", s)),
+        }
+    }
+}
+
+impl Display for ByteCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteCount::Payload => write!(f, "payload"),
+            ByteCount::Wire => write!(f, "wire"),
+        }
+    }
+}
+
+/// Tls protocol version.
+#[cfg(feature = "transport")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    #[default]
+    V1_2,
+    V1_3,
+}
+
+#[cfg(feature = "transport")]
+impl TlsVersion {
+    /// Parse the version field of a tls message.
+    pub const fn from_wire(x: u16) -> Option<Self> {
+        match x {
+            0x0303 => Some(TlsVersion::V1_2),
+            0x0304 => Some(TlsVersion::V1_3),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.2" => Ok(TlsVersion::V1_2),
+            "1.3" => Ok(TlsVersion::V1_3),
+            _ => Err(format!("unsupported tls version: {}", s)),
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
+impl Display for TlsVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsVersion::V1_2 => write!(f, "1.2"),
+            TlsVersion::V1_3 => write!(f, "1.3"),
+        }
+    }
+}
+
+// cipher suites supported by the tls transport, and if it is a tls1.3 suite
+#[cfg(feature = "transport")]
+const TLS_CIPHERS: &[(&str, u16, bool)] = &[
+    ("TLS13_AES_128_GCM_SHA256", 0x1301, true),
+    ("TLS13_AES_256_GCM_SHA384", 0x1302, true),
+    ("TLS13_CHACHA20_POLY1305_SHA256", 0x1303, true),
+    ("TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256", 0xc02b, false),
+    ("TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384", 0xc02c, false),
+    ("TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256", 0xcca9, false),
+    ("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256", 0xc02f, false),
+    ("TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384", 0xc030, false),
+    ("TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256", 0xcca8, false),
+];
+
+// key exchange groups supported by the tls transport
+#[cfg(feature = "transport")]
+const TLS_GROUPS: &[(&str, u16)] = &[("x25519", 0x001d), ("secp256r1", 0x0017), ("secp384r1", 0x0018)];
+
+/// Restrictions on the negotiated tls parameters,
+/// a handshake is aborted if it violates the policy.
+///
+/// Empty cipher or group list means no restriction.
+/// Groups are only checked for tls1.3.
+#[cfg(feature = "transport")]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlsPolicy {
+    pub min_version: TlsVersion,
+    pub ciphers: Vec<u16>,
+    pub groups: Vec<u16>,
+}
+
+#[cfg(feature = "transport")]
+impl TlsPolicy {
+    /// Construct from names, fails on unknown names or unsupported combinations.
+    pub fn new<S: AsRef<str>>(min_version: &str, ciphers: &[S], groups: &[S]) -> Result<Self, String> {
+        let min_version: TlsVersion = min_version.parse()?;
+
+        let ciphers = ciphers
+            .iter()
+            .map(|x| {
+                let x = x.as_ref();
+                TLS_CIPHERS
+                    .iter()
+                    .find(|c| c.0.eq_ignore_ascii_case(x))
+                    .map(|c| c.1)
+                    .ok_or_else(|| format!("unsupported tls cipher suite: {}", x))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let groups = groups
+            .iter()
+            .map(|x| {
+                let x = x.as_ref();
+                TLS_GROUPS
+                    .iter()
+                    .find(|g| g.0.eq_ignore_ascii_case(x))
+                    .map(|g| g.1)
+                    .ok_or_else(|| format!("unsupported tls group: {}", x))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let is_tls13 = |x: &u16| TLS_CIPHERS.iter().any(|c| c.1 == *x && c.2);
+        if min_version == TlsVersion::V1_3 && !ciphers.is_empty() && !ciphers.iter().any(is_tls13) {
+            return Err(String::from("no tls1.3 cipher suite is allowed"));
+        }
+        if min_version < TlsVersion::V1_3 && !groups.is_empty() {
+            return Err(String::from("tls groups require min version 1.3"));
+        }
+
+        Ok(Self {
+            min_version,
+            ciphers,
+            groups,
+        })
+    }
+
+    /// Check if the negotiated parameters are allowed.
+    pub fn check(&self, version: TlsVersion, cipher: u16, group: Option<u16>) -> Result<(), String> {
+        if version < self.min_version {
+            return Err(format!("tls version {} < {}", version, self.min_version));
+        }
+        if !self.ciphers.is_empty() && !self.ciphers.contains(&cipher) {
+            return Err(format!("tls cipher suite {} is not allowed", tls_cipher_name(cipher)));
+        }
+        if let (TlsVersion::V1_3, Some(group)) = (version, group) {
+            if !self.groups.is_empty() && !self.groups.contains(&group) {
+                return Err(format!("tls group {} is not allowed", tls_group_name(group)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "transport")]
+impl Display for TlsPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "min-version={}", self.min_version)?;
+        if !self.ciphers.is_empty() {
+            let ciphers: Vec<_> = self.ciphers.iter().map(|x| tls_cipher_name(*x)).collect();
+            write!(f, ", ciphers=[{}]", ciphers.join(", "))?;
+        }
+        if !self.groups.is_empty() {
+            let groups: Vec<_> = self.groups.iter().map(|x| tls_group_name(*x)).collect();
+            write!(f, ", groups=[{}]", groups.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of a cipher suite, or its hex code if unknown.
+#[cfg(feature = "transport")]
+pub fn tls_cipher_name(x: u16) -> String {
+    TLS_CIPHERS
+        .iter()
+        .find(|c| c.1 == x)
+        .map_or_else(|| format!("{:#06x}", x), |c| c.0.to_string())
+}
+
+/// Name of a group, or its hex code if unknown.
+#[cfg(feature = "transport")]
+pub fn tls_group_name(x: u16) -> String {
+    TLS_GROUPS
+        .iter()
+        .find(|g| g.1 == x)
+        .map_or_else(|| format!("{:#06x}", x), |g| g.0.to_string())
+}
+
+/// Quarantine an endpoint if there are `threshold` connect failures
+/// within `window` seconds, then auto-recover after `cooldown` seconds.
+///
+/// Threshold = 0 means never quarantine.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct QuarantineOpts {
+    pub threshold: usize,
+    pub window: usize,
+    pub cooldown: usize,
+}
+
+/// Eject a remote peer from the balancer after `threshold` consecutive
+/// connect failures, then restore it after `cooldown` seconds.
+///
+/// Threshold = 0 means never eject.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EjectOpts {
+    pub threshold: usize,
+    pub cooldown: usize,
+}
+
 /// Connect or associate options.
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct ConnectOpts {
     pub connect_timeout: usize,
+    // connect to the remote again after a failure, waiting
+    // `connect_retry_delay` milliseconds doubled each time, up to 30 seconds
+    pub connect_retries: usize,
+    pub connect_retry_delay: usize,
+    /// Seconds before an idle udp association is dropped,
+    /// 0 for [`DEFAULT_ASSOCIATE_TIMEOUT`].
     pub associate_timeout: usize,
+    // close a tcp connection with no bytes relayed for this many seconds, 0 to disable
+    pub idle_timeout: usize,
     pub tcp_keepalive: usize,
     pub tcp_keepalive_probe: usize,
+    pub tcp_linger: Option<usize>,
+    // TCP_NODELAY of accepted and connected sockets, set by default
+    pub tcp_nodelay: bool,
+    // SO_SNDBUF and SO_RCVBUF of connected sockets, system default if not set
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub associate_concurrency: usize,
+    /// Udp packets received or sent with one syscall, up to [`MAX_PACKETS`](crate::udp::MAX_PACKETS),
+    /// 0 means the max.
+    ///
+    /// A buffer of this many packets (1500 bytes each) is allocated for
+    /// the listener, and another one for each association. A smaller batch
+    /// saves memory with many rules or associations, while a larger one
+    /// takes fewer syscalls under heavy traffic.
+    pub udp_batch_size: usize,
+    pub max_bytes: usize,
+    pub rate_limit_bps: u64,
+    pub write_coalesce_size: usize,
+    pub write_coalesce_delay: usize,
+    pub upstream_eof: UpstreamEof,
+    pub byte_count: ByteCount,
+    pub quarantine: QuarantineOpts,
+    // rotate among resolved addresses, or always use the first one
+    pub dns_round_robin: bool,
+    // milliseconds before racing the next resolved address, 0 to connect one by one
+    #[cfg(feature = "happy-eyeballs")]
+    pub happy_eyeballs_delay: usize,
     pub bind_address: Option<SocketAddr>,
     pub bind_interface: Option<String>,
+    pub socks5_proxy: Option<Socks5Proxy>,
+    // DSCP of connected sockets, 0 to leave it alone
+    pub dscp: u8,
+    // connect with multipath tcp, fall back to tcp if not supported
+    #[cfg(feature = "mptcp")]
+    pub mptcp: bool,
+    // allow all if not set
+    pub accept_filter: Option<SharedAcceptFilter>,
+    #[cfg(feature = "alert")]
+    pub alert: Option<std::sync::Arc<Alert>>,
 
     #[cfg(feature = "proxy")]
     pub proxy_opts: ProxyOpts,
 
+    #[cfg(feature = "hook")]
+    pub hook_fail_mode: HookFailMode,
+
     #[cfg(feature = "transport")]
-    pub transport: Option<(MixAccept, MixConnect)>,
+    pub transport: Option<SharedTransport>,
+
+    #[cfg(feature = "transport")]
+    pub trace_header: Option<String>,
+
+    #[cfg(feature = "transport")]
+    pub tls_policy: Option<std::sync::Arc<TlsPolicy>>,
+
+    #[cfg(feature = "balance")]
+    pub balancer: SharedBalancer,
 
     #[cfg(feature = "balance")]
-    pub balancer: Balancer,
+    pub eject: EjectOpts,
+
+    // seconds between active checks of remote peers, 0 to disable
+    #[cfg(feature = "balance")]
+    pub health_check_interval: usize,
+
+    #[cfg(feature = "geoip")]
+    pub geoip: Option<std::sync::Arc<GeoRouter>>,
 }
 
-#[derive(Debug, Default, Clone)]
+impl Default for ConnectOpts {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 0,
+            connect_retries: 0,
+            connect_retry_delay: 0,
+            associate_timeout: 0,
+            idle_timeout: 0,
+            tcp_keepalive: 0,
+            tcp_keepalive_probe: 0,
+            tcp_linger: None,
+            tcp_nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            associate_concurrency: 0,
+            udp_batch_size: 0,
+            max_bytes: 0,
+            rate_limit_bps: 0,
+            write_coalesce_size: 0,
+            write_coalesce_delay: 0,
+            upstream_eof: UpstreamEof::default(),
+            byte_count: ByteCount::default(),
+            quarantine: QuarantineOpts::default(),
+            dns_round_robin: false,
+            #[cfg(feature = "happy-eyeballs")]
+            happy_eyeballs_delay: 0,
+            bind_address: None,
+            bind_interface: None,
+            socks5_proxy: None,
+            dscp: 0,
+            #[cfg(feature = "mptcp")]
+            mptcp: false,
+            accept_filter: None,
+            #[cfg(feature = "alert")]
+            alert: None,
+            #[cfg(feature = "proxy")]
+            proxy_opts: ProxyOpts::default(),
+            #[cfg(feature = "hook")]
+            hook_fail_mode: HookFailMode::default(),
+            #[cfg(feature = "transport")]
+            transport: None,
+            #[cfg(feature = "transport")]
+            trace_header: None,
+            #[cfg(feature = "transport")]
+            tls_policy: None,
+            #[cfg(feature = "balance")]
+            balancer: SharedBalancer::default(),
+            #[cfg(feature = "balance")]
+            eject: EjectOpts::default(),
+            #[cfg(feature = "balance")]
+            health_check_interval: 0,
+            #[cfg(feature = "geoip")]
+            geoip: None,
+        }
+    }
+}
+
+impl ConnectOpts {
+    /// Get the udp association timeout, which is never 0,
+    /// so that an association is not kept forever.
+    pub fn effective_associate_timeout(&self) -> usize {
+        match self.associate_timeout {
+            0 => DEFAULT_ASSOCIATE_TIMEOUT,
+            x => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct BindOpts {
     pub ipv6_only: bool,
+    pub reuse_address: bool,
+    pub tcp_linger: Option<usize>,
+    // SO_SNDBUF and SO_RCVBUF of listeners, inherited by accepted sockets
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    // pending connections of tcp listeners, 1024 if unset
+    pub listen_backlog: Option<u32>,
     pub bind_interface: Option<String>,
+    // listeners bound to the same address with SO_REUSEPORT, 0 or 1 for a single one
+    pub reuse_port_workers: usize,
+    // DSCP of listeners and accepted sockets, 0 to leave it alone
+    pub dscp: u8,
+    // listen with multipath tcp, fall back to tcp if not supported
+    #[cfg(feature = "mptcp")]
+    pub mptcp: bool,
+}
+
+impl Default for BindOpts {
+    fn default() -> Self {
+        Self {
+            ipv6_only: false,
+            reuse_address: true,
+            tcp_linger: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            listen_backlog: None,
+            bind_interface: None,
+            reuse_port_workers: 0,
+            dscp: 0,
+            #[cfg(feature = "mptcp")]
+            mptcp: false,
+        }
+    }
+}
+
+impl BindOpts {
+    /// Listeners to bind, always 1 if SO_REUSEPORT is not supported.
+    pub fn workers(&self) -> usize {
+        if cfg!(unix) {
+            self.reuse_port_workers.max(1)
+        } else {
+            1
+        }
+    }
 }
 
 /// Relay endpoint.
 #[derive(Debug, Clone)]
 pub struct Endpoint {
-    pub laddr: SocketAddr,
+    pub id: String,
+    pub laddr: LocalAddr,
     pub raddr: RemoteAddr,
     pub bind_opts: BindOpts,
     pub conn_opts: ConnectOpts,
     pub extra_raddrs: Vec<RemoteAddr>,
+    // replace raddr for tcp or udp only
+    pub tcp_raddr: Option<RemoteAddr>,
+    pub udp_raddr: Option<RemoteAddr>,
 }
 
 // display impl below
@@ -78,17 +857,36 @@ impl Display for RemoteAddr {
         match self {
             SocketAddr(addr) => write!(f, "{}", addr),
             DomainName(host, port) => write!(f, "{}:{}", host, port),
+            #[cfg(unix)]
+            UnixSocket(path) => write!(f, "{}{}", UNIX_SOCKET_PREFIX, path.display()),
+        }
+    }
+}
+
+impl Display for LocalAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalAddr::SocketAddr(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            LocalAddr::UnixSocket(path) => write!(f, "{}{}", UNIX_SOCKET_PREFIX, path.display()),
         }
     }
 }
 
 impl Display for Endpoint {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} -> [{}", &self.laddr, &self.raddr)?;
+        write!(f, "{} -> [{}", self.laddr, self.raddr)?;
         for raddr in self.extra_raddrs.iter() {
             write!(f, "|{}", raddr)?;
         }
-        write!(f, "]; options: {}; {}", &self.bind_opts, &self.conn_opts)
+        write!(f, "]")?;
+        if let Some(raddr) = &self.tcp_raddr {
+            write!(f, ", tcp -> {}", raddr)?;
+        }
+        if let Some(raddr) = &self.udp_raddr {
+            write!(f, ", udp -> {}", raddr)?;
+        }
+        write!(f, "; options: {}; {}", self.bind_opts, self.conn_opts)
     }
 }
 
@@ -96,13 +894,51 @@ impl Display for BindOpts {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let BindOpts {
             ipv6_only,
+            reuse_address,
+            tcp_linger,
+            send_buffer_size,
+            recv_buffer_size,
+            listen_backlog,
             bind_interface,
+            reuse_port_workers,
+            dscp,
+            #[cfg(feature = "mptcp")]
+            mptcp,
         } = self;
 
-        write!(f, "ipv6-only={}", ipv6_only)?;
+        write!(f, "ipv6-only={}, reuse-address={}", ipv6_only, reuse_address)?;
+
+        if *reuse_port_workers > 1 {
+            write!(f, ", reuse-port-workers={}", reuse_port_workers)?;
+        }
+
+        if let Some(linger) = tcp_linger {
+            write!(f, ", tcp-linger={}s", linger)?;
+        }
+
+        if let Some(size) = send_buffer_size {
+            write!(f, ", send-buffer={}", size)?;
+        }
+
+        if let Some(size) = recv_buffer_size {
+            write!(f, ", recv-buffer={}", size)?;
+        }
+
+        if let Some(backlog) = listen_backlog {
+            write!(f, ", listen-backlog={}", backlog)?;
+        }
 
         if let Some(iface) = bind_interface {
-            write!(f, "listen-iface={}", iface)?;
+            write!(f, ", listen-iface={}", iface)?;
+        }
+
+        if *dscp != 0 {
+            write!(f, ", dscp={}", dscp)?;
+        }
+
+        #[cfg(feature = "mptcp")]
+        if *mptcp {
+            write!(f, ", mptcp")?;
         }
 
         Ok(())
@@ -113,20 +949,70 @@ impl Display for ConnectOpts {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let ConnectOpts {
             connect_timeout,
-            associate_timeout,
+            connect_retries,
+            connect_retry_delay,
+            // never 0 as shown
+            associate_timeout: _,
+            idle_timeout,
             tcp_keepalive,
             tcp_keepalive_probe,
+            tcp_linger,
+            tcp_nodelay,
+            send_buffer_size,
+            recv_buffer_size,
+            max_connections,
+            max_connections_per_ip,
+            associate_concurrency,
+            udp_batch_size,
+            max_bytes,
+            rate_limit_bps,
+            write_coalesce_size,
+            write_coalesce_delay,
+            upstream_eof,
+            byte_count,
+            quarantine,
+            dns_round_robin,
             bind_address,
             bind_interface,
+            socks5_proxy,
+            dscp,
+            accept_filter,
+
+            #[cfg(feature = "alert")]
+            alert,
+
+            #[cfg(feature = "happy-eyeballs")]
+            happy_eyeballs_delay,
+
+            #[cfg(feature = "mptcp")]
+            mptcp,
 
             #[cfg(feature = "proxy")]
             proxy_opts,
 
+            #[cfg(feature = "hook")]
+            hook_fail_mode,
+
             #[cfg(feature = "transport")]
             transport,
 
+            #[cfg(feature = "transport")]
+            trace_header,
+
+            #[cfg(feature = "transport")]
+            tls_policy,
+
             #[cfg(feature = "balance")]
             balancer,
+
+            #[cfg(feature = "balance")]
+            eject,
+
+            #[cfg(feature = "balance")]
+            health_check_interval,
+
+            #[cfg(feature = "geoip")]
+            geoip,
         } = self;
 
         if let Some(iface) = bind_interface {
@@ -137,6 +1023,15 @@ impl Display for ConnectOpts {
             write!(f, "send-through={}; ", send_through)?;
         }
 
+        if accept_filter.is_some() {
+            write!(f, "accept-filter; ")?;
+        }
+
+        #[cfg(feature = "alert")]
+        if let Some(alert) = alert {
+            write!(f, "alert={}; ", alert.webhook)?;
+        }
+
         #[cfg(feature = "proxy")]
         {
             let ProxyOpts {
@@ -144,27 +1039,206 @@ impl Display for ConnectOpts {
                 accept_proxy,
                 send_proxy_version,
                 accept_proxy_timeout,
+                send_proxy_tlvs,
+                accept_proxy_tlvs,
             } = proxy_opts;
             write!(
                 f,
                 "send-proxy={0}, send-proxy-version={2}, accept-proxy={1}, accept-proxy-timeout={3}s; ",
                 send_proxy, accept_proxy, send_proxy_version, accept_proxy_timeout
             )?;
+            if !send_proxy_tlvs.is_empty() {
+                let tlvs: Vec<_> = send_proxy_tlvs.iter().map(|x| x.to_string()).collect();
+                write!(f, "send-proxy-tlvs=[{}]; ", tlvs.join(", "))?;
+            }
+            if *accept_proxy_tlvs {
+                write!(f, "accept-proxy-tlvs; ")?;
+            }
         }
 
+        #[cfg(feature = "hook")]
+        write!(f, "hook-fail-mode={}; ", hook_fail_mode)?;
+
         write!(
             f,
             "tcp-keepalive={}s[{}] connect-timeout={}s, associate-timeout={}s; ",
-            tcp_keepalive, tcp_keepalive_probe, connect_timeout, associate_timeout
+            tcp_keepalive,
+            tcp_keepalive_probe,
+            connect_timeout,
+            self.effective_associate_timeout()
         )?;
 
+        if let Some(linger) = tcp_linger {
+            write!(f, "tcp-linger={}s; ", linger)?;
+        }
+
+        if *connect_retries != 0 {
+            write!(f, "connect-retries={}[{}ms]; ", connect_retries, connect_retry_delay)?;
+        }
+
+        if *tcp_nodelay {
+            write!(f, "tcp-nodelay; ")?;
+        }
+
+        if let Some(size) = send_buffer_size {
+            write!(f, "send-buffer={}; ", size)?;
+        }
+
+        if let Some(size) = recv_buffer_size {
+            write!(f, "recv-buffer={}; ", size)?;
+        }
+
+        if *idle_timeout != 0 {
+            write!(f, "idle-timeout={}s; ", idle_timeout)?;
+        }
+
+        if *max_connections != 0 {
+            write!(f, "max-connections={}; ", max_connections)?;
+        }
+
+        if *max_connections_per_ip != 0 {
+            write!(f, "max-connections-per-ip={}; ", max_connections_per_ip)?;
+        }
+
+        if *associate_concurrency != 0 {
+            write!(f, "associate-concurrency={}; ", associate_concurrency)?;
+        }
+
+        if *udp_batch_size != 0 {
+            write!(f, "udp-batch-size={}; ", udp_batch_size)?;
+        }
+
+        if *max_bytes != 0 {
+            write!(f, "max-bytes={}; ", max_bytes)?;
+        }
+
+        if *rate_limit_bps != 0 {
+            write!(f, "rate-limit={}bps; ", rate_limit_bps)?;
+        }
+
+        if quarantine.threshold != 0 {
+            let QuarantineOpts {
+                threshold,
+                window,
+                cooldown,
+            } = quarantine;
+            write!(
+                f,
+                "quarantine={}/{}s[cooldown={}s]; ",
+                threshold, window, cooldown
+            )?;
+        }
+
+        if *write_coalesce_delay != 0 {
+            write!(
+                f,
+                "write-coalesce={}ms[{}]; ",
+                write_coalesce_delay, write_coalesce_size
+            )?;
+        }
+
+        if *upstream_eof != UpstreamEof::default() {
+            write!(f, "upstream-eof={}; ", upstream_eof)?;
+        }
+
+        if *byte_count != ByteCount::default() {
+            write!(f, "byte-count={}; ", byte_count)?;
+        }
+
+        if let Some(proxy) = socks5_proxy {
+            write!(f, "socks5-proxy={}; ", proxy)?;
+        }
+
+        if *dscp != 0 {
+            write!(f, "dscp={}; ", dscp)?;
+        }
+
+        #[cfg(feature = "mptcp")]
+        if *mptcp {
+            write!(f, "mptcp; ")?;
+        }
+
+        if *dns_round_robin {
+            write!(f, "dns-round-robin; ")?;
+        }
+
+        #[cfg(feature = "happy-eyeballs")]
+        if *happy_eyeballs_delay != 0 {
+            write!(f, "happy-eyeballs-delay={}ms; ", happy_eyeballs_delay)?;
+        }
+
+        #[cfg(feature = "transport")]
+        if let Some(transport) = transport {
+            let transport = transport.load();
+            write!(f, "transport={}||{}; ", transport.0, transport.1)?;
+        }
+
         #[cfg(feature = "transport")]
-        if let Some((ac, cc)) = transport {
-            write!(f, "transport={}||{}; ", ac, cc)?;
+        if let Some(header) = trace_header {
+            write!(f, "trace-header={}; ", header)?;
+        }
+
+        #[cfg(feature = "transport")]
+        if let Some(policy) = tls_policy {
+            write!(f, "tls-policy=[{}]; ", policy)?;
+        }
+
+        #[cfg(feature = "geoip")]
+        if let Some(geoip) = geoip {
+            write!(f, "geoip={}; ", geoip.path())?;
+        }
+
+        #[cfg(feature = "balance")]
+        if eject.threshold != 0 {
+            write!(f, "eject={}[cooldown={}s]; ", eject.threshold, eject.cooldown)?;
+        }
+
+        #[cfg(feature = "balance")]
+        if *health_check_interval != 0 {
+            write!(f, "health-check={}s; ", health_check_interval)?;
         }
 
         #[cfg(feature = "balance")]
-        write!(f, "balance={}", balancer.strategy())?;
+        write!(f, "balance={}", balancer.load().strategy())?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn associate_timeout_default() {
+        let mut opts = ConnectOpts::default();
+        assert_eq!(opts.effective_associate_timeout(), DEFAULT_ASSOCIATE_TIMEOUT);
+        assert!(opts.to_string().contains("associate-timeout=30s"));
+
+        opts.associate_timeout = 5;
+        assert_eq!(opts.effective_associate_timeout(), 5);
+    }
+
+    #[test]
+    fn parse_socks5_proxy() {
+        let proxy: Socks5Proxy = "127.0.0.1:1080".parse().unwrap();
+        assert_eq!((proxy.addr, proxy.auth), ("127.0.0.1:1080".parse().unwrap(), None));
+
+        let proxy: Socks5Proxy = "user:p@ss@[::1]:1080".parse().unwrap();
+        assert_eq!(proxy.auth, Some(("user".to_string(), "p@ss".to_string())));
+        assert_eq!(proxy.to_string(), "user:***@[::1]:1080");
+
+        assert!("user@127.0.0.1:1080".parse::<Socks5Proxy>().is_err());
+        assert!(":pass@127.0.0.1:1080".parse::<Socks5Proxy>().is_err());
+        assert!("localhost:1080".parse::<Socks5Proxy>().is_err());
+    }
+
+    #[test]
+    fn chain_accept_filters() {
+        let odd = SharedAcceptFilter::new(|peer: SocketAddr| peer.port() % 2 == 1);
+        let low = SharedAcceptFilter::new(|peer: SocketAddr| peer.port() < 1000);
+        let filter = odd.and(low);
+        assert!(filter.allow("10.0.0.1:999".parse().unwrap()));
+        assert!(!filter.allow("10.0.0.1:998".parse().unwrap()));
+        assert!(!filter.allow("10.0.0.1:1001".parse().unwrap()));
+    }
+}
@@ -0,0 +1,211 @@
+//! Flow records of completed connections, in NetFlow v5 format.
+//!
+//! Each tcp connection or udp association is exported as two
+//! unidirectional flows, client -> remote and remote -> client.
+//!
+//! NetFlow v5 only carries ipv4 addresses, flows with an ipv6
+//! client or remote peer are not exported.
+
+use std::io::{Result, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::OnceCell;
+
+use crate::monitor::ConnectionMetrics;
+
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+const VERSION: u16 = 5;
+const HEADER_LEN: usize = 24;
+const RECORD_LEN: usize = 48;
+
+static EXPORTER: OnceCell<Exporter> = OnceCell::new();
+
+struct Exporter {
+    socket: UdpSocket,
+    // sys_uptime of the header is counted from here
+    boot: Instant,
+    sequence: AtomicU32,
+}
+
+/// Send flow records to a collector.
+///
+/// Only the first collector takes effect.
+pub fn set_collector<A: ToSocketAddrs>(addr: A) -> Result<()> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no address"))?;
+    let socket = match addr {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+    };
+    socket.connect(addr)?;
+    socket.set_nonblocking(true)?;
+    let _ = EXPORTER.set(Exporter {
+        socket,
+        boot: Instant::now(),
+        sequence: AtomicU32::new(0),
+    });
+    Ok(())
+}
+
+/// Whether there is a collector, packets are only counted if so.
+pub fn enabled() -> bool {
+    EXPORTER.get().is_some()
+}
+
+/// A unidirectional flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Flow {
+    src: (Ipv4Addr, u16),
+    dst: (Ipv4Addr, u16),
+    proto: u8,
+    packets: u32,
+    bytes: u32,
+    // milliseconds since boot
+    first: u32,
+    last: u32,
+}
+
+fn ipv4(addr: SocketAddr) -> Option<(Ipv4Addr, u16)> {
+    match addr.ip() {
+        IpAddr::V4(ip) => Some((ip, addr.port())),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map(|ip| (ip, addr.port())),
+    }
+}
+
+fn millis_since(boot: Instant, t: Instant) -> u32 {
+    t.saturating_duration_since(boot).as_millis() as u32
+}
+
+fn flows(proto: u8, metrics: &ConnectionMetrics, boot: Instant, now: Instant) -> Option<[Flow; 2]> {
+    let client = ipv4(metrics.peer?)?;
+    let remote = ipv4(metrics.remote?)?;
+    let first = millis_since(boot, metrics.start_time);
+    let last = millis_since(boot, now);
+    let flow = |src, dst, packets: u64, bytes: u64| Flow {
+        src,
+        dst,
+        proto,
+        packets: packets.min(u32::MAX as u64) as u32,
+        bytes: bytes.min(u32::MAX as u64) as u32,
+        first,
+        last,
+    };
+    Some([
+        flow(client, remote, metrics.traffic.tx_packets, metrics.traffic.tx_bytes),
+        flow(remote, client, metrics.traffic.rx_packets, metrics.traffic.rx_bytes),
+    ])
+}
+
+fn encode(buf: &mut Vec<u8>, uptime: u32, unix: SystemTime, sequence: u32, flows: &[Flow]) {
+    let unix = unix.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    // header
+    buf.extend_from_slice(&VERSION.to_be_bytes());
+    buf.extend_from_slice(&(flows.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&uptime.to_be_bytes());
+    buf.extend_from_slice(&(unix.as_secs() as u32).to_be_bytes());
+    buf.extend_from_slice(&unix.subsec_nanos().to_be_bytes());
+    buf.extend_from_slice(&sequence.to_be_bytes());
+    // engine type, engine id, sampling interval
+    buf.extend_from_slice(&[0; 4]);
+
+    for flow in flows {
+        buf.extend_from_slice(&flow.src.0.octets());
+        buf.extend_from_slice(&flow.dst.0.octets());
+        // next hop, input and output interfaces
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&flow.packets.to_be_bytes());
+        buf.extend_from_slice(&flow.bytes.to_be_bytes());
+        buf.extend_from_slice(&flow.first.to_be_bytes());
+        buf.extend_from_slice(&flow.last.to_be_bytes());
+        buf.extend_from_slice(&flow.src.1.to_be_bytes());
+        buf.extend_from_slice(&flow.dst.1.to_be_bytes());
+        // pad, tcp flags
+        buf.extend_from_slice(&[0; 2]);
+        buf.push(flow.proto);
+        // tos, as numbers, masks, pad
+        buf.extend_from_slice(&[0; 9]);
+    }
+}
+
+/// Export a completed connection, if there is a collector.
+pub fn export(proto: u8, metrics: &ConnectionMetrics) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let now = Instant::now();
+    let Some(flows) = flows(proto, metrics, exporter.boot, now) else {
+        log::debug!("[flow]skip non-ipv4 flow: {:?} <-> {:?}", metrics.peer, metrics.remote);
+        return;
+    };
+
+    let sequence = exporter.sequence.fetch_add(flows.len() as u32, Ordering::Relaxed);
+    let mut buf = Vec::with_capacity(HEADER_LEN + RECORD_LEN * flows.len());
+    encode(
+        &mut buf,
+        millis_since(exporter.boot, now),
+        SystemTime::now(),
+        sequence,
+        &flows,
+    );
+    if let Err(e) = exporter.socket.send(&buf) {
+        log::debug!("[flow]failed to send flow records: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn encode_flows() {
+        let boot = Instant::now();
+        let mut metrics = ConnectionMetrics::new();
+        metrics.start_time = boot + Duration::from_millis(100);
+        metrics.peer = Some("10.0.0.1:40000".parse().unwrap());
+        metrics.remote = Some("[::ffff:10.0.0.2]:443".parse().unwrap());
+        metrics.traffic.tx_bytes = 100;
+        metrics.traffic.rx_bytes = 2000;
+        metrics.traffic.tx_packets = 2;
+        metrics.traffic.rx_packets = 3;
+
+        let [up, down] = flows(PROTO_TCP, &metrics, boot, boot + Duration::from_millis(1100)).unwrap();
+        assert_eq!(up.src, (Ipv4Addr::new(10, 0, 0, 1), 40000));
+        assert_eq!(up.dst, (Ipv4Addr::new(10, 0, 0, 2), 443));
+        assert_eq!((up.packets, up.bytes, up.first, up.last), (2, 100, 100, 1100));
+        assert_eq!((down.src, down.dst, down.packets, down.bytes), (up.dst, up.src, 3, 2000));
+
+        let mut buf = Vec::new();
+        encode(&mut buf, 1200, UNIX_EPOCH + Duration::from_secs(7), 3, &[up, down]);
+        assert_eq!(buf.len(), HEADER_LEN + RECORD_LEN * 2);
+        assert_eq!(&buf[..4], &[0, 5, 0, 2]);
+        assert_eq!(&buf[4..8], &1200u32.to_be_bytes());
+        assert_eq!(&buf[8..12], &7u32.to_be_bytes());
+        assert_eq!(&buf[16..20], &3u32.to_be_bytes());
+
+        let record = &buf[HEADER_LEN..HEADER_LEN + RECORD_LEN];
+        assert_eq!(&record[..4], &[10, 0, 0, 1]);
+        assert_eq!(&record[4..8], &[10, 0, 0, 2]);
+        assert_eq!(&record[16..20], &2u32.to_be_bytes());
+        assert_eq!(&record[20..24], &100u32.to_be_bytes());
+        assert_eq!(&record[32..34], &40000u16.to_be_bytes());
+        assert_eq!(&record[34..36], &443u16.to_be_bytes());
+        assert_eq!(record[38], PROTO_TCP);
+    }
+
+    #[test]
+    fn skip_ipv6() {
+        let boot = Instant::now();
+        let mut metrics = ConnectionMetrics::new();
+        metrics.peer = Some("[2001:db8::1]:40000".parse().unwrap());
+        metrics.remote = Some("10.0.0.2:443".parse().unwrap());
+        assert!(flows(PROTO_UDP, &metrics, boot, boot).is_none());
+    }
+}
@@ -0,0 +1,61 @@
+//! GeoIP based remote selection.
+
+use std::fmt::{Debug, Formatter};
+use std::collections::HashMap;
+use std::io::{Result, Error, ErrorKind};
+use std::net::IpAddr;
+
+use maxminddb::{Reader, geoip2};
+
+/// Map the region of a client to a remote peer.
+///
+/// A region is either a country iso code(e.g. `US`) or a
+/// continent code(e.g. `EU`), country takes precedence.
+pub struct GeoRouter {
+    path: String,
+    reader: Reader<Vec<u8>>,
+    // region => peer index, 0 is the default remote peer,
+    // n is the (n - 1)th extra remote peer
+    regions: HashMap<String, usize>,
+}
+
+impl GeoRouter {
+    /// Load a MaxMind country or city database.
+    pub fn open(path: &str, regions: HashMap<String, usize>) -> Result<Self> {
+        let reader = Reader::open_readfile(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let regions = regions.into_iter().map(|(k, v)| (k.to_ascii_uppercase(), v)).collect();
+        Ok(Self {
+            path: path.to_string(),
+            reader,
+            regions,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Get the region of an ip, and the index of the remote peer mapped to it.
+    pub fn lookup(&self, ip: IpAddr) -> Option<(String, Option<usize>)> {
+        let record: geoip2::Country = self.reader.lookup(ip).ok()?;
+        let country = record.country.and_then(|x| x.iso_code);
+        let continent = record.continent.and_then(|x| x.code);
+
+        let idx = [country, continent]
+            .into_iter()
+            .flatten()
+            .find_map(|x| self.regions.get(x).copied());
+        let region = country.or(continent)?.to_string();
+
+        Some((region, idx))
+    }
+}
+
+impl Debug for GeoRouter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoRouter")
+            .field("path", &self.path)
+            .field("regions", &self.regions)
+            .finish()
+    }
+}
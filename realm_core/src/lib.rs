@@ -8,6 +8,15 @@ pub mod trick;
 pub mod endpoint;
 pub mod monitor;
 pub mod api;
+pub mod shutdown;
+pub mod flow;
+pub mod acl;
+#[cfg(feature = "alert")]
+pub mod alert;
+pub mod access;
+
+#[cfg(feature = "geoip")]
+pub mod geoip;
 
 pub use realm_io;
 pub use realm_syscall;
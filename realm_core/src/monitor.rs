@@ -1,29 +1,118 @@
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
 use once_cell::sync::Lazy;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
 // use uuid::Uuid; // Removed as it's not used at the top-level of this file
 use serde::Serialize; // Serialize is used by TrafficStats
 
+use crate::endpoint::QuarantineOpts;
+
 pub static TCP_CONNECTION_METRICS: Lazy<DashMap<String, Arc<Mutex<ConnectionMetrics>>>> = Lazy::new(DashMap::new);
 pub static UDP_ASSOCIATION_METRICS: Lazy<DashMap<SocketAddr, Arc<Mutex<ConnectionMetrics>>>> = Lazy::new(DashMap::new);
 
+// keyed by endpoint id
+pub static RULE_METRICS: Lazy<DashMap<String, Arc<RuleMetrics>>> = Lazy::new(DashMap::new);
+
+/// Default bucket boundaries of per-connection total bytes.
+pub const DEFAULT_BYTES_BUCKETS: &[f64] = &[
+    1024.0,
+    16384.0,
+    65536.0,
+    262144.0,
+    1048576.0,
+    16777216.0,
+    67108864.0,
+    268435456.0,
+    1073741824.0,
+];
+
+/// Default bucket boundaries of connection durations, in seconds.
+pub const DEFAULT_DURATION_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+// summaries of completed connections/associations
+pub static TCP_CONNECTION_HISTOGRAMS: Lazy<Mutex<ConnectionHistograms>> = Lazy::new(Default::default);
+pub static UDP_ASSOCIATION_HISTOGRAMS: Lazy<Mutex<ConnectionHistograms>> = Lazy::new(Default::default);
+
 #[derive(Debug, Serialize, Default, Clone)]
 pub struct TrafficStats {
     pub tx_bytes: u64,
     pub rx_bytes: u64,
+    // udp, or tcp if flow records are exported
+    pub tx_packets: u64,
+    pub rx_packets: u64,
+}
+
+/// Traffic of a live tcp connection, counted while relaying.
+#[derive(Debug, Default)]
+pub struct Relayed {
+    // written from local to remote, and from remote to local
+    pub bytes: [AtomicU64; 2],
+    // reads of each side, if flow records are exported
+    pub packets: [AtomicU64; 2],
+}
+
+impl Relayed {
+    /// Bytes written to the remote and to the local.
+    pub fn bytes(&self) -> (u64, u64) {
+        (self.bytes[0].load(Ordering::Relaxed), self.bytes[1].load(Ordering::Relaxed))
+    }
+
+    /// Packets read from the local and from the remote.
+    pub fn packets(&self) -> (u64, u64) {
+        (self.packets[0].load(Ordering::Relaxed), self.packets[1].load(Ordering::Relaxed))
+    }
 }
 
+/// Application-level metadata of a connection.
+pub type Tags = BTreeMap<String, String>;
+
 #[derive(Debug, Clone)] // Removed Serialize
 pub struct ConnectionMetrics {
     pub traffic: TrafficStats, // TrafficStats still derives Serialize
     pub start_time: Instant,
+    // wall clock time when established, not changed by reset
+    pub started_at: DateTime<Utc>,
     pub last_tx_bytes: u64, // Made public for Serialize and Clone
     pub last_rx_bytes: u64, // Made public for Serialize and Clone
     pub last_speed_update_time: Instant, // Made public for Serialize and Clone
     pub upload_speed_bps: f64,
     pub download_speed_bps: f64,
+    // since established or reset
+    pub peak_upload_speed_bps: f64,
+    pub peak_download_speed_bps: f64,
+    pub avg_upload_speed_bps: f64,
+    pub avg_download_speed_bps: f64,
+    // time covered by the average
+    pub measured_seconds: f64,
+    // resolved by geoip
+    pub region: Option<String>,
+    // propagated across relay hops
+    pub trace_id: Option<String>,
+    // the rule and client of this connection
+    pub rule_id: Option<String>,
+    pub peer: Option<SocketAddr>,
+    // the remote peer connected or associated to
+    pub remote: Option<SocketAddr>,
+    // the remote address selected among remote and extra remotes, before resolved
+    pub raddr: Option<String>,
+    // attached by the pre-connect hook
+    pub tags: Tags,
+    // time taken by the successful tcp connect, including dns resolution
+    pub connect_latency_ms: Option<u64>,
+    // closes the udp association when notified
+    pub closer: Option<Arc<tokio::sync::Notify>>,
+    // counted by the relay of a tcp connection, pulled into `traffic`
+    pub relayed: Option<Arc<Relayed>>,
+    // traffic before the last reset, taken out of `traffic`
+    pub baseline: TrafficStats,
 }
 
 impl Default for ConnectionMetrics {
@@ -31,11 +120,28 @@ impl Default for ConnectionMetrics {
         Self {
             traffic: TrafficStats::default(),
             start_time: Instant::now(),
+            started_at: Utc::now(),
             last_tx_bytes: 0,
             last_rx_bytes: 0,
             last_speed_update_time: Instant::now(),
             upload_speed_bps: 0.0,
             download_speed_bps: 0.0,
+            peak_upload_speed_bps: 0.0,
+            peak_download_speed_bps: 0.0,
+            avg_upload_speed_bps: 0.0,
+            avg_download_speed_bps: 0.0,
+            measured_seconds: 0.0,
+            region: None,
+            trace_id: None,
+            rule_id: None,
+            peer: None,
+            remote: None,
+            raddr: None,
+            tags: Tags::new(),
+            connect_latency_ms: None,
+            closer: None,
+            relayed: None,
+            baseline: TrafficStats::default(),
         }
     }
 }
@@ -46,11 +152,28 @@ impl ConnectionMetrics {
         Self {
             traffic: TrafficStats::default(),
             start_time: now,
+            started_at: Utc::now(),
             last_tx_bytes: 0,
             last_rx_bytes: 0,
             last_speed_update_time: now,
             upload_speed_bps: 0.0,
             download_speed_bps: 0.0,
+            peak_upload_speed_bps: 0.0,
+            peak_download_speed_bps: 0.0,
+            avg_upload_speed_bps: 0.0,
+            avg_download_speed_bps: 0.0,
+            measured_seconds: 0.0,
+            region: None,
+            trace_id: None,
+            rule_id: None,
+            peer: None,
+            remote: None,
+            raddr: None,
+            tags: Tags::new(),
+            connect_latency_ms: None,
+            closer: None,
+            relayed: None,
+            baseline: TrafficStats::default(),
         }
     }
 
@@ -62,6 +185,36 @@ impl ConnectionMetrics {
         self.traffic.rx_bytes += bytes;
     }
 
+    pub fn update_tx_packets(&mut self, packets: u64) {
+        self.traffic.tx_packets += packets;
+    }
+
+    pub fn update_rx_packets(&mut self, packets: u64) {
+        self.traffic.rx_packets += packets;
+    }
+
+    /// Pull the traffic counted by the relay of a tcp connection,
+    /// less the traffic before the last reset.
+    pub fn sync(&mut self) {
+        let Some(relayed) = &self.relayed else {
+            return;
+        };
+        let (tx_bytes, rx_bytes) = relayed.bytes();
+        let (tx_packets, rx_packets) = relayed.packets();
+        let baseline = &self.baseline;
+        self.traffic = TrafficStats {
+            tx_bytes: tx_bytes.saturating_sub(baseline.tx_bytes),
+            rx_bytes: rx_bytes.saturating_sub(baseline.rx_bytes),
+            tx_packets: tx_packets.saturating_sub(baseline.tx_packets),
+            rx_packets: rx_packets.saturating_sub(baseline.rx_packets),
+        };
+    }
+
+    /// Bytes in both directions since established, not changed by reset.
+    pub fn total_bytes(&self) -> u64 {
+        self.traffic.tx_bytes + self.traffic.rx_bytes + self.baseline.tx_bytes + self.baseline.rx_bytes
+    }
+
     pub fn calculate_speed(&mut self) {
         let now = Instant::now();
         let duration = now.duration_since(self.last_speed_update_time);
@@ -80,32 +233,652 @@ impl ConnectionMetrics {
         self.upload_speed_bps = (tx_diff as f64 * 8.0) / seconds;
         self.download_speed_bps = (rx_diff as f64 * 8.0) / seconds;
 
+        self.peak_upload_speed_bps = self.peak_upload_speed_bps.max(self.upload_speed_bps);
+        self.peak_download_speed_bps = self.peak_download_speed_bps.max(self.download_speed_bps);
+
+        // weighted by the length of each interval
+        let measured = self.measured_seconds + seconds;
+        self.avg_upload_speed_bps += (self.upload_speed_bps - self.avg_upload_speed_bps) * seconds / measured;
+        self.avg_download_speed_bps += (self.download_speed_bps - self.avg_download_speed_bps) * seconds / measured;
+        self.measured_seconds = measured;
+
         self.last_tx_bytes = self.traffic.tx_bytes;
         self.last_rx_bytes = self.traffic.rx_bytes;
         self.last_speed_update_time = now;
     }
+
+    /// Start counting from zero, as if the connection is just established.
+    ///
+    /// The next speed is calculated from the bytes since reset.
+    /// Traffic before it is moved to the baseline, which is
+    /// still counted by [`total_bytes`](Self::total_bytes).
+    pub fn reset(&mut self) {
+        let now = Instant::now();
+        self.sync();
+        let traffic = std::mem::take(&mut self.traffic);
+        self.baseline.tx_bytes += traffic.tx_bytes;
+        self.baseline.rx_bytes += traffic.rx_bytes;
+        self.baseline.tx_packets += traffic.tx_packets;
+        self.baseline.rx_packets += traffic.rx_packets;
+        self.last_tx_bytes = 0;
+        self.last_rx_bytes = 0;
+        self.start_time = now;
+        self.last_speed_update_time = now;
+        self.peak_upload_speed_bps = 0.0;
+        self.peak_download_speed_bps = 0.0;
+        self.avg_upload_speed_bps = 0.0;
+        self.avg_download_speed_bps = 0.0;
+        self.measured_seconds = 0.0;
+    }
 }
 
-pub async fn periodically_calculate_speeds() {
-    log::info!("Starting periodic speed calculation task.");
-    loop {
-        tokio::time::sleep(Duration::from_secs(5)).await; // Interval can be configurable later
+/// Per-rule (endpoint) state shared by all of its connections.
+#[derive(Debug, Default)]
+pub struct RuleMetrics {
+    pub id: String,
+    // active tcp connections per source ip
+    pub sources: DashMap<IpAddr, usize>,
+    // connections dropped by max_connections_per_ip
+    pub rejected_per_ip: AtomicU64,
+    // live tcp connections, and those refused by max_connections
+    pub connections: AtomicUsize,
+    pub rejected_max_connections: AtomicU64,
+    // udp packets dropped by associate_concurrency
+    pub associate_dropped: AtomicU64,
+    // tcp connections and udp packets refused by the accept filter
+    pub rejected_filtered: AtomicU64,
+    pub filtered_dropped: AtomicU64,
+    // bytes of closed tcp connections and udp associations
+    pub lifetime_tx_bytes: AtomicU64,
+    pub lifetime_rx_bytes: AtomicU64,
+    // live tcp connections of each remote peer selected by the balancer
+    pub peer_connections: Mutex<Vec<usize>>,
+    // remote peers ejected by connect failures
+    #[cfg(feature = "balance")]
+    pub peer_health: realm_lb::Health,
+    pub quarantine: Mutex<Quarantine>,
+    pub connects: Mutex<ConnectStats>,
+    // cleared once a relay succeeds
+    pub last_error: Mutex<Option<LastError>>,
+    // traffic thresholds to post alerts
+    #[cfg(feature = "alert")]
+    pub alert: arc_swap::ArcSwapOption<crate::alert::Alert>,
+    #[cfg(feature = "alert")]
+    pub alert_state: Mutex<crate::alert::AlertState>,
+    #[cfg(feature = "balance")]
+    pub balancer: arc_swap::ArcSwapOption<RuleBalancer>,
+}
 
-        for entry in TCP_CONNECTION_METRICS.iter() {
-            let Ok(mut metrics) = entry.value().lock() else {
-                log::warn!("Failed to lock TCP metrics for speed calculation for key: {}", entry.key());
-                continue;
-            };
-            metrics.calculate_speed();
+/// Live balancer of a rule.
+#[cfg(feature = "balance")]
+#[derive(Debug)]
+pub struct RuleBalancer {
+    pub live: crate::endpoint::SharedBalancer,
+    // remote + extra remotes
+    pub peers: usize,
+    pub remotes: Vec<String>,
+}
+
+/// Quarantine state of a rule.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    // recent connect failures
+    failures: VecDeque<Instant>,
+    until: Option<Instant>,
+    // how many times the rule has been quarantined
+    pub count: u64,
+}
+
+impl Quarantine {
+    /// Remaining time of the quarantine, auto-recover if expired.
+    pub fn remaining(&mut self) -> Option<Duration> {
+        let until = self.until?;
+        let now = Instant::now();
+        if now >= until {
+            self.until = None;
+            return None;
         }
+        Some(until - now)
+    }
+
+    /// Record a connect failure, return true if this starts a quarantine.
+    pub fn record_failure(&mut self, opts: &QuarantineOpts) -> bool {
+        if opts.threshold == 0 || self.remaining().is_some() {
+            return false;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(opts.window as u64);
+        while self.failures.front().is_some_and(|x| now.duration_since(*x) > window) {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+
+        if self.failures.len() < opts.threshold {
+            return false;
+        }
+
+        self.failures.clear();
+        self.until = Some(now + Duration::from_secs(opts.cooldown as u64));
+        self.count += 1;
+        true
+    }
+
+    /// Lift the quarantine, return true if it was quarantined.
+    pub fn lift(&mut self) -> bool {
+        self.failures.clear();
+        self.until.take().is_some_and(|x| x > Instant::now())
+    }
+}
+
+/// The last failed relay of a rule, e.g. the remote could not be
+/// resolved or connected.
+#[derive(Debug, Clone)]
+pub struct LastError {
+    pub message: String,
+    pub at: DateTime<Utc>,
+    // failures in a row
+    pub count: u64,
+}
+
+/// Window of the connect success rate.
+pub const CONNECT_STATS_WINDOW: Duration = Duration::from_secs(60);
+
+// granularity of the window
+const CONNECT_STATS_SLOT: Duration = Duration::from_secs(1);
+
+// the latest samples within the window are kept
+const CONNECT_LATENCY_SAMPLES: usize = 1024;
+
+/// Recent tcp connect attempts of a rule, in a sliding window.
+#[derive(Debug, Default)]
+pub struct ConnectStats {
+    // (start, attempts, successes), oldest first
+    slots: VecDeque<(Instant, u64, u64)>,
+    // (time, milliseconds) of successful connects, oldest first
+    latencies: VecDeque<(Instant, u64)>,
+}
+
+/// Connect latency of a rule within the window, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectLatency {
+    pub avg_ms: f64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl ConnectStats {
+    fn expire(&mut self, now: Instant) {
+        while self
+            .slots
+            .front()
+            .is_some_and(|(x, ..)| now.duration_since(*x) >= CONNECT_STATS_WINDOW)
+        {
+            self.slots.pop_front();
+        }
+        while self
+            .latencies
+            .front()
+            .is_some_and(|(x, _)| now.duration_since(*x) >= CONNECT_STATS_WINDOW)
+        {
+            self.latencies.pop_front();
+        }
+    }
+
+    fn record_at(&mut self, now: Instant, success: bool) {
+        self.expire(now);
+        match self.slots.back_mut() {
+            Some((start, attempts, successes)) if now.duration_since(*start) < CONNECT_STATS_SLOT => {
+                *attempts += 1;
+                *successes += success as u64;
+            }
+            _ => self.slots.push_back((now, 1, success as u64)),
+        }
+    }
+
+    fn record_latency_at(&mut self, now: Instant, latency: Duration) {
+        self.expire(now);
+        if self.latencies.len() >= CONNECT_LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back((now, latency.as_millis() as u64));
+    }
+
+    fn latency_at(&mut self, now: Instant) -> Option<ConnectLatency> {
+        self.expire(now);
+        let mut samples: Vec<u64> = self.latencies.iter().map(|(_, x)| *x).collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        // nearest rank
+        let rank = (samples.len() * 95).div_ceil(100);
+        Some(ConnectLatency {
+            avg_ms: samples.iter().sum::<u64>() as f64 / samples.len() as f64,
+            p95_ms: samples[rank - 1],
+            max_ms: samples[samples.len() - 1],
+        })
+    }
+
+    fn get_at(&mut self, now: Instant) -> (u64, u64) {
+        self.expire(now);
+        self.slots.iter().fold((0, 0), |(a, s), (_, x, y)| (a + x, s + y))
+    }
+
+    /// Record a connect attempt.
+    pub fn record(&mut self, success: bool) {
+        self.record_at(Instant::now(), success)
+    }
+
+    /// Record the time taken by a successful connect.
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.record_latency_at(Instant::now(), latency)
+    }
+
+    /// Attempts and successes within the window.
+    pub fn get(&mut self) -> (u64, u64) {
+        self.get_at(Instant::now())
+    }
+
+    /// Latency of the recent successful connects within the window,
+    /// `None` if there is no success.
+    pub fn latency(&mut self) -> Option<ConnectLatency> {
+        self.latency_at(Instant::now())
+    }
+
+    /// Success ratio within the window, `None` if there is no attempt.
+    pub fn ratio(&mut self) -> Option<f64> {
+        match self.get() {
+            (0, _) => None,
+            (attempts, successes) => Some(successes as f64 / attempts as f64),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.slots.clear();
+        self.latencies.clear();
+    }
+}
+
+impl RuleMetrics {
+    /// Add the traffic of a closed connection or association to the lifetime totals.
+    pub fn add_lifetime(&self, metrics: &ConnectionMetrics) {
+        self.lifetime_tx_bytes.fetch_add(metrics.traffic.tx_bytes, Ordering::Relaxed);
+        self.lifetime_rx_bytes.fetch_add(metrics.traffic.rx_bytes, Ordering::Relaxed);
+    }
+
+    /// Keep a failed relay as the last error.
+    pub fn record_error(&self, e: impl std::fmt::Display) {
+        if let Ok(mut last) = self.last_error.lock() {
+            let count = last.as_ref().map_or(0, |x| x.count) + 1;
+            *last = Some(LastError {
+                message: e.to_string(),
+                at: Utc::now(),
+                count,
+            });
+        }
+    }
+
+    /// Forget the last error once a relay succeeds.
+    pub fn clear_error(&self) {
+        if let Ok(mut last) = self.last_error.lock() {
+            last.take();
+        }
+    }
+
+    /// Clear counters of dropped connections or packets, connect attempts,
+    /// the lifetime traffic and the last error.
+    pub fn reset(&self) {
+        self.rejected_per_ip.store(0, Ordering::Relaxed);
+        self.rejected_max_connections.store(0, Ordering::Relaxed);
+        self.associate_dropped.store(0, Ordering::Relaxed);
+        self.rejected_filtered.store(0, Ordering::Relaxed);
+        self.filtered_dropped.store(0, Ordering::Relaxed);
+        self.lifetime_tx_bytes.store(0, Ordering::Relaxed);
+        self.lifetime_rx_bytes.store(0, Ordering::Relaxed);
+        if let Ok(mut connects) = self.connects.lock() {
+            connects.reset();
+        }
+        self.clear_error();
+    }
+
+    /// Count a new connection from `ip`, fails if there are already
+    /// `limit` connections from it. Limit = 0 means unlimited.
+    pub fn acquire_source(self: &Arc<Self>, ip: IpAddr, limit: usize) -> Option<SourceGuard> {
+        let mut count = self.sources.entry(ip).or_insert(0);
+        if limit != 0 && *count >= limit {
+            drop(count);
+            self.rejected_per_ip.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        *count += 1;
+        Some(SourceGuard {
+            rule: self.clone(),
+            ip,
+        })
+    }
+
+    /// Count a new tcp connection, fails if there are already
+    /// `limit` connections. Limit = 0 means unlimited.
+    pub fn acquire_connection(self: &Arc<Self>, limit: usize) -> Option<ConnectionGuard> {
+        let acquired = self
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (limit == 0 || n < limit).then_some(n + 1)
+            })
+            .is_ok();
+        if !acquired {
+            self.rejected_max_connections.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(ConnectionGuard { rule: self.clone() })
+    }
+
+    /// Select a remote peer with live connections of each peer,
+    /// then count a new connection to the selected one.
+    pub fn acquire_peer(self: &Arc<Self>, select: impl FnOnce(&[usize]) -> usize) -> PeerGuard {
+        // held while selecting, so that concurrent connections see each other
+        let mut peers = self.peer_connections.lock().unwrap_or_else(PoisonError::into_inner);
+        let idx = select(&peers);
+        if peers.len() <= idx {
+            peers.resize(idx + 1, 0);
+        }
+        peers[idx] += 1;
+        PeerGuard { rule: self.clone(), idx }
+    }
+
+    fn release_source(&self, ip: IpAddr) {
+        if let Entry::Occupied(mut entry) = self.sources.entry(ip) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+
+    /// Check if the rule is quarantined now.
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantine.lock().is_ok_and(|mut x| x.remaining().is_some())
+    }
+
+    /// Source ips with the most active connections, in descending order.
+    pub fn top_sources(&self, n: usize) -> Vec<(IpAddr, usize)> {
+        let mut sources: Vec<_> = self.sources.iter().map(|x| (*x.key(), *x.value())).collect();
+        sources.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        sources.truncate(n);
+        sources
+    }
+}
+
+/// Decrease the source ip's connection count on drop.
+#[derive(Debug)]
+pub struct SourceGuard {
+    rule: Arc<RuleMetrics>,
+    ip: IpAddr,
+}
+
+impl Drop for SourceGuard {
+    fn drop(&mut self) {
+        self.rule.release_source(self.ip);
+    }
+}
+
+/// Decrease the rule's connection count on drop.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    rule: Arc<RuleMetrics>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.rule.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Decrease the peer's connection count on drop.
+#[derive(Debug)]
+pub struct PeerGuard {
+    rule: Arc<RuleMetrics>,
+    pub idx: usize,
+}
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        let mut peers = self.rule.peer_connections.lock().unwrap_or_else(PoisonError::into_inner);
+        peers[self.idx] -= 1;
+    }
+}
+
+/// Lock the metrics of a connection or association.
+///
+/// A relay task which panicked while holding the lock poisons it,
+/// the metrics are still taken as they are, and the poison is cleared.
+pub fn lock_metrics(metrics: &Mutex<ConnectionMetrics>) -> MutexGuard<'_, ConnectionMetrics> {
+    metrics.lock().unwrap_or_else(|e| {
+        log::warn!("[monitor]recovered metrics from a poisoned lock");
+        metrics.clear_poison();
+        e.into_inner()
+    })
+}
+
+/// Get the state of a rule, create it if not exist.
+pub fn rule_metrics(id: &str) -> Arc<RuleMetrics> {
+    RULE_METRICS
+        .entry(id.to_string())
+        .or_insert_with(|| {
+            Arc::new(RuleMetrics {
+                id: id.to_string(),
+                ..Default::default()
+            })
+        })
+        .clone()
+}
+
+/// Histogram with fixed bucket boundaries.
+///
+/// A value falls into the first bucket whose boundary is not less than it,
+/// values greater than all boundaries fall into the implicit `+Inf` bucket.
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    pub bounds: Vec<f64>,
+    // len = bounds.len() + 1, not cumulative
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(mut bounds: Vec<f64>) -> Self {
+        bounds.retain(|x| x.is_finite());
+        bounds.sort_by(|a, b| a.total_cmp(b));
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self {
+            bounds,
+            counts,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let idx = self.bounds.partition_point(|&b| b < value);
+        self.counts[idx] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Iterate over `(upper bound, cumulative count)` pairs,
+    /// the last one is `(+Inf, count)`.
+    pub fn cumulative(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        let bounds = self.bounds.iter().copied().chain(std::iter::once(f64::INFINITY));
+        bounds.zip(self.counts.iter().scan(0, |acc, &n| {
+            *acc += n;
+            Some(*acc)
+        }))
+    }
+}
+
+/// Distributions of completed connections.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionHistograms {
+    pub bytes: Histogram,
+    pub duration: Histogram,
+}
+
+impl Default for ConnectionHistograms {
+    fn default() -> Self {
+        Self::new(DEFAULT_BYTES_BUCKETS.to_vec(), DEFAULT_DURATION_BUCKETS.to_vec())
+    }
+}
+
+impl ConnectionHistograms {
+    pub fn new(bytes_bounds: Vec<f64>, duration_bounds: Vec<f64>) -> Self {
+        Self {
+            bytes: Histogram::new(bytes_bounds),
+            duration: Histogram::new(duration_bounds),
+        }
+    }
+
+    pub fn observe(&mut self, metrics: &ConnectionMetrics) {
+        let bytes = metrics.traffic.tx_bytes + metrics.traffic.rx_bytes;
+        self.bytes.observe(bytes as f64);
+        self.duration.observe(metrics.start_time.elapsed().as_secs_f64());
+    }
+}
 
-        for entry in UDP_ASSOCIATION_METRICS.iter() {
-             let Ok(mut metrics) = entry.value().lock() else {
-                log::warn!("Failed to lock UDP metrics for speed calculation for key: {:?}", entry.key());
-                continue;
-            };
-            metrics.calculate_speed();
+/// Replace bucket boundaries of all histograms, recorded samples are dropped.
+pub fn set_histogram_buckets(bytes_bounds: Vec<f64>, duration_bounds: Vec<f64>) {
+    for histograms in [&TCP_CONNECTION_HISTOGRAMS, &UDP_ASSOCIATION_HISTOGRAMS] {
+        if let Ok(mut histograms) = histograms.lock() {
+            *histograms = ConnectionHistograms::new(bytes_bounds.clone(), duration_bounds.clone());
         }
+    }
+}
+
+/// Record a closed tcp connection.
+pub fn record_tcp_connection(metrics: &ConnectionMetrics) {
+    match TCP_CONNECTION_HISTOGRAMS.lock() {
+        Ok(mut histograms) => histograms.observe(metrics),
+        Err(_) => log::warn!("Failed to lock TCP histograms"),
+    }
+    crate::flow::export(crate::flow::PROTO_TCP, metrics);
+}
+
+/// Record a closed udp association.
+pub fn record_udp_association(metrics: &ConnectionMetrics) {
+    match UDP_ASSOCIATION_HISTOGRAMS.lock() {
+        Ok(mut histograms) => histograms.observe(metrics),
+        Err(_) => log::warn!("Failed to lock UDP histograms"),
+    }
+    crate::flow::export(crate::flow::PROTO_UDP, metrics);
+}
+
+/// Copies of all connection metrics, so that readers never
+/// contend with the relay tasks for the live mutexes.
+#[derive(Debug, Default)]
+pub struct MetricsSnapshot {
+    pub tcp: HashMap<String, ConnectionMetrics>,
+    pub udp: HashMap<SocketAddr, ConnectionMetrics>,
+    /// Identical snapshots have the same digest, uptime is not considered.
+    pub digest: u64,
+}
+
+// independent of the iteration order of entries
+fn entry_digest(key: impl Hash, metrics: &ConnectionMetrics) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    metrics.traffic.tx_bytes.hash(&mut hasher);
+    metrics.traffic.rx_bytes.hash(&mut hasher);
+    metrics.traffic.tx_packets.hash(&mut hasher);
+    metrics.traffic.rx_packets.hash(&mut hasher);
+    metrics.upload_speed_bps.to_bits().hash(&mut hasher);
+    metrics.download_speed_bps.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub static METRICS_SNAPSHOT: Lazy<ArcSwap<MetricsSnapshot>> = Lazy::new(Default::default);
+
+// notified once the snapshot is refreshed
+static SNAPSHOT_REFRESHED: Lazy<tokio::sync::watch::Sender<()>> = Lazy::new(|| tokio::sync::watch::channel(()).0);
+
+// visit all live metrics, and take a snapshot of them
+fn visit_and_snapshot(f: impl Fn(&mut ConnectionMetrics)) {
+    let mut snapshot = MetricsSnapshot::default();
+
+    for entry in TCP_CONNECTION_METRICS.iter() {
+        let mut metrics = lock_metrics(entry.value());
+        // bytes of a live connection are pulled from its relay
+        metrics.sync();
+        f(&mut metrics);
+        snapshot.digest = snapshot.digest.wrapping_add(entry_digest(entry.key(), &metrics));
+        snapshot.tcp.insert(entry.key().clone(), metrics.clone());
+    }
+
+    for entry in UDP_ASSOCIATION_METRICS.iter() {
+        let mut metrics = lock_metrics(entry.value());
+        f(&mut metrics);
+        snapshot.digest = snapshot.digest.wrapping_add(entry_digest(entry.key(), &metrics));
+        snapshot.udp.insert(*entry.key(), metrics.clone());
+    }
+
+    METRICS_SNAPSHOT.store(Arc::new(snapshot));
+    SNAPSHOT_REFRESHED.send_replace(());
+}
+
+/// Reset the metrics of live connections and associations of a rule,
+/// or all of them if `rule` is `None`. Return how many are reset.
+///
+/// The snapshot is refreshed afterwards.
+pub fn reset_metrics(rule: Option<&str>) -> usize {
+    let mut count = 0;
+    let mut reset = |metrics: &Mutex<ConnectionMetrics>| {
+        let mut metrics = lock_metrics(metrics);
+        if rule.is_none() || metrics.rule_id.as_deref() == rule {
+            metrics.reset();
+            count += 1;
+        }
+    };
+    TCP_CONNECTION_METRICS.iter().for_each(|x| reset(x.value()));
+    UDP_ASSOCIATION_METRICS.iter().for_each(|x| reset(x.value()));
+
+    match rule {
+        Some(id) => {
+            if let Some(rule) = RULE_METRICS.get(id) {
+                rule.reset();
+            }
+        }
+        None => RULE_METRICS.iter().for_each(|x| x.reset()),
+    }
+
+    refresh_snapshot();
+    count
+}
+
+/// Refresh the snapshot immediately.
+pub fn refresh_snapshot() {
+    visit_and_snapshot(|_| {});
+}
+
+/// Get the latest snapshot, which is refreshed along with speed calculation.
+pub fn metrics_snapshot() -> Arc<MetricsSnapshot> {
+    METRICS_SNAPSHOT.load_full()
+}
+
+/// Wait for refreshes of the snapshot.
+pub fn subscribe_snapshot() -> tokio::sync::watch::Receiver<()> {
+    SNAPSHOT_REFRESHED.subscribe()
+}
+
+/// Calculate speeds and refresh the snapshot every `interval`.
+pub async fn periodically_calculate_speeds(interval: Duration) {
+    log::info!("Starting periodic speed calculation task, interval: {:?}.", interval);
+    loop {
+        tokio::time::sleep(interval).await;
+
+        visit_and_snapshot(ConnectionMetrics::calculate_speed);
+        #[cfg(feature = "alert")]
+        crate::alert::check_all(&metrics_snapshot());
         log::debug!("Periodic speed calculation complete.");
     }
 }
@@ -121,6 +894,8 @@ mod tests {
         let stats = TrafficStats::default();
         assert_eq!(stats.tx_bytes, 0);
         assert_eq!(stats.rx_bytes, 0);
+        assert_eq!(stats.tx_packets, 0);
+        assert_eq!(stats.rx_packets, 0);
     }
 
     #[test]
@@ -168,6 +943,25 @@ mod tests {
         assert_eq!(metrics.download_speed_bps, 0.0);
     }
 
+    #[test]
+    fn test_calculate_speed_one_second() {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.update_tx(1000);
+        metrics.update_rx(2000);
+        metrics.last_speed_update_time = Instant::now() - Duration::from_secs(1);
+        metrics.calculate_speed();
+
+        assert!((metrics.upload_speed_bps - 8000.0).abs() < 80.0);
+        assert!((metrics.download_speed_bps - 16000.0).abs() < 160.0);
+
+        // called again right away, the last speeds are kept
+        metrics.update_tx(1000);
+        metrics.last_speed_update_time = Instant::now();
+        metrics.calculate_speed();
+        assert!(metrics.upload_speed_bps.is_finite());
+        assert!((metrics.upload_speed_bps - 8000.0).abs() < 80.0);
+    }
+
     #[test]
     fn test_calculate_speed_no_new_bytes() {
         let mut metrics = ConnectionMetrics::new();
@@ -239,4 +1033,342 @@ mod tests {
         assert_eq!(metrics.last_tx_bytes, 1500);
         assert_eq!(metrics.last_rx_bytes, 3000);
     }
+
+    #[test]
+    fn test_peak_and_avg_speed() {
+        let mut metrics = ConnectionMetrics::new();
+
+        // 8000 bps for 1s, then 24000 bps for 1s, then idle for 2s
+        metrics.update_tx(1000);
+        metrics.last_speed_update_time -= Duration::from_secs(1);
+        metrics.calculate_speed();
+        metrics.update_tx(3000);
+        metrics.update_rx(500);
+        metrics.last_speed_update_time -= Duration::from_secs(1);
+        metrics.calculate_speed();
+        assert!((metrics.peak_upload_speed_bps - 24000.0).abs() < 240.0);
+        assert!((metrics.peak_download_speed_bps - 4000.0).abs() < 40.0);
+
+        metrics.last_speed_update_time -= Duration::from_secs(2);
+        metrics.calculate_speed();
+        assert_eq!(metrics.upload_speed_bps, 0.0);
+        // the peaks are kept
+        assert!((metrics.peak_upload_speed_bps - 24000.0).abs() < 240.0);
+        assert!((metrics.peak_download_speed_bps - 4000.0).abs() < 40.0);
+        // 32000 bits over 4s
+        assert!((metrics.avg_upload_speed_bps - 8000.0).abs() < 80.0);
+        assert!((metrics.avg_download_speed_bps - 1000.0).abs() < 10.0);
+        assert!((metrics.measured_seconds - 4.0).abs() < 0.04);
+
+        metrics.reset();
+        assert_eq!(metrics.peak_upload_speed_bps, 0.0);
+        assert_eq!(metrics.avg_upload_speed_bps, 0.0);
+        assert_eq!(metrics.measured_seconds, 0.0);
+    }
+
+    #[test]
+    fn test_rule_sources_limit() {
+        let rule = Arc::new(RuleMetrics::default());
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let g1 = rule.acquire_source(a, 2).unwrap();
+        let g2 = rule.acquire_source(a, 2).unwrap();
+        assert!(rule.acquire_source(a, 2).is_none());
+        assert_eq!(rule.rejected_per_ip.load(Ordering::Relaxed), 1);
+
+        let g3 = rule.acquire_source(b, 0).unwrap();
+        assert_eq!(rule.top_sources(10), vec![(a, 2), (b, 1)]);
+        assert_eq!(rule.top_sources(1), vec![(a, 2)]);
+
+        drop(g1);
+        drop(g2);
+        assert!(rule.sources.get(&a).is_none());
+        drop(g3);
+        assert!(rule.sources.is_empty());
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let opts = QuarantineOpts {
+            threshold: 3,
+            window: 60,
+            cooldown: 60,
+        };
+        let mut q = Quarantine::default();
+        assert!(!q.record_failure(&opts));
+        assert!(!q.record_failure(&opts));
+        assert!(q.record_failure(&opts));
+        assert!(q.remaining().is_some());
+        assert_eq!(q.count, 1);
+
+        // failures during quarantine are ignored
+        assert!(!q.record_failure(&opts));
+
+        assert!(q.lift());
+        assert!(q.remaining().is_none());
+        assert!(!q.lift());
+
+        // disabled
+        let mut q = Quarantine::default();
+        let opts = QuarantineOpts::default();
+        assert!(!q.record_failure(&opts));
+    }
+
+    #[test]
+    fn test_quarantine_recover() {
+        let opts = QuarantineOpts {
+            threshold: 1,
+            window: 1,
+            cooldown: 0,
+        };
+        let mut q = Quarantine::default();
+        assert!(q.record_failure(&opts));
+        thread::sleep(Duration::from_millis(10));
+        assert!(q.remaining().is_none());
+    }
+
+    #[test]
+    fn recover_poisoned_metrics() {
+        let metrics = Arc::new(Mutex::new(ConnectionMetrics::new()));
+        let poisoned = metrics.clone();
+        let _ = thread::spawn(move || {
+            let mut metrics = poisoned.lock().unwrap();
+            metrics.update_tx(100);
+            panic!("relay panicked");
+        })
+        .join();
+        assert!(metrics.is_poisoned());
+
+        lock_metrics(&metrics).update_tx(100);
+        assert_eq!(lock_metrics(&metrics).traffic.tx_bytes, 200);
+        assert!(!metrics.is_poisoned());
+    }
+
+    #[test]
+    fn sync_live_tcp() {
+        let relayed = Arc::new(Relayed::default());
+        let metrics = Arc::new(Mutex::new(ConnectionMetrics::new()));
+        {
+            let mut metrics = lock_metrics(&metrics);
+            metrics.relayed = Some(relayed.clone());
+            metrics.last_speed_update_time -= Duration::from_secs(1);
+        }
+        TCP_CONNECTION_METRICS.insert("sync-test".to_string(), metrics.clone());
+
+        // pulled from the relay while it is running
+        relayed.bytes[0].store(1000, Ordering::Relaxed);
+        relayed.bytes[1].store(2000, Ordering::Relaxed);
+        visit_and_snapshot(ConnectionMetrics::calculate_speed);
+        TCP_CONNECTION_METRICS.remove("sync-test");
+
+        let metrics = lock_metrics(&metrics);
+        assert_eq!(metrics.traffic.tx_bytes, 1000);
+        assert_eq!(metrics.traffic.rx_bytes, 2000);
+        assert!(metrics.upload_speed_bps > 0.0);
+        assert!(metrics.download_speed_bps > 0.0);
+    }
+
+    #[test]
+    fn test_metrics_snapshot() {
+        let metrics = Arc::new(Mutex::new(ConnectionMetrics::new()));
+        TCP_CONNECTION_METRICS.insert("snapshot-test".to_string(), metrics.clone());
+        metrics.lock().unwrap().update_tx(100);
+
+        refresh_snapshot();
+        metrics.lock().unwrap().update_tx(100);
+
+        // the snapshot is not affected by later updates
+        let snapshot = metrics_snapshot();
+        assert_eq!(snapshot.tcp["snapshot-test"].traffic.tx_bytes, 100);
+
+        TCP_CONNECTION_METRICS.remove("snapshot-test");
+    }
+
+    #[test]
+    fn test_reset_metrics() {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.update_tx(1000);
+        metrics.update_rx(2000);
+        metrics.last_speed_update_time -= Duration::from_secs(1);
+        metrics.calculate_speed();
+        assert!((metrics.upload_speed_bps - 8000.0).abs() < 10.0);
+
+        // reset mid relay, the wall clock start is kept
+        let started_at = metrics.started_at;
+        metrics.reset();
+        assert_eq!(metrics.started_at, started_at);
+        metrics.update_tx(10);
+        assert_eq!(metrics.traffic.tx_bytes, 10);
+        assert_eq!(metrics.traffic.rx_bytes, 0);
+        assert!(metrics.start_time.elapsed() < Duration::from_secs(1));
+        metrics.last_speed_update_time -= Duration::from_secs(1);
+        metrics.calculate_speed();
+        assert_eq!(metrics.download_speed_bps, 0.0);
+        assert!((metrics.upload_speed_bps - 80.0).abs() < 1.0);
+
+        // counters lower than the last seen ones never give a negative speed
+        metrics.traffic = TrafficStats::default();
+        metrics.last_speed_update_time -= Duration::from_secs(1);
+        metrics.calculate_speed();
+        assert_eq!(metrics.upload_speed_bps, 0.0);
+    }
+
+    #[test]
+    fn reset_live_tcp() {
+        let relayed = Arc::new(Relayed::default());
+        let mut metrics = ConnectionMetrics::new();
+        metrics.relayed = Some(relayed.clone());
+        relayed.bytes[0].store(1000, Ordering::Relaxed);
+        relayed.bytes[1].store(2000, Ordering::Relaxed);
+
+        // bytes before reset are not counted again
+        metrics.reset();
+        assert_eq!(metrics.traffic.tx_bytes, 0);
+        relayed.bytes[0].store(1100, Ordering::Relaxed);
+        metrics.sync();
+        assert_eq!(metrics.traffic.tx_bytes, 100);
+        assert_eq!(metrics.traffic.rx_bytes, 0);
+        assert_eq!(metrics.total_bytes(), 3100);
+
+        let rule = RuleMetrics::default();
+        rule.add_lifetime(&metrics);
+        assert_eq!(rule.lifetime_tx_bytes.load(Ordering::Relaxed), 100);
+        assert_eq!(rule.lifetime_rx_bytes.load(Ordering::Relaxed), 0);
+
+        // reset again
+        metrics.reset();
+        relayed.bytes[1].store(2050, Ordering::Relaxed);
+        metrics.sync();
+        assert_eq!(metrics.traffic.tx_bytes, 0);
+        assert_eq!(metrics.traffic.rx_bytes, 50);
+        assert_eq!(metrics.total_bytes(), 3150);
+    }
+
+    #[test]
+    fn reset_keeps_total() {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.update_tx(500);
+        metrics.reset();
+        metrics.update_rx(100);
+        assert_eq!(metrics.traffic.tx_bytes, 0);
+        assert_eq!(metrics.traffic.rx_bytes, 100);
+        assert_eq!(metrics.total_bytes(), 600);
+    }
+
+    #[test]
+    fn test_acquire_peer() {
+        let rule = Arc::new(RuleMetrics::default());
+        let a = rule.acquire_peer(|active| {
+            assert!(active.is_empty());
+            2
+        });
+        let b = rule.acquire_peer(|active| {
+            assert_eq!(active, &[0, 0, 1]);
+            0
+        });
+        assert_eq!(*rule.peer_connections.lock().unwrap(), vec![1, 0, 1]);
+
+        drop(a);
+        drop(b);
+        assert_eq!(*rule.peer_connections.lock().unwrap(), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn acquire_peer_poisoned() {
+        let rule = Arc::new(RuleMetrics::default());
+        let a = rule.acquire_peer(|_| 1);
+        let poisoned = rule.clone();
+        let _ = thread::spawn(move || {
+            let _peers = poisoned.peer_connections.lock().unwrap();
+            panic!("relay panicked");
+        })
+        .join();
+        assert!(rule.peer_connections.is_poisoned());
+
+        let b = rule.acquire_peer(|active| {
+            assert_eq!(active, &[0, 1]);
+            1
+        });
+        drop(a);
+        drop(b);
+        assert_eq!(*rule.peer_connections.lock().unwrap_or_else(PoisonError::into_inner), vec![0, 0]);
+    }
+
+    #[test]
+    fn test_histogram_observe() {
+        let mut hist = Histogram::new(vec![10.0, 1.0, f64::NAN, 5.0, 5.0]);
+        assert_eq!(hist.bounds, vec![1.0, 5.0, 10.0]);
+
+        for v in [0.5, 1.0, 3.0, 5.0, 7.0, 100.0] {
+            hist.observe(v);
+        }
+        assert_eq!(hist.counts, vec![2, 2, 1, 1]);
+        assert_eq!(hist.count, 6);
+        assert_eq!(hist.sum, 116.5);
+
+        let cumulative: Vec<_> = hist.cumulative().collect();
+        assert_eq!(cumulative, vec![(1.0, 2), (5.0, 4), (10.0, 5), (f64::INFINITY, 6)]);
+    }
+
+    #[test]
+    fn test_connection_histograms_observe() {
+        let mut hists = ConnectionHistograms::new(vec![100.0, 1000.0], vec![60.0]);
+        let mut metrics = ConnectionMetrics::new();
+        metrics.update_tx(300);
+        metrics.update_rx(400);
+        hists.observe(&metrics);
+
+        assert_eq!(hists.bytes.counts, vec![0, 1, 0]);
+        assert_eq!(hists.duration.counts, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_connect_stats_window() {
+        let mut stats = ConnectStats::default();
+        assert_eq!(stats.ratio(), None);
+
+        let start = Instant::now();
+        stats.record_at(start, true);
+        stats.record_at(start, false);
+        stats.record_at(start + Duration::from_secs(30), true);
+        stats.record_at(start + Duration::from_secs(30), true);
+        assert_eq!(stats.get_at(start + Duration::from_secs(30)), (4, 3));
+
+        // the first slot expires
+        assert_eq!(stats.get_at(start + Duration::from_secs(61)), (2, 2));
+        assert_eq!(stats.get_at(start + Duration::from_secs(91)), (0, 0));
+
+        stats.record(false);
+        stats.reset();
+        assert_eq!(stats.get(), (0, 0));
+    }
+
+    #[test]
+    fn test_connect_latency() {
+        let mut stats = ConnectStats::default();
+        assert_eq!(stats.latency(), None);
+
+        let start = Instant::now();
+        for ms in 1..=20 {
+            stats.record_latency_at(start, Duration::from_millis(ms));
+        }
+        stats.record_latency_at(start + Duration::from_secs(30), Duration::from_millis(100));
+        let latency = stats.latency_at(start + Duration::from_secs(30)).unwrap();
+        assert_eq!(latency.avg_ms, 310.0 / 21.0);
+        assert_eq!((latency.p95_ms, latency.max_ms), (20, 100));
+
+        // the first samples expire
+        let latency = stats.latency_at(start + Duration::from_secs(61)).unwrap();
+        assert_eq!((latency.avg_ms, latency.p95_ms, latency.max_ms), (100.0, 100, 100));
+
+        // only the latest samples are kept
+        for _ in 0..CONNECT_LATENCY_SAMPLES {
+            stats.record_latency_at(start + Duration::from_secs(62), Duration::from_millis(5));
+        }
+        assert_eq!(stats.latency_at(start + Duration::from_secs(62)).unwrap().max_ms, 5);
+
+        stats.reset();
+        assert_eq!(stats.latency(), None);
+    }
 }
@@ -0,0 +1,213 @@
+//! Graceful shutdown.
+//!
+//! Once draining begins, tcp listeners stop accepting new connections
+//! and udp relays stop creating new associations, while established ones
+//! are left alone until they finish or the drain deadline is reached.
+//!
+//! A single relay could also be stopped with [`Stop`], e.g. to replace it.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+
+use crate::monitor::{TCP_CONNECTION_METRICS, UDP_ASSOCIATION_METRICS};
+
+/// Drain deadline.
+#[derive(Debug, Clone, Copy)]
+pub struct Drain {
+    pub deadline: Instant,
+    // unix timestamp in seconds
+    pub deadline_unix: u64,
+}
+
+static DRAIN: Lazy<watch::Sender<Option<Drain>>> = Lazy::new(|| watch::channel(None).0);
+
+/// Begin draining, return false if it has already begun.
+pub fn begin_drain(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let deadline_unix = SystemTime::now()
+        .checked_add(timeout)
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |x| x.as_secs());
+
+    DRAIN.send_if_modified(|drain| match drain {
+        Some(_) => false,
+        None => {
+            *drain = Some(Drain {
+                deadline,
+                deadline_unix,
+            });
+            true
+        }
+    })
+}
+
+/// Get the drain deadline if draining has begun.
+pub fn drain_state() -> Option<Drain> {
+    *DRAIN.borrow()
+}
+
+/// Resolve once draining begins.
+pub async fn draining() {
+    let mut rx = DRAIN.subscribe();
+    let _ = rx.wait_for(|x| x.is_some()).await;
+}
+
+/// Active tcp connections and udp associations.
+pub fn active() -> (usize, usize) {
+    (TCP_CONNECTION_METRICS.len(), UDP_ASSOCIATION_METRICS.len())
+}
+
+/// Check if there is no active connection or association.
+pub fn is_drained() -> bool {
+    active() == (0, 0)
+}
+
+/// Wait until drained or the deadline is reached,
+/// return false if the deadline is reached.
+pub async fn wait_drained() -> bool {
+    draining().await;
+    let Some(Drain { deadline, .. }) = drain_state() else {
+        unreachable!()
+    };
+
+    while !is_drained() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    true
+}
+
+/// Stop signal of a single relay.
+///
+/// Once stopped, the relay closes its listeners, while established
+/// tcp connections are left alone until they finish.
+#[derive(Debug, Clone)]
+pub struct Stop(Arc<StopInner>);
+
+#[derive(Debug)]
+struct StopInner {
+    stopped: watch::Sender<bool>,
+    // relays that may hold a listener
+    listeners: AtomicUsize,
+    // relays that hold a bound listener
+    bound: AtomicUsize,
+}
+
+impl Default for Stop {
+    fn default() -> Self {
+        Self(Arc::new(StopInner {
+            stopped: watch::channel(false).0,
+            listeners: AtomicUsize::new(0),
+            bound: AtomicUsize::new(0),
+        }))
+    }
+}
+
+impl Stop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the relay, resolve once its listeners are closed.
+    pub async fn stop(&self) {
+        self.0.stopped.send_replace(true);
+        while self.0.listeners.load(Ordering::SeqCst) != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Count relays whose listeners are bound and not closed yet.
+    pub fn bound(&self) -> usize {
+        self.0.bound.load(Ordering::SeqCst)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.0.stopped.borrow()
+    }
+
+    /// Resolve once the relay is stopped.
+    pub async fn stopped(&self) {
+        let mut rx = self.0.stopped.subscribe();
+        let _ = rx.wait_for(|x| *x).await;
+    }
+
+    /// Register a listener, which should be dropped along with the guard.
+    ///
+    /// Return `None` if already stopped.
+    pub fn listen(&self) -> Option<ListenGuard> {
+        // register before checking, so that either the relay
+        // sees the signal, or the signal sender waits for it
+        self.0.listeners.fetch_add(1, Ordering::SeqCst);
+        let guard = ListenGuard(self.clone(), false);
+        (!self.is_stopped()).then_some(guard)
+    }
+}
+
+/// A registered listener of [`Stop`].
+#[derive(Debug)]
+pub struct ListenGuard(Stop, bool);
+
+impl ListenGuard {
+    /// Mark the listener as bound, until the guard is dropped.
+    pub fn bind(&mut self) {
+        if !self.1 {
+            self.1 = true;
+            self.0 .0.bound.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for ListenGuard {
+    fn drop(&mut self) {
+        if self.1 {
+            self.0 .0.bound.fetch_sub(1, Ordering::SeqCst);
+        }
+        self.0 .0.listeners.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stop_relay() {
+        let stop = Stop::new();
+        let guard = stop.listen().unwrap();
+
+        let waiter = tokio::spawn({
+            let stop = stop.clone();
+            async move { stop.stop().await }
+        });
+        stop.stopped().await;
+        assert!(!waiter.is_finished());
+
+        // wait for the listener to be closed
+        drop(guard);
+        waiter.await.unwrap();
+        assert!(stop.listen().is_none());
+    }
+
+    #[test]
+    fn bound_listeners() {
+        let stop = Stop::new();
+        let mut tcp = stop.listen().unwrap();
+        let mut udp = stop.listen().unwrap();
+        assert_eq!(stop.bound(), 0);
+
+        tcp.bind();
+        tcp.bind();
+        assert_eq!(stop.bound(), 1);
+        udp.bind();
+        assert_eq!(stop.bound(), 2);
+
+        drop(tcp);
+        assert_eq!(stop.bound(), 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! Cut a connection once enough bytes are transferred.
+
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// bytes left for both directions, flags of each side in the top bits
+struct Budget {
+    state: AtomicU64,
+    // readers of each side, woken once the budget is used up
+    readers: [AtomicWaker; 2],
+}
+
+// a side is reading into its reservation
+const READING: [u64; 2] = [1 << 63, 1 << 62];
+// a side waits for the other one to give back its reservation
+const WAITING: [u64; 2] = [1 << 61, 1 << 60];
+const BYTES: u64 = (1 << 60) - 1;
+
+/// A stream which reaches EOF once the shared budget is used up.
+///
+/// Both sides of a relay share the same budget, the idle side
+/// is woken to reach EOF as well, so that the relay finishes normally.
+pub struct Capped<S> {
+    inner: S,
+    budget: Arc<Budget>,
+    side: usize,
+}
+
+/// Cap the total bytes read from `a` and `b`.
+pub fn pair<A, B>(a: A, b: B, max_bytes: u64) -> (Capped<A>, Capped<B>) {
+    let budget = Arc::new(Budget {
+        state: AtomicU64::new(max_bytes.min(BYTES)),
+        readers: Default::default(),
+    });
+    let a = Capped {
+        inner: a,
+        budget: budget.clone(),
+        side: 0,
+    };
+    let b = Capped { inner: b, budget, side: 1 };
+    (a, b)
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Capped<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let budget = &*this.budget;
+        let side = this.side;
+        budget.readers[side].register(cx.waker());
+
+        // reserve before reading, so that the budget is never overshot
+        let want = buf.remaining() as u64;
+        let state = budget.state.fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+            let left = x & BYTES;
+            if left != 0 {
+                Some((x - left.min(want)) | READING[side])
+            } else if x & READING[1 - side] != 0 {
+                Some(x | WAITING[side])
+            } else {
+                None
+            }
+        });
+        let reserved = match state {
+            Err(_) => return Poll::Ready(Ok(())),
+            // the other side may give back some of its reservation
+            Ok(x) if x & BYTES == 0 => return Poll::Pending,
+            Ok(x) => (x & BYTES).min(want),
+        };
+
+        // read into the front of the unfilled part, without a copy
+        let mut limited = buf.take(reserved as usize);
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+        let n = match result {
+            Poll::Ready(Ok(())) => limited.filled().len(),
+            _ => 0,
+        };
+        // SAFETY: the first n bytes of the unfilled part were just filled
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+
+        // give back what is not read
+        let unused = reserved - n as u64;
+        let before = budget
+            .state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| {
+                Some((x + unused) & !READING[side] & !WAITING[1 - side])
+            })
+            .unwrap_or_default();
+        if before & WAITING[1 - side] != 0 || (n != 0 && (before + unused) & BYTES == 0) {
+            budget.readers[1 - side].wake();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Capped<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn read_within_budget() {
+        let (mut a_peer, a) = duplex(64);
+        let (_b_peer, b) = duplex(64);
+        let (mut a, mut b) = pair(a, b, 10);
+
+        a_peer.write_all(b"0123456789abcdef").await.unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(a.read(&mut buf).await.unwrap(), 10);
+        assert_eq!(&buf[..10], b"0123456789");
+
+        // both sides reach EOF, though b has never been written
+        assert_eq!(a.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(b.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn wake_idle_side() {
+        let (mut a_peer, a) = duplex(64);
+        let (_b_peer, b) = duplex(64);
+        let (mut a, mut b) = pair(a, b, 4);
+
+        let idle = tokio::spawn(async move {
+            let mut buf = [0; 16];
+            b.read(&mut buf).await.unwrap()
+        });
+        tokio::task::yield_now().await;
+
+        a_peer.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(a.read(&mut buf).await.unwrap(), 4);
+        assert_eq!(idle.await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn share_budget() {
+        let (mut a_peer, a) = duplex(64);
+        let (mut b_peer, b) = duplex(64);
+        let (mut a, mut b) = pair(a, b, 10);
+
+        a_peer.write_all(b"01234567").await.unwrap();
+        b_peer.write_all(b"89abcdef").await.unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(a.read(&mut buf).await.unwrap(), 8);
+        assert_eq!(b.read(&mut buf).await.unwrap(), 2);
+        assert_eq!(&buf[..2], b"89");
+        assert_eq!(a.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(b.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_readers() {
+        let (mut a_peer, a) = duplex(1024);
+        let (mut b_peer, b) = duplex(1024);
+        let (a, b) = pair(a, b, 100);
+
+        let read_all = |mut s: Capped<tokio::io::DuplexStream>| {
+            tokio::spawn(async move {
+                let mut total = 0;
+                let mut buf = [0; 7];
+                loop {
+                    match s.read(&mut buf).await.unwrap() {
+                        0 => return total,
+                        n => total += n,
+                    }
+                }
+            })
+        };
+        let (a, b) = (read_all(a), read_all(b));
+        for _ in 0..64 {
+            a_peer.write_all(&[0; 8]).await.unwrap();
+            b_peer.write_all(&[0; 8]).await.unwrap();
+        }
+        assert_eq!(a.await.unwrap() + b.await.unwrap(), 100);
+    }
+}
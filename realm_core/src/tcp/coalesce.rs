@@ -0,0 +1,167 @@
+//! Coalesce tiny writes.
+
+use std::io::Result;
+use std::pin::Pin;
+use std::future::Future;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// A Nagle-like writer.
+///
+/// Small writes are buffered until `size` bytes are accumulated,
+/// or `delay` has passed since the first buffered byte.
+/// Shutdown always flushes the buffered bytes immediately.
+pub struct Coalesce<S> {
+    inner: S,
+    buf: Vec<u8>,
+    pos: usize,
+    size: usize,
+    delay: Duration,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Coalesce<S> {
+    pub fn new(inner: S, size: usize, delay: Duration) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(size),
+            pos: 0,
+            size,
+            delay,
+            deadline: None,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Coalesce<S> {
+    // write out all buffered bytes
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.pos < self.buf.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buf[self.pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+        }
+        self.buf.clear();
+        self.pos = 0;
+        self.deadline = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Coalesce<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Coalesce<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = &mut *self;
+
+        if this.buf.len() + data.len() > this.size {
+            ready!(this.poll_drain(cx))?;
+        }
+
+        // large enough, no need to buffer
+        if data.len() >= this.size {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        }
+
+        if this.buf.is_empty() {
+            this.deadline = Some(Box::pin(sleep(this.delay)));
+        }
+        this.buf.extend_from_slice(data);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+
+        // wait for more data before the deadline
+        if this.buf.len() < this.size {
+            if let Some(deadline) = this.deadline.as_mut() {
+                ready!(deadline.as_mut().poll(cx));
+            }
+        }
+
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[derive(Default)]
+    struct Counter {
+        writes: usize,
+        data: Vec<u8>,
+    }
+
+    impl AsyncWrite for Counter {
+        fn poll_write(mut self: Pin<&mut Self>, _: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+            self.writes += 1;
+            self.data.extend_from_slice(data);
+            Poll::Ready(Ok(data.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesce_tiny_writes() {
+        let mut w = Coalesce::new(Counter::default(), 4096, Duration::from_millis(5));
+        for _ in 0..100 {
+            w.write_all(b"0123456789").await.unwrap();
+        }
+        w.shutdown().await.unwrap();
+
+        // 100 writes => 1 write
+        assert_eq!(w.inner.writes, 1);
+        assert_eq!(w.inner.data.len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn coalesce_size_limit() {
+        let mut w = Coalesce::new(Counter::default(), 64, Duration::from_secs(60));
+        for _ in 0..100 {
+            w.write_all(b"0123456789").await.unwrap();
+        }
+        // 6 writes per 60 bytes
+        assert_eq!(w.inner.writes, 16);
+
+        w.write_all(&[0; 100]).await.unwrap();
+        assert_eq!(w.inner.writes, 18);
+        assert_eq!(w.inner.data.len(), 1100);
+    }
+
+    #[tokio::test]
+    async fn coalesce_flush_after_delay() {
+        let mut w = Coalesce::new(Counter::default(), 4096, Duration::from_millis(20));
+        w.write_all(b"ping").await.unwrap();
+        assert_eq!(w.inner.writes, 0);
+
+        let start = std::time::Instant::now();
+        w.flush().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert_eq!(w.inner.data, b"ping");
+    }
+}
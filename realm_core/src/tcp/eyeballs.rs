@@ -0,0 +1,132 @@
+//! Happy Eyeballs (RFC 8305) connect, racing ipv6 and ipv4 addresses.
+
+use std::io::{Error, ErrorKind, Result};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::time::sleep;
+
+/// Alternate between address families, starting with the family of the first address.
+pub fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(|x| x.is_ipv6());
+    let (a, b): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|x| x.is_ipv6() == first_v6);
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => break,
+            (x, y) => out.extend(x.into_iter().chain(y)),
+        }
+    }
+    out
+}
+
+/// Connect to the interleaved addresses, starting the next attempt once
+/// the previous one fails or after `delay`, whichever comes first.
+/// The first established connection wins, the others are dropped.
+pub async fn race<F, Fut, T>(addrs: Vec<SocketAddr>, delay: Duration, connect: F) -> Result<T>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut addrs = interleave(addrs).into_iter().peekable();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.push(connect(addr));
+        }
+
+        let finished = if addrs.peek().is_some() {
+            match select(attempts.next(), pin!(sleep(delay))).await {
+                Either::Left((x, _)) => x,
+                Either::Right(_) => continue,
+            }
+        } else {
+            attempts.next().await
+        };
+
+        match finished {
+            Some(Ok(x)) => return Ok(x),
+            Some(Err(e)) => last_err = Some(e),
+            None => break,
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| Error::new(ErrorKind::InvalidInput, "could not connect to any address")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn addrs(x: &[&str]) -> Vec<SocketAddr> {
+        x.iter().map(|x| x.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleave_families() {
+        let x = addrs(&["[::1]:1", "[::1]:2", "[::1]:3", "127.0.0.1:1"]);
+        assert_eq!(interleave(x), addrs(&["[::1]:1", "127.0.0.1:1", "[::1]:2", "[::1]:3"]));
+
+        let x = addrs(&["127.0.0.1:1", "127.0.0.1:2", "[::1]:1", "[::1]:2"]);
+        assert_eq!(
+            interleave(x),
+            addrs(&["127.0.0.1:1", "[::1]:1", "127.0.0.1:2", "[::1]:2"])
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_v6() {
+        let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let v4 = lis.local_addr().unwrap();
+        // the v6 route is a black hole
+        let connect = |addr: SocketAddr| async move {
+            if addr.is_ipv6() {
+                sleep(Duration::from_secs(60)).await;
+            }
+            TcpStream::connect(addr).await
+        };
+
+        let start = Instant::now();
+        let x = vec!["[2001:db8::1]:443".parse().unwrap(), v4];
+        let stream = race(x, Duration::from_millis(250), connect).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), v4);
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(250) && elapsed < Duration::from_secs(2),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_v6() {
+        let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let v4 = lis.local_addr().unwrap();
+        let connect = |addr: SocketAddr| async move {
+            if addr.is_ipv6() {
+                return Err(ErrorKind::NetworkUnreachable.into());
+            }
+            TcpStream::connect(addr).await
+        };
+
+        // the next one is started at once
+        let start = Instant::now();
+        let x = vec!["[2001:db8::1]:443".parse().unwrap(), v4];
+        race(x, Duration::from_secs(5), connect).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // the last error is returned if all fail
+        let x = addrs(&["[2001:db8::1]:443", "[2001:db8::2]:443"]);
+        let e = race(x, Duration::from_secs(5), connect).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NetworkUnreachable);
+    }
+}
@@ -0,0 +1,53 @@
+//! Active health checks of remote peers.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::task::JoinHandle;
+
+use realm_lb::Token;
+
+use super::socket;
+use crate::endpoint::{RemoteAddr, ConnectOpts};
+use crate::monitor::RuleMetrics;
+
+/// Periodically dial each peer and mark it up or down
+/// in the balancer, stopped on drop.
+pub struct Prober(JoinHandle<()>);
+
+impl Prober {
+    pub fn spawn(peers: Vec<RemoteAddr>, conn_opts: ConnectOpts, rule: Arc<RuleMetrics>) -> Self {
+        let interval = Duration::from_secs(conn_opts.health_check_interval as u64);
+        Self(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                probe(&peers, &conn_opts, &rule).await;
+            }
+        }))
+    }
+}
+
+impl Drop for Prober {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn probe(peers: &[RemoteAddr], conn_opts: &ConnectOpts, rule: &RuleMetrics) {
+    let connected = join_all(peers.iter().map(|raddr| socket::connect(raddr, conn_opts))).await;
+
+    for (idx, (raddr, connected)) in peers.iter().zip(connected).enumerate() {
+        let token = Token(idx as u8);
+        match connected {
+            Ok(_) if rule.peer_health.set_up(token, true) => {
+                log::info!("[tcp]{} peer {} is up", rule.id, raddr);
+            }
+            Err(e) if rule.peer_health.set_up(token, false) => {
+                log::warn!("[tcp]{} peer {} is down: {}", rule.id, raddr, e);
+            }
+            _ => {}
+        }
+    }
+}
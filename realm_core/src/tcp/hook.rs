@@ -1,33 +1,92 @@
 use std::io::{Result, Error, ErrorKind};
 
 use tokio::net::TcpStream;
-use realm_hook::pre_conn::{self, first_pkt_len, decide_remote_idx};
+use realm_hook::pre_conn::{self, first_pkt_len, decide_remote_idx, connection_tags};
 
-use crate::endpoint::RemoteAddr;
+use crate::endpoint::{RemoteAddr, HookFailMode};
+use crate::monitor::Tags;
 
+/// Select a remote peer, and get the tags of the connection.
 pub async fn pre_connect_hook<'a>(
     local: &mut TcpStream,
     raddr: &'a RemoteAddr,
     extra_raddrs: &'a [RemoteAddr],
-) -> Result<&'a RemoteAddr> {
+    fail_mode: HookFailMode,
+) -> Result<(&'a RemoteAddr, Tags)> {
     if !pre_conn::is_loaded() {
-        return Ok(raddr);
+        return Ok((raddr, Tags::new()));
     }
 
+    match decide(local, extra_raddrs.len()).await {
+        Ok(Some((0, tags))) => Ok((raddr, tags)),
+        Ok(Some((i, tags))) => Ok((&extra_raddrs[i - 1], tags)),
+        Ok(None) => Err(Error::other("rejected by pre-connect hook")),
+        Err(e) => match fail_mode {
+            HookFailMode::Closed => {
+                log::warn!("[tcp]pre-connect hook failed: {}, fail closed", e);
+                Err(e)
+            }
+            HookFailMode::Open => {
+                log::warn!("[tcp]pre-connect hook failed: {}, fail open", e);
+                Ok((raddr, Tags::new()))
+            }
+        },
+    }
+}
+
+// parse `key=value` lines, ignore lines without a key
+fn parse_tags(s: &str) -> Tags {
+    s.lines()
+        .filter_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            let k = k.trim();
+            (!k.is_empty()).then(|| (k.to_string(), v.trim().to_string()))
+        })
+        .collect()
+}
+
+// Ok(None) means the connection is rejected,
+// while Err means the hook could not make a decision.
+async fn decide(local: &mut TcpStream, extra_count: usize) -> Result<Option<(usize, Tags)>> {
     let len = first_pkt_len() as usize;
     let mut buf = Vec::<u8>::new();
 
     if len != 0 {
         buf.resize(len, 0);
-        while local.peek(&mut buf).await? < len {}
+        loop {
+            match local.peek(&mut buf).await? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                n if n >= len => break,
+                _ => {}
+            }
+        }
     }
 
-    let mut idx = extra_raddrs.len() as i32;
-    idx = decide_remote_idx(idx, buf.as_ptr());
+    let idx = decide_remote_idx(extra_count as i32, buf.as_ptr());
 
     match idx {
-        0 => Ok(raddr),
-        i if i >= 1 && i <= idx => Ok(&extra_raddrs[i as usize - 1]),
-        _ => Err(Error::new(ErrorKind::Other, "rejected by pre-connect hook")),
+        i if i < 0 => Ok(None),
+        i if i as usize <= extra_count => {
+            let tags = connection_tags(&buf).map(|x| parse_tags(&x)).unwrap_or_default();
+            Ok(Some((i as usize, tags)))
+        }
+        i => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("pre-connect hook returned invalid index {}", i),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hook_tags() {
+        let tags = parse_tags("user=alice\n tier = gold \n\nbad\n=x\nempty=\n");
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags["user"], "alice");
+        assert_eq!(tags["tier"], "gold");
+        assert_eq!(tags["empty"], "");
     }
 }
@@ -0,0 +1,121 @@
+//! Tell if a connection is idle while relaying.
+
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes read from both sides of a relay.
+pub type Activity = Arc<AtomicU64>;
+
+/// A stream whose reads are counted, pass through if there is no counter.
+pub struct Watched<S> {
+    inner: S,
+    activity: Option<Activity>,
+}
+
+/// Count reads from `a` and `b` with the same counter.
+pub fn pair<A, B>(a: A, b: B, activity: Option<Activity>) -> (Watched<A>, Watched<B>) {
+    let a = Watched {
+        inner: a,
+        activity: activity.clone(),
+    };
+    let b = Watched { inner: b, activity };
+    (a, b)
+}
+
+/// Resolve once the counter stays unchanged for `timeout`.
+///
+/// The counter is checked every second, or more often if the timeout is shorter.
+pub async fn idle(activity: &AtomicU64, timeout: Duration) {
+    let tick = timeout.min(Duration::from_secs(1));
+    let mut last = activity.load(Ordering::Relaxed);
+    let mut active = Instant::now();
+    loop {
+        tokio::time::sleep(tick).await;
+        let now = activity.load(Ordering::Relaxed);
+        if now != last {
+            last = now;
+            active = Instant::now();
+        } else if active.elapsed() >= timeout {
+            return;
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Watched<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(activity) = &this.activity {
+            activity.fetch_add((buf.filled().len() - filled) as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Watched<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn count_reads() {
+        let (mut a_peer, a) = duplex(64);
+        let (mut b_peer, b) = duplex(64);
+        let activity = Activity::default();
+        let (mut a, mut b) = pair(a, b, Some(activity.clone()));
+
+        let mut buf = [0; 16];
+        a_peer.write_all(b"0123").await.unwrap();
+        assert_eq!(a.read(&mut buf).await.unwrap(), 4);
+        b_peer.write_all(b"45").await.unwrap();
+        assert_eq!(b.read(&mut buf).await.unwrap(), 2);
+        assert_eq!(activity.load(Ordering::Relaxed), 6);
+
+        // writes are not counted
+        a.write_all(b"6789").await.unwrap();
+        assert_eq!(activity.load(Ordering::Relaxed), 6);
+    }
+
+    #[tokio::test]
+    async fn idle_after_timeout() {
+        let activity = Activity::default();
+        let timeout = Duration::from_millis(300);
+
+        // kept active
+        let bump = async {
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                activity.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        tokio::select! {
+            _ = idle(&activity, timeout) => panic!("idle while active"),
+            _ = bump => {}
+        }
+
+        let start = Instant::now();
+        idle(&activity, timeout).await;
+        assert!(start.elapsed() >= timeout);
+    }
+}
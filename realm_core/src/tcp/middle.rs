@@ -1,8 +1,10 @@
-use std::io::Result;
-use tokio::net::TcpStream;
+use std::io::{Result, Error};
 
 use super::socket;
+use super::stream::Stream;
 use super::plain;
+use super::throttle::Throttle;
+use super::idle::{self, Activity};
 
 #[cfg(feature = "hook")]
 use super::hook;
@@ -13,105 +15,422 @@ use super::proxy;
 #[cfg(feature = "transport")]
 use super::transport;
 
+use crate::access;
 use crate::trick::Ref;
 use crate::endpoint::{RemoteAddr, ConnectOpts};
-use crate::monitor::{ConnectionMetrics, TCP_CONNECTION_METRICS};
+use crate::monitor::{ConnectionMetrics, Relayed, RuleMetrics, TCP_CONNECTION_METRICS, lock_metrics, record_tcp_connection};
 use std::sync::{Arc, Mutex};
+use std::pin::pin;
+use std::time::{Duration, Instant};
+use futures::future::{select, Either};
 use uuid::Uuid;
 
+// the longest wait before a connect retry
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// `delay` milliseconds doubled for each attempt, up to `MAX_RETRY_DELAY`
+fn retry_delay(delay: usize, attempt: usize) -> Duration {
+    Duration::from_millis(delay as u64)
+        .checked_mul(1 << attempt.min(16))
+        .map_or(MAX_RETRY_DELAY, |x| x.min(MAX_RETRY_DELAY))
+}
+
 #[allow(unused)]
 pub async fn connect_and_relay(
-    mut local: TcpStream,
+    mut local: Stream,
     raddr: Ref<RemoteAddr>,
     conn_opts: Ref<ConnectOpts>,
     extra_raddrs: Ref<Vec<RemoteAddr>>,
+    rule: Arc<RuleMetrics>,
+    throttle: Option<Arc<Throttle>>,
 ) -> Result<()> {
     let ConnectOpts {
         #[cfg(feature = "proxy")]
         proxy_opts,
 
+        #[cfg(feature = "hook")]
+        hook_fail_mode,
+
         #[cfg(feature = "transport")]
         transport,
 
+        #[cfg(feature = "transport")]
+        trace_header,
+
+        #[cfg(feature = "transport")]
+        tls_policy,
+
+        #[cfg(feature = "transport")]
+        byte_count,
+
         #[cfg(feature = "balance")]
         balancer,
 
+        #[cfg(feature = "balance")]
+        eject,
+
+        #[cfg(feature = "geoip")]
+        geoip,
+
         tcp_keepalive,
+        idle_timeout,
+        connect_retries,
+        connect_retry_delay,
+        max_connections_per_ip,
+        max_bytes,
+        write_coalesce_size,
+        write_coalesce_delay,
+        upstream_eof,
+        quarantine,
         ..
     } = conn_opts.as_ref();
 
+    // none if accepted from a unix socket
+    let peer_ip = local.peer_addr()?.map(|x| x.ip());
+
+    // released once the connection is closed
+    let _source = match peer_ip {
+        Some(ip) => match rule.acquire_source(ip, *max_connections_per_ip) {
+            Some(x) => Some(x),
+            None => {
+                return Err(Error::other(format!("too many connections from {}", ip)))
+            }
+        },
+        None => None,
+    };
+
+    // region of the client and the peer mapped to it
+    #[cfg(feature = "geoip")]
+    let (region, geo_idx) = match peer_ip.zip(geoip.as_ref()).and_then(|(ip, x)| x.lookup(ip)) {
+        Some((region, idx)) => (Some(region), idx),
+        None => (None, None),
+    };
+
     // before connect:
     // - pre-connect hook
+    // - geoip
     // - load balance
     // ..
-    let raddr = {
+    let nth_raddr = |idx: usize| match idx {
+        0 => raddr.as_ref(),
+        i => &extra_raddrs.as_ref()[i - 1],
+    };
+    let (mut raddr, tags, mut peer) = {
+        // accept or deny connection,
+        // or select a remote peer if balance is not enabled.
+        // the hook peeks a tcp stream
         #[cfg(feature = "hook")]
-        {
-            // accept or deny connection.
-            #[cfg(feature = "balance")]
-            {
-                hook::pre_connect_hook(&mut local, raddr.as_ref(), extra_raddrs.as_ref()).await?;
+        let (hooked, tags) = match local.as_tcp() {
+            Some(local) => {
+                hook::pre_connect_hook(local, raddr.as_ref(), extra_raddrs.as_ref(), *hook_fail_mode).await?
             }
+            None => (raddr.as_ref(), crate::monitor::Tags::new()),
+        };
 
-            // accept or deny connection, or select a remote peer.
+        #[cfg(not(feature = "hook"))]
+        let (hooked, tags) = (raddr.as_ref(), crate::monitor::Tags::new());
+
+        #[cfg(feature = "geoip")]
+        let routed = geo_idx.map(nth_raddr);
+
+        #[cfg(not(feature = "geoip"))]
+        let routed: Option<&RemoteAddr> = None;
+
+        let (selected, peer) = match routed {
+            Some(x) => {
+                log::debug!("[tcp]select remote peer by geoip: {}", x);
+                (x, None)
+            }
+            #[cfg(feature = "balance")]
+            None => {
+                use std::net::{IpAddr, Ipv4Addr};
+                use realm_lb::{Token, BalanceCtx};
+                let balancer = balancer.load();
+                let peer = rule.acquire_peer(|active| {
+                    let token = balancer.next(BalanceCtx {
+                        src_ip: &peer_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                        active,
+                        health: &rule.peer_health,
+                    });
+                    log::debug!("[tcp]select remote peer, token: {:?}", token);
+                    token.map_or(0, |Token(idx)| idx as usize)
+                });
+                (nth_raddr(peer.idx), Some(peer))
+            }
             #[cfg(not(feature = "balance"))]
-            {
-                hook::pre_connect_hook(&mut local, raddr.as_ref(), extra_raddrs.as_ref()).await?
+            None => (hooked, Option::<crate::monitor::PeerGuard>::None),
+        };
+        (selected, tags, peer)
+    };
+
+    // failover tries the other peers in order at once, before any retry
+    #[cfg(feature = "balance")]
+    let failover = peer.is_some() && balancer.load().strategy() == realm_lb::Strategy::Failover;
+    #[cfg(not(feature = "balance"))]
+    let failover = false;
+    let mut standby = if failover { extra_raddrs.len() } else { 0 };
+
+    // connect!
+    // retry with exponential backoff
+    let mut attempt = 0;
+    #[cfg(feature = "balance")]
+    let mut tried = Vec::new();
+    let (mut remote, connect_latency) = loop {
+        // including dns resolution, unless cached by the resolver
+        let connect_start = Instant::now();
+        let connected = socket::connect(raddr, conn_opts.as_ref()).await;
+        let connect_latency = connect_start.elapsed();
+        if let Ok(mut connects) = rule.connects.lock() {
+            connects.record(connected.is_ok());
+            if connected.is_ok() {
+                connects.record_latency(connect_latency);
+            }
+        }
+        #[cfg(feature = "balance")]
+        if let Some(peer) = &peer {
+            use realm_lb::Token;
+            let token = Token(peer.idx as u8);
+            match &connected {
+                Ok(_) => {
+                    rule.peer_health.report_success(token);
+                    balancer.load().connected(token);
+                }
+                Err(_) => {
+                    let cooldown = Duration::from_secs(eject.cooldown as u64);
+                    if rule.peer_health.report_failure(token, eject.threshold, cooldown) {
+                        log::warn!(
+                            "[tcp]{} ejected peer {} for {}s after {} connect failures",
+                            rule.id,
+                            raddr,
+                            eject.cooldown,
+                            eject.threshold
+                        );
+                    }
+                }
+            }
+        }
+        let e = match connected {
+            Ok(x) => {
+                rule.clear_error();
+                break (x, connect_latency);
             }
+            Err(e) if standby > 0 || attempt < *connect_retries => e,
+            Err(e) => {
+                if rule.quarantine.lock().is_ok_and(|mut x| x.record_failure(quarantine)) {
+                    log::warn!(
+                        "[tcp]{} quarantined for {}s after {} connect failures",
+                        rule.id,
+                        quarantine.cooldown,
+                        quarantine.threshold
+                    );
+                }
+                return Err(e);
+            }
+        };
+
+        if standby > 0 {
+            standby -= 1;
+            log::debug!("[tcp]connect to {}: {}, fail over to the next peer", raddr, e);
+        } else {
+            let delay = retry_delay(*connect_retry_delay, attempt);
+            attempt += 1;
+            log::debug!(
+                "[tcp]connect to {}: {}, retry {}/{} in {}ms",
+                raddr,
+                e,
+                attempt,
+                connect_retries,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
         }
 
+        // move on to another peer if selected by the balancer,
+        // start over once all of them are tried
         #[cfg(feature = "balance")]
-        {
+        if let Some(last) = peer.take() {
+            use std::net::{IpAddr, Ipv4Addr};
             use realm_lb::{Token, BalanceCtx};
-            let token = balancer.next(BalanceCtx {
-                src_ip: &local.peer_addr()?.ip(),
+            tried.push(Token(last.idx as u8));
+            drop(last);
+            let balancer = balancer.load();
+            let src_ip = peer_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            let next = rule.acquire_peer(|active| {
+                let ctx = || BalanceCtx {
+                    src_ip: &src_ip,
+                    active,
+                    health: &rule.peer_health,
+                };
+                let token = balancer.next_untried(ctx(), &tried).or_else(|| {
+                    tried.clear();
+                    balancer.next(ctx())
+                });
+                log::debug!("[tcp]select remote peer to retry, token: {:?}", token);
+                token.map_or(0, |Token(idx)| idx as usize)
             });
-            log::debug!("[tcp]select remote peer, token: {:?}", token);
-            match token {
-                None | Some(Token(0)) => raddr.as_ref(),
-                Some(Token(idx)) => &extra_raddrs.as_ref()[idx as usize - 1],
-            }
+            raddr = nth_raddr(next.idx);
+            peer = Some(next);
         }
-
-        #[cfg(not(any(feature = "hook", feature = "balance")))]
-        raddr.as_ref()
     };
-
-    // connect!
-    let mut remote = socket::connect(raddr, conn_opts.as_ref()).await?;
-    log::info!("[tcp]{} => {} as {}", local.peer_addr()?, raddr, remote.peer_addr()?);
+    let peer = local.peer_addr()?;
+    let remote_addr = remote.peer_addr()?;
+    // a unix socket is named by itself
+    let (peer_s, remote_s) = (
+        peer.map_or_else(|| String::from("unix"), |x| x.to_string()),
+        remote_addr.map_or_else(|| raddr.to_string(), |x| x.to_string()),
+    );
+    if tags.is_empty() {
+        log::info!("[tcp]{} => {} as {}", peer_s, raddr, remote_s);
+    } else {
+        log::info!("[tcp]{} => {} as {}, tags: {:?}", peer_s, raddr, remote_s, tags);
+    }
 
     // after connected
     // ..
     #[cfg(feature = "proxy")]
-    if proxy_opts.enabled() {
-        proxy::handle_proxy(&mut local, &mut remote, *proxy_opts).await?;
-    }
+    let tags = if proxy_opts.enabled() {
+        let Some(src) = local.as_tcp() else {
+            return Err(Error::new(std::io::ErrorKind::Unsupported, "proxy protocol requires a tcp client"));
+        };
+        let tlvs = proxy::handle_proxy(src, &mut remote, proxy_opts).await?;
+        let mut tags = tags;
+        if proxy_opts.accept_proxy_tlvs {
+            tags.extend(tlvs.iter().map(proxy::tlv_tag));
+        }
+        tags
+    } else {
+        tags
+    };
 
     // relay
-    let metrics = Arc::new(Mutex::new(ConnectionMetrics::new()));
+    let metrics = {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.rule_id = Some(rule.id.clone());
+        metrics.peer = peer;
+        metrics.remote = remote_addr;
+        metrics.raddr = Some(raddr.to_string());
+        metrics.tags = tags;
+        metrics.connect_latency_ms = Some(connect_latency.as_millis() as u64);
+        #[cfg(feature = "geoip")]
+        {
+            metrics.region = region;
+        }
+        Arc::new(Mutex::new(metrics))
+    };
     let conn_id = Uuid::new_v4().to_string();
     TCP_CONNECTION_METRICS.insert(conn_id.clone(), metrics.clone());
     log::debug!("[tcp] Stored metrics for connection {}", conn_id);
 
+    // delay = 0 disables coalescing
+    let coalesce = match *write_coalesce_delay {
+        0 => None,
+        ms => Some((*write_coalesce_size, std::time::Duration::from_millis(ms as u64))),
+    };
+
+    log::debug!("[tcp]{} upstream eof: {}", conn_id, upstream_eof);
+
+    // bytes read while relaying, watched for idle timeout
+    let activity = match *idle_timeout {
+        0 => None,
+        _ => Some(Activity::default()),
+    };
+
+    // counted while relaying, pulled into the metrics
+    let relayed = Arc::new(Relayed::default());
+    lock_metrics(&metrics).relayed = Some(relayed.clone());
+
     let relay_result = async {
         #[cfg(feature = "transport")]
         {
-            if let Some((ac, cc)) = transport {
-                transport::run_relay(local, remote, ac, cc, metrics.clone()).await
+            if let Some(transport) = transport {
+                let (Stream::Tcp(local), Stream::Tcp(remote)) = (local, remote) else {
+                    return Err(Error::new(std::io::ErrorKind::Unsupported, "transport requires tcp streams"));
+                };
+                // kept by this connection if replaced
+                let transport = transport.load_full();
+                let (ac, cc) = transport.as_ref();
+                let trace = trace_header.as_deref().map(|header| transport::Trace {
+                    header,
+                    conn_id: &conn_id,
+                });
+                transport::run_relay(
+                    local,
+                    remote,
+                    ac,
+                    cc,
+                    metrics.clone(),
+                    &relayed,
+                    trace,
+                    tls_policy.as_ref(),
+                    *upstream_eof,
+                    *byte_count,
+                    *max_bytes as u64,
+                    throttle,
+                    activity.clone(),
+                )
+                .await
             } else {
-                plain::run_relay(local, remote, metrics.clone()).await
+                plain::run_relay(
+                    local,
+                    remote,
+                    &relayed,
+                    coalesce,
+                    *upstream_eof,
+                    *max_bytes as u64,
+                    throttle,
+                    activity.clone(),
+                )
+                .await
             }
         }
         #[cfg(not(feature = "transport"))]
         {
-            plain::run_relay(local, remote, metrics.clone()).await
+            plain::run_relay(
+                local,
+                remote,
+                &relayed,
+                coalesce,
+                *upstream_eof,
+                *max_bytes as u64,
+                throttle,
+                activity.clone(),
+            )
+            .await
         }
-    }.await;
+    };
 
+    // abort the relay once idle
+    let relay_result = match &activity {
+        None => relay_result.await,
+        Some(activity) => {
+            let timeout = Duration::from_secs(*idle_timeout as u64);
+            match select(pin!(relay_result), pin!(idle::idle(activity, timeout))).await {
+                Either::Left((x, _)) => x,
+                Either::Right(_) => {
+                    log::info!("[tcp]{} closed after idle for {}s", conn_id, idle_timeout);
+                    Ok(())
+                }
+            }
+        }
+    };
+
+    // counted by the rule before it leaves the live ones,
+    // including the bytes of a relay aborted once idle
+    {
+        let mut metrics = lock_metrics(&metrics);
+        metrics.sync();
+        rule.add_lifetime(&metrics);
+    }
     TCP_CONNECTION_METRICS.remove(&conn_id);
     log::debug!("[tcp] Removed metrics for connection {}", conn_id);
 
+    {
+        let metrics = lock_metrics(&metrics);
+        record_tcp_connection(&metrics);
+        let error = relay_result.as_ref().err().map(|e| e.to_string());
+        access::log("tcp", &metrics, error.as_deref());
+    }
+
     // ignore relay error
     if let Err(e) = &relay_result {
         log::debug!("[tcp]forward error: {}, ignored", e);
@@ -119,3 +438,16 @@ pub async fn connect_and_relay(
 
     relay_result.map(|_| ())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_capped() {
+        assert_eq!(retry_delay(100, 0), Duration::from_millis(100));
+        assert_eq!(retry_delay(100, 3), Duration::from_millis(800));
+        assert_eq!(retry_delay(100, 64), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(usize::MAX, 16), MAX_RETRY_DELAY);
+    }
+}
@@ -1,8 +1,15 @@
 //! TCP relay entrance.
 
 mod socket;
+mod stream;
 mod middle;
 mod plain;
+mod coalesce;
+mod cap;
+mod throttle;
+mod idle;
+mod packets;
+mod socks5;
 
 #[cfg(feature = "hook")]
 mod hook;
@@ -13,32 +20,135 @@ mod proxy;
 #[cfg(feature = "transport")]
 mod transport;
 
-use std::io::{ErrorKind, Result};
+#[cfg(feature = "balance")]
+mod health;
 
+#[cfg(feature = "happy-eyeballs")]
+mod eyeballs;
+
+use std::io::{Error, ErrorKind, Result};
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::net::SocketAddr;
+
+use futures::future::{select, select_all, Either};
+use crate::shutdown;
 use crate::trick::Ref;
-use crate::endpoint::Endpoint;
+use crate::endpoint::{Endpoint, LocalAddr};
+use crate::monitor::rule_metrics;
+
+#[cfg(feature = "balance")]
+use crate::monitor::RuleBalancer;
 
 use middle::connect_and_relay;
+use stream::{Listener, Stream};
+pub(crate) use socket::set_dscp;
 
 /// Launch a tcp relay.
 pub async fn run_tcp(endpoint: Endpoint) -> Result<()> {
+    run_tcp_with_stop(endpoint, shutdown::Stop::new()).await
+}
+
+/// Listeners of a tcp relay, bound before it is launched.
+pub struct TcpListeners {
+    listeners: Vec<Listener>,
+    listening: shutdown::ListenGuard,
+}
+
+/// Bind the listeners of a tcp relay, which is launched later
+/// with [`run_tcp_with_listeners`].
+///
+/// Return `None` if already stopped.
+pub fn bind_tcp(endpoint: &Endpoint, stop: &shutdown::Stop) -> Result<Option<TcpListeners>> {
+    let Endpoint { laddr, bind_opts, .. } = endpoint;
+
+    let Some(mut listening) = stop.listen() else {
+        return Ok(None);
+    };
+
+    let listeners: Vec<_> = match laddr {
+        LocalAddr::SocketAddr(addr) => (0..bind_opts.workers())
+            .map(|_| socket::bind(addr, bind_opts.clone()).map(Listener::Tcp))
+            .collect::<Result<_>>(),
+        // a socket file could not be shared
+        #[cfg(unix)]
+        LocalAddr::UnixSocket(path) => socket::bind_unix(path).map(|x| vec![Listener::Unix(x)]),
+    }
+    .map_err(|e| Error::new(e.kind(), format!("[tcp]failed to bind {}: {}", laddr, e)))?;
+    listening.bind();
+
+    Ok(Some(TcpListeners { listeners, listening }))
+}
+
+/// Launch a tcp relay, which could be stopped later.
+///
+/// Once stopped, this returns after all established connections finish.
+pub async fn run_tcp_with_stop(endpoint: Endpoint, stop: shutdown::Stop) -> Result<()> {
+    match bind_tcp(&endpoint, &stop)? {
+        Some(listeners) => run_tcp_with_listeners(endpoint, listeners, stop).await,
+        None => Ok(()),
+    }
+}
+
+/// Same as [`run_tcp_with_stop`], but with listeners from [`bind_tcp`].
+pub async fn run_tcp_with_listeners(endpoint: Endpoint, listeners: TcpListeners, stop: shutdown::Stop) -> Result<()> {
     let Endpoint {
+        id,
         laddr,
         raddr,
         bind_opts,
         conn_opts,
         extra_raddrs,
+        tcp_raddr,
+        ..
     } = endpoint;
 
+    let raddr = tcp_raddr.unwrap_or(raddr);
     let raddr = Ref::new(&raddr);
     let conn_opts = Ref::new(&conn_opts);
     let extra_raddrs = Ref::new(&extra_raddrs);
+    let rule = rule_metrics(&id);
+    // shared by all connections
+    let throttle = throttle::Throttle::new(conn_opts.rate_limit_bps);
+
+    let TcpListeners { listeners, listening } = listeners;
+
+    // checked along with speed calculation
+    #[cfg(feature = "alert")]
+    rule.alert.store(conn_opts.alert.clone());
+
+    // remote + extra remotes
+    #[cfg(feature = "balance")]
+    let peers: Vec<_> = std::iter::once(raddr.as_ref()).chain(extra_raddrs.iter()).cloned().collect();
+
+    // expose the balancer to the api
+    #[cfg(feature = "balance")]
+    rule.balancer.store(Some(Arc::new(RuleBalancer {
+        live: conn_opts.balancer.clone(),
+        peers: peers.len(),
+        remotes: peers.iter().map(ToString::to_string).collect(),
+    })));
+
+    // aborted once the relay returns
+    #[cfg(feature = "balance")]
+    let _prober = (conn_opts.health_check_interval != 0)
+        .then(|| health::Prober::spawn(peers, conn_opts.as_ref().clone(), rule.clone()));
 
-    let lis = socket::bind(&laddr, bind_opts).unwrap_or_else(|e| panic!("[tcp]failed to bind {}: {}", &laddr, e));
     let keepalive = socket::keepalive::build(&conn_opts);
+    let mut draining = pin!(shutdown::draining());
+    let mut stopped = pin!(stop.stopped());
+    // held by each connection
+    let alive = Arc::new(());
 
-    loop {
-        let (local, addr) = match lis.accept().await {
+    let stopped = loop {
+        let accepted = match select(pin!(accept_any(&listeners)), select(draining.as_mut(), stopped.as_mut())).await {
+            Either::Left((x, _)) => x,
+            Either::Right((Either::Left(_), _)) => break false,
+            Either::Right((Either::Right(_), _)) => break true,
+        };
+        let (local, addr) = match accepted {
             Ok(x) => x,
             Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
                 log::warn!("[tcp]failed to accept: {}", e);
@@ -46,25 +156,96 @@ pub async fn run_tcp(endpoint: Endpoint) -> Result<()> {
             }
             Err(e) => {
                 log::error!("[tcp]failed to accept: {}", e);
-                break;
+                return Ok(());
             }
         };
 
-        // ignore error
-        let _ = local.set_nodelay(true);
-        // set tcp_keepalive
-        if let Some(kpa) = &keepalive {
-            use socket::keepalive::SockRef;
-            SockRef::from(&local).set_tcp_keepalive(kpa)?;
+        // clients of a unix socket are named by the socket
+        let peer = addr.map_or_else(|| laddr.to_string(), |x| x.to_string());
+
+        if let (Some(addr), Some(filter)) = (addr, conn_opts.accept_filter.as_ref()) {
+            if !filter.allow(addr) {
+                log::info!("[tcp]{} => {}, refused: filtered", peer, raddr.as_ref());
+                rule.rejected_filtered.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
         }
 
+        // refuse new connections
+        if rule.is_quarantined() {
+            log::debug!("[tcp]{} => {}, refused: quarantined", peer, raddr.as_ref());
+            continue;
+        }
+
+        // released once the connection is closed
+        let Some(connection) = rule.acquire_connection(conn_opts.max_connections) else {
+            log::debug!("[tcp]{} => {}, refused: too many connections", peer, raddr.as_ref());
+            continue;
+        };
+
+        if let Stream::Tcp(local) = &local {
+            // ignore error
+            if conn_opts.tcp_nodelay {
+                let _ = local.set_nodelay(true);
+            }
+            // set tcp_keepalive
+            if let Some(kpa) = &keepalive {
+                use socket::keepalive::SockRef;
+                SockRef::from(local).set_tcp_keepalive(kpa)?;
+            }
+            // not every platform passes it on to accepted sockets
+            if let (dscp @ 1.., Ok(addr)) = (bind_opts.dscp, local.local_addr()) {
+                use socket::keepalive::SockRef;
+                socket::set_dscp(&SockRef::from(local), &addr, dscp);
+            }
+        }
+
+        let rule = rule.clone();
+        let throttle = throttle.clone();
+        let alive = alive.clone();
         tokio::spawn(async move {
-            match connect_and_relay(local, raddr, conn_opts, extra_raddrs).await {
-                Ok(..) => log::debug!("[tcp]{} => {}, finish", addr, raddr.as_ref()),
-                Err(e) => log::error!("[tcp]{} => {}, error: {}", addr, raddr.as_ref(), e),
+            match connect_and_relay(local, raddr, conn_opts, extra_raddrs, rule.clone(), throttle).await {
+                Ok(..) => log::debug!("[tcp]{} => {}, finish", peer, raddr.as_ref()),
+                Err(e) => {
+                    log::error!("[tcp]{} => {}, error: {}", peer, raddr.as_ref(), e);
+                    rule.record_error(e);
+                }
             }
+            drop(connection);
+            drop(alive);
         });
+    };
+
+    drop(listeners);
+    drop(listening);
+
+    // leave no socket file behind
+    #[cfg(unix)]
+    if let LocalAddr::UnixSocket(path) = &laddr {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // stop accepting, established connections still
+    // refer to this frame until the runtime is dropped
+    if !stopped {
+        log::info!("[tcp]{} stopped accepting: draining", laddr);
+        std::future::pending::<()>().await;
     }
 
+    // or until they finish
+    log::info!("[tcp]{} stopped, wait for {} connections", laddr, Arc::strong_count(&alive) - 1);
+    while Arc::strong_count(&alive) > 1 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    log::info!("[tcp]{} stopped", laddr);
+
     Ok(())
 }
+
+// accept from any of the listeners sharing the address
+async fn accept_any(listeners: &[Listener]) -> Result<(Stream, Option<SocketAddr>)> {
+    match listeners {
+        [lis] => lis.accept().await,
+        _ => select_all(listeners.iter().map(|x| Box::pin(x.accept()))).await.0,
+    }
+}
@@ -0,0 +1,88 @@
+//! Count packets while relaying.
+//!
+//! A tcp stream has no packet boundary, each non-empty read
+//! is taken as a packet.
+
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::monitor::Relayed;
+
+/// A stream whose reads are counted, pass through if there is no counter.
+pub struct Counted<S> {
+    inner: S,
+    packets: Option<(Arc<Relayed>, usize)>,
+}
+
+/// Count reads from `a` and `b` separately.
+pub fn pair<A, B>(a: A, b: B, packets: Option<Arc<Relayed>>) -> (Counted<A>, Counted<B>) {
+    let a = Counted {
+        inner: a,
+        packets: packets.clone().map(|p| (p, 0)),
+    };
+    let b = Counted {
+        inner: b,
+        packets: packets.map(|p| (p, 1)),
+    };
+    (a, b)
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some((relayed, side)) = &this.packets {
+            if buf.filled().len() > filled {
+                relayed.packets[*side].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn count_reads() {
+        let (mut a_peer, a) = duplex(64);
+        let (mut b_peer, b) = duplex(64);
+        let relayed = Arc::new(Relayed::default());
+        let (mut a, mut b) = pair(a, b, Some(relayed.clone()));
+
+        let mut buf = [0; 16];
+        a_peer.write_all(b"0123").await.unwrap();
+        assert_eq!(a.read(&mut buf).await.unwrap(), 4);
+        a_peer.write_all(b"45").await.unwrap();
+        assert_eq!(a.read(&mut buf).await.unwrap(), 2);
+        b_peer.write_all(b"6").await.unwrap();
+        assert_eq!(b.read(&mut buf).await.unwrap(), 1);
+
+        // eof is not a packet
+        drop(b_peer);
+        assert_eq!(b.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(relayed.packets(), (2, 1));
+    }
+}
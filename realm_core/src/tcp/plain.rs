@@ -1,43 +1,222 @@
 use std::io::Result;
-use tokio::net::TcpStream;
-use crate::monitor::ConnectionMetrics;
-use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use realm_io::{CopyBuffer, bidi_copy_buf_counted, buf_size};
+use crate::endpoint::UpstreamEof;
+use crate::monitor::Relayed;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
 
+use super::cap;
+use super::stream::Stream;
+use super::idle::{self, Activity};
+use super::coalesce::Coalesce;
+use super::throttle::{self, Throttle};
+use super::packets;
+
+/// Relay between local and remote, `max_bytes` = 0 means unlimited.
+///
+/// Bytes written in each direction are stored in `relayed` while relaying,
+/// so they are kept if the relay is aborted or fails.
+/// Bytes read from both sides are added to `activity` if provided.
+/// Packets are counted if flow records are exported.
 #[inline]
-pub async fn run_relay(mut local: TcpStream, mut remote: TcpStream, metrics: Arc<Mutex<ConnectionMetrics>>) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+pub async fn run_relay(
+    mut local: Stream,
+    mut remote: Stream,
+    relayed: &Arc<Relayed>,
+    coalesce: Option<(usize, Duration)>,
+    upstream_eof: UpstreamEof,
+    max_bytes: u64,
+    throttle: Option<Arc<Throttle>>,
+    activity: Option<Activity>,
+) -> Result<()> {
+    let close = upstream_eof == UpstreamEof::Close;
+    let counted = &relayed.bytes;
+    let packets = crate::flow::enabled().then(|| relayed.clone());
+
+    // zero copy is not possible with a userspace write buffer,
+    // nor if bytes are counted or paced while relaying
+    if coalesce.is_some() || max_bytes != 0 || throttle.is_some() || activity.is_some() || packets.is_some() {
+        return match coalesce {
+            Some((size, delay)) => {
+                let local = Coalesce::new(local, size, delay);
+                let remote = Coalesce::new(remote, size, delay);
+                limited_copy(local, remote, close, max_bytes, throttle, activity, counted, packets).await
+            }
+            None => limited_copy(local, remote, close, max_bytes, throttle, activity, counted, packets).await,
+        }
+        .map(|_| ());
+    }
+
+    // zero copy between tcp streams
     #[cfg(target_os = "linux")]
-    {
+    if let (Stream::Tcp(local), Stream::Tcp(remote)) = (&mut local, &mut remote) {
         use std::io::ErrorKind;
-        let result = realm_io::bidi_zero_copy(&mut local, &mut remote).await;
-        match result {
-            Ok((a_to_b, b_to_a)) => {
-                let mut w_metrics = metrics.lock().unwrap();
-                w_metrics.update_tx(a_to_b);
-                w_metrics.update_rx(b_to_a);
-                Ok(())
-            }
+        let result = bidi_zero_copy(local, remote, close, counted).await;
+        return match result {
+            Ok(_) => Ok(()),
             Err(ref e) if e.kind() == ErrorKind::InvalidInput => {
                 // Fallback to bidi_copy if zero_copy is not supported or fails with InvalidInput
-                let fallback_result = realm_io::bidi_copy(&mut local, &mut remote).await;
-                if let Ok((a_to_b, b_to_a)) = fallback_result {
-                    let mut w_metrics = metrics.lock().unwrap();
-                    w_metrics.update_tx(a_to_b);
-                    w_metrics.update_rx(b_to_a);
-                }
-                fallback_result.map(|_| ())
+                bidi_copy(local, remote, close, counted).await.map(|_| ())
             }
             Err(e) => Err(e),
-        }
+        };
     }
 
-    #[cfg(not(target_os = "linux"))]
-    {
-        let result = realm_io::bidi_copy(&mut local, &mut remote).await;
-        if let Ok((a_to_b, b_to_a)) = result {
-            let mut w_metrics = metrics.lock().unwrap();
-            w_metrics.update_tx(a_to_b);
-            w_metrics.update_rx(b_to_a);
+    bidi_copy(&mut local, &mut remote, close, counted).await.map(|_| ())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn limited_copy<A, B>(
+    a: A,
+    b: B,
+    close_on_b_eof: bool,
+    max_bytes: u64,
+    throttle: Option<Arc<Throttle>>,
+    activity: Option<Activity>,
+    counted: &[AtomicU64; 2],
+    packets: Option<Arc<Relayed>>,
+) -> Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let (a, b) = packets::pair(a, b, packets);
+    let (a, b) = idle::pair(a, b, activity);
+    let (mut a, mut b) = throttle::pair(a, b, throttle);
+    match max_bytes {
+        0 => bidi_copy(&mut a, &mut b, close_on_b_eof, counted).await,
+        max => {
+            let (mut a, mut b) = cap::pair(a, b, max);
+            bidi_copy(&mut a, &mut b, close_on_b_eof, counted).await
         }
-        result.map(|_| ())
+    }
+}
+
+// same as realm_io::bidi_copy,
+// but optionally stop once the remote peer reaches EOF
+async fn bidi_copy<A, B>(a: &mut A, b: &mut B, close_on_b_eof: bool, counted: &[AtomicU64; 2]) -> Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let a_to_b_buf = CopyBuffer::new(vec![0u8; buf_size()].into_boxed_slice());
+    let b_to_a_buf = CopyBuffer::new(vec![0u8; buf_size()].into_boxed_slice());
+    bidi_copy_buf_counted(a, b, a_to_b_buf, b_to_a_buf, close_on_b_eof, counted).await
+}
+
+// same as realm_io::bidi_zero_copy,
+// but optionally stop once the remote peer reaches EOF
+#[cfg(target_os = "linux")]
+async fn bidi_zero_copy(
+    a: &mut tokio::net::TcpStream,
+    b: &mut tokio::net::TcpStream,
+    close_on_b_eof: bool,
+    counted: &[AtomicU64; 2],
+) -> Result<(u64, u64)> {
+    use realm_io::Pipe;
+    let a_to_b_buf = CopyBuffer::new(Pipe::new()?);
+    let b_to_a_buf = CopyBuffer::new(Pipe::new()?);
+    bidi_copy_buf_counted(a, b, a_to_b_buf, b_to_a_buf, close_on_b_eof, counted).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // client <-> [local | relay | remote] <-> server
+    async fn relay_pair(
+        upstream_eof: UpstreamEof,
+        max_bytes: u64,
+    ) -> (TcpStream, TcpStream, Arc<Relayed>, tokio::task::JoinHandle<Result<()>>) {
+        let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = lis.local_addr().unwrap();
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (local, _) = lis.accept().await.unwrap();
+        let remote = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = lis.accept().await.unwrap();
+
+        let relayed = Arc::new(Relayed::default());
+        let relay = {
+            let relayed = relayed.clone();
+            tokio::spawn(async move {
+                run_relay(Stream::Tcp(local), Stream::Tcp(remote), &relayed, None, upstream_eof, max_bytes, None, None).await
+            })
+        };
+        (client, server, relayed, relay)
+    }
+
+    #[tokio::test]
+    async fn upstream_eof_drain() {
+        let (mut client, mut server, _, relay) = relay_pair(UpstreamEof::Drain, 0).await;
+
+        server.shutdown().await.unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+
+        // the client direction is still open
+        client.write_all(b"pending").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut data = Vec::new();
+        server.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"pending");
+
+        relay.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn upstream_eof_close() {
+        let (mut client, mut server, _, relay) = relay_pair(UpstreamEof::Close, 0).await;
+
+        server.shutdown().await.unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+
+        // finished without waiting for the client
+        relay.await.unwrap().unwrap();
+        let mut data = Vec::new();
+        server.read_to_end(&mut data).await.unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn max_bytes() {
+        let (mut client, mut server, relayed, relay) = relay_pair(UpstreamEof::Drain, 10).await;
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        server.read_exact(&mut buf).await.unwrap();
+
+        // cut in the middle, the server is idle
+        client.write_all(b"world, more").await.unwrap();
+        let mut data = Vec::new();
+        server.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"world");
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+
+        relay.await.unwrap().unwrap();
+        assert_eq!(relayed.bytes(), (10, 0));
+    }
+
+    #[tokio::test]
+    async fn count_while_relaying() {
+        let (mut client, mut server, relayed, relay) = relay_pair(UpstreamEof::Drain, 0).await;
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        server.read_exact(&mut buf).await.unwrap();
+        server.write_all(b"hi").await.unwrap();
+        client.read_exact(&mut buf[..2]).await.unwrap();
+        assert_eq!(relayed.bytes(), (5, 2));
+
+        // kept once aborted
+        relay.abort();
+        let _ = relay.await;
+        assert_eq!(relayed.bytes(), (5, 2));
     }
 }
@@ -9,22 +9,28 @@ use proxy_protocol::ProxyHeader;
 use proxy_protocol::{version1 as v1, version2 as v2};
 use proxy_protocol::{encode, parse};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 
-use crate::endpoint::ProxyOpts;
+use crate::endpoint::{ProxyOpts, ProxyTlv};
 use crate::time::timeoutfut;
 
 // TODO: replace the "proxy-protocol" crate, and then avoid heap allocation.
 
 // client -> relay -> server
-pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyOpts) -> Result<()> {
+// return tlvs received from the client
+pub async fn handle_proxy<W>(src: &mut TcpStream, dst: &mut W, opts: &ProxyOpts) -> Result<Vec<ProxyTlv>>
+where
+    W: AsyncWrite + Unpin,
+{
     let ProxyOpts {
         send_proxy,
         accept_proxy,
         send_proxy_version,
         accept_proxy_timeout,
-    } = opts;
+        ref send_proxy_tlvs,
+        ..
+    } = *opts;
 
     let mut client_addr = MaybeUninit::<SocketAddr>::uninit();
     let mut server_addr = MaybeUninit::<SocketAddr>::uninit();
@@ -35,6 +41,9 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
     // with src and dst got from header
     let mut fwd_hdr = false;
 
+    // tlvs of a v2 header
+    let mut tlvs = Vec::new();
+
     // parse PROXY header from client and write log
     // may not get src and dst addr
     if accept_proxy {
@@ -54,10 +63,19 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
         let mut slice = buf.as_ref();
 
         // slice is advanced
-        let header = parse(&mut slice).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let header = parse(&mut slice).map_err(Error::other)?;
         let parsed_n = peek_n - slice.remaining();
         debug!("[tcp]proxy-protocol parsed, {} bytes", parsed_n);
 
+        // the parser skips tlvs, read them from the raw header
+        if let ProxyHeader::Version2 { .. } = header {
+            tlvs = parse_tlvs(&buf[..parsed_n]);
+            if !tlvs.is_empty() {
+                let x: Vec<_> = tlvs.iter().map(|x| x.to_string()).collect();
+                info!("[tcp]accept proxy-protocol-v2 tlvs: [{}]", x.join(", "));
+            }
+        }
+
         // handle parsed header, and print log
         if let Some((src, dst)) = handle_header(header) {
             client_addr.write(src);
@@ -71,7 +89,7 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
 
         // do not send header to server
         if !send_proxy {
-            return Ok(());
+            return Ok(tlvs);
         }
     }
 
@@ -93,12 +111,71 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
     let server_addr = unsafe { server_addr.assume_init() };
 
     // write header
-    let header = encode(make_header(client_addr, server_addr, send_proxy_version))
-        .map_err(|e| Error::new(ErrorKind::Other, e))?;
+    let header = encode_header(make_header(client_addr, server_addr, send_proxy_version), send_proxy_tlvs)?;
     debug!("[tcp]send initial {} bytes: {:#x}", header.len(), &header);
     dst.write_all(&header).await?;
 
-    Ok(())
+    Ok(tlvs)
+}
+
+// the proxy-protocol crate does not support tlvs,
+// append them to an encoded v2 header and fix the length
+fn encode_header(header: ProxyHeader, tlvs: &[ProxyTlv]) -> Result<BytesMut> {
+    let is_v2 = matches!(header, ProxyHeader::Version2 { .. });
+    let mut buf = encode(header).map_err(Error::other)?;
+    if !is_v2 || tlvs.is_empty() {
+        return Ok(buf);
+    }
+
+    for ProxyTlv { kind, value } in tlvs {
+        buf.extend_from_slice(&[*kind]);
+        buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        buf.extend_from_slice(value);
+    }
+
+    // length of addresses and tlvs, following the 16 bytes fixed header
+    let len = u16::try_from(buf.len() - 16).map_err(|_| Error::new(ErrorKind::InvalidInput, "proxy tlvs are too long"))?;
+    buf[14..16].copy_from_slice(&len.to_be_bytes());
+    Ok(buf)
+}
+
+/// Read tlvs from a raw v2 header, a truncated tlv is ignored.
+pub fn parse_tlvs(header: &[u8]) -> Vec<ProxyTlv> {
+    if header.len() < 16 {
+        return Vec::new();
+    }
+
+    // the address block is followed by tlvs
+    let addr_len = match header[13] >> 4 {
+        1 => 12,
+        2 => 36,
+        3 => 216,
+        _ => 0,
+    };
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let Some(mut block) = header.get(16 + addr_len..16 + len) else {
+        return Vec::new();
+    };
+
+    let mut tlvs = Vec::new();
+    while block.len() >= 3 {
+        let kind = block[0];
+        let n = u16::from_be_bytes([block[1], block[2]]) as usize;
+        let Some(value) = block.get(3..3 + n) else {
+            break;
+        };
+        tlvs.push(ProxyTlv {
+            kind,
+            value: value.to_vec(),
+        });
+        block = &block[3 + n..];
+    }
+    tlvs
+}
+
+/// Name of a received tlv as a connection tag.
+pub fn tlv_tag(tlv: &ProxyTlv) -> (String, String) {
+    (format!("proxy-tlv-{:#04x}", tlv.kind), tlv.value_string())
 }
 
 macro_rules! unpack {
@@ -125,7 +202,7 @@ fn make_header(client_addr: SocketAddr, server_addr: SocketAddr, send_proxy_vers
 }
 
 fn make_header_v1(client_addr: SocketAddr, server_addr: SocketAddr) -> ProxyHeader {
-    debug!("[tcp]send proxy-protocol-v1: {} => {}", &client_addr, &server_addr);
+    debug!("[tcp]send proxy-protocol-v1: {} => {}", client_addr, server_addr);
 
     if client_addr.is_ipv4() {
         ProxyHeader::Version1 {
@@ -145,7 +222,7 @@ fn make_header_v1(client_addr: SocketAddr, server_addr: SocketAddr) -> ProxyHead
 }
 
 fn make_header_v2(client_addr: SocketAddr, server_addr: SocketAddr) -> ProxyHeader {
-    debug!("[tcp]send proxy-protocol-v2: {} => {}", &client_addr, &server_addr);
+    debug!("[tcp]send proxy-protocol-v2: {} => {}", client_addr, server_addr);
 
     ProxyHeader::Version2 {
         command: v2::ProxyCommand::Proxy,
@@ -188,11 +265,11 @@ fn handle_header_v1(addr: v1::ProxyAddresses) -> Option<(SocketAddr, SocketAddr)
             None
         }
         Ipv4 { source, destination } => {
-            info!("[tcp]accept proxy-protocol-v1: {} => {}", &source, &destination);
+            info!("[tcp]accept proxy-protocol-v1: {} => {}", source, destination);
             Some((SocketAddr::V4(source), SocketAddr::V4(destination)))
         }
         Ipv6 { source, destination } => {
-            info!("[tcp]accept proxy-protocol-v1: {} => {}", &source, &destination);
+            info!("[tcp]accept proxy-protocol-v1: {} => {}", source, destination);
             Some((SocketAddr::V6(source), SocketAddr::V6(destination)))
         }
     }
@@ -232,11 +309,11 @@ fn handle_header_v2(
 
     match addr {
         Address::Ipv4 { source, destination } => {
-            info!("[tcp]accept proxy-protocol-v2: {} => {}", &source, &destination);
+            info!("[tcp]accept proxy-protocol-v2: {} => {}", source, destination);
             Some((SocketAddr::V4(source), SocketAddr::V4(destination)))
         }
         Address::Ipv6 { source, destination } => {
-            info!("[tcp]accept proxy-protocol-v2: {} => {}", &source, &destination);
+            info!("[tcp]accept proxy-protocol-v2: {} => {}", source, destination);
             Some((SocketAddr::V6(source), SocketAddr::V6(destination)))
         }
         Address::Unspec => {
@@ -249,3 +326,103 @@ fn handle_header_v2(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn tlv(kind: u8, value: &[u8]) -> ProxyTlv {
+        ProxyTlv {
+            kind,
+            value: value.to_vec(),
+        }
+    }
+
+    #[test]
+    fn tlv_round_trip() {
+        let tlvs = [tlv(0xe0, b"tenant-a"), tlv(0xe1, &[]), tlv(0x05, &[0, 1, 2])];
+        for (client, server) in [
+            ("1.2.3.4:5000", "5.6.7.8:443"),
+            ("[2001:db8::1]:5000", "[2001:db8::2]:443"),
+        ] {
+            let (client, server) = (client.parse().unwrap(), server.parse().unwrap());
+            let buf = encode_header(make_header(client, server, 2), &tlvs).unwrap();
+            assert_eq!(parse_tlvs(&buf), tlvs);
+
+            // addresses are still parsed
+            let mut slice = buf.as_ref();
+            let header = parse(&mut slice).unwrap();
+            assert!(slice.is_empty());
+            assert_eq!(handle_header(header), Some((client, server)));
+        }
+
+        // no tlvs, or v1
+        let (client, server) = ("1.2.3.4:5000".parse().unwrap(), "5.6.7.8:443".parse().unwrap());
+        let buf = encode_header(make_header(client, server, 2), &[]).unwrap();
+        assert!(parse_tlvs(&buf).is_empty());
+        let buf = encode_header(make_header(client, server, 1), &tlvs).unwrap();
+        assert!(buf.starts_with(b"PROXY TCP4"));
+
+        // truncated
+        let buf = encode_header(make_header(client, server, 2), &tlvs).unwrap();
+        assert!(parse_tlvs(&buf[..buf.len() - 1]).is_empty());
+    }
+
+    #[test]
+    fn tlv_from_str() {
+        assert_eq!("0xe0=tenant-a".parse(), Ok(tlv(0xe0, b"tenant-a")));
+        assert_eq!("225=hex:00FF".parse(), Ok(tlv(0xe1, &[0, 0xff])));
+        assert_eq!("0xe2=".parse(), Ok(tlv(0xe2, b"")));
+        assert!("0xe0".parse::<ProxyTlv>().is_err());
+        assert!("256=x".parse::<ProxyTlv>().is_err());
+        assert!("0xe0=hex:0g".parse::<ProxyTlv>().is_err());
+
+        for x in ["0xe0=tenant-a", "0xe1=hex:00ff", "0xe2=hex:6865783a"] {
+            assert_eq!(x.parse::<ProxyTlv>().unwrap().to_string(), x);
+        }
+        assert_eq!(tlv_tag(&tlv(0xe0, b"a b")), ("proxy-tlv-0xe0".to_string(), "a b".to_string()));
+    }
+
+    async fn pair(lis: &TcpListener) -> (TcpStream, TcpStream) {
+        let addr = lis.local_addr().unwrap();
+        let (a, b) = tokio::join!(TcpStream::connect(addr), lis.accept());
+        (a.unwrap(), b.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn relay_tlvs() {
+        let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (mut client, mut src) = pair(&lis).await;
+        let (mut dst, mut server) = pair(&lis).await;
+
+        // client -> relay, with a tlv
+        let (caddr, saddr) = ("1.2.3.4:5000".parse().unwrap(), "5.6.7.8:443".parse().unwrap());
+        let header = encode_header(make_header(caddr, saddr, 2), &[tlv(0xe0, b"from-client")]).unwrap();
+        client.write_all(&header).await.unwrap();
+        client.write_all(b"payload").await.unwrap();
+
+        // relay -> server, with another tlv
+        let opts = ProxyOpts {
+            send_proxy: true,
+            accept_proxy: true,
+            send_proxy_version: 2,
+            accept_proxy_timeout: 5,
+            send_proxy_tlvs: vec![tlv(0xe1, b"from-relay")],
+            accept_proxy_tlvs: true,
+        };
+        let tlvs = handle_proxy(&mut src, &mut dst, &opts).await.unwrap();
+        assert_eq!(tlvs, [tlv(0xe0, b"from-client")]);
+
+        // the header is consumed
+        let mut buf = vec![0; 7];
+        src.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"payload");
+
+        let mut buf = vec![0; 256];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(parse_tlvs(&buf[..n]), [tlv(0xe1, b"from-relay")]);
+        let header = parse(&mut &buf[..n]).unwrap();
+        assert_eq!(handle_header(header), Some((caddr, saddr)));
+    }
+}
@@ -3,17 +3,44 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use realm_syscall::new_tcp_socket;
+use realm_syscall::socket2::Socket;
 use tokio::net::{TcpSocket, TcpStream, TcpListener};
 
-use crate::dns::resolve_addr;
-use crate::time::timeoutfut;
+#[cfg(unix)]
+use std::path::Path;
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::dns::{resolve_addr, next_turn};
+use crate::time::{timeoutfut, connect_timeout_or_default};
 use crate::endpoint::{RemoteAddr, BindOpts, ConnectOpts};
 
+use super::stream::Stream;
+use super::socks5;
+
+// used unless listen_backlog is set
+const LISTEN_BACKLOG: u32 = 1024;
+
 pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<TcpListener> {
+    #[cfg(unix)]
+    let workers = bind_opts.workers();
     let BindOpts {
         ipv6_only,
+        reuse_address,
+        tcp_linger,
+        send_buffer_size,
+        recv_buffer_size,
+        listen_backlog,
         bind_interface,
+        dscp,
+        #[cfg(feature = "mptcp")]
+        mptcp,
+        ..
     } = bind_opts;
+    #[cfg(feature = "mptcp")]
+    let socket = new_mptcp_or_tcp_socket(laddr, mptcp)?;
+    #[cfg(not(feature = "mptcp"))]
     let socket = new_tcp_socket(laddr)?;
 
     // ipv6_only
@@ -21,72 +48,235 @@ pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<TcpListener> {
         socket.set_only_v6(ipv6_only)?;
     }
 
-    // bind interface
+    // bind interface, which requires CAP_NET_RAW before linux 5.7
     #[cfg(target_os = "linux")]
     if let Some(iface) = bind_interface {
-        realm_syscall::bind_to_device(&socket, &iface)?;
+        realm_syscall::bind_to_device(&socket, &iface).map_err(|e| {
+            log::error!("[tcp]failed to bind {} to device {}: {}", laddr, iface, e);
+            Error::new(e.kind(), format!("bind to device {}: {}", iface, e))
+        })?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    if let Some(iface) = bind_interface {
+        log::warn!("[tcp]listen interface {} of {} is ignored, only supported on linux", iface, laddr);
     }
 
     // ignore error
-    let _ = socket.set_reuse_address(true);
+    let _ = socket.set_reuse_address(reuse_address);
+
+    // share the address with other workers
+    #[cfg(unix)]
+    if workers > 1 {
+        socket.set_reuse_port(true)?;
+    }
+
+    set_dscp(&socket, laddr, dscp);
+
+    // accepted sockets inherit linger from the listener
+    if let Some(secs) = tcp_linger {
+        socket.set_linger(Some(Duration::from_secs(secs as u64)))?;
+    }
+
+    // accepted sockets inherit buffer sizes from the listener,
+    // set before listen so that the window scale is negotiated
+    if let Some(size) = send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
 
     socket.bind(&(*laddr).into())?;
-    socket.listen(1024)?;
+    // the kernel may cap it, e.g. by net.core.somaxconn on linux
+    let backlog = listen_backlog.unwrap_or(LISTEN_BACKLOG);
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
 
     TcpListener::from_std(socket.into())
 }
 
-pub async fn connect(raddr: &RemoteAddr, conn_opts: &ConnectOpts) -> Result<TcpStream> {
+/// Listen on a unix socket, a stale socket file left by
+/// a previous run is removed first.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    if std::fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+pub async fn connect(raddr: &RemoteAddr, conn_opts: &ConnectOpts) -> Result<Stream> {
+    #[cfg(unix)]
+    if let RemoteAddr::UnixSocket(path) = raddr {
+        let connect_timeout = connect_timeout_or_default(conn_opts.connect_timeout);
+        return match timeoutfut(UnixStream::connect(path), connect_timeout).await {
+            Ok(Ok(stream)) => {
+                log::debug!("[tcp]connect to {}", raddr);
+                Ok(Stream::Unix(stream))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, format!("connect to {} timeout", raddr))),
+        };
+    }
+
+    let keepalive = keepalive::build(conn_opts);
+
+    // the remote is resolved by the proxy
+    if let Some(proxy) = &conn_opts.socks5_proxy {
+        let paddr = RemoteAddr::SocketAddr(proxy.addr);
+        let mut stream = connect_addr(&paddr, proxy.addr, conn_opts, keepalive.as_ref()).await?;
+        let connect_timeout = connect_timeout_or_default(conn_opts.connect_timeout);
+        return match timeoutfut(socks5::handshake(&mut stream, raddr, proxy.auth.as_ref()), connect_timeout).await {
+            Ok(Ok(())) => {
+                log::debug!("[tcp]connect to {} through socks5 proxy {}", raddr, proxy);
+                Ok(Stream::Tcp(stream))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, format!("socks5 connect to {} timeout", raddr))),
+        };
+    }
+
+    let turn = if conn_opts.dns_round_robin { next_turn(raddr) } else { 0 };
+    let addrs: Vec<_> = resolve_addr(raddr).await?.iter_from(turn).collect();
+
+    // race ipv6 and ipv4 addresses
+    #[cfg(feature = "happy-eyeballs")]
+    if conn_opts.happy_eyeballs_delay != 0 && addrs.len() > 1 {
+        let delay = Duration::from_millis(conn_opts.happy_eyeballs_delay as u64);
+        let connect = |addr| connect_addr(raddr, addr, conn_opts, keepalive.as_ref());
+        return super::eyeballs::race(addrs, delay, connect).await.map(Stream::Tcp);
+    }
+
+    // or one by one
+    let mut last_err = None;
+    for addr in addrs {
+        match connect_addr(raddr, addr, conn_opts, keepalive.as_ref()).await {
+            Ok(stream) => return Ok(Stream::Tcp(stream)),
+            Err(e) => last_err = Some(e),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| Error::new(ErrorKind::InvalidInput, "could not connect to any address")))
+}
+
+async fn connect_addr(
+    raddr: &RemoteAddr,
+    addr: SocketAddr,
+    conn_opts: &ConnectOpts,
+    keepalive: Option<&keepalive::TcpKeepalive>,
+) -> Result<TcpStream> {
     let ConnectOpts {
         connect_timeout,
+        tcp_linger,
+        tcp_nodelay,
+        send_buffer_size,
+        recv_buffer_size,
         bind_address,
+        dscp,
 
         #[cfg(target_os = "linux")]
         bind_interface,
+
+        #[cfg(feature = "mptcp")]
+        mptcp,
         ..
     } = conn_opts;
 
-    let mut last_err = None;
-    let keepalive = keepalive::build(conn_opts);
-
-    for addr in resolve_addr(raddr).await?.iter() {
-        log::debug!("[tcp]{} resolved as {}", raddr, &addr);
+    log::debug!("[tcp]{} resolved as {}", raddr, addr);
 
-        let socket = new_tcp_socket(&addr)?;
+    #[cfg(feature = "mptcp")]
+    let socket = new_mptcp_or_tcp_socket(&addr, *mptcp)?;
+    #[cfg(not(feature = "mptcp"))]
+    let socket = new_tcp_socket(&addr)?;
 
-        // ignore error
+    // ignore error
+    if *tcp_nodelay {
         let _ = socket.set_nodelay(true);
-        let _ = socket.set_reuse_address(true);
+    }
+    let _ = socket.set_reuse_address(true);
 
-        if let Some(addr) = *bind_address {
-            socket.bind(&addr.into())?;
-        }
+    if let Some(addr) = *bind_address {
+        socket.bind(&addr.into())?;
+    }
 
-        #[cfg(target_os = "linux")]
-        if let Some(iface) = bind_interface {
-            realm_syscall::bind_to_device(&socket, iface)?;
-        }
+    #[cfg(target_os = "linux")]
+    if let Some(iface) = bind_interface {
+        realm_syscall::bind_to_device(&socket, iface)?;
+    }
 
-        if let Some(kpa) = &keepalive {
-            socket.set_tcp_keepalive(kpa)?;
-        }
+    if let Some(kpa) = keepalive {
+        socket.set_tcp_keepalive(kpa)?;
+    }
 
-        let socket = TcpSocket::from_std_stream(socket.into());
+    if let Some(secs) = *tcp_linger {
+        socket.set_linger(Some(Duration::from_secs(secs as u64)))?;
+    }
 
-        match timeoutfut(socket.connect(addr), *connect_timeout).await {
-            Ok(Ok(stream)) => {
-                log::debug!("[tcp]connect to {} as {}", raddr, &addr,);
-                return Ok(stream);
-            }
-            Ok(Err(e)) => {
-                log::warn!("[tcp]connect to {} as {}: {}, try next ip", raddr, &addr, &e);
-                last_err = Some(e);
-            }
-            Err(_) => log::warn!("[tcp]connect to {} as {} timeout, try next ip", raddr, &addr),
+    set_dscp(&socket, &addr, *dscp);
+
+    // before connect so that the window scale is negotiated
+    if let Some(size) = *send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = *recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+
+    let socket = TcpSocket::from_std_stream(socket.into());
+    let connect_timeout = connect_timeout_or_default(*connect_timeout);
+
+    match timeoutfut(socket.connect(addr), connect_timeout).await {
+        Ok(Ok(stream)) => {
+            log::debug!("[tcp]connect to {} as {}", raddr, addr);
+            Ok(stream)
+        }
+        Ok(Err(e)) => {
+            log::warn!("[tcp]connect to {} as {}: {}, try next ip", raddr, addr, e);
+            Err(e)
+        }
+        Err(_) => {
+            log::warn!("[tcp]connect to {} as {} timeout, try next ip", raddr, addr);
+            Err(Error::new(ErrorKind::TimedOut, "connect timeout"))
         }
     }
+}
 
-    Err(last_err.unwrap_or_else(|| Error::new(ErrorKind::InvalidInput, "could not connect to any address")))
+/// Create a multipath tcp socket if enabled, or fall back to tcp
+/// if it is rejected by the kernel, which is warned only once.
+#[cfg(feature = "mptcp")]
+fn new_mptcp_or_tcp_socket(addr: &SocketAddr, mptcp: bool) -> Result<Socket> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    if !mptcp {
+        return new_tcp_socket(addr);
+    }
+
+    #[cfg(target_os = "linux")]
+    let e = match realm_syscall::new_mptcp_socket(addr) {
+        Ok(socket) => return Ok(socket),
+        Err(e) => e,
+    };
+    #[cfg(not(target_os = "linux"))]
+    let e = Error::new(ErrorKind::Unsupported, "only supported on linux");
+
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        log::warn!("[tcp]failed to create mptcp socket for {}: {}, fall back to tcp", addr, e);
+    } else {
+        log::debug!("[tcp]failed to create mptcp socket for {}: {}, fall back to tcp", addr, e);
+    }
+    new_tcp_socket(addr)
+}
+
+/// Set the DSCP if not 0, an error is ignored,
+/// e.g. the option is not supported by the platform.
+pub(crate) fn set_dscp(socket: &Socket, addr: &SocketAddr, dscp: u8) {
+    if dscp == 0 {
+        return;
+    }
+    if let Err(e) = realm_syscall::set_dscp(socket, addr, dscp) {
+        log::debug!("[sys]failed to set dscp of {}: {}", addr, e);
+    }
 }
 
 pub(super) mod keepalive {
@@ -116,3 +306,132 @@ pub(super) mod keepalive {
         Some(kpa)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keepalive::SockRef;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bind_unknown_device() {
+        let laddr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let bind_opts = BindOpts {
+            bind_interface: Some("no-such-if".to_string()),
+            ..Default::default()
+        };
+        let e = bind(&laddr, bind_opts).unwrap_err();
+        assert!(e.to_string().starts_with("bind to device no-such-if: "), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn nodelay_and_buffers() {
+        let laddr: SocketAddr = "127.0.0.1:10460".parse().unwrap();
+        let bind_opts = BindOpts {
+            send_buffer_size: Some(64 * 1024),
+            recv_buffer_size: Some(128 * 1024),
+            listen_backlog: Some(16),
+            ..Default::default()
+        };
+        let lis = bind(&laddr, bind_opts).unwrap();
+
+        let conn_opts = ConnectOpts {
+            tcp_nodelay: true,
+            send_buffer_size: Some(32 * 1024),
+            recv_buffer_size: Some(256 * 1024),
+            ..Default::default()
+        };
+        let remote = connect(&RemoteAddr::SocketAddr(laddr), &conn_opts).await.unwrap();
+        let Stream::Tcp(remote) = remote else {
+            unreachable!()
+        };
+        let (local, _) = lis.accept().await.unwrap();
+
+        // the kernel may round the sizes up
+        let remote = SockRef::from(&remote);
+        assert!(remote.nodelay().unwrap());
+        assert!(remote.send_buffer_size().unwrap() >= 32 * 1024);
+        assert!(remote.recv_buffer_size().unwrap() >= 256 * 1024);
+        let local = SockRef::from(&local);
+        assert!(local.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(local.recv_buffer_size().unwrap() >= 128 * 1024);
+
+        // set by default
+        let remote = connect(&RemoteAddr::SocketAddr(laddr), &ConnectOpts::default()).await.unwrap();
+        let Stream::Tcp(remote) = remote else {
+            unreachable!()
+        };
+        assert!(SockRef::from(&remote).nodelay().unwrap());
+
+        // left alone if disabled
+        let conn_opts = ConnectOpts {
+            tcp_nodelay: false,
+            ..Default::default()
+        };
+        let remote = connect(&RemoteAddr::SocketAddr(laddr), &conn_opts).await.unwrap();
+        let Stream::Tcp(remote) = remote else {
+            unreachable!()
+        };
+        assert!(!SockRef::from(&remote).nodelay().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn mark_dscp() {
+        let laddr: SocketAddr = "127.0.0.1:10520".parse().unwrap();
+        let bind_opts = BindOpts {
+            dscp: 46,
+            ..Default::default()
+        };
+        let lis = bind(&laddr, bind_opts).unwrap();
+        assert_eq!(SockRef::from(&lis).tos().unwrap(), 46 << 2);
+
+        let conn_opts = ConnectOpts {
+            dscp: 10,
+            ..Default::default()
+        };
+        let remote = connect(&RemoteAddr::SocketAddr(laddr), &conn_opts).await.unwrap();
+        let Stream::Tcp(remote) = remote else {
+            unreachable!()
+        };
+        assert_eq!(SockRef::from(&remote).tos().unwrap(), 10 << 2);
+
+        // left alone if not set
+        let remote = connect(&RemoteAddr::SocketAddr(laddr), &ConnectOpts::default()).await.unwrap();
+        let Stream::Tcp(remote) = remote else {
+            unreachable!()
+        };
+        assert_eq!(SockRef::from(&remote).tos().unwrap(), 0);
+    }
+
+    #[cfg(all(feature = "mptcp", target_os = "linux"))]
+    #[tokio::test]
+    async fn mptcp_or_tcp() {
+        use realm_syscall::socket2::Protocol;
+
+        let laddr: SocketAddr = "127.0.0.1:10521".parse().unwrap();
+        let bind_opts = BindOpts {
+            mptcp: true,
+            ..Default::default()
+        };
+        let lis = bind(&laddr, bind_opts).unwrap();
+
+        let conn_opts = ConnectOpts {
+            mptcp: true,
+            ..Default::default()
+        };
+        let remote = connect(&RemoteAddr::SocketAddr(laddr), &conn_opts).await.unwrap();
+        let Stream::Tcp(remote) = remote else {
+            unreachable!()
+        };
+        lis.accept().await.unwrap();
+
+        // plain tcp if the kernel does not support it
+        let expected = match realm_syscall::new_mptcp_socket(&laddr) {
+            Ok(_) => Protocol::MPTCP,
+            Err(_) => Protocol::TCP,
+        };
+        assert_eq!(SockRef::from(&lis).protocol().unwrap(), Some(expected));
+        assert_eq!(SockRef::from(&remote).protocol().unwrap(), Some(expected));
+    }
+}
@@ -0,0 +1,201 @@
+//! Socks5 client, to connect to the remote through a proxy.
+//!
+//! Only the CONNECT command is supported, see rfc1928,
+//! with username/password authentication of rfc1929.
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::endpoint::RemoteAddr;
+
+const VERSION: u8 = 0x05;
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE: u8 = 0xff;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("socks5: {}", msg))
+}
+
+/// Ask the proxy to connect to the remote, which is ready
+/// to relay once done. Domain names are resolved by the proxy.
+pub async fn handshake<S>(stream: &mut S, raddr: &RemoteAddr, auth: Option<&(String, String)>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // offer no auth as well, in case the proxy does not need it
+    let greeting: &[u8] = match auth {
+        Some(_) => &[VERSION, 2, NO_AUTH, USER_PASS],
+        None => &[VERSION, 1, NO_AUTH],
+    };
+    stream.write_all(greeting).await?;
+
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).await?;
+    if buf[0] != VERSION {
+        return Err(invalid("unexpected version"));
+    }
+    match (buf[1], auth) {
+        (NO_AUTH, _) => {}
+        (USER_PASS, Some((username, password))) => {
+            let mut request = vec![0x01, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            stream.read_exact(&mut buf).await?;
+            if buf[1] != 0 {
+                return Err(Error::new(ErrorKind::PermissionDenied, "socks5: authentication failed"));
+            }
+        }
+        (NO_ACCEPTABLE, _) => {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "socks5: no acceptable auth method",
+            ));
+        }
+        _ => return Err(invalid("unexpected auth method")),
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match raddr {
+        RemoteAddr::SocketAddr(SocketAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+            request.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        RemoteAddr::SocketAddr(SocketAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+            request.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        RemoteAddr::DomainName(host, port) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "socks5: domain name is too long"))?;
+            request.extend_from_slice(&[ATYP_DOMAIN, len]);
+            request.extend_from_slice(host.as_bytes());
+            request.extend_from_slice(&port.to_be_bytes());
+        }
+        #[cfg(unix)]
+        RemoteAddr::UnixSocket(_) => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "socks5: unix socket is not supported",
+            ));
+        }
+    }
+    stream.write_all(&request).await?;
+
+    // version, reply, reserved, and the bound address
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid("unexpected version"));
+    }
+    if reply[1] != 0 {
+        return Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("socks5: {}", reply_msg(reply[1])),
+        ));
+    }
+    let len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(invalid("unexpected address type")),
+    };
+    // not used
+    let mut bound = vec![0; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+fn reply_msg(rep: u8) -> &'static str {
+    match rep {
+        0x01 => "general server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "ttl expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn connect_domain_with_auth() {
+        let (mut client, mut server) = duplex(1024);
+        let proxy = tokio::spawn(async move {
+            let mut buf = [0; 4];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [VERSION, 2, NO_AUTH, USER_PASS]);
+            server.write_all(&[VERSION, USER_PASS]).await.unwrap();
+
+            let mut buf = [0; 9];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"\x01\x01u\x05passw");
+            server.write_all(&[0x01, 0]).await.unwrap();
+
+            let mut buf = [0; 14];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"\x05\x01\x00\x03\x07a.b.com\x01\xbb");
+            server
+                .write_all(&[VERSION, 0, 0, ATYP_IPV4, 127, 0, 0, 1, 0x04, 0x38])
+                .await
+                .unwrap();
+            server.write_all(b"hi").await.unwrap();
+        });
+
+        let raddr = RemoteAddr::DomainName("a.b.com".to_string(), 443);
+        let auth = ("u".to_string(), "passw".to_string());
+        handshake(&mut client, &raddr, Some(&auth)).await.unwrap();
+        // the bound address is consumed
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+        proxy.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connect_rejected() {
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut buf = [0; 3];
+            server.read_exact(&mut buf).await.unwrap();
+            server.write_all(&[VERSION, NO_AUTH]).await.unwrap();
+            let mut buf = [0; 10];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf[3..], &[ATYP_IPV4, 10, 0, 0, 1, 0, 80]);
+            server.write_all(&[VERSION, 0x05, 0, ATYP_IPV4]).await.unwrap();
+        });
+
+        let raddr = RemoteAddr::SocketAddr("10.0.0.1:80".parse().unwrap());
+        let e = handshake(&mut client, &raddr, None).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ConnectionRefused);
+        assert_eq!(e.to_string(), "socks5: connection refused");
+
+        // the proxy requires auth
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut buf = [0; 3];
+            server.read_exact(&mut buf).await.unwrap();
+            server.write_all(&[VERSION, NO_ACCEPTABLE]).await.unwrap();
+        });
+        let e = handshake(&mut client, &raddr, None).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,93 @@
+//! Streams and listeners of a tcp relay.
+
+use std::io::Result;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// A tcp stream, or a unix stream which is relayed in the same way.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Address of the peer, none for a unix stream.
+    pub fn peer_addr(&self) -> Result<Option<SocketAddr>> {
+        match self {
+            Stream::Tcp(x) => x.peer_addr().map(Some),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(None),
+        }
+    }
+
+    #[cfg(any(feature = "hook", feature = "proxy"))]
+    pub fn as_tcp(&mut self) -> Option<&mut TcpStream> {
+        match self {
+            Stream::Tcp(x) => Some(x),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+/// A tcp listener, or a unix listener.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Accept a stream, and get the address of its peer if any.
+    pub async fn accept(&self) -> Result<(Stream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(x) => x.accept().await.map(|(s, addr)| (Stream::Tcp(s), Some(addr))),
+            #[cfg(unix)]
+            Listener::Unix(x) => x.accept().await.map(|(s, _)| (Stream::Unix(s), None)),
+        }
+    }
+}
+
+macro_rules! delegate {
+    ($self: ident, $x: ident => $e: expr) => {
+        match $self.get_mut() {
+            Stream::Tcp($x) => {
+                let $x = Pin::new($x);
+                $e
+            }
+            #[cfg(unix)]
+            Stream::Unix($x) => {
+                let $x = Pin::new($x);
+                $e
+            }
+        }
+    };
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        delegate!(self, x => x.poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        delegate!(self, x => x.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        delegate!(self, x => x.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        delegate!(self, x => x.poll_shutdown(cx))
+    }
+}
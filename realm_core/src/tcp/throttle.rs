@@ -0,0 +1,207 @@
+//! Pace reads with token buckets.
+
+use std::io::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+// never wait for less than this many bytes, to avoid tiny reads
+const MIN_CHUNK: f64 = 4096.0;
+
+/// A token bucket in bytes.
+#[derive(Debug)]
+pub struct Bucket {
+    // bytes per second
+    rate: f64,
+    // burst of 100ms
+    capacity: f64,
+    // tokens could be negative, if overdrawn by concurrent readers
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    fn new(bytes_per_sec: f64) -> Self {
+        let capacity = (bytes_per_sec / 10.0).max(MIN_CHUNK);
+        Self {
+            rate: bytes_per_sec,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Bytes which could be read now, or how long to wait.
+    fn available(&self, now: Instant) -> std::result::Result<usize, Duration> {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = &mut *state;
+        *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * self.rate).min(self.capacity);
+        *last = now;
+
+        let chunk = self.capacity.min(MIN_CHUNK);
+        if *tokens >= chunk {
+            Ok(*tokens as usize)
+        } else {
+            Err(Duration::from_secs_f64((chunk - *tokens) / self.rate))
+        }
+    }
+
+    fn consume(&self, n: usize) {
+        self.state.lock().unwrap().0 -= n as f64;
+    }
+}
+
+/// Upload and download buckets shared by all connections of a rule.
+#[derive(Debug)]
+pub struct Throttle {
+    up: Bucket,
+    down: Bucket,
+}
+
+impl Throttle {
+    /// Rate is in bits per second, 0 means unlimited.
+    pub fn new(bps: u64) -> Option<Arc<Self>> {
+        let bytes_per_sec = match bps {
+            0 => return None,
+            x => x as f64 / 8.0,
+        };
+        Some(Arc::new(Self {
+            up: Bucket::new(bytes_per_sec),
+            down: Bucket::new(bytes_per_sec),
+        }))
+    }
+}
+
+/// A stream whose reads are paced by a bucket, pass through if there is none.
+pub struct Throttled<S> {
+    inner: S,
+    throttle: Option<Arc<Throttle>>,
+    upload: bool,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+/// Pace reads from `local` by upload, and reads from `remote` by download.
+pub fn pair<A, B>(local: A, remote: B, throttle: Option<Arc<Throttle>>) -> (Throttled<A>, Throttled<B>) {
+    let local = Throttled {
+        inner: local,
+        throttle: throttle.clone(),
+        upload: true,
+        delay: None,
+    };
+    let remote = Throttled {
+        inner: remote,
+        throttle,
+        upload: false,
+        delay: None,
+    };
+    (local, remote)
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let Some(throttle) = &this.throttle else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+        let bucket = if this.upload { &throttle.up } else { &throttle.down };
+
+        let available = loop {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+            match bucket.available(Instant::now()) {
+                Ok(n) => break n,
+                Err(wait) => this.delay = Some(Box::pin(sleep(wait))),
+            }
+        };
+
+        let n = if available >= buf.remaining() {
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            buf.filled().len() - filled
+        } else {
+            // read into the front of the unfilled part, without a copy
+            let mut limited = buf.take(available);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+            let n = limited.filled().len();
+            // SAFETY: the first n bytes of the unfilled part were just filled
+            unsafe { buf.assume_init(n) };
+            buf.advance(n);
+            n
+        };
+        bucket.consume(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn bucket_refill() {
+        // 40KB/s, 4KB capacity
+        let bucket = Bucket::new(40960.0);
+        let now = Instant::now();
+        assert_eq!(bucket.available(now), Ok(4096));
+
+        bucket.consume(4096);
+        assert_eq!(bucket.available(now), Err(Duration::from_millis(100)));
+        assert_eq!(bucket.available(now + Duration::from_millis(100)), Ok(4096));
+
+        // overdrawn
+        bucket.consume(8192);
+        assert_eq!(bucket.available(now + Duration::from_millis(100)), Err(Duration::from_millis(200)));
+    }
+
+    #[tokio::test]
+    async fn pace_reads() {
+        // 40KB/s in both directions
+        let throttle = Throttle::new(40960 * 8);
+        let (mut peer, local) = duplex(1 << 16);
+        let (_, remote) = duplex(64);
+        let (mut local, _remote) = pair(local, remote, throttle);
+
+        peer.write_all(&[0; 12288]).await.unwrap();
+        let start = Instant::now();
+        let mut buf = vec![0; 12288];
+        local.read_exact(&mut buf).await.unwrap();
+        // a full bucket, and two refills
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn read_less_than_buf() {
+        // 40KB/s, reads are cut to the 4KB bucket
+        let throttle = Throttle::new(40960 * 8);
+        let (mut peer, local) = duplex(1 << 16);
+        let (_, remote) = duplex(64);
+        let (mut local, _remote) = pair(local, remote, throttle);
+
+        let data: Vec<u8> = (0..8192).map(|x| x as u8).collect();
+        peer.write_all(&data).await.unwrap();
+        let mut buf = vec![0; 8192];
+        assert_eq!(local.read(&mut buf).await.unwrap(), 4096);
+        assert_eq!(&buf[..4096], &data[..4096]);
+    }
+}
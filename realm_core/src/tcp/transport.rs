@@ -1,23 +1,91 @@
 use std::io::Result;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
 use futures::try_join;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 use kaminari::{AsyncAccept, AsyncConnect, IOStream};
 use kaminari::mix::{MixAccept, MixConnect};
 
-use realm_io::{CopyBuffer, bidi_copy_buf, buf_size};
-use crate::monitor::ConnectionMetrics;
+use realm_io::{CopyBuffer, bidi_copy_buf_counted, buf_size};
+use crate::endpoint::{ByteCount, TlsPolicy, TlsVersion, UpstreamEof, tls_cipher_name, tls_group_name};
+use crate::monitor::{ConnectionMetrics, Relayed, lock_metrics};
+
+use super::plain;
+use super::throttle::Throttle;
+use super::idle::Activity;
+
+/// Max length of a trace id.
+const MAX_TRACE_ID_LEN: usize = 128;
+
+/// Max bytes to inspect for the trace header.
+const MAX_SNIFF_LEN: usize = 8192;
+
+/// Max bytes to inspect for the tls server hello,
+/// a record header plus a full record.
+const MAX_HELLO_LEN: usize = 5 + 16384;
 
+/// Trace id propagation of a connection.
+///
+/// An id received with a websocket request is reused,
+/// otherwise the local connection id is used.
+/// The id is injected into the outgoing websocket request.
+///
+/// Only works for plain websocket, since the
+/// handshake is invisible under tls.
+pub struct Trace<'a> {
+    pub header: &'a str,
+    pub conn_id: &'a str,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run_relay<S: IOStream>(
     src: S,
     dst: S,
     ac: &MixAccept,
     cc: &MixConnect,
     metrics: Arc<Mutex<ConnectionMetrics>>,
+    relayed: &Arc<Relayed>,
+    trace: Option<Trace<'_>>,
+    tls_policy: Option<&Arc<TlsPolicy>>,
+    upstream_eof: UpstreamEof,
+    byte_count: ByteCount,
+    max_bytes: u64,
+    throttle: Option<Arc<Throttle>>,
+    activity: Option<Activity>,
 ) -> Result<()> {
+    // bytes received on the sockets, beneath all the layers
+    let wire = (byte_count == ByteCount::Wire).then(WireBytes::default);
+    let (src, dst) = match &wire {
+        Some(wire) => (WireCount::new(src, &wire.tx), WireCount::new(dst, &wire.rx)),
+        None => (WireCount::disabled(src), WireCount::disabled(dst)),
+    };
+
+    // tls is the outermost layer, check the server hello
+    // sent to the client, or received from the remote peer
+    let (src, dst) = match tls_policy {
+        Some(policy) => (
+            TlsInspect::new(src, policy, matches!(ac, MixAccept::Tls(_) | MixAccept::Wss(_)), false),
+            TlsInspect::new(dst, policy, matches!(cc, MixConnect::Tls(_) | MixConnect::Wss(_)), true),
+        ),
+        None => (TlsInspect::disabled(src), TlsInspect::disabled(dst)),
+    };
+
+    let seen = Arc::new(OnceLock::new());
+    let (src, dst) = match &trace {
+        Some(trace) => (
+            TraceStream::sniff(src, trace.header, matches!(ac, MixAccept::Ws(_)), seen.clone()),
+            TraceStream::inject(dst, matches!(cc, MixConnect::Ws(_))),
+        ),
+        None => (TraceStream::sniff(src, "", false, seen.clone()), TraceStream::inject(dst, false)),
+    };
+
     macro_rules! hs_relay {
         ($ac: expr, $cc: expr) => {
-            handshake_and_relay(src, dst, $ac, $cc, metrics.clone()).await
+            handshake_and_relay(src, dst, $ac, $cc, metrics.clone(), relayed, trace, &seen, wire.as_ref(), upstream_eof, max_bytes, throttle.clone(), activity.clone()).await
         };
     }
 
@@ -48,36 +116,629 @@ pub async fn run_relay<S: IOStream>(
     }
 
     // The direct call to handshake_and_relay also needs the metrics argument
-    handshake_and_relay(src, dst, ac, cc, metrics).await
+    handshake_and_relay(
+        src,
+        dst,
+        ac,
+        cc,
+        metrics,
+        relayed,
+        trace,
+        &seen,
+        wire.as_ref(),
+        upstream_eof,
+        max_bytes,
+        throttle,
+        activity,
+    )
+    .await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handshake_and_relay<S, AC, CC>(
-    src: S,
-    dst: S,
+    src: TraceStream<S>,
+    mut dst: TraceStream<S>,
     ac: &AC,
     cc: &CC,
     metrics: Arc<Mutex<ConnectionMetrics>>,
+    relayed: &Arc<Relayed>,
+    trace: Option<Trace<'_>>,
+    seen: &OnceLock<String>,
+    wire: Option<&WireBytes>,
+    upstream_eof: UpstreamEof,
+    max_bytes: u64,
+    throttle: Option<Arc<Throttle>>,
+    activity: Option<Activity>,
 ) -> Result<()>
 where
     S: IOStream,
-    AC: AsyncAccept<S>,
-    CC: AsyncConnect<S>,
+    AC: AsyncAccept<TraceStream<S>>,
+    CC: AsyncConnect<TraceStream<S>>,
 {
     let mut buf1 = vec![0; buf_size()];
     let mut buf2 = vec![0; buf_size()];
 
-    let (mut src, mut dst) = try_join!(ac.accept(src, &mut buf1), cc.connect(dst, &mut buf2))?;
-
-    let buf1 = CopyBuffer::new(buf1);
-    let buf2 = CopyBuffer::new(buf2);
+    let (mut src, mut dst) = match trace {
+        None => try_join!(ac.accept(src, &mut buf1), cc.connect(dst, &mut buf2))?,
+        // the id to propagate is known after accept
+        Some(Trace { header, conn_id }) => {
+            let src = ac.accept(src, &mut buf1).await?;
+            let trace_id = seen.get().map_or(conn_id, |x| x.as_str());
+            log::info!("[tcp]{} trace id: {}", conn_id, trace_id);
+            lock_metrics(&metrics).trace_id = Some(trace_id.to_string());
+            dst.set_trace_id(header, trace_id);
+            let dst = cc.connect(dst, &mut buf2).await?;
+            (src, dst)
+        }
+    };
 
-    let result = bidi_copy_buf(&mut src, &mut dst, buf1, buf2).await;
+    // copied bytes are the payload, wire bytes are stored once finished
+    let payload = Default::default();
+    let counted = match wire {
+        Some(_) => &payload,
+        None => &relayed.bytes,
+    };
+    let close = upstream_eof == UpstreamEof::Close;
+    let result = match (max_bytes, throttle, activity) {
+        (0, None, None) => {
+            bidi_copy_buf_counted(&mut src, &mut dst, CopyBuffer::new(buf1), CopyBuffer::new(buf2), close, counted).await
+        }
+        (max, throttle, activity) => plain::limited_copy(src, dst, close, max, throttle, activity, counted, None).await,
+    };
 
-    if let Ok((tx_bytes, rx_bytes)) = result {
-        let mut w_metrics = metrics.lock().unwrap();
-        w_metrics.update_tx(tx_bytes);
-        w_metrics.update_rx(rx_bytes);
+    if let Some(wire) = wire {
+        relayed.bytes[0].store(wire.tx.load(Ordering::Relaxed), Ordering::Relaxed);
+        relayed.bytes[1].store(wire.rx.load(Ordering::Relaxed), Ordering::Relaxed);
     }
 
     result.map(|_| ())
 }
+
+/// Reads the trace header from the incoming http request,
+/// or injects the trace header to the outgoing http request.
+///
+/// Bytes are passed through as is once the request is done.
+pub struct TraceStream<S> {
+    inner: S,
+    state: TraceState,
+}
+
+enum TraceState {
+    Done,
+    Sniff {
+        header: String,
+        buf: Vec<u8>,
+        seen: Arc<OnceLock<String>>,
+    },
+    // header line to inject, and the pending request
+    Inject {
+        line: Vec<u8>,
+        pending: Option<(Vec<u8>, usize)>,
+    },
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> { buf.windows(4).position(|x| x == b"\r\n\r\n") }
+
+fn parse_trace_id<'a>(request: &'a [u8], header: &str) -> Option<&'a str> {
+    let request = std::str::from_utf8(request).ok()?;
+    request.split("\r\n").skip(1).find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        let v = v.trim();
+        let valid = !v.is_empty() && v.len() <= MAX_TRACE_ID_LEN && v.bytes().all(|b| b.is_ascii_graphic());
+        (k.trim().eq_ignore_ascii_case(header) && valid).then_some(v)
+    })
+}
+
+impl<S> TraceStream<S> {
+    /// Read the trace header if enabled, which is stored in `seen`.
+    pub fn sniff(inner: S, header: &str, enabled: bool, seen: Arc<OnceLock<String>>) -> Self {
+        let state = match enabled {
+            true => TraceState::Sniff {
+                header: header.to_string(),
+                buf: Vec::new(),
+                seen,
+            },
+            false => TraceState::Done,
+        };
+        Self { inner, state }
+    }
+
+    /// Inject the trace header if enabled, after [`set_trace_id`](Self::set_trace_id).
+    pub fn inject(inner: S, enabled: bool) -> Self {
+        let state = match enabled {
+            true => TraceState::Inject {
+                line: Vec::new(),
+                pending: None,
+            },
+            false => TraceState::Done,
+        };
+        Self { inner, state }
+    }
+
+    pub fn set_trace_id(&mut self, header: &str, trace_id: &str) {
+        if let TraceState::Inject { line, .. } = &mut self.state {
+            *line = format!("{}: {}\r\n", header, trace_id).into_bytes();
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TraceStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        if let TraceState::Sniff { header, buf: sniffed, seen } = &mut this.state {
+            sniffed.extend_from_slice(&buf.filled()[filled..]);
+            let end = find_header_end(sniffed);
+            if let Some(end) = end {
+                if let Some(id) = parse_trace_id(&sniffed[..end], header) {
+                    let _ = seen.set(id.to_string());
+                }
+            }
+            if end.is_some() || sniffed.len() > MAX_SNIFF_LEN || buf.filled().len() == filled {
+                this.state = TraceState::Done;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TraceStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = &mut *self;
+
+        let TraceState::Inject { line, pending } = &mut this.state else {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        };
+
+        // the request is written at once, insert the header before the empty line
+        if pending.is_none() {
+            let Some(end) = find_header_end(data) else {
+                return Pin::new(&mut this.inner).poll_write(cx, data);
+            };
+            let mut request = Vec::with_capacity(data.len() + line.len());
+            request.extend_from_slice(&data[..end + 2]);
+            request.extend_from_slice(line);
+            request.extend_from_slice(&data[end + 2..]);
+            *pending = Some((request, 0));
+        }
+
+        // the caller retries with the same data until it is consumed
+        let (request, pos) = pending.as_mut().unwrap();
+        while *pos < request.len() {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &request[*pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            *pos += n;
+        }
+        this.state = TraceState::Done;
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Checks the negotiated tls parameters against the policy.
+///
+/// The server hello is in plaintext, which is inspected on the raw stream
+/// before it reaches the tls client (the remote side), or leaves the tls server (the local side).
+/// A violation aborts the handshake.
+pub struct TlsInspect<S> {
+    inner: S,
+    state: Option<Inspect>,
+}
+
+struct Inspect {
+    policy: Arc<TlsPolicy>,
+    // inspect incoming or outgoing bytes
+    incoming: bool,
+    buf: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Hello {
+    Incomplete,
+    // not a server hello, e.g. an alert
+    Other,
+    Server {
+        version: Option<TlsVersion>,
+        cipher: u16,
+        group: Option<u16>,
+    },
+}
+
+// parse the first handshake message of the first record
+fn parse_server_hello(buf: &[u8]) -> Hello {
+    macro_rules! u16_at {
+        ($buf: expr, $i: expr) => {
+            u16::from_be_bytes([$buf[$i], $buf[$i + 1]])
+        };
+    }
+
+    if buf.len() < 5 {
+        return Hello::Incomplete;
+    }
+    // handshake record
+    if buf[0] != 0x16 {
+        return Hello::Other;
+    }
+    let record_len = u16_at!(buf, 3) as usize;
+    if buf.len() < 5 + record_len {
+        return Hello::Incomplete;
+    }
+    let record = &buf[5..5 + record_len];
+
+    // server hello message
+    if record.len() < 4 || record[0] != 0x02 {
+        return Hello::Other;
+    }
+    let len = u32::from_be_bytes([0, record[1], record[2], record[3]]) as usize;
+    let Some(hello) = record.get(4..4 + len) else {
+        return Hello::Other;
+    };
+
+    // legacy version + random + session id
+    let Some(&sid_len) = hello.get(34) else {
+        return Hello::Other;
+    };
+    let mut i = 35 + sid_len as usize;
+    if hello.len() < i + 3 {
+        return Hello::Other;
+    }
+    let mut version = u16_at!(hello, 0);
+    let cipher = u16_at!(hello, i);
+    let mut group = None;
+
+    // cipher suite + compression method + extensions
+    i += 3;
+    if hello.len() >= i + 2 {
+        let end = (i + 2 + u16_at!(hello, i) as usize).min(hello.len());
+        i += 2;
+        while i + 4 <= end {
+            let (ty, len) = (u16_at!(hello, i), u16_at!(hello, i + 2) as usize);
+            let data = &hello[i + 4..(i + 4 + len).min(end)];
+            match ty {
+                // supported versions
+                0x002b if data.len() >= 2 => version = u16_at!(data, 0),
+                // key share
+                0x0033 if data.len() >= 2 => group = Some(u16_at!(data, 0)),
+                _ => {}
+            }
+            i += 4 + len;
+        }
+    }
+
+    Hello::Server {
+        version: TlsVersion::from_wire(version),
+        cipher,
+        group,
+    }
+}
+
+impl Inspect {
+    // return true if done
+    fn verdict(&self, buf: &[u8]) -> Result<bool> {
+        use std::io::{Error, ErrorKind};
+
+        let (version, cipher, group) = match parse_server_hello(buf) {
+            Hello::Incomplete if buf.len() > MAX_HELLO_LEN => {
+                return Err(Error::new(ErrorKind::InvalidData, "tls server hello is too large"));
+            }
+            Hello::Incomplete => return Ok(false),
+            Hello::Other => return Ok(true),
+            Hello::Server { version, cipher, group } => (version, cipher, group),
+        };
+
+        let Some(version) = version else {
+            return Err(Error::new(ErrorKind::PermissionDenied, "unknown tls version"));
+        };
+        log::debug!(
+            "[tcp]tls negotiated: version={}, cipher={}, group={}",
+            version,
+            tls_cipher_name(cipher),
+            group.map_or_else(|| String::from("-"), tls_group_name)
+        );
+        self.policy
+            .check(version, cipher, group)
+            .map_err(|e| Error::new(ErrorKind::PermissionDenied, e))?;
+        Ok(true)
+    }
+}
+
+impl<S> TlsInspect<S> {
+    pub fn new(inner: S, policy: &Arc<TlsPolicy>, enabled: bool, incoming: bool) -> Self {
+        let state = enabled.then(|| Inspect {
+            policy: policy.clone(),
+            incoming,
+            buf: Vec::new(),
+        });
+        Self { inner, state }
+    }
+
+    pub fn disabled(inner: S) -> Self { Self { inner, state: None } }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TlsInspect<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        if let Some(inspect) = this.state.as_mut().filter(|x| x.incoming) {
+            let eof = buf.filled().len() == filled;
+            inspect.buf.extend_from_slice(&buf.filled()[filled..]);
+            if inspect.verdict(&inspect.buf)? || eof {
+                this.state = None;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TlsInspect<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = &mut *self;
+
+        let Some(inspect) = this.state.as_mut().filter(|x| !x.incoming) else {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        };
+
+        // check before the bytes leave, only keep what is written
+        let done = inspect.verdict(&[inspect.buf.as_slice(), data].concat())?;
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, data))?;
+        if done {
+            this.state = None;
+        } else {
+            inspect.buf.extend_from_slice(&data[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Bytes received from the client and the remote peer.
+#[derive(Default)]
+struct WireBytes {
+    tx: Arc<AtomicU64>,
+    rx: Arc<AtomicU64>,
+}
+
+/// Counts the bytes read from the raw stream.
+pub struct WireCount<S> {
+    inner: S,
+    count: Option<Arc<AtomicU64>>,
+}
+
+impl<S> WireCount<S> {
+    pub fn new(inner: S, count: &Arc<AtomicU64>) -> Self {
+        Self {
+            inner,
+            count: Some(count.clone()),
+        }
+    }
+
+    pub fn disabled(inner: S) -> Self { Self { inner, count: None } }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WireCount<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(count) = &this.count {
+            count.fetch_add((buf.filled().len() - filled) as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WireCount<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, data)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kaminari::nop::{NopAccept, NopConnect};
+    use kaminari::ws::{WsAccept, WsConf, WsConnect};
+
+    #[test]
+    fn parse_trace_header() {
+        let request = b"GET / HTTP/1.1\r\nHost: a.b.c\r\nx-realm-trace-id: abc-123";
+        assert_eq!(parse_trace_id(request, "X-Realm-Trace-Id"), Some("abc-123"));
+        assert_eq!(parse_trace_id(request, "X-Other"), None);
+
+        let request = b"GET / HTTP/1.1\r\nX-Realm-Trace-Id: a b";
+        assert_eq!(parse_trace_id(request, "X-Realm-Trace-Id"), None);
+    }
+
+    #[test]
+    fn parse_tls_server_hello() {
+        // tls1.3 server hello with supported versions and key share
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[0, 0x13, 0x02, 0]);
+        let exts = [0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, 0x00, 0x33, 0x00, 0x04, 0x00, 0x1d, 0xaa, 0xbb];
+        hello.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&exts);
+
+        let mut record = vec![0x02, 0, 0, hello.len() as u8];
+        record.extend_from_slice(&hello);
+        let mut buf = vec![0x16, 0x03, 0x03];
+        buf.extend_from_slice(&(record.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&record);
+
+        assert_eq!(parse_server_hello(&buf[..3]), Hello::Incomplete);
+        assert_eq!(parse_server_hello(&buf[..buf.len() - 1]), Hello::Incomplete);
+        assert_eq!(
+            parse_server_hello(&buf),
+            Hello::Server {
+                version: Some(TlsVersion::V1_3),
+                cipher: 0x1302,
+                group: Some(0x001d),
+            }
+        );
+
+        // alert
+        assert_eq!(parse_server_hello(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28]), Hello::Other);
+    }
+
+    #[tokio::test]
+    async fn enforce_tls_policy() {
+        use kaminari::tls::{TlsAccept, TlsClientConf, TlsConnect, TlsServerConf};
+
+        async fn handshake(policy: TlsPolicy, incoming: bool) -> Result<()> {
+            let ac = TlsAccept::new(
+                NopAccept {},
+                TlsServerConf {
+                    crt: String::new(),
+                    key: String::new(),
+                    ocsp: String::new(),
+                    server_name: String::from("a.b.c"),
+                },
+            );
+            let cc = TlsConnect::new(
+                NopConnect {},
+                TlsClientConf {
+                    sni: String::from("a.b.c"),
+                    alpn: Vec::new(),
+                    insecure: true,
+                    early_data: false,
+                },
+            );
+
+            // check on either side
+            let policy = Arc::new(policy);
+            let (client, server) = tokio::io::duplex(65536);
+            let client = TlsInspect::new(client, &policy, incoming, true);
+            let server = TlsInspect::new(server, &policy, !incoming, false);
+
+            let mut buf1 = vec![0; 4096];
+            let mut buf2 = vec![0; 4096];
+            try_join!(cc.connect(client, &mut buf1), ac.accept(server, &mut buf2)).map(|_| ())
+        }
+
+        for incoming in [true, false] {
+            let policy = TlsPolicy::new::<&str>("1.3", &[], &[]).unwrap();
+            assert!(handshake(policy, incoming).await.is_ok());
+
+            let policy = TlsPolicy::new("1.3", &["TLS13_AES_256_GCM_SHA384", "TLS13_AES_128_GCM_SHA256"], &["x25519"]);
+            assert!(handshake(policy.unwrap(), incoming).await.is_ok());
+
+            // rustls prefers aes-gcm
+            let policy = TlsPolicy::new("1.3", &["TLS13_CHACHA20_POLY1305_SHA256"], &[]);
+            assert!(handshake(policy.unwrap(), incoming).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn send_configured_sni() {
+        use std::time::Duration;
+        use tokio::io::AsyncReadExt;
+        use kaminari::opt::get_tls_client_conf;
+        use kaminari::tls::TlsConnect;
+
+        // the remote address is never taken as the server name
+        let conf = get_tls_client_conf("tls;sni=front.example;insecure").unwrap();
+        let cc = TlsConnect::new(NopConnect {}, conf);
+
+        let (client, mut server) = tokio::io::duplex(65536);
+        let mut buf = vec![0; 4096];
+        let mut hello = vec![0; 4096];
+        // never answered
+        let connect = tokio::time::timeout(Duration::from_millis(200), cc.connect(client, &mut buf));
+        let (_, n) = tokio::join!(connect, server.read(&mut hello));
+        let hello = &hello[..n.unwrap()];
+        assert_eq!(hello[0], 0x16);
+        assert!(hello.windows(13).any(|x| x == b"front.example"));
+    }
+
+    #[test]
+    fn reject_tls_policy() {
+        assert!(TlsPolicy::new::<&str>("1.1", &[], &[]).is_err());
+        assert!(TlsPolicy::new("1.3", &["TLS_RSA_WITH_RC4_128_SHA"], &[]).is_err());
+        assert!(TlsPolicy::new("1.3", &["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"], &[]).is_err());
+        assert!(TlsPolicy::new("1.2", &[], &["x25519"]).is_err());
+        assert!(TlsPolicy::new("1.3", &[], &["ffdhe2048"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn propagate_trace_id() {
+        let conf = WsConf {
+            host: String::from("a.b.c"),
+            path: String::from("/"),
+        };
+        let cc = WsConnect::new(NopConnect {}, conf.clone());
+        let ac = WsAccept::new(NopAccept {}, conf);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client = TraceStream::inject(client, true);
+        client.set_trace_id("X-Realm-Trace-Id", "abc-123");
+        let seen = Arc::new(OnceLock::new());
+        let server = TraceStream::sniff(server, "X-Realm-Trace-Id", true, seen.clone());
+
+        let mut buf1 = vec![0; 4096];
+        let mut buf2 = vec![0; 4096];
+        try_join!(cc.connect(client, &mut buf1), ac.accept(server, &mut buf2)).unwrap();
+        assert_eq!(seen.get().map(|x| x.as_str()), Some("abc-123"));
+    }
+
+    #[tokio::test]
+    async fn count_wire_bytes() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let conf = WsConf {
+            host: String::from("a.b.c"),
+            path: String::from("/"),
+        };
+        let cc = WsConnect::new(NopConnect {}, conf.clone());
+        let ac = WsAccept::new(NopAccept {}, conf);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let wire = WireBytes::default();
+        let server = WireCount::new(server, &wire.tx);
+
+        let mut buf1 = vec![0; 4096];
+        let mut buf2 = vec![0; 4096];
+        let (mut client, mut server) = try_join!(cc.connect(client, &mut buf1), ac.accept(server, &mut buf2)).unwrap();
+        let handshake = wire.tx.load(Ordering::Relaxed);
+        assert!(handshake > 0);
+
+        client.write_all(&[1; 100]).await.unwrap();
+        let mut buf = [0; 100];
+        server.read_exact(&mut buf).await.unwrap();
+        // payload and the websocket frame header
+        let read = wire.tx.load(Ordering::Relaxed);
+        assert!(read > handshake + 100);
+
+        // writes are not counted
+        server.write_all(&[2; 100]).await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(wire.tx.load(Ordering::Relaxed), read);
+    }
+}
@@ -5,6 +5,7 @@ use std::task::{Poll, Context};
 use std::future::Future;
 use std::time::Duration;
 use std::io::{Result, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use tokio::time::Sleep;
 
@@ -62,3 +63,24 @@ pub fn timeoutfut<F: Future>(future: F, timeout: usize) -> Timeout<F> {
     };
     Timeout { value: future, delay }
 }
+
+// fallback connect timeout, never 0
+static CONNECT_TIMEOUT: AtomicUsize = AtomicUsize::new(5);
+
+/// Set the fallback connect timeout, which applies to
+/// endpoints whose connect timeout is 0.
+///
+/// Timeout = 0 is ignored.
+pub fn set_connect_timeout(timeout: usize) {
+    if timeout != 0 {
+        CONNECT_TIMEOUT.store(timeout, Ordering::Relaxed);
+    }
+}
+
+/// Get the effective connect timeout, which is never 0.
+pub fn connect_timeout_or_default(timeout: usize) -> usize {
+    match timeout {
+        0 => CONNECT_TIMEOUT.load(Ordering::Relaxed),
+        x => x,
+    }
+}
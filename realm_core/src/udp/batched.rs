@@ -61,7 +61,7 @@ pub struct PacketRef<'buf, 'addr> {
     addr: &'addr SockAddrStore,
 }
 
-impl<'buf, 'addr> PacketRef<'buf, 'addr> {
+impl PacketRef<'_, '_> {
     pub fn len(&self) -> usize {
         self.buf.len()
     }
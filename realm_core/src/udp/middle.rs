@@ -1,16 +1,23 @@
 use std::io::Result;
 use std::net::SocketAddr;
+use std::pin::pin;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
+use tokio::sync::{Notify, Semaphore, OwnedSemaphorePermit};
+use futures::future::{select, Either};
 
-use crate::monitor::{ConnectionMetrics, UDP_ASSOCIATION_METRICS};
+use crate::monitor::{ConnectionMetrics, RuleMetrics, UDP_ASSOCIATION_METRICS, lock_metrics, record_udp_association};
 use super::SockMap;
+use super::{CloseReason, SessionLog};
 use super::{socket, batched};
 
 use crate::trick::Ref;
 use crate::time::timeoutfut;
-use crate::dns::resolve_addr;
+use crate::dns::{resolve_addr, next_turn};
 use crate::endpoint::{RemoteAddr, ConnectOpts};
+use crate::shutdown::{self, Stop};
 
 use batched::{Packet, SockAddrStore};
 use registry::Registry;
@@ -46,7 +53,7 @@ mod registry {
             group_by_inner(&mut self.pkts[..n], &mut self.groups, |a, b| a.addr == b.addr);
         }
 
-        pub fn group_iter(&self) -> GroupIter {
+        pub fn group_iter(&self) -> GroupIter<'_> {
             GroupIter {
                 pkts: &self.pkts,
                 ranges: self.groups.iter(),
@@ -107,106 +114,237 @@ mod registry {
     }
 }
 
+// clamped to the max, 0 means the max
+fn batch_size(n: usize) -> usize {
+    match n {
+        0 => batched::MAX_PACKETS,
+        n => n.min(batched::MAX_PACKETS),
+    }
+}
+
+// both directions are counted, 0 means unlimited,
+// not lifted by a reset of the metrics
+fn exceed_max_bytes(metrics: &ConnectionMetrics, max_bytes: usize) -> bool {
+    max_bytes != 0 && metrics.total_bytes() >= max_bytes as u64
+}
+
+// blocked as long as an idle association lives
+fn block_until(timeout: usize) -> Option<Instant> {
+    Some(Instant::now() + Duration::from_secs(timeout as u64))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn associate_and_relay(
     lis: Ref<UdpSocket>,
     rname: Ref<RemoteAddr>,
     conn_opts: Ref<ConnectOpts>,
     sockmap: Ref<SockMap>,
+    session: Ref<SessionLog>,
+    rule: &Arc<RuleMetrics>,
+    pending: Option<&Arc<Semaphore>>,
+    stop: &Stop,
+    alive: &Arc<()>,
 ) -> Result<()> {
-    let mut registry = Registry::new(batched::MAX_PACKETS);
+    let mut registry = Registry::new(batch_size(conn_opts.udp_batch_size));
 
     loop {
         registry.batched_recv_on(&lis).await?;
         log::debug!("[udp]entry batched recvfrom[{}]", registry.count());
-        let raddr = resolve_addr(&rname).await?.iter().next().unwrap();
-        log::debug!("[udp]{} resolved as {}", *rname, raddr);
+        let resolved = resolve_addr(&rname).await?;
+        let first = resolved.iter().next().unwrap();
+        log::debug!("[udp]{} resolved as {}", *rname, first);
 
         registry.group_by_addr();
         for pkts in registry.group_iter() {
             let laddr = pkts[0].addr.clone().into();
+            if conn_opts.accept_filter.as_ref().is_some_and(|x| !x.allow(laddr)) {
+                log::debug!("[udp]filtered, drop {} packets from {}", pkts.len(), laddr);
+                rule.filtered_dropped.fetch_add(pkts.len() as u64, Ordering::Relaxed);
+                continue;
+            }
+            if conn_opts.max_bytes != 0 && sockmap.is_blocked(&laddr) {
+                log::debug!("[udp]max bytes reached, drop {} packets from {}", pkts.len(), laddr);
+                continue;
+            }
+            // established associations are left alone while draining
+            if shutdown::drain_state().is_some() && sockmap.find(&laddr).is_none() {
+                log::debug!("[udp]draining, drop {} packets from {}", pkts.len(), laddr);
+                continue;
+            }
             let rsock = sockmap.find_or_insert(&laddr, || {
-                let s = Arc::new(socket::associate(&raddr, &conn_opts)?);
+                // none if too many associations are being created
+                let permit = match pending.map(|x| x.clone().try_acquire_owned()) {
+                    Some(Ok(x)) => Some(x),
+                    Some(Err(_)) => return Err(None),
+                    None => None,
+                };
+                // a new association takes the next address in turn
+                let raddr = match conn_opts.dns_round_robin {
+                    true => resolved.iter_from(next_turn(&rname)).next().unwrap(),
+                    false => first,
+                };
+                let s = Arc::new(socket::associate(&raddr, &conn_opts).map_err(Some)?);
+                let closed = Arc::new(Notify::new());
                 let metrics_for_laddr = UDP_ASSOCIATION_METRICS
                     .entry(laddr)
-                    .or_insert_with(|| Arc::new(Mutex::new(ConnectionMetrics::new())))
+                    .or_insert_with(|| {
+                        let mut metrics = ConnectionMetrics::new();
+                        metrics.rule_id = Some(session.id().to_string());
+                        metrics.peer = Some(laddr);
+                        metrics.remote = Some(raddr);
+                        metrics.closer = Some(closed.clone());
+                        Arc::new(Mutex::new(metrics))
+                    })
                     .value()
                     .clone();
                 log::debug!("[udp] Ensuring metrics for association {} stored/retrieved.", laddr);
-                tokio::spawn(send_back(
+                let relay = send_back(
                     lis,
                     laddr,
+                    raddr,
                     s.clone(),
                     conn_opts,
                     sockmap,
+                    session,
                     metrics_for_laddr,
-                ));
-                log::info!("[udp]new association {} => {} as {}", laddr, *rname, raddr);
-                Result::Ok(s)
-            })?;
+                    rule.clone(),
+                    stop.clone(),
+                    closed.clone(),
+                    permit,
+                );
+                let alive = alive.clone();
+                tokio::spawn(async move {
+                    relay.await;
+                    drop(alive);
+                });
+                session.created(&laddr, &rname, &raddr);
+                rule.clear_error();
+                Ok((s, raddr, closed))
+            });
+            let (rsock, raddr, closed) = match rsock {
+                Ok(x) => x,
+                Err(Some(e)) => return Err(e),
+                Err(None) => {
+                    log::debug!("[udp]too many pending associations, drop {} packets from {}", pkts.len(), laddr);
+                    rule.associate_dropped.fetch_add(pkts.len() as u64, Ordering::Relaxed);
+                    continue;
+                }
+            };
+
+            // stick to the same address while round-robin,
+            // otherwise follow the first resolved address
+            let raddr = if conn_opts.dns_round_robin { raddr } else { first };
+            let raddr_s: SockAddrStore = raddr.into();
 
             // Uplink traffic processing
-            let packets_to_send_iter_vec: Vec<_> = pkts.iter().map(|x| x.ref_with_addr(&raddr.into())).collect();
+            let packets_to_send_iter_vec: Vec<_> = pkts.iter().map(|x| x.ref_with_addr(&raddr_s)).collect();
             let total_bytes_uplink: usize = packets_to_send_iter_vec.iter().map(|p_ref| p_ref.len()).sum();
 
             batched::send_all(&rsock, packets_to_send_iter_vec.into_iter()).await?;
 
             if let Some(metrics_entry) = UDP_ASSOCIATION_METRICS.get(&laddr) {
                 let metrics = metrics_entry.value(); // This is &Arc<Mutex<ConnectionMetrics>>
-                if let Ok(mut w_metrics) = metrics.lock() {
-                    w_metrics.update_tx(total_bytes_uplink as u64);
-                } else {
-                    log::warn!("[udp] Failed to lock metrics for TX update for {}", laddr);
+                let mut w_metrics = lock_metrics(metrics);
+                w_metrics.update_tx(total_bytes_uplink as u64);
+                w_metrics.update_tx_packets(pkts.len() as u64);
+                // drop packets from now on, and let the rear relay tear it down
+                if exceed_max_bytes(&w_metrics, conn_opts.max_bytes) {
+                    sockmap.block(laddr, block_until(conn_opts.effective_associate_timeout()));
+                    closed.notify_one();
                 }
             } else {
-                log::warn!("[udp] No metrics found for uplink for {} (key: {}). Total uplink bytes: {}", rname.to_string(), laddr, total_bytes_uplink);
+                log::warn!("[udp] No metrics found for uplink for {} (key: {}). Total uplink bytes: {}", *rname, laddr, total_bytes_uplink);
             }
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn send_back(
     lsock: Ref<UdpSocket>,
     laddr: SocketAddr,
+    raddr: SocketAddr,
     rsock: Arc<UdpSocket>,
     conn_opts: Ref<ConnectOpts>,
     sockmap: Ref<SockMap>,
+    session: Ref<SessionLog>,
     metrics: Arc<Mutex<ConnectionMetrics>>,
+    rule: Arc<RuleMetrics>,
+    stop: Stop,
+    closed: Arc<Notify>,
+    mut pending: Option<OwnedSemaphorePermit>,
 ) {
-    let mut registry = Registry::new(batched::MAX_PACKETS);
-    let timeout = conn_opts.associate_timeout;
+    let mut registry = Registry::new(batch_size(conn_opts.udp_batch_size));
+    let timeout = conn_opts.effective_associate_timeout();
     let laddr_s: SockAddrStore = laddr.into();
+    let mut stopped = pin!(stop.stopped());
+    let mut closed = pin!(closed.notified());
 
-    loop {
-        match timeoutfut(registry.batched_recv_on(&rsock), timeout).await {
+    let reason = loop {
+        let recv = {
+            let recv = pin!(timeoutfut(registry.batched_recv_on(&rsock), timeout));
+            match select(recv, select(stopped.as_mut(), closed.as_mut())).await {
+                Either::Left((x, _)) => x,
+                Either::Right((Either::Left(_), _)) => {
+                    log::debug!("[udp]rear relay stopped");
+                    break CloseReason::Stopped;
+                }
+                // the client is blocked by max bytes, otherwise closed by the api
+                Either::Right((Either::Right(_), _)) if sockmap.is_blocked(&laddr) => {
+                    log::debug!("[udp]max bytes reached by {}", laddr);
+                    break CloseReason::MaxBytes;
+                }
+                Either::Right((Either::Right(_), _)) => {
+                    log::info!("[udp]association of {} closed", laddr);
+                    break CloseReason::Closed;
+                }
+            }
+        };
+        match recv {
             Err(_) => {
                 log::debug!("[udp]rear recvfrom timeout");
-                break;
+                break CloseReason::Timeout;
             }
             Ok(Err(e)) => {
                 log::error!("[udp]rear recvfrom failed: {}", e);
-                break;
+                break CloseReason::RecvError;
             }
             Ok(Ok(())) => {
                 log::debug!("[udp]rear batched recvfrom[{}]", registry.count())
             }
         };
 
+        // established once the remote peer replies
+        drop(pending.take());
+
         let packets_to_send_iter_vec: Vec<_> = registry.iter().map(|pkt| pkt.ref_with_addr(&laddr_s)).collect();
         let total_bytes_downlink: usize = packets_to_send_iter_vec.iter().map(|p_ref| p_ref.len()).sum();
 
         if let Err(e) = batched::send_all(&lsock, packets_to_send_iter_vec.into_iter()).await {
-            log::error!("[udp]failed to sendto client{}: {}", &laddr, e);
-            break;
-        } else {
-            if let Ok(mut w_metrics) = metrics.lock() {
-                 w_metrics.update_rx(total_bytes_downlink as u64);
-            } else {
-                log::warn!("[udp] Failed to lock metrics for RX update for {}", laddr);
-            }
+            log::error!("[udp]failed to sendto client{}: {}", laddr, e);
+            break CloseReason::SendError;
         }
-    }
 
+        let mut w_metrics = lock_metrics(&metrics);
+        w_metrics.update_rx(total_bytes_downlink as u64);
+        w_metrics.update_rx_packets(registry.count() as u64);
+        if exceed_max_bytes(&w_metrics, conn_opts.max_bytes) {
+            log::debug!("[udp]max bytes reached by {}", laddr);
+            break CloseReason::MaxBytes;
+        }
+    };
+
+    // keep the client from associating again at once
+    if reason == CloseReason::MaxBytes {
+        sockmap.block(laddr, block_until(timeout));
+    }
     sockmap.remove(&laddr);
+    // counted by the rule before it leaves the live ones
+    rule.add_lifetime(&lock_metrics(&metrics));
     UDP_ASSOCIATION_METRICS.remove(&laddr);
-    log::debug!("[udp]remove association and metrics for {}", &laddr);
+    log::debug!("[udp]remove association and metrics for {}", laddr);
+
+    let metrics = lock_metrics(&metrics);
+    record_udp_association(&metrics);
+    session.closed(&laddr, &raddr, &metrics, reason);
 }
@@ -4,36 +4,172 @@ mod socket;
 mod sockmap;
 mod middle;
 mod batched;
+mod session;
 
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+use std::net::SocketAddr;
+
+use futures::future::{join_all, select, Either};
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
 
 use crate::trick::Ref;
-use crate::endpoint::Endpoint;
+use crate::endpoint::{Endpoint, LocalAddr};
+use crate::shutdown::{ListenGuard, Stop};
+use crate::monitor::rule_metrics;
+
+pub use batched::MAX_PACKETS;
 
 use sockmap::SockMap;
+use session::{CloseReason, SessionLog};
 use middle::associate_and_relay;
 
 /// Launch a udp relay.
 pub async fn run_udp(endpoint: Endpoint) -> Result<()> {
-    let Endpoint {
+    run_udp_with_stop(endpoint, Stop::new()).await
+}
+
+/// Listeners of a udp relay, bound before it is launched.
+#[derive(Debug)]
+pub struct UdpListeners {
+    laddr: SocketAddr,
+    listeners: Vec<UdpSocket>,
+    listening: ListenGuard,
+}
+
+/// Bind the listeners of a udp relay, which is launched later
+/// with [`run_udp_with_listeners`].
+///
+/// Return `None` if already stopped.
+pub fn bind_udp(endpoint: &Endpoint, stop: &Stop) -> Result<Option<UdpListeners>> {
+    let Endpoint { laddr, bind_opts, .. } = endpoint;
+
+    let laddr = match laddr {
+        LocalAddr::SocketAddr(x) => *x,
+        #[cfg(unix)]
+        LocalAddr::UnixSocket(_) => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("[udp]could not listen on {}", laddr),
+            ))
+        }
+    };
+
+    // dropped after the listener
+    let Some(mut listening) = stop.listen() else {
+        return Ok(None);
+    };
+
+    let listeners = (0..bind_opts.workers())
+        .map(|_| socket::bind(&laddr, bind_opts.clone()))
+        .collect::<Result<_>>()
+        .map_err(|e| Error::new(e.kind(), format!("[udp]failed to bind {}: {}", laddr, e)))?;
+    listening.bind();
+
+    Ok(Some(UdpListeners {
         laddr,
+        listeners,
+        listening,
+    }))
+}
+
+/// Launch a udp relay, which could be stopped later.
+///
+/// Once stopped, all associations are closed.
+pub async fn run_udp_with_stop(endpoint: Endpoint, stop: Stop) -> Result<()> {
+    match bind_udp(&endpoint, &stop)? {
+        Some(listeners) => run_udp_with_listeners(endpoint, listeners, stop).await,
+        None => Ok(()),
+    }
+}
+
+/// Same as [`run_udp_with_stop`], but with listeners from [`bind_udp`].
+pub async fn run_udp_with_listeners(endpoint: Endpoint, listeners: UdpListeners, stop: Stop) -> Result<()> {
+    let Endpoint {
+        id,
         raddr,
-        bind_opts,
         conn_opts,
+        udp_raddr,
         ..
     } = endpoint;
+    let UdpListeners {
+        laddr,
+        listeners,
+        listening,
+    } = listeners;
+
+    let raddr = udp_raddr.unwrap_or(raddr);
 
     let sockmap = SockMap::new();
+    let rule = rule_metrics(&id);
+    let session = SessionLog::new(id);
 
-    let lis = socket::bind(&laddr, bind_opts).unwrap_or_else(|e| panic!("[udp]failed to bind {}: {}", laddr, e));
+    // checked along with speed calculation
+    #[cfg(feature = "alert")]
+    rule.alert.store(conn_opts.alert.clone());
+
+    // associations waiting for the first reply
+    let pending = match conn_opts.associate_concurrency {
+        0 => None,
+        n => Some(Arc::new(Semaphore::new(n))),
+    };
+
+    // held by each worker and association
+    let alive = Arc::new(());
 
-    let lis = Ref::new(&lis);
     let raddr = Ref::new(&raddr);
     let conn_opts = Ref::new(&conn_opts);
     let sockmap = Ref::new(&sockmap);
-    loop {
-        if let Err(e) = associate_and_relay(lis, raddr, conn_opts, sockmap).await {
-            log::error!("[udp]error: {}", e);
-        }
+    let session = Ref::new(&session);
+
+    // each worker relays packets received by its own listener,
+    // while associations are shared
+    let workers: Vec<_> = listeners
+        .iter()
+        .map(|lis| {
+            let lis = Ref::new(lis);
+            let rule = rule.clone();
+            let pending = pending.clone();
+            let stop = stop.clone();
+            let alive = alive.clone();
+            tokio::spawn(async move {
+                let mut stopped = pin!(stop.stopped());
+                loop {
+                    let relay = pin!(associate_and_relay(
+                        lis,
+                        raddr,
+                        conn_opts,
+                        sockmap,
+                        session,
+                        &rule,
+                        pending.as_ref(),
+                        &stop,
+                        &alive,
+                    ));
+                    match select(relay, stopped.as_mut()).await {
+                        Either::Left((Err(e), _)) => {
+                            log::error!("[udp]error: {}", e);
+                            rule.record_error(e);
+                        }
+                        Either::Left((Ok(()), _)) => {}
+                        Either::Right(_) => break,
+                    }
+                }
+            })
+        })
+        .collect();
+    let _ = join_all(workers).await;
+
+    // associations refer to this frame, including the listener
+    while Arc::strong_count(&alive) > 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
     }
+    drop(listeners);
+    drop(listening);
+    log::info!("[udp]{} stopped", laddr);
+
+    Ok(())
 }
@@ -0,0 +1,132 @@
+//! Association lifecycle logs.
+
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::endpoint::RemoteAddr;
+use crate::monitor::ConnectionMetrics;
+
+/// Lifecycle logs allowed per second, the rest are suppressed.
+const MAX_LOGS_PER_SEC: usize = 32;
+
+/// Why an association is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    Timeout,
+    Stopped,
+    /// Reached `max_bytes`.
+    MaxBytes,
+    /// Closed by the api.
+    Closed,
+    RecvError,
+    SendError,
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            CloseReason::Timeout => "timeout",
+            CloseReason::Stopped => "stopped",
+            CloseReason::MaxBytes => "max bytes",
+            CloseReason::Closed => "closed",
+            CloseReason::RecvError => "recv error",
+            CloseReason::SendError => "send error",
+        };
+        f.write_str(s)
+    }
+}
+
+struct Window {
+    begin: Instant,
+    count: usize,
+    suppressed: usize,
+}
+
+/// Log creation and teardown of associations of an endpoint.
+///
+/// Logs are rate-limited so that a high-churn endpoint
+/// will not flood the output.
+pub struct SessionLog {
+    id: String,
+    window: Mutex<Window>,
+}
+
+impl SessionLog {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            window: Mutex::new(Window {
+                begin: Instant::now(),
+                count: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn allow(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        let now = Instant::now();
+
+        if now.duration_since(window.begin) >= Duration::from_secs(1) {
+            if window.suppressed != 0 {
+                log::info!("[udp]{}: {} association logs suppressed", self.id, window.suppressed);
+            }
+            *window = Window {
+                begin: now,
+                count: 0,
+                suppressed: 0,
+            };
+        }
+
+        if window.count < MAX_LOGS_PER_SEC {
+            window.count += 1;
+            true
+        } else {
+            window.suppressed += 1;
+            false
+        }
+    }
+
+    pub fn created(&self, laddr: &SocketAddr, rname: &RemoteAddr, raddr: &SocketAddr) {
+        if self.allow() {
+            log::info!("[udp]{}: new association {} => {} as {}", self.id, laddr, rname, raddr);
+        }
+    }
+
+    pub fn closed(&self, laddr: &SocketAddr, raddr: &SocketAddr, metrics: &ConnectionMetrics, reason: CloseReason) {
+        if self.allow() {
+            log::info!(
+                "[udp]{}: close association {} => {}, reason={}, lifetime={}s, tx={}, rx={}",
+                self.id,
+                laddr,
+                raddr,
+                reason,
+                metrics.start_time.elapsed().as_secs(),
+                metrics.traffic.tx_bytes,
+                metrics.traffic.rx_bytes
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_log_rate_limit() {
+        let session = SessionLog::new("test".to_string());
+        for _ in 0..MAX_LOGS_PER_SEC {
+            assert!(session.allow());
+        }
+        assert!(!session.allow());
+        assert!(!session.allow());
+        assert_eq!(session.window.lock().unwrap().suppressed, 2);
+    }
+}
@@ -5,11 +5,17 @@ use tokio::net::UdpSocket;
 use realm_syscall::new_udp_socket;
 
 use crate::endpoint::{BindOpts, ConnectOpts};
+use crate::tcp::set_dscp;
 
 pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<UdpSocket> {
+    #[cfg(unix)]
+    let workers = bind_opts.workers();
     let BindOpts {
         ipv6_only,
+        reuse_address,
         bind_interface,
+        dscp,
+        ..
     } = bind_opts;
     let socket = new_udp_socket(laddr)?;
 
@@ -18,13 +24,29 @@ pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<UdpSocket> {
         socket.set_only_v6(ipv6_only)?;
     }
 
+    // bind interface, which requires CAP_NET_RAW before linux 5.7
     #[cfg(target_os = "linux")]
     if let Some(iface) = bind_interface {
-        realm_syscall::bind_to_device(&socket, &iface)?;
+        realm_syscall::bind_to_device(&socket, &iface).map_err(|e| {
+            log::error!("[udp]failed to bind {} to device {}: {}", laddr, iface, e);
+            std::io::Error::new(e.kind(), format!("bind to device {}: {}", iface, e))
+        })?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    if let Some(iface) = bind_interface {
+        log::warn!("[udp]listen interface {} of {} is ignored, only supported on linux", iface, laddr);
     }
 
     // ignore error
-    let _ = socket.set_reuse_address(true);
+    let _ = socket.set_reuse_address(reuse_address);
+
+    // share the address with other workers
+    #[cfg(unix)]
+    if workers > 1 {
+        socket.set_reuse_port(true)?;
+    }
+
+    set_dscp(&socket, laddr, dscp);
 
     socket.bind(&(*laddr).into())?;
 
@@ -34,6 +56,7 @@ pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<UdpSocket> {
 pub fn associate(raddr: &SocketAddr, conn_opts: &ConnectOpts) -> Result<UdpSocket> {
     let ConnectOpts {
         bind_address,
+        dscp,
 
         #[cfg(target_os = "linux")]
         bind_interface,
@@ -54,5 +77,49 @@ pub fn associate(raddr: &SocketAddr, conn_opts: &ConnectOpts) -> Result<UdpSocke
         realm_syscall::bind_to_device(&socket, iface)?;
     }
 
+    set_dscp(&socket, raddr, *dscp);
+
     UdpSocket::from_std(socket.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn reuse_port_spread() {
+        let laddr: SocketAddr = "127.0.0.1:10430".parse().unwrap();
+        let bind_opts = BindOpts {
+            reuse_port_workers: 4,
+            ..Default::default()
+        };
+        let listeners: Vec<_> = (0..4).map(|_| bind(&laddr, bind_opts.clone()).unwrap()).collect();
+
+        // hashed by the source address
+        for _ in 0..64 {
+            let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            client.send_to(b"ping", laddr).unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let mut buf = [0; 8];
+        let mut received = Vec::new();
+        for lis in &listeners {
+            let mut n = 0;
+            while lis.try_recv_from(&mut buf).is_ok() {
+                n += 1;
+            }
+            received.push(n);
+        }
+        assert_eq!(received.iter().sum::<usize>(), 64);
+        assert!(received.iter().all(|n| *n > 0), "{:?}", received);
+
+        // not shared without the option, udp allows it with SO_REUSEADDR though
+        let bind_opts = BindOpts {
+            reuse_address: false,
+            ..Default::default()
+        };
+        assert!(bind(&laddr, bind_opts).is_err());
+    }
+}
@@ -1,21 +1,33 @@
 use std::net::SocketAddr;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use std::collections::HashMap;
 
 use tokio::net::UdpSocket;
+use tokio::sync::Notify;
 
-pub struct SockMap(RwLock<HashMap<SocketAddr, Arc<UdpSocket>>>);
+// (socket, remote address, teardown signal) of each client
+pub type Association = (Arc<UdpSocket>, SocketAddr, Arc<Notify>);
+
+pub struct SockMap {
+    socks: RwLock<HashMap<SocketAddr, Association>>,
+    // clients whose packets are dropped, until the deadline if any
+    blocked: Mutex<HashMap<SocketAddr, Option<Instant>>>,
+}
 
 impl SockMap {
     pub fn new() -> Self {
-        Self(RwLock::new(HashMap::new()))
+        Self {
+            socks: RwLock::new(HashMap::new()),
+            blocked: Mutex::new(HashMap::new()),
+        }
     }
 
     #[inline]
-    pub fn find(&self, addr: &SocketAddr) -> Option<Arc<UdpSocket>> {
+    pub fn find(&self, addr: &SocketAddr) -> Option<Association> {
         // fetch the lock
 
-        let sockmap = self.0.read().unwrap();
+        let sockmap = self.socks.read().unwrap();
 
         sockmap.get(addr).cloned()
 
@@ -23,26 +35,26 @@ impl SockMap {
     }
 
     #[inline]
-    pub fn insert(&self, addr: SocketAddr, socket: Arc<UdpSocket>) {
+    pub fn insert(&self, addr: SocketAddr, assoc: Association) {
         // fetch the lock
-        let mut sockmap = self.0.write().unwrap();
+        let mut sockmap = self.socks.write().unwrap();
 
-        let _ = sockmap.insert(addr, socket);
+        let _ = sockmap.insert(addr, assoc);
 
         // drop the lock
     }
 
     #[inline]
-    pub fn find_or_insert<E, F>(&self, addr: &SocketAddr, f: F) -> Result<Arc<UdpSocket>, E>
+    pub fn find_or_insert<E, F>(&self, addr: &SocketAddr, f: F) -> Result<Association, E>
     where
-        F: Fn() -> Result<Arc<UdpSocket>, E>,
+        F: Fn() -> Result<Association, E>,
     {
         match self.find(addr) {
             Some(x) => Ok(x),
             None => {
-                let socket = f()?;
-                self.insert(*addr, Arc::clone(&socket));
-                Ok(socket)
+                let assoc = f()?;
+                self.insert(*addr, assoc.clone());
+                Ok(assoc)
             }
         }
     }
@@ -50,10 +62,30 @@ impl SockMap {
     #[inline]
     pub fn remove(&self, addr: &SocketAddr) {
         // fetch the lock
-        let mut sockmap = self.0.write().unwrap();
+        let mut sockmap = self.socks.write().unwrap();
 
         let _ = sockmap.remove(addr);
 
         // drop the lock
     }
+
+    /// Drop packets from the client until `until`, or forever if `None`.
+    pub fn block(&self, addr: SocketAddr, until: Option<Instant>) {
+        let mut blocked = self.blocked.lock().unwrap();
+        let now = Instant::now();
+        blocked.retain(|_, x| x.is_none_or(|x| x > now));
+        blocked.insert(addr, until);
+    }
+
+    pub fn is_blocked(&self, addr: &SocketAddr) -> bool {
+        let mut blocked = self.blocked.lock().unwrap();
+        match blocked.get(addr) {
+            Some(Some(until)) if *until <= Instant::now() => {
+                blocked.remove(addr);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
 }
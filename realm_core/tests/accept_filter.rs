@@ -0,0 +1,105 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::udp::run_udp;
+use realm_core::acl::Acl;
+use realm_core::endpoint::{ConnectOpts, Endpoint, RemoteAddr, SharedAcceptFilter};
+use realm_core::monitor::rule_metrics;
+
+// an unused local address
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn endpoint(laddr: SocketAddr, raddr: SocketAddr, allowed: &Arc<AtomicBool>) -> Endpoint {
+    let allowed = allowed.clone();
+    Endpoint {
+        id: format!("filter-{}", laddr),
+        laddr: laddr.into(),
+        raddr: RemoteAddr::SocketAddr(raddr),
+        conn_opts: ConnectOpts {
+            accept_filter: Some(SharedAcceptFilter::new(move |peer: SocketAddr| {
+                peer.ip().is_loopback() && allowed.load(Ordering::Relaxed)
+            })),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    }
+}
+
+#[tokio::test]
+async fn tcp_accept_filter() {
+    let allowed = Arc::new(AtomicBool::new(false));
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let laddr = free_addr();
+    tokio::spawn(run_tcp(endpoint(laddr, lis.local_addr().unwrap(), &allowed)));
+    sleep(Duration::from_millis(500)).await;
+
+    // closed before connecting to the remote peer
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    assert!(timeout(Duration::from_millis(200), lis.accept()).await.is_err());
+
+    allowed.store(true, Ordering::Relaxed);
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    let (mut server, _) = lis.accept().await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn udp_accept_filter() {
+    let allowed = Arc::new(AtomicBool::new(false));
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let laddr = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    tokio::spawn(run_udp(endpoint(laddr, server.local_addr().unwrap(), &allowed)));
+    sleep(Duration::from_millis(500)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0; 16];
+    client.send_to(b"dropped", laddr).await.unwrap();
+    assert!(timeout(Duration::from_millis(200), server.recv_from(&mut buf)).await.is_err());
+
+    allowed.store(true, Ordering::Relaxed);
+    client.send_to(b"ping", laddr).await.unwrap();
+    let (n, _) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+}
+
+#[tokio::test]
+async fn acl_counted() {
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = UdpSocket::bind(lis.local_addr().unwrap()).await.unwrap();
+    let laddr = free_addr();
+    let mut endpoint = endpoint(laddr, lis.local_addr().unwrap(), &Arc::new(AtomicBool::new(true)));
+    let acl = Acl::new(&["127.0.0.0/8"], &["127.0.0.1"]).unwrap();
+    endpoint.conn_opts.accept_filter = Some(SharedAcceptFilter::new(acl));
+    tokio::spawn(run_tcp(endpoint.clone()));
+    tokio::spawn(run_udp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    assert!(timeout(Duration::from_millis(200), lis.accept()).await.is_err());
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(b"dropped", laddr).await.unwrap();
+    assert!(timeout(Duration::from_millis(200), server.recv_from(&mut buf)).await.is_err());
+
+    let rule = rule_metrics(&format!("filter-{}", laddr));
+    assert_eq!(rule.rejected_filtered.load(Ordering::Relaxed), 1);
+    assert_eq!(rule.filtered_dropped.load(Ordering::Relaxed), 1);
+}
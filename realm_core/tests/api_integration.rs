@@ -2,35 +2,49 @@
 // If api.rs is in its own new crate, this test would be for that crate.
 // Assuming api.rs is now in realm_core.
 
-use actix_web::{test, web, App};
-use realm_core::monitor::{ConnectionMetrics, TCP_CONNECTION_METRICS, UDP_ASSOCIATION_METRICS};
+use actix_web::{test, App};
+use realm_core::monitor::{ConnectionMetrics, TCP_CONNECTION_METRICS, UDP_ASSOCIATION_METRICS, rule_metrics, refresh_snapshot};
 use realm_core::api::{list_tcp_connections, get_tcp_connection_stats, list_udp_associations, get_udp_association_stats}; // Adjusted path
+use realm_core::api::{get_rule_sources, unquarantine_rule, get_top_connections, get_metrics, get_rule_stats};
+use realm_core::api::{reset_rule, reset_rules};
+use realm_core::endpoint::QuarantineOpts;
 use std::sync::{Arc, Mutex};
 use std::net::SocketAddr;
 use uuid::Uuid;
 use serde_json::Value;
 
+// tests sharing the global metrics maps run one by one
+static METRICS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
 fn setup_test_app() -> App<impl actix_web::dev::ServiceFactory<
     actix_web::dev::ServiceRequest,
     Config = (),
     Response = actix_web::dev::ServiceResponse,
     Error = actix_web::Error,
+    InitError = (),
 >> {
     App::new()
         .service(list_tcp_connections)
         .service(get_tcp_connection_stats)
         .service(list_udp_associations)
         .service(get_udp_association_stats)
+        .service(get_rule_sources)
+        .service(unquarantine_rule)
 }
 
 #[actix_rt::test]
 async fn test_tcp_stats_endpoints_integration() {
+    let _guard = METRICS_LOCK.lock().await;
     TCP_CONNECTION_METRICS.clear();
 
     let conn_id1 = Uuid::new_v4().to_string();
     let metrics1 = Arc::new(Mutex::new(ConnectionMetrics::new()));
     metrics1.lock().unwrap().update_tx(1000);
     metrics1.lock().unwrap().update_rx(2000);
+    metrics1.lock().unwrap().peer = Some("10.0.0.1:5000".parse().unwrap());
+    metrics1.lock().unwrap().remote = Some("10.0.0.2:443".parse().unwrap());
+    metrics1.lock().unwrap().raddr = Some("b.example:443".to_string());
+    metrics1.lock().unwrap().connect_latency_ms = Some(12);
     // Note: calculate_speed() is not explicitly called here, so speeds might be 0 if no global task runs in test.
     // Uptime will be based on the Instant::now() in ConnectionMetrics::new().
     TCP_CONNECTION_METRICS.insert(conn_id1.clone(), metrics1.clone());
@@ -39,6 +53,8 @@ async fn test_tcp_stats_endpoints_integration() {
     let metrics2 = Arc::new(Mutex::new(ConnectionMetrics::new()));
     metrics2.lock().unwrap().update_tx(3000);
     TCP_CONNECTION_METRICS.insert(conn_id2.clone(), metrics2.clone());
+    // handlers read the snapshot
+    refresh_snapshot();
     
     let srv = test::init_service(setup_test_app()).await;
 
@@ -51,6 +67,19 @@ async fn test_tcp_stats_endpoints_integration() {
     assert_eq!(conn1_data["stats"]["tx_bytes"], 1000);
     assert_eq!(conn1_data["stats"]["rx_bytes"], 2000);
     assert!(conn1_data["stats"]["uptime_seconds"].as_u64().is_some());
+    let started_at = conn1_data["stats"]["started_at"].as_str().unwrap();
+    let started_at = chrono::DateTime::parse_from_rfc3339(started_at).unwrap();
+    assert!((chrono::Utc::now() - started_at.with_timezone(&chrono::Utc)).num_seconds() < 5);
+    assert_eq!(conn1_data["peer"], "10.0.0.1:5000");
+    assert_eq!(conn1_data["remote"], "b.example:443");
+    assert_eq!(conn1_data["remote_addr"], "10.0.0.2:443");
+    assert_eq!(conn1_data["connect_latency_ms"], 12);
+
+    // not known yet
+    let conn2_data = resp_list.iter().find(|x| x["id"] == conn_id2).expect("conn_id2 not found");
+    assert!(conn2_data.get("peer").is_none() && conn2_data.get("remote").is_none());
+    // udp only
+    assert!(conn1_data["stats"].get("tx_packets").is_none());
 
 
     // Test GET /rules/tcp/{conn_id}/stats for conn_id1
@@ -69,6 +98,7 @@ async fn test_tcp_stats_endpoints_integration() {
 
 #[actix_rt::test]
 async fn test_udp_stats_endpoints_integration() {
+    let _guard = METRICS_LOCK.lock().await;
     UDP_ASSOCIATION_METRICS.clear();
 
     let addr1_str = "1.2.3.4:1234";
@@ -76,7 +106,10 @@ async fn test_udp_stats_endpoints_integration() {
     let metrics_udp1 = Arc::new(Mutex::new(ConnectionMetrics::new()));
     metrics_udp1.lock().unwrap().update_tx(500);
     metrics_udp1.lock().unwrap().update_rx(1500);
+    metrics_udp1.lock().unwrap().update_tx_packets(2);
+    metrics_udp1.lock().unwrap().update_rx_packets(3);
     UDP_ASSOCIATION_METRICS.insert(addr1, metrics_udp1.clone());
+    refresh_snapshot();
 
     let srv_udp = test::init_service(setup_test_app()).await;
 
@@ -86,11 +119,14 @@ async fn test_udp_stats_endpoints_integration() {
     assert_eq!(resp_list_udp.len(), 1);
     assert_eq!(resp_list_udp[0]["client_addr"], addr1_str);
     assert_eq!(resp_list_udp[0]["stats"]["tx_bytes"], 500);
+    assert_eq!(resp_list_udp[0]["stats"]["tx_packets"], 2);
+    assert_eq!(resp_list_udp[0]["stats"]["rx_packets"], 3);
 
     // Test GET /rules/udp/{client_addr}/stats for addr1
     let req_addr1 = test::TestRequest::get().uri(&format!("/rules/udp/{}/stats", addr1_str)).to_request();
     let resp_addr1: Value = test::call_and_read_body_json(&srv_udp, req_addr1).await;
     assert_eq!(resp_addr1["tx_bytes"], 500);
+    assert_eq!(resp_addr1["rx_packets"], 3);
 
     // Test with invalid client_addr format
     let req_invalid_addr = test::TestRequest::get().uri("/rules/udp/invalid-addr/stats").to_request();
@@ -99,3 +135,395 @@ async fn test_udp_stats_endpoints_integration() {
     
     UDP_ASSOCIATION_METRICS.clear();
 }
+
+#[actix_rt::test]
+async fn test_rule_sources_endpoint_integration() {
+    let rule = rule_metrics("sources-test");
+    let _a1 = rule.acquire_source("10.0.0.1".parse().unwrap(), 0).unwrap();
+    let _a2 = rule.acquire_source("10.0.0.1".parse().unwrap(), 0).unwrap();
+    let _b1 = rule.acquire_source("10.0.0.2".parse().unwrap(), 0).unwrap();
+    assert!(rule.acquire_source("10.0.0.2".parse().unwrap(), 1).is_none());
+
+    let srv = test::init_service(setup_test_app()).await;
+
+    let req = test::TestRequest::get().uri("/rules/sources-test/sources").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["rejected_per_ip"], 1);
+    assert_eq!(resp["sources"][0]["ip"], "10.0.0.1");
+    assert_eq!(resp["sources"][0]["connections"], 2);
+    assert_eq!(resp["sources"][1]["connections"], 1);
+
+    let req = test::TestRequest::get().uri("/rules/sources-test/sources?limit=1").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["sources"].as_array().unwrap().len(), 1);
+
+    let req = test::TestRequest::get().uri("/rules/no-such-rule/sources").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn test_unquarantine_endpoint_integration() {
+    let rule = rule_metrics("quarantine-test");
+    let opts = QuarantineOpts {
+        threshold: 1,
+        window: 60,
+        cooldown: 60,
+    };
+    assert!(rule.quarantine.lock().unwrap().record_failure(&opts));
+    assert!(rule.is_quarantined());
+
+    let srv = test::init_service(setup_test_app()).await;
+
+    let req = test::TestRequest::post().uri("/rules/quarantine-test/unquarantine").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["was_quarantined"], true);
+    assert!(!rule.is_quarantined());
+
+    let req = test::TestRequest::post().uri("/rules/quarantine-test/unquarantine").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["was_quarantined"], false);
+
+    let req = test::TestRequest::post().uri("/rules/no-such-rule/unquarantine").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "balance")]
+#[actix_rt::test]
+async fn test_balancer_weights_endpoint_integration() {
+    use realm_core::api::patch_balancer_weights;
+    use realm_core::balance::{Balancer, Strategy};
+    use realm_core::monitor::RuleBalancer;
+    use serde_json::json;
+
+    let live = Arc::new(Arc::new(Balancer::new(Strategy::RoundRobin, &[1, 1])).into());
+    let rule = rule_metrics("balancer-test");
+    rule.balancer.store(Some(Arc::new(RuleBalancer {
+        live: Arc::clone(&live),
+        peers: 2,
+        remotes: vec!["a:443".to_string(), "b:443".to_string()],
+    })));
+    let old = live.load_full();
+
+    let srv = test::init_service(App::new().service(patch_balancer_weights)).await;
+
+    // weight count mismatch
+    let req = test::TestRequest::patch()
+        .uri("/rules/balancer-test/balancer/weights")
+        .set_json(json!({ "weights": [1, 2, 3] }))
+        .to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let resp: Value = test::read_body_json(resp).await;
+    assert_eq!(resp["error"], "invalid_weights");
+    assert_eq!(resp["detail"], "Expect 2 weights, got 3");
+
+    let req = test::TestRequest::patch()
+        .uri("/rules/balancer-test/balancer/weights")
+        .set_json(json!({ "weights": [9, 1], "revert_after": 1 }))
+        .to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["strategy"], "roundrobin");
+    assert_eq!(resp["weights"], json!([9, 1]));
+    assert!(!Arc::ptr_eq(&old, &live.load_full()));
+    assert_eq!(live.load().strategy(), Strategy::RoundRobin);
+
+    // reverted
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    assert!(Arc::ptr_eq(&old, &live.load_full()));
+
+    let req = test::TestRequest::patch()
+        .uri("/rules/no-such-rule/balancer/weights")
+        .set_json(json!({ "weights": [1] }))
+        .to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn test_top_connections_endpoint_integration() {
+    let _guard = METRICS_LOCK.lock().await;
+    let tcp_id = Uuid::new_v4().to_string();
+    let mut tcp = ConnectionMetrics::new();
+    tcp.rule_id = Some(String::from("top-test"));
+    tcp.peer = Some("127.0.0.1:30001".parse().unwrap());
+    tcp.upload_speed_bps = 1e9;
+    tcp.update_rx(1);
+    TCP_CONNECTION_METRICS.insert(tcp_id.clone(), Arc::new(Mutex::new(tcp)));
+
+    let udp_addr: SocketAddr = "127.0.0.1:30002".parse().unwrap();
+    let mut udp = ConnectionMetrics::new();
+    udp.upload_speed_bps = 2e9;
+    udp.update_rx(u32::MAX as u64);
+    UDP_ASSOCIATION_METRICS.insert(udp_addr, Arc::new(Mutex::new(udp)));
+    refresh_snapshot();
+
+    let srv = test::init_service(App::new().service(get_top_connections)).await;
+
+    let req = test::TestRequest::get().uri("/stats/top?n=2").to_request();
+    let resp: Vec<Value> = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp.len(), 2);
+    assert_eq!(resp[0]["protocol"], "udp");
+    assert_eq!(resp[0]["id"], udp_addr.to_string());
+    assert_eq!(resp[1]["protocol"], "tcp");
+    assert_eq!(resp[1]["rule"], "top-test");
+    assert_eq!(resp[1]["peer"], "127.0.0.1:30001");
+
+    let req = test::TestRequest::get().uri("/stats/top?by=rx_bytes&n=1").to_request();
+    let resp: Vec<Value> = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp.len(), 1);
+    assert_eq!(resp[0]["id"], udp_addr.to_string());
+
+    let req = test::TestRequest::get().uri("/stats/top?by=nothing").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+    TCP_CONNECTION_METRICS.remove(&tcp_id);
+    UDP_ASSOCIATION_METRICS.remove(&udp_addr);
+}
+
+#[actix_rt::test]
+async fn test_metrics_endpoint_integration() {
+    let _guard = METRICS_LOCK.lock().await;
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+
+    let mut metrics = ConnectionMetrics::new();
+    metrics.rule_id = Some("web \"v2\"".to_string());
+    metrics.update_tx(1000);
+    metrics.update_rx(2000);
+    TCP_CONNECTION_METRICS.insert("conn1".to_string(), Arc::new(Mutex::new(metrics)));
+
+    let client_addr: SocketAddr = "[::1]:5000".parse().unwrap();
+    UDP_ASSOCIATION_METRICS.insert(client_addr, Arc::new(Mutex::new(ConnectionMetrics::new())));
+    refresh_snapshot();
+
+    let srv = test::init_service(App::new().service(get_metrics)).await;
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = test::call_and_read_body(&srv, req).await;
+    let body = std::str::from_utf8(&body).unwrap();
+
+    assert!(body.contains("# TYPE realm_tx_bytes_total counter"));
+    assert!(body.contains("realm_tx_bytes_total{proto=\"tcp\",rule=\"web \\\"v2\\\"\",conn_id=\"conn1\"} 1000"));
+    assert!(body.contains("realm_rx_bytes_total{proto=\"tcp\",rule=\"web \\\"v2\\\"\",conn_id=\"conn1\"} 2000"));
+    assert!(body.contains("realm_rx_bytes_total{proto=\"udp\",rule=\"\",client_addr=\"[::1]:5000\"} 0"));
+    assert!(body.contains("realm_connection_bytes_bucket{proto=\"tcp\",le=\"+Inf\"}"));
+    assert!(body.contains("# TYPE realm_connection_duration_seconds histogram"));
+
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+    refresh_snapshot();
+}
+
+#[actix_rt::test]
+async fn test_rule_stats_endpoint_integration() {
+    let _guard = METRICS_LOCK.lock().await;
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+    let rule = rule_metrics("stats-rule");
+
+    let new_metrics = |rule: &str, tx: u64, speed: f64| {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.rule_id = Some(rule.to_string());
+        metrics.update_tx(tx);
+        metrics.upload_speed_bps = speed;
+        Arc::new(Mutex::new(metrics))
+    };
+    TCP_CONNECTION_METRICS.insert("conn1".to_string(), new_metrics("stats-rule", 100, 10.0));
+    TCP_CONNECTION_METRICS.insert("conn2".to_string(), new_metrics("stats-rule", 200, 20.0));
+    TCP_CONNECTION_METRICS.insert("conn3".to_string(), new_metrics("other-rule", 400, 40.0));
+    let client_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    UDP_ASSOCIATION_METRICS.insert(client_addr, new_metrics("stats-rule", 300, 30.0));
+    refresh_snapshot();
+
+    let srv = test::init_service(App::new().service(get_rule_stats)).await;
+    let req = test::TestRequest::get().uri("/rules/stats-rule/stats").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["active_connections"], 2);
+    assert_eq!(resp["active_associations"], 1);
+    assert_eq!(resp["tx_bytes"], 600);
+    assert_eq!(resp["rx_bytes"], 0);
+    assert_eq!(resp["avg_upload_speed_bps"], 20.0);
+    assert_eq!(resp["lifetime_tx_bytes"], 0);
+    assert!(resp["last_error"].is_null());
+
+    // kept by the rule once closed
+    let (_, closed) = TCP_CONNECTION_METRICS.remove("conn1").unwrap();
+    closed.lock().unwrap().update_rx(50);
+    rule.add_lifetime(&closed.lock().unwrap());
+    refresh_snapshot();
+    let req = test::TestRequest::get().uri("/rules/stats-rule/stats").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["active_connections"], 1);
+    assert_eq!(resp["tx_bytes"], 500);
+    assert_eq!(resp["lifetime_tx_bytes"], 100);
+    assert_eq!(resp["lifetime_rx_bytes"], 50);
+
+    rule.record_error("connection refused");
+    rule.record_error("connection reset");
+    let req = test::TestRequest::get().uri("/rules/stats-rule/stats").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["last_error"]["message"], "connection reset");
+    assert_eq!(resp["last_error"]["count"], 2);
+    assert!(resp["last_error"]["at"].as_str().unwrap().ends_with('Z'));
+
+    // cleared by a reset
+    rule.reset();
+    let req = test::TestRequest::get().uri("/rules/stats-rule/stats").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["lifetime_tx_bytes"], 0);
+    assert!(resp["last_error"].is_null());
+
+    let req = test::TestRequest::get().uri("/rules/no-such-rule/stats").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+    refresh_snapshot();
+}
+
+#[actix_rt::test]
+async fn test_conditional_get_integration() {
+    let _guard = METRICS_LOCK.lock().await;
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+
+    let metrics = Arc::new(Mutex::new(ConnectionMetrics::new()));
+    TCP_CONNECTION_METRICS.insert("etag-conn".to_string(), metrics.clone());
+    refresh_snapshot();
+
+    let srv = test::init_service(setup_test_app()).await;
+    let req = test::TestRequest::get().uri("/rules/tcp").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    let etag = resp.headers().get("etag").unwrap().clone();
+    assert!(etag.to_str().unwrap().starts_with("W/\""));
+
+    // unchanged
+    refresh_snapshot();
+    let req = test::TestRequest::get()
+        .uri("/rules/tcp")
+        .insert_header(("if-none-match", etag.clone()))
+        .to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers().get("etag"), Some(&etag));
+    assert!(test::read_body(resp).await.is_empty());
+
+    // changed
+    metrics.lock().unwrap().update_tx(10);
+    refresh_snapshot();
+    let req = test::TestRequest::get()
+        .uri("/rules/tcp")
+        .insert_header(("if-none-match", etag.clone()))
+        .to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::OK);
+    assert_ne!(resp.headers().get("etag"), Some(&etag));
+
+    TCP_CONNECTION_METRICS.clear();
+    refresh_snapshot();
+}
+
+#[actix_rt::test]
+async fn test_reset_endpoints_integration() {
+    let _guard = METRICS_LOCK.lock().await;
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+    rule_metrics("reset-rule");
+    rule_metrics("other-rule");
+
+    let new_metrics = |rule: &str| {
+        let mut metrics = ConnectionMetrics::new();
+        metrics.rule_id = Some(rule.to_string());
+        metrics.update_tx(100);
+        metrics.update_rx(200);
+        Arc::new(Mutex::new(metrics))
+    };
+    let conn1 = new_metrics("reset-rule");
+    let conn2 = new_metrics("other-rule");
+    let assoc = new_metrics("reset-rule");
+    TCP_CONNECTION_METRICS.insert("conn1".to_string(), conn1.clone());
+    TCP_CONNECTION_METRICS.insert("conn2".to_string(), conn2.clone());
+    UDP_ASSOCIATION_METRICS.insert("127.0.0.1:5000".parse().unwrap(), assoc.clone());
+
+    let srv = test::init_service(App::new().service(reset_rules).service(reset_rule)).await;
+    let req = test::TestRequest::post().uri("/rules/reset-rule/reset").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["id"], "reset-rule");
+    assert_eq!(resp["reset"], 2);
+    assert_eq!(conn1.lock().unwrap().traffic.tx_bytes, 0);
+    assert_eq!(assoc.lock().unwrap().traffic.rx_bytes, 0);
+    assert_eq!(conn2.lock().unwrap().traffic.tx_bytes, 100);
+
+    // still alive, and counted from zero
+    conn1.lock().unwrap().update_tx(10);
+    refresh_snapshot();
+    assert_eq!(realm_core::monitor::metrics_snapshot().tcp["conn1"].traffic.tx_bytes, 10);
+
+    let req = test::TestRequest::post().uri("/rules/reset").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["reset"], 3);
+    assert_eq!(conn2.lock().unwrap().traffic.tx_bytes, 0);
+
+    let req = test::TestRequest::post().uri("/rules/no-such-rule/reset").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+    TCP_CONNECTION_METRICS.clear();
+    UDP_ASSOCIATION_METRICS.clear();
+    refresh_snapshot();
+}
+
+#[cfg(feature = "balance")]
+#[actix_rt::test]
+async fn test_peer_enable_endpoint_integration() {
+    use realm_core::api::{disable_peer, enable_peer, get_rule_health};
+    use realm_core::balance::{Balancer, Strategy, Token};
+    use realm_core::monitor::RuleBalancer;
+
+    let live = Arc::new(Arc::new(Balancer::new(Strategy::RoundRobin, &[1, 1])).into());
+    let rule = rule_metrics("peer-enable-test");
+    rule.balancer.store(Some(Arc::new(RuleBalancer {
+        live,
+        peers: 2,
+        remotes: vec!["a:443".to_string(), "b:443".to_string()],
+    })));
+
+    let srv = test::init_service(
+        App::new()
+            .service(disable_peer)
+            .service(enable_peer)
+            .service(get_rule_health),
+    )
+    .await;
+
+    let req = test::TestRequest::post().uri("/rules/peer-enable-test/peers/0/disable").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["remote"], "a:443");
+    assert_eq!(resp["enabled"], false);
+    assert_eq!(resp["changed"], true);
+    assert!(!rule.peer_health.is_enabled(Token(0)));
+
+    let req = test::TestRequest::get().uri("/rules/peer-enable-test/health").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["peers"][0]["enabled"], false);
+    assert_eq!(resp["peers"][0]["healthy"], false);
+    assert_eq!(resp["peers"][1]["healthy"], true);
+
+    let req = test::TestRequest::post().uri("/rules/peer-enable-test/peers/0/enable").to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["changed"], true);
+    assert!(rule.peer_health.is_enabled(Token(0)));
+
+    // out of range
+    let req = test::TestRequest::post().uri("/rules/peer-enable-test/peers/2/disable").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+    let req = test::TestRequest::post().uri("/rules/no-such-rule/peers/0/disable").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{Codec, Frame, Message};
+use actix_web::web::BytesMut;
+use actix_web::{App, HttpServer};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use realm_core::api::stream_stats;
+use realm_core::monitor::{ConnectionMetrics, TCP_CONNECTION_METRICS, refresh_snapshot};
+
+const HANDSHAKE: &str = "GET /rules/stream HTTP/1.1\r\n\
+    Host: 127.0.0.1\r\n\
+    Connection: Upgrade\r\n\
+    Upgrade: websocket\r\n\
+    Sec-WebSocket-Version: 13\r\n\
+    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+async fn next_frame(stream: &mut TcpStream, codec: &mut Codec, buf: &mut BytesMut) -> Frame {
+    loop {
+        if let Some(frame) = codec.decode(buf).unwrap() {
+            return frame;
+        }
+        let n = timeout(Duration::from_secs(3), stream.read_buf(buf)).await.unwrap().unwrap();
+        assert_ne!(n, 0, "closed before a frame");
+    }
+}
+
+fn text(frame: Frame) -> Value {
+    match frame {
+        Frame::Text(x) => serde_json::from_slice(&x).unwrap(),
+        x => panic!("expect text, got {:?}", x),
+    }
+}
+
+#[actix_rt::test]
+async fn stream_stats_on_refresh() {
+    let server = HttpServer::new(|| App::new().service(stream_stats))
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let addr = server.addrs()[0];
+    actix_rt::spawn(server.run());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(HANDSHAKE.as_bytes()).await.unwrap();
+
+    // skip the response head
+    let mut buf = BytesMut::new();
+    let head_end = loop {
+        stream.read_buf(&mut buf).await.unwrap();
+        if let Some(i) = buf.windows(4).position(|x| x == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    assert!(head.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="), "{}", head);
+    let _ = buf.split_to(head_end);
+
+    // the current snapshot is pushed at once
+    let mut codec = Codec::new().client_mode();
+    let msg = text(next_frame(&mut stream, &mut codec, &mut buf).await);
+    assert_eq!(msg["tcp"].as_array().unwrap().len(), 0);
+
+    // then pushed on each refresh
+    let metrics = Arc::new(Mutex::new(ConnectionMetrics::new()));
+    metrics.lock().unwrap().update_tx(100);
+    TCP_CONNECTION_METRICS.insert("stream-test".to_string(), metrics);
+    refresh_snapshot();
+    let msg = text(next_frame(&mut stream, &mut codec, &mut buf).await);
+    assert_eq!(msg["tcp"][0]["id"], "stream-test");
+    assert_eq!(msg["tcp"][0]["stats"]["tx_bytes"], 100);
+
+    // ping and close
+    let mut wbuf = BytesMut::new();
+    codec.encode(Message::Ping("x".into()), &mut wbuf).unwrap();
+    codec.encode(Message::Close(None), &mut wbuf).unwrap();
+    stream.write_all(&wbuf).await.unwrap();
+    assert!(matches!(next_frame(&mut stream, &mut codec, &mut buf).await, Frame::Pong(_)));
+    assert!(matches!(next_frame(&mut stream, &mut codec, &mut buf).await, Frame::Close(_)));
+
+    TCP_CONNECTION_METRICS.remove("stream-test");
+}
@@ -17,6 +17,7 @@ async fn proxy_v2() {
     env_logger::init();
 
     let endpoint1 = Endpoint {
+        id: "endpoint1".to_string(),
         laddr: "127.0.0.1:10000".parse().unwrap(),
         raddr: "127.0.0.1:15000"
             .parse::<SocketAddr>()
@@ -32,9 +33,12 @@ async fn proxy_v2() {
         },
         bind_opts: Default::default(),
         extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
     };
 
     let endpoint2 = Endpoint {
+        id: "endpoint2".to_string(),
         laddr: "127.0.0.1:15000".parse().unwrap(),
         raddr: "127.0.0.1:20000"
             .parse::<SocketAddr>()
@@ -50,6 +54,8 @@ async fn proxy_v2() {
         },
         bind_opts: Default::default(),
         extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
     };
 
     tokio::spawn(run_tcp(endpoint1));
@@ -63,7 +69,7 @@ async fn proxy_v2() {
         let mut buf = vec![0; 32];
 
         for _ in 0..20 {
-            stream.write(b"Ping Ping Ping").await.unwrap();
+            stream.write_all(b"Ping Ping Ping").await.unwrap();
             let n = stream.read(&mut buf).await.unwrap();
             log::debug!("a got: {:?}", std::str::from_utf8(&buf[..n]).unwrap());
             assert_eq!(b"Pong Pong Pong", &buf[..n]);
@@ -80,7 +86,7 @@ async fn proxy_v2() {
             let n = stream.read(&mut buf).await.unwrap();
             log::debug!("b got: {:?}", std::str::from_utf8(&buf[..n]).unwrap());
             assert_eq!(b"Ping Ping Ping", &buf[..n]);
-            stream.write(b"Pong Pong Pong").await.unwrap();
+            stream.write_all(b"Pong Pong Pong").await.unwrap();
         }
     };
 
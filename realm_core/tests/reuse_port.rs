@@ -0,0 +1,97 @@
+#![cfg(unix)]
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::sleep;
+
+use realm_core::tcp::run_tcp;
+use realm_core::udp::run_udp;
+use realm_core::endpoint::{BindOpts, Endpoint, RemoteAddr};
+use realm_core::monitor::{RULE_METRICS, UDP_ASSOCIATION_METRICS};
+
+fn endpoint(id: &str, laddr: SocketAddr, raddr: SocketAddr) -> Endpoint {
+    Endpoint {
+        id: id.to_string(),
+        laddr: laddr.into(),
+        raddr: RemoteAddr::SocketAddr(raddr),
+        conn_opts: Default::default(),
+        bind_opts: BindOpts {
+            reuse_port_workers: 4,
+            ..Default::default()
+        },
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn udp_reuse_port() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    tokio::spawn(run_udp(endpoint("udp-reuse-port", relay, server.local_addr().unwrap())));
+    sleep(Duration::from_millis(500)).await;
+
+    // echo
+    tokio::spawn(async move {
+        let mut buf = vec![0; 64];
+        loop {
+            let (n, peer) = server.recv_from(&mut buf).await.unwrap();
+            server.send_to(&buf[..n], peer).await.unwrap();
+        }
+    });
+
+    let mut clients = Vec::new();
+    for i in 0..32u8 {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&[i; 10], &relay).await.unwrap();
+        clients.push(client);
+    }
+
+    // every client is relayed by one of the workers
+    let mut buf = vec![0; 64];
+    for (i, client) in clients.iter().enumerate() {
+        let (n, peer) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(peer, relay);
+        assert_eq!(&buf[..n], &[i as u8; 10]);
+    }
+
+    // associations of all workers are counted for the endpoint
+    sleep(Duration::from_millis(100)).await;
+    for client in &clients {
+        let laddr = client.local_addr().unwrap();
+        let metrics = UDP_ASSOCIATION_METRICS.get(&laddr).unwrap().value().clone();
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.rule_id.as_deref(), Some("udp-reuse-port"));
+        assert_eq!((metrics.traffic.tx_bytes, metrics.traffic.rx_bytes), (10, 10));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn tcp_reuse_port() {
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    tokio::spawn(run_tcp(endpoint("tcp-reuse-port", relay, server.local_addr().unwrap())));
+    sleep(Duration::from_millis(500)).await;
+
+    // hold all connections
+    let mut clients = Vec::new();
+    let mut accepted = Vec::new();
+    for i in 0..16u8 {
+        let mut client = TcpStream::connect(relay).await.unwrap();
+        client.write_all(&[i]).await.unwrap();
+        let (mut conn, _) = server.accept().await.unwrap();
+        let mut buf = [0; 1];
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[0], i);
+        clients.push(client);
+        accepted.push(conn);
+    }
+
+    let rule = RULE_METRICS.get("tcp-reuse-port").unwrap().value().clone();
+    assert_eq!(rule.connections.load(Ordering::Relaxed), 16);
+}
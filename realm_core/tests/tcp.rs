@@ -7,11 +7,13 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use realm_core::tcp::run_tcp;
 use realm_core::endpoint::{Endpoint, RemoteAddr};
+use realm_core::monitor::rule_metrics;
 
 #[tokio::test]
 async fn tcp() {
     env_logger::init();
     let endpoint = Endpoint {
+        id: "endpoint".to_string(),
         laddr: "127.0.0.1:10000".parse().unwrap(),
         raddr: "127.0.0.1:20000"
             .parse::<SocketAddr>()
@@ -20,6 +22,8 @@ async fn tcp() {
         conn_opts: Default::default(),
         bind_opts: Default::default(),
         extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
     };
 
     tokio::spawn(run_tcp(endpoint));
@@ -31,7 +35,7 @@ async fn tcp() {
         let mut buf = vec![0; 32];
 
         for _ in 0..20 {
-            stream.write(b"Ping Ping Ping").await.unwrap();
+            stream.write_all(b"Ping Ping Ping").await.unwrap();
             let n = stream.read(&mut buf).await.unwrap();
             log::debug!("a got: {:?}", std::str::from_utf8(&buf[..n]).unwrap());
             assert_eq!(b"Pong Pong Pong", &buf[..n]);
@@ -48,9 +52,15 @@ async fn tcp() {
             let n = stream.read(&mut buf).await.unwrap();
             log::debug!("b got: {:?}", std::str::from_utf8(&buf[..n]).unwrap());
             assert_eq!(b"Ping Ping Ping", &buf[..n]);
-            stream.write(b"Pong Pong Pong").await.unwrap();
+            stream.write_all(b"Pong Pong Pong").await.unwrap();
         }
     };
 
     tokio::join!(task1, task2);
+
+    // kept by the rule once closed
+    sleep(Duration::from_millis(200)).await;
+    let rule = rule_metrics("endpoint");
+    assert_eq!(rule.lifetime_tx_bytes.load(std::sync::atomic::Ordering::Relaxed), 280);
+    assert_eq!(rule.lifetime_rx_bytes.load(std::sync::atomic::Ordering::Relaxed), 280);
 }
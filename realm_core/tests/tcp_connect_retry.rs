@@ -0,0 +1,129 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{ConnectOpts, Endpoint, RemoteAddr};
+use realm_core::monitor::rule_metrics;
+
+// an unused local address
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn endpoint(laddr: SocketAddr, raddrs: &[SocketAddr], connect_retries: usize) -> Endpoint {
+    let mut raddrs = raddrs.iter().copied().map(RemoteAddr::SocketAddr);
+    Endpoint {
+        id: format!("retry-{}", laddr),
+        laddr: laddr.into(),
+        raddr: raddrs.next().unwrap(),
+        conn_opts: ConnectOpts {
+            connect_retries,
+            connect_retry_delay: 200,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: raddrs.collect(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    }
+}
+
+async fn ping(client: &mut TcpStream, lis: &TcpListener) {
+    let (mut server, _) = timeout(Duration::from_secs(3), lis.accept()).await.unwrap().unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn tcp_connect_retry() {
+    let (laddr1, laddr2, raddr) = (free_addr(), free_addr(), free_addr());
+    tokio::spawn(run_tcp(endpoint(laddr1, &[raddr], 3)));
+    tokio::spawn(run_tcp(endpoint(laddr2, &[raddr], 0)));
+    sleep(Duration::from_millis(500)).await;
+
+    // closed at once without retries
+    let mut client = TcpStream::connect(laddr2).await.unwrap();
+    let mut buf = [0; 4];
+    let n = timeout(Duration::from_secs(1), client.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+    sleep(Duration::from_millis(100)).await;
+    let rule = rule_metrics(&format!("retry-{}", laddr2));
+    let e = rule.last_error.lock().unwrap().clone().unwrap();
+    assert_eq!(e.count, 1);
+
+    // the remote comes up before the second retry, 200ms + 400ms
+    let mut client = TcpStream::connect(laddr1).await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    let lis = TcpListener::bind(raddr).await.unwrap();
+    ping(&mut client, &lis).await;
+
+    // cleared once connected
+    let mut client = TcpStream::connect(laddr2).await.unwrap();
+    ping(&mut client, &lis).await;
+    assert!(rule.last_error.lock().unwrap().is_none());
+}
+
+#[cfg(feature = "balance")]
+#[tokio::test]
+async fn tcp_connect_retry_next_peer() {
+    use std::sync::Arc;
+    use realm_core::balance::{Balancer, Strategy};
+
+    // the first peer is down
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let laddr = free_addr();
+    let mut endpoint = endpoint(laddr, &[free_addr(), lis.local_addr().unwrap()], 1);
+    let balancer = Balancer::new(Strategy::RoundRobin, &[1, 1]);
+    endpoint.conn_opts.balancer = Arc::new(Arc::new(balancer).into());
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    ping(&mut client, &lis).await;
+}
+
+#[cfg(feature = "balance")]
+#[tokio::test]
+async fn tcp_failover() {
+    use std::sync::Arc;
+    use realm_core::balance::{Balancer, Strategy};
+
+    // the primary is down
+    let lis2 = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let lis3 = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (laddr, raddr1) = (free_addr(), free_addr());
+    let raddrs = [raddr1, lis2.local_addr().unwrap(), lis3.local_addr().unwrap()];
+    let mut endpoint = endpoint(laddr, &raddrs, 0);
+    let balancer = Balancer::new(Strategy::Failover, &[1, 1, 1]);
+    endpoint.conn_opts.balancer = Arc::new(Arc::new(balancer).into());
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    // the standby is tried at once, without retries
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    ping(&mut client, &lis2).await;
+
+    // and kept after the primary is back
+    let lis1 = TcpListener::bind(raddr1).await.unwrap();
+    for _ in 0..3 {
+        let mut client = TcpStream::connect(laddr).await.unwrap();
+        ping(&mut client, &lis2).await;
+    }
+
+    // until it fails
+    drop(lis2);
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    ping(&mut client, &lis3).await;
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    ping(&mut client, &lis3).await;
+    assert!(timeout(Duration::from_millis(200), lis1.accept()).await.is_err());
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "balance")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{ConnectOpts, Endpoint, RemoteAddr};
+use realm_core::monitor::rule_metrics;
+use realm_core::balance::{Balancer, Strategy, Token};
+
+#[tokio::test]
+async fn tcp_health_check() {
+    let free_addr = || std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let laddr = free_addr();
+    let endpoint = Endpoint {
+        id: "health-check".to_string(),
+        laddr: laddr.into(),
+        // nothing listens on it
+        raddr: RemoteAddr::SocketAddr(free_addr()),
+        conn_opts: ConnectOpts {
+            connect_timeout: 1,
+            health_check_interval: 1,
+            balancer: Arc::new(Arc::new(Balancer::new(Strategy::RoundRobin, &[1, 1])).into()),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![RemoteAddr::SocketAddr(lis.local_addr().unwrap())],
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let rule = rule_metrics("health-check");
+    assert!(!rule.peer_health.is_up(Token(0)));
+    assert!(rule.peer_health.is_up(Token(1)));
+
+    // every connection goes to the healthy peer
+    for _ in 0..4 {
+        let _client = TcpStream::connect(laddr).await.unwrap();
+        timeout(Duration::from_millis(500), lis.accept()).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+use realm_core::monitor::{RULE_METRICS, TCP_CONNECTION_METRICS};
+
+#[tokio::test]
+async fn tcp_idle_timeout() {
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "idle-endpoint".to_string(),
+        laddr: relay.into(),
+        raddr: RemoteAddr::SocketAddr(lis.local_addr().unwrap()),
+        conn_opts: ConnectOpts {
+            idle_timeout: 2,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect(relay).await.unwrap();
+    let (mut server, _) = lis.accept().await.unwrap();
+    let mut buf = vec![0; 32];
+
+    // kept open while bytes flow
+    for _ in 0..6 {
+        client.write_all(b"ping").await.unwrap();
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        server.write_all(b"pong!").await.unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"pong!");
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(TCP_CONNECTION_METRICS.len(), 1);
+
+    // then closed once idle
+    let idle = Instant::now();
+    let n = timeout(Duration::from_secs(5), client.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+    assert!(idle.elapsed() >= Duration::from_millis(1500));
+    let n = timeout(Duration::from_secs(1), server.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+    assert!(TCP_CONNECTION_METRICS.is_empty());
+
+    // bytes relayed before closed are counted
+    let rule = RULE_METRICS.get("idle-endpoint").map(|x| x.value().clone()).unwrap();
+    assert_eq!(rule.lifetime_tx_bytes.load(Ordering::Relaxed), 24);
+    assert_eq!(rule.lifetime_rx_bytes.load(Ordering::Relaxed), 30);
+}
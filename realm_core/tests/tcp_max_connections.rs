@@ -0,0 +1,55 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{ConnectOpts, Endpoint, RemoteAddr};
+use realm_core::monitor::rule_metrics;
+
+#[tokio::test]
+async fn tcp_max_connections() {
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "max-connections".to_string(),
+        laddr: relay.into(),
+        raddr: RemoteAddr::SocketAddr(lis.local_addr().unwrap()),
+        conn_opts: ConnectOpts {
+            max_connections: 1,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client1 = TcpStream::connect(relay).await.unwrap();
+    let (mut server1, _) = lis.accept().await.unwrap();
+    client1.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server1.read_exact(&mut buf).await.unwrap();
+
+    // refused while the first one is alive
+    let mut client2 = TcpStream::connect(relay).await.unwrap();
+    assert_eq!(client2.read(&mut buf).await.unwrap(), 0);
+    assert!(timeout(Duration::from_millis(200), lis.accept()).await.is_err());
+
+    let rule = rule_metrics("max-connections");
+    assert_eq!(rule.connections.load(Ordering::Relaxed), 1);
+    assert_eq!(rule.rejected_max_connections.load(Ordering::Relaxed), 1);
+
+    drop(client1);
+    drop(server1);
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(rule.connections.load(Ordering::Relaxed), 0);
+
+    let _client3 = TcpStream::connect(relay).await.unwrap();
+    lis.accept().await.unwrap();
+}
@@ -0,0 +1,82 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+// accept one client with username/password auth,
+// and connect it to the requested ipv4 address
+async fn mock_socks5(lis: TcpListener) -> SocketAddr {
+    let (mut client, _) = lis.accept().await.unwrap();
+
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, [5, 2, 0, 2]);
+    client.write_all(&[5, 2]).await.unwrap();
+
+    let mut buf = [0; 2];
+    client.read_exact(&mut buf).await.unwrap();
+    let mut username = vec![0; buf[1] as usize];
+    client.read_exact(&mut username).await.unwrap();
+    let len = client.read_u8().await.unwrap();
+    let mut password = vec![0; len as usize];
+    client.read_exact(&mut password).await.unwrap();
+    assert_eq!((username.as_slice(), password.as_slice()), (&b"user"[..], &b"pass"[..]));
+    client.write_all(&[1, 0]).await.unwrap();
+
+    let mut buf = [0; 10];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf[..4], [5, 1, 0, 1]);
+    let ip = [buf[4], buf[5], buf[6], buf[7]];
+    let target = SocketAddr::from((ip, u16::from_be_bytes([buf[8], buf[9]])));
+    let mut remote = TcpStream::connect(target).await.unwrap();
+    client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).await.unwrap();
+
+    tokio::spawn(async move {
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut remote).await;
+    });
+    target
+}
+
+#[tokio::test]
+async fn tcp_socks5() {
+    let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (paddr, raddr) = (proxy.local_addr().unwrap(), server.local_addr().unwrap());
+    let laddr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "socks5-endpoint".to_string(),
+        laddr: laddr.into(),
+        raddr: RemoteAddr::SocketAddr(raddr),
+        conn_opts: ConnectOpts {
+            socks5_proxy: Some(format!("user:pass@{}", paddr).parse().unwrap()),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    let proxy = tokio::spawn(mock_socks5(proxy));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+
+    // tunneled by the proxy
+    let (mut stream, _) = server.accept().await.unwrap();
+    assert_eq!(proxy.await.unwrap(), raddr);
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    stream.write_all(b"pong").await.unwrap();
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+}
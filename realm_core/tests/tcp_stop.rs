@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp_with_stop;
+use realm_core::shutdown::Stop;
+use realm_core::endpoint::{Endpoint, RemoteAddr};
+
+#[tokio::test]
+async fn tcp_stop() {
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let laddr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "stop-endpoint".to_string(),
+        laddr: laddr.into(),
+        raddr: RemoteAddr::SocketAddr(lis.local_addr().unwrap()),
+        conn_opts: Default::default(),
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    let stop = Stop::new();
+    let relay = tokio::spawn(run_tcp_with_stop(endpoint, stop.clone()));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    let (mut server, _) = lis.accept().await.unwrap();
+
+    // listener is closed once stopped
+    stop.stop().await;
+    assert!(TcpStream::connect(laddr).await.is_err());
+
+    // established connection still works
+    let mut buf = [0; 4];
+    client.write_all(b"ping").await.unwrap();
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert!(!relay.is_finished());
+
+    // relay finishes along with the connection
+    drop(client);
+    drop(server);
+    timeout(Duration::from_secs(5), relay).await.unwrap().unwrap().unwrap();
+}
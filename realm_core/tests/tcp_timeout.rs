@@ -0,0 +1,56 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio::io::AsyncReadExt;
+
+use realm_core::tcp::run_tcp;
+use realm_core::time::set_connect_timeout;
+use realm_core::endpoint::{Endpoint, RemoteAddr};
+use realm_core::realm_syscall::socket2::{Socket, Domain, Type};
+
+#[tokio::test]
+async fn tcp_blackhole_timeout() {
+    env_logger::init();
+    set_connect_timeout(1);
+
+    // a listener that never accepts, once its backlog is full
+    // further syn packets are silently dropped
+    let blackhole = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+    blackhole.bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into()).unwrap();
+    blackhole.listen(0).unwrap();
+    let baddr = blackhole.local_addr().unwrap().as_socket().unwrap();
+
+    let mut fillers = Vec::new();
+    for _ in 0..4 {
+        if let Ok(Ok(stream)) = timeout(Duration::from_millis(200), TcpStream::connect(baddr)).await {
+            fillers.push(stream);
+        }
+    }
+
+    let laddr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    // connect_timeout = 0 falls back to the global default
+    let endpoint = Endpoint {
+        id: "endpoint".to_string(),
+        laddr: laddr.into(),
+        raddr: RemoteAddr::SocketAddr(baddr),
+        conn_opts: Default::default(),
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = vec![0; 32];
+
+    // realm should give up and close the connection
+    let res = timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+    assert!(res.is_ok(), "connection to a blackholed upstream hangs");
+    assert!(!matches!(res, Ok(Ok(n)) if n > 0));
+}
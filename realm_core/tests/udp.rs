@@ -11,6 +11,7 @@ use realm_core::endpoint::{Endpoint, RemoteAddr};
 async fn udp() {
     env_logger::init();
     let endpoint = Endpoint {
+        id: "endpoint".to_string(),
         laddr: "127.0.0.1:10000".parse().unwrap(),
         raddr: "127.0.0.1:20000"
             .parse::<SocketAddr>()
@@ -19,6 +20,8 @@ async fn udp() {
         conn_opts: Default::default(),
         bind_opts: Default::default(),
         extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
     };
 
     tokio::spawn(run_udp(endpoint));
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use actix_web::{test, App};
+use serde_json::Value;
+use tokio::net::UdpSocket;
+use tokio::time::sleep;
+
+use realm_core::api::close_udp_association;
+use realm_core::udp::run_udp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+use realm_core::monitor::UDP_ASSOCIATION_METRICS;
+
+#[actix_rt::test]
+async fn udp_close() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "close-endpoint".to_string(),
+        laddr: relay.into(),
+        raddr: RemoteAddr::SocketAddr(server.local_addr().unwrap()),
+        conn_opts: ConnectOpts {
+            associate_timeout: 30,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_udp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let laddr = client.local_addr().unwrap();
+    let mut buf = vec![0; 64];
+    client.send_to(&[1; 10], relay).await.unwrap();
+    let (_, peer) = server.recv_from(&mut buf).await.unwrap();
+    assert!(UDP_ASSOCIATION_METRICS.contains_key(&laddr));
+
+    let srv = test::init_service(App::new().service(close_udp_association)).await;
+    let req = test::TestRequest::post()
+        .uri(&format!("/rules/udp/{}/close", laddr))
+        .to_request();
+    let resp: Value = test::call_and_read_body_json(&srv, req).await;
+    assert_eq!(resp["client_addr"], laddr.to_string());
+    assert_eq!(resp["closed"], true);
+
+    // torn down before the timeout
+    sleep(Duration::from_millis(200)).await;
+    assert!(!UDP_ASSOCIATION_METRICS.contains_key(&laddr));
+    let req = test::TestRequest::post()
+        .uri(&format!("/rules/udp/{}/close", laddr))
+        .to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+
+    // the client is not blocked, and associates again
+    client.send_to(&[2; 10], relay).await.unwrap();
+    let (n, new_peer) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 10);
+    assert_ne!(new_peer, peer);
+    assert!(UDP_ASSOCIATION_METRICS.contains_key(&laddr));
+
+    let req = test::TestRequest::post().uri("/rules/udp/x/close").to_request();
+    let resp = test::call_service(&srv, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+}
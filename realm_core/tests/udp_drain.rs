@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+
+use realm_core::udp::run_udp;
+use realm_core::shutdown::begin_drain;
+use realm_core::endpoint::{Endpoint, RemoteAddr};
+
+#[tokio::test]
+async fn udp_drain() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "drain-endpoint".to_string(),
+        laddr: relay.into(),
+        raddr: RemoteAddr::SocketAddr(server.local_addr().unwrap()),
+        conn_opts: Default::default(),
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_udp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let client1 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = vec![0; 32];
+
+    client1.send_to(b"ping1", relay).await.unwrap();
+    let (n, peer1) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping1");
+
+    assert!(begin_drain(Duration::from_secs(30)));
+
+    // no new association
+    client2.send_to(b"ping2", relay).await.unwrap();
+    assert!(timeout(Duration::from_millis(500), server.recv_from(&mut buf)).await.is_err());
+
+    // while the established one still works
+    client1.send_to(b"ping1", relay).await.unwrap();
+    let (n, _) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping1");
+    server.send_to(b"pong1", peer1).await.unwrap();
+    let (n, _) = client1.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"pong1");
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+
+use realm_core::udp::run_udp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+use realm_core::monitor::UDP_ASSOCIATION_METRICS;
+
+#[tokio::test]
+async fn udp_max_bytes() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "max-bytes-endpoint".to_string(),
+        laddr: relay.into(),
+        raddr: RemoteAddr::SocketAddr(server.local_addr().unwrap()),
+        conn_opts: ConnectOpts {
+            associate_timeout: 5,
+            max_bytes: 100,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_udp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let laddr = client.local_addr().unwrap();
+    let mut buf = vec![0; 64];
+
+    // 40 + 40 bytes
+    client.send_to(&[1; 40], &relay).await.unwrap();
+    let (n, peer) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 40);
+    server.send_to(&[2; 40], peer).await.unwrap();
+    let (n, _) = client.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 40);
+    assert!(UDP_ASSOCIATION_METRICS.contains_key(&laddr));
+
+    // the packet crossing the limit is still relayed
+    client.send_to(&[3; 40], &relay).await.unwrap();
+    let (n, _) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 40);
+
+    // then the association is torn down
+    sleep(Duration::from_millis(200)).await;
+    assert!(!UDP_ASSOCIATION_METRICS.contains_key(&laddr));
+
+    // and packets from the client are dropped
+    client.send_to(&[4; 40], &relay).await.unwrap();
+    assert!(timeout(Duration::from_millis(500), server.recv_from(&mut buf)).await.is_err());
+    server.send_to(&[5; 40], peer).await.unwrap();
+    assert!(timeout(Duration::from_millis(500), client.recv_from(&mut buf)).await.is_err());
+
+    // other clients are not affected
+    let client2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client2.send_to(&[6; 40], &relay).await.unwrap();
+    let (n, _) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(n, 40);
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+
+use realm_core::udp::run_udp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+use realm_core::monitor::RULE_METRICS;
+
+#[tokio::test]
+async fn udp_pending() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let endpoint = Endpoint {
+        id: "pending-endpoint".to_string(),
+        laddr: relay.into(),
+        raddr: RemoteAddr::SocketAddr(server.local_addr().unwrap()),
+        conn_opts: ConnectOpts {
+            associate_timeout: 5,
+            associate_concurrency: 1,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    };
+
+    tokio::spawn(run_udp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let client1 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let client2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = vec![0; 32];
+
+    // the first association is pending until the server replies
+    client1.send_to(b"ping1", &relay).await.unwrap();
+    let (n, peer1) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping1");
+
+    // so the second one is dropped
+    client2.send_to(b"ping2", &relay).await.unwrap();
+    assert!(timeout(Duration::from_millis(500), server.recv_from(&mut buf)).await.is_err());
+    let rule = RULE_METRICS.get("pending-endpoint").unwrap().value().clone();
+    assert_eq!(rule.associate_dropped.load(Ordering::Relaxed), 1);
+
+    // established
+    server.send_to(b"pong1", peer1).await.unwrap();
+    let (n, _) = client1.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"pong1");
+
+    client2.send_to(b"ping2", &relay).await.unwrap();
+    let (n, _) = server.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping2");
+}
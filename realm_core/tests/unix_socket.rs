@@ -0,0 +1,97 @@
+#![cfg(unix)]
+
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, LocalAddr, RemoteAddr};
+use realm_core::monitor::TCP_CONNECTION_METRICS;
+
+fn endpoint(id: &str, laddr: LocalAddr, raddr: RemoteAddr) -> Endpoint {
+    Endpoint {
+        id: id.to_string(),
+        laddr,
+        raddr,
+        conn_opts: Default::default(),
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+        tcp_raddr: None,
+        udp_raddr: None,
+    }
+}
+
+fn socket_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("realm-{}-{}.sock", name, std::process::id()))
+}
+
+async fn echo_once<A, B>(client: &mut A, server: &mut B)
+where
+    A: AsyncReadExt + AsyncWriteExt + Unpin,
+    B: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    let mut buf = vec![0; 32];
+    client.write_all(b"ping").await.unwrap();
+    let n = server.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+    server.write_all(b"pong").await.unwrap();
+    let n = client.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"pong");
+}
+
+#[tokio::test]
+async fn tcp_to_unix_socket() {
+    let path = socket_path("remote");
+    let _ = std::fs::remove_file(&path);
+    let lis = UnixListener::bind(&path).unwrap();
+
+    let raddr = RemoteAddr::UnixSocket(path.clone());
+    let laddr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    tokio::spawn(run_tcp(endpoint("tcp-to-unix", laddr.into(), raddr)));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect(laddr).await.unwrap();
+    let (mut server, _) = lis.accept().await.unwrap();
+    echo_once(&mut client, &mut server).await;
+
+    // the peer is known, while the remote has no socket address
+    let metrics = TCP_CONNECTION_METRICS
+        .iter()
+        .map(|x| x.value().lock().unwrap().clone())
+        .find(|x| x.rule_id.as_deref() == Some("tcp-to-unix"))
+        .unwrap();
+    assert_eq!(metrics.peer, Some(client.local_addr().unwrap()));
+    assert_eq!(metrics.remote, None);
+    assert_eq!(metrics.raddr, Some(format!("unix:{}", path.display())));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn unix_socket_to_tcp() {
+    let path = socket_path("listen");
+    // a stale socket file is replaced
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(Path::new(&path).exists());
+
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let laddr = format!("unix:{}", path.display()).parse().unwrap();
+    let raddr = RemoteAddr::SocketAddr(lis.local_addr().unwrap());
+    tokio::spawn(run_tcp(endpoint("unix-to-tcp", laddr, raddr)));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = UnixStream::connect(&path).await.unwrap();
+    let (mut server, _) = lis.accept().await.unwrap();
+    echo_once(&mut client, &mut server).await;
+
+    let metrics = TCP_CONNECTION_METRICS
+        .iter()
+        .map(|x| x.value().lock().unwrap().clone())
+        .find(|x| x.rule_id.as_deref() == Some("unix-to-tcp"))
+        .unwrap();
+    assert_eq!(metrics.peer, None);
+    assert_eq!(metrics.remote, Some(server.local_addr().unwrap()));
+}
@@ -0,0 +1,20 @@
+#include <cstdio>
+#include "../realm.h"
+
+
+uint32_t realm_first_pkt_len()
+{
+    return 0;
+};
+
+
+int32_t realm_decide_remote_idx(int32_t idx, const char *buf)
+{
+    return 0;
+};
+
+
+int32_t realm_connection_tags(const char *buf, uint32_t len, char *tags, uint32_t cap)
+{
+    return snprintf(tags, cap, "user=anonymous\n");
+};
@@ -18,6 +18,13 @@ uint32_t realm_first_pkt_len();
 // idx = 0 means **default**.
 int32_t realm_decide_remote_idx(int32_t max_remote_idx, const char *pkt);
 
+// [Optional] Attach tags to an accepted connection,
+// which appear in connection stats and logs.
+//
+// Write `key=value` lines to `tags` (at most `cap` bytes),
+// return the number of bytes written, <= 0 means no tag.
+int32_t realm_connection_tags(const char *pkt, uint32_t pkt_len, char *tags, uint32_t cap);
+
 
 
 #ifdef __cplusplus
@@ -6,6 +6,8 @@
 //!
 //! [`decide_remote_idx`](pre_conn::decide_remote_idx)
 //!
+//! [`connection_tags`](pre_conn::connection_tags) (optional)
+//!
 
 pub mod pre_conn;
 
@@ -1,3 +1,5 @@
+#![allow(static_mut_refs)]
+
 //! Pre-connect hook.
 
 use once_cell::unsync::OnceCell;
@@ -28,6 +30,29 @@ pub fn first_pkt_len() -> u32 {
     call_ffi!(DYLIB, b"realm_first_pkt_len" => unsafe extern "C" fn() -> u32)
 }
 
+/// Max length of connection tags.
+pub const MAX_TAGS_LEN: usize = 1024;
+
+/// Get the tags of a connection, which is optional.
+///
+/// Tags are written as `key=value` lines,
+/// `None` if the symbol is not exported, or no tag is written.
+pub fn connection_tags(buf: &[u8]) -> Option<String> {
+    type TagsFn = unsafe extern "C" fn(*const u8, u32, *mut u8, u32) -> i32;
+
+    let mut tags = vec![0u8; MAX_TAGS_LEN];
+    let n = unsafe {
+        let fp = DYLIB.get()?.get::<TagsFn>(b"realm_connection_tags").ok()?;
+        fp(buf.as_ptr(), buf.len() as u32, tags.as_mut_ptr(), MAX_TAGS_LEN as u32)
+    };
+
+    if n <= 0 {
+        return None;
+    }
+    tags.truncate((n as usize).min(MAX_TAGS_LEN));
+    String::from_utf8(tags).ok()
+}
+
 /// Get the index of the selected remote peer.
 ///
 /// Remote peers are defined in `remote`(default) and `extra_remotes`(extended),
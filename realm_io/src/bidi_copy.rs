@@ -2,6 +2,7 @@ use std::io::Result;
 use std::pin::Pin;
 use std::task::{Context, Poll, ready};
 use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -13,6 +14,16 @@ enum TransferState<B, SR, SW> {
     Done(u64),
 }
 
+impl<B, SR, SW> TransferState<B, SR, SW> {
+    // bytes written so far
+    const fn amount(&self) -> u64 {
+        match self {
+            TransferState::Running(buf) => buf.amt,
+            TransferState::ShuttingDown(count) | TransferState::Done(count) => *count,
+        }
+    }
+}
+
 fn transfer<B, SL, SR>(
     cx: &mut Context<'_>,
     state: &mut TransferState<B, SL, SR>,
@@ -88,9 +99,12 @@ where
     b: &'a mut <CopyBuffer<B, SL, SR> as AsyncIOBuf>::StreamW,
     a_to_b: TransferState<B, SL, SR>,
     b_to_a: TransferState<B, SR, SL>,
+    close_on_b_eof: bool,
+    // bytes written in each direction, stored after each poll
+    counted: Option<&'a [AtomicU64; 2]>,
 }
 
-impl<'a, B, SL, SR> Future for BidiCopy<'a, B, SL, SR>
+impl<B, SL, SR> Future for BidiCopy<'_, B, SL, SR>
 where
     B: Unpin,
     SL: AsyncRead + AsyncWrite + Unpin,
@@ -102,28 +116,36 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         // Unpack self into mut refs to each field to avoid borrow check issues.
-        let BidiCopy { a, b, a_to_b, b_to_a } = self.get_mut();
-
-        let a_to_b = transfer(cx, a_to_b, a, b)?;
-        let b_to_a = transfer2::<B, SL, SR>(cx, b_to_a, b, a)?;
-
-        // graceful shutdown
-        #[cfg(not(feature = "brutal-shutdown"))]
-        {
-            let a_to_b = ready!(a_to_b);
-            let b_to_a = ready!(b_to_a);
-            Poll::Ready(Ok((a_to_b, b_to_a)))
+        let BidiCopy {
+            a,
+            b,
+            a_to_b: a_to_b_state,
+            b_to_a: b_to_a_state,
+            close_on_b_eof,
+            counted,
+        } = self.get_mut();
+
+        let a_to_b = transfer(cx, a_to_b_state, a, b);
+        if let Some(counted) = counted {
+            counted[0].store(a_to_b_state.amount(), Ordering::Relaxed);
         }
-
-        // brutal shutdown
-        #[cfg(feature = "brutal-shutdown")]
-        {
-            match (a_to_b, b_to_a) {
-                (Poll::Ready(a), Poll::Ready(b)) => Poll::Ready(Ok((a, b))),
-                (Poll::Pending, Poll::Ready(b)) => Poll::Ready(Ok((0, b))),
-                (Poll::Ready(a), Poll::Pending) => Poll::Ready(Ok((a, 0))),
-                _ => Poll::Pending,
-            }
+        let a_to_b = a_to_b?;
+        let b_to_a = transfer2::<B, SL, SR>(cx, b_to_a_state, b, a);
+        if let Some(counted) = counted {
+            counted[1].store(b_to_a_state.amount(), Ordering::Relaxed);
+        }
+        let b_to_a = b_to_a?;
+
+        // graceful shutdown waits for both directions,
+        // brutal shutdown returns once either direction finishes.
+        //
+        // once b finishes, `close_on_b_eof` decides whether to wait for a
+        match (a_to_b, b_to_a) {
+            (Poll::Ready(a), Poll::Ready(b)) => Poll::Ready(Ok((a, b))),
+            (Poll::Pending, Poll::Ready(b)) if *close_on_b_eof => Poll::Ready(Ok((a_to_b_state.amount(), b))),
+            #[cfg(feature = "brutal-shutdown")]
+            (Poll::Ready(a), Poll::Pending) => Poll::Ready(Ok((a, b_to_a_state.amount()))),
+            _ => Poll::Pending,
         }
     }
 }
@@ -135,6 +157,29 @@ pub async fn bidi_copy_buf<B, SR, SW>(
     a_to_b_buf: CopyBuffer<B, SR, SW>,
     b_to_a_buf: CopyBuffer<B, SW, SR>,
 ) -> Result<(u64, u64)>
+where
+    B: Unpin,
+    SR: AsyncRead + AsyncWrite + Unpin,
+    SW: AsyncRead + AsyncWrite + Unpin,
+    CopyBuffer<B, SR, SW>: AsyncIOBuf,
+    CopyBuffer<B, SW, SR>: AsyncIOBuf,
+{
+    bidi_copy_buf_with(a, b, a_to_b_buf, b_to_a_buf, cfg!(feature = "brutal-shutdown")).await
+}
+
+/// Copy data bidirectionally between two streams with provided buffer.
+///
+/// Once `b` reaches EOF and the EOF has been passed to `a`, return immediately
+/// if `close_on_b_eof` is set, otherwise wait for the `a` to `b` direction.
+/// This takes precedence over the `brutal-shutdown` feature, which
+/// still applies to EOF from `a`.
+pub async fn bidi_copy_buf_with<B, SR, SW>(
+    a: &mut <CopyBuffer<B, SR, SW> as AsyncIOBuf>::StreamR,
+    b: &mut <CopyBuffer<B, SR, SW> as AsyncIOBuf>::StreamW,
+    a_to_b_buf: CopyBuffer<B, SR, SW>,
+    b_to_a_buf: CopyBuffer<B, SW, SR>,
+    close_on_b_eof: bool,
+) -> Result<(u64, u64)>
 where
     B: Unpin,
     SR: AsyncRead + AsyncWrite + Unpin,
@@ -145,5 +190,46 @@ where
     let a_to_b = TransferState::Running(a_to_b_buf);
     let b_to_a = TransferState::Running(b_to_a_buf);
 
-    BidiCopy { a, b, a_to_b, b_to_a }.await
+    BidiCopy {
+        a,
+        b,
+        a_to_b,
+        b_to_a,
+        close_on_b_eof,
+        counted: None,
+    }
+    .await
+}
+
+/// Same as [`bidi_copy_buf_with`], and store the bytes written
+/// from `a` to `b` and from `b` to `a` in `counted` while copying.
+///
+/// The counts are kept even if the copy is dropped or fails halfway.
+pub async fn bidi_copy_buf_counted<B, SR, SW>(
+    a: &mut <CopyBuffer<B, SR, SW> as AsyncIOBuf>::StreamR,
+    b: &mut <CopyBuffer<B, SR, SW> as AsyncIOBuf>::StreamW,
+    a_to_b_buf: CopyBuffer<B, SR, SW>,
+    b_to_a_buf: CopyBuffer<B, SW, SR>,
+    close_on_b_eof: bool,
+    counted: &[AtomicU64; 2],
+) -> Result<(u64, u64)>
+where
+    B: Unpin,
+    SR: AsyncRead + AsyncWrite + Unpin,
+    SW: AsyncRead + AsyncWrite + Unpin,
+    CopyBuffer<B, SR, SW>: AsyncIOBuf,
+    CopyBuffer<B, SW, SR>: AsyncIOBuf,
+{
+    let a_to_b = TransferState::Running(a_to_b_buf);
+    let b_to_a = TransferState::Running(b_to_a_buf);
+
+    BidiCopy {
+        a,
+        b,
+        a_to_b,
+        b_to_a,
+        close_on_b_eof,
+        counted: Some(counted),
+    }
+    .await
 }
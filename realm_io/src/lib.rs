@@ -42,13 +42,19 @@
 //! This is helpful when handling connections from a poorly implemented client or server,
 //! which may never shutdown its write side nor close the underlying socket.
 //!
+//! [`bidi_copy_buf_with`] decides the behavior on EOF from the right side at runtime,
+//! regardless of this feature.
+//!
+//! [`bidi_copy_buf_counted`] also reports the bytes copied so far in each
+//! direction, which could be read while copying.
+//!
 
 mod buf;
 mod mem_copy;
 mod bidi_copy;
 
 pub use buf::{AsyncIOBuf, CopyBuffer};
-pub use bidi_copy::bidi_copy_buf;
+pub use bidi_copy::{bidi_copy_buf, bidi_copy_buf_counted, bidi_copy_buf_with};
 pub use mem_copy::{bidi_copy, buf_size, set_buf_size};
 
 #[cfg(target_os = "linux")]
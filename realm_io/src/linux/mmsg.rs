@@ -82,7 +82,7 @@ mod store {
     pub struct Mutable {}
 
     /// # Safety: mmsghdr is POD.
-    unsafe impl<'a, 'b, 'iov, 'ctrl, M> Send for MmsgHdrStore<'a, 'b, 'iov, 'ctrl, M> {}
+    unsafe impl<M> Send for MmsgHdrStore<'_, '_, '_, '_, M> {}
 
     /// # Safety: Inner pointers come from references ruled by the borrow-checker,
     /// thereby mutable pointers which point to the same memory address cant co-exist.
@@ -90,7 +90,7 @@ mod store {
     /// We provide a thin and limited interface that neither [`Const`] nor &[`Mutable`]
     /// can have mutable access to the internal data, while restriction of mutable access
     /// behind `&mut`[`Mutable`] will be enforced by the borrow-checker.
-    unsafe impl<'a, 'b, 'iov, 'ctrl, M> Sync for MmsgHdrStore<'a, 'b, 'iov, 'ctrl, M> {}
+    unsafe impl<M> Sync for MmsgHdrStore<'_, '_, '_, '_, M> {}
 
     /// Represent [`libc::mmsghdr`].
     #[repr(C)]
@@ -192,7 +192,7 @@ mod store {
         }
     }
 
-    impl<'a, 'b, 'iov, 'ctrl, M> Default for MmsgHdrStore<'a, 'b, 'iov, 'ctrl, M> {
+    impl<M> Default for MmsgHdrStore<'_, '_, '_, '_, M> {
         fn default() -> Self {
             Self::new()
         }
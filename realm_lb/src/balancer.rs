@@ -2,9 +2,16 @@ use std::net::IpAddr;
 use std::sync::Arc;
 use std::fmt::{Display, Formatter};
 
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
 use crate::{Token, Balance};
+use crate::health::Health;
 use crate::ip_hash::IpHash;
 use crate::round_robin::RoundRobin;
+use crate::least_conn::LeastConn;
+use crate::random::Random;
+use crate::consistent_hash::ConsistentHash;
+use crate::failover::Failover;
 
 /// Balance strategy.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,16 +19,26 @@ pub enum Strategy {
     Off,
     IpHash,
     RoundRobin,
+    LeastConn,
+    Random,
+    ConsistentHash,
+    Failover,
 }
 
-impl From<&str> for Strategy {
-    fn from(s: &str) -> Self {
+impl TryFrom<&str> for Strategy {
+    type Error = ParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
         use Strategy::*;
         match s {
-            "off" => Off,
-            "iphash" => IpHash,
-            "roundrobin" => RoundRobin,
-            _ => panic!("unknown strategy: {}", s),
+            "off" => Ok(Off),
+            "iphash" => Ok(IpHash),
+            "roundrobin" => Ok(RoundRobin),
+            "leastconn" => Ok(LeastConn),
+            "random" => Ok(Random),
+            "consistenthash" => Ok(ConsistentHash),
+            "failover" => Ok(Failover),
+            _ => Err(ParseError::UnknownStrategy(s.to_string())),
         }
     }
 }
@@ -32,22 +49,54 @@ impl Display for Strategy {
             Strategy::Off => write!(f, "off"),
             Strategy::IpHash => write!(f, "iphash"),
             Strategy::RoundRobin => write!(f, "roundrobin"),
+            Strategy::LeastConn => write!(f, "leastconn"),
+            Strategy::Random => write!(f, "random"),
+            Strategy::ConsistentHash => write!(f, "consistenthash"),
+            Strategy::Failover => write!(f, "failover"),
+        }
+    }
+}
+
+/// Error of parsing a balancer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// No `:` between strategy and weights.
+    MissingColon(String),
+    UnknownStrategy(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MissingColon(s) => write!(f, "expect $strategy: $weights, got: {}", s),
+            ParseError::UnknownStrategy(s) => write!(f, "unknown strategy: {}", s),
         }
     }
 }
 
+impl std::error::Error for ParseError {}
+
 /// Balance context to select next peer.
 #[derive(Debug)]
 pub struct BalanceCtx<'a> {
     pub src_ip: &'a IpAddr,
+    // active connections of each peer, indexed by token
+    pub active: &'a [usize],
+    // ejected peers are skipped
+    pub health: &'a Health,
 }
 
 /// Combinated load balancer.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum Balancer {
+    #[default]
     Off,
     IpHash(Arc<IpHash>),
     RoundRobin(Arc<RoundRobin>),
+    LeastConn(Arc<LeastConn>),
+    Random(Arc<Random>),
+    ConsistentHash(Arc<ConsistentHash>),
+    Failover(Arc<Failover>),
 }
 
 impl Balancer {
@@ -57,6 +106,10 @@ impl Balancer {
             Strategy::Off => Self::Off,
             Strategy::IpHash => Self::IpHash(Arc::new(IpHash::new(weights))),
             Strategy::RoundRobin => Self::RoundRobin(Arc::new(RoundRobin::new(weights))),
+            Strategy::LeastConn => Self::LeastConn(Arc::new(LeastConn::new(weights))),
+            Strategy::Random => Self::Random(Arc::new(Random::new(weights))),
+            Strategy::ConsistentHash => Self::ConsistentHash(Arc::new(ConsistentHash::new(weights))),
+            Strategy::Failover => Self::Failover(Arc::new(Failover::new(weights))),
         }
     }
 
@@ -66,6 +119,10 @@ impl Balancer {
             Balancer::Off => Strategy::Off,
             Balancer::IpHash(_) => Strategy::IpHash,
             Balancer::RoundRobin(_) => Strategy::RoundRobin,
+            Balancer::LeastConn(_) => Strategy::LeastConn,
+            Balancer::Random(_) => Strategy::Random,
+            Balancer::ConsistentHash(_) => Strategy::ConsistentHash,
+            Balancer::Failover(_) => Strategy::Failover,
         }
     }
 
@@ -75,37 +132,137 @@ impl Balancer {
             Balancer::Off => 0,
             Balancer::IpHash(iphash) => iphash.total(),
             Balancer::RoundRobin(rr) => rr.total(),
+            Balancer::LeastConn(lc) => lc.total(),
+            Balancer::Random(random) => random.total(),
+            Balancer::ConsistentHash(ch) => ch.total(),
+            Balancer::Failover(fo) => fo.total(),
+        }
+    }
+
+    /// Get weights of peers.
+    pub fn weights(&self) -> &[u8] {
+        match self {
+            Balancer::Off => &[],
+            Balancer::IpHash(iphash) => iphash.weights(),
+            Balancer::RoundRobin(rr) => rr.weights(),
+            Balancer::LeastConn(lc) => lc.weights(),
+            Balancer::Random(random) => random.weights(),
+            Balancer::ConsistentHash(ch) => ch.weights(),
+            Balancer::Failover(fo) => fo.weights(),
         }
     }
 
-    /// Select next peer.
+    /// Select next peer, skip unhealthy ones.
+    /// Fall back to the first enabled peer if all of them are unhealthy,
+    /// or the first peer if all of them are disabled.
     pub fn next(&self, ctx: BalanceCtx) -> Option<Token> {
+        let token = self.pick(&ctx)?;
+        let total = self.total();
+        if total <= 1 || ctx.health.is_healthy(token) {
+            return Some(token);
+        }
+
+        // stateful strategies may give another peer
+        for _ in 1..total {
+            match self.pick(&ctx) {
+                Some(x) if ctx.health.is_healthy(x) => return Some(x),
+                _ => {}
+            }
+        }
+
+        // otherwise fall back to the next healthy peer
+        (1..total)
+            .map(|i| Token(((token.0 as usize + i as usize) % total as usize) as u8))
+            .find(|x| ctx.health.is_healthy(*x))
+            .or_else(|| (0..total).map(Token).find(|x| ctx.health.is_enabled(*x)))
+            .or(Some(Token(0)))
+    }
+
+    /// Select next peer as [`Balancer::next`] does, skip the ones already tried.
+    /// None if all the enabled peers are tried, or balance is off.
+    pub fn next_untried(&self, ctx: BalanceCtx, tried: &[Token]) -> Option<Token> {
+        let total = self.total();
+        let untried = |x: &Token| !tried.contains(x);
+
+        // stateful strategies may give another peer
+        for _ in 0..total {
+            match self.pick(&ctx) {
+                Some(x) if untried(&x) && ctx.health.is_healthy(x) => return Some(x),
+                _ => {}
+            }
+        }
+
+        // otherwise fall back to the next untried one in order
+        let start = tried.last().map_or(0, |x| x.0 as usize + 1);
+        let in_order = || (0..total as usize).map(|i| Token(((start + i) % total as usize) as u8)).filter(untried);
+        in_order()
+            .find(|x| ctx.health.is_healthy(*x))
+            .or_else(|| in_order().find(|x| ctx.health.is_enabled(*x)))
+    }
+
+    fn pick(&self, ctx: &BalanceCtx) -> Option<Token> {
         match self {
             Balancer::Off => Some(Token(0)),
             Balancer::IpHash(iphash) => iphash.next(ctx.src_ip),
             Balancer::RoundRobin(rr) => rr.next(&()),
+            Balancer::LeastConn(lc) => lc.next(ctx.active),
+            Balancer::Random(random) => random.next(&()),
+            Balancer::ConsistentHash(ch) => ch.next(ctx.src_ip),
+            Balancer::Failover(fo) => fo.next(&()),
+        }
+    }
+
+    /// Report the peer connected, which is kept by failover.
+    pub fn connected(&self, token: Token) {
+        if let Balancer::Failover(fo) = self {
+            fo.stick(token);
         }
     }
 
     /// Parse balancer from string.
     /// Format: $strategy: $weight1, $weight2, ...
-    pub fn parse_from_str(s: &str) -> Self {
-        let (strategy, weights) = s.split_once(':').unwrap();
+    pub fn parse_from_str(s: &str) -> Result<Self, ParseError> {
+        let (strategy, weights) = s.split_once(':').ok_or_else(|| ParseError::MissingColon(s.to_string()))?;
 
-        let strategy = Strategy::from(strategy.trim());
+        let strategy = Strategy::try_from(strategy.trim())?;
         let weights: Vec<u8> = weights
             .trim()
             .split(',')
             .filter_map(|s| s.trim().parse().ok())
             .collect();
 
-        Self::new(strategy, &weights)
+        Ok(Self::new(strategy, &weights))
+    }
+
+    /// Format balancer as string, which could be parsed back.
+    /// Format: $strategy: $weight1, $weight2, ...
+    pub fn to_config_string(&self) -> String {
+        let weights: Vec<String> = self.weights().iter().map(|x| x.to_string()).collect();
+        format!("{}: {}", self.strategy(), weights.join(", "))
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl PartialEq for Balancer {
+    fn eq(&self, other: &Self) -> bool {
+        self.strategy() == other.strategy() && self.weights() == other.weights()
+    }
+}
+
+impl Eq for Balancer {}
+
+impl Serialize for Balancer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_config_string())
     }
 }
 
-impl Default for Balancer {
-    fn default() -> Self {
-        Balancer::Off
+impl<'de> Deserialize<'de> for Balancer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let s = String::deserialize(deserializer)?;
+        Self::parse_from_str(&s).map_err(D::Error::custom)
     }
 }
 
@@ -123,7 +280,7 @@ mod tests {
                 s.push_str(&format!("{}, ", weight));
             }
 
-            let balancer = Balancer::parse_from_str(&s);
+            let balancer = Balancer::parse_from_str(&s).unwrap();
 
             println!("balancer: {:?}", balancer);
 
@@ -140,5 +297,181 @@ mod tests {
         run(Strategy::RoundRobin, &[1, 2, 3]);
         run(Strategy::RoundRobin, &[1, 2, 3]);
         run(Strategy::RoundRobin, &[1, 2, 3]);
+        run(Strategy::LeastConn, &[]);
+        run(Strategy::LeastConn, &[1, 2, 3]);
+        run(Strategy::Random, &[]);
+        run(Strategy::Random, &[1, 2, 3]);
+        run(Strategy::ConsistentHash, &[]);
+        run(Strategy::ConsistentHash, &[1, 2, 3]);
+        run(Strategy::Failover, &[]);
+        run(Strategy::Failover, &[1, 1]);
+    }
+
+    #[test]
+    fn skip_ejected() {
+        use std::time::Duration;
+
+        let health = Health::new();
+        let src_ip: IpAddr = "1.1.1.1".parse().unwrap();
+        let ctx = || BalanceCtx {
+            src_ip: &src_ip,
+            active: &[],
+            health: &health,
+        };
+
+        for strategy in [
+            Strategy::IpHash,
+            Strategy::RoundRobin,
+            Strategy::LeastConn,
+            Strategy::Random,
+            Strategy::ConsistentHash,
+            Strategy::Failover,
+        ] {
+            health.reset();
+            let balancer = Balancer::new(strategy, &[1, 1, 1]);
+            let cooldown = Duration::from_secs(60);
+            assert!(health.report_failure(Token(0), 1, cooldown));
+            assert!(health.report_failure(Token(1), 1, cooldown));
+
+            for _ in 0..16 {
+                assert_eq!(balancer.next(ctx()), Some(Token(2)));
+            }
+
+            // all unhealthy
+            assert!(health.set_up(Token(2), false));
+            assert_eq!(balancer.next(ctx()), Some(Token(0)));
+
+            // disabled primary
+            health.reset();
+            assert!(health.set_enabled(Token(0), false));
+            assert!(health.set_up(Token(2), false));
+            for _ in 0..16 {
+                assert_eq!(balancer.next(ctx()), Some(Token(1)));
+            }
+            assert!(health.set_up(Token(1), false));
+            assert_ne!(balancer.next(ctx()), Some(Token(0)));
+        }
+    }
+
+    #[test]
+    fn failover_sticky() {
+        use std::time::Duration;
+
+        let health = Health::new();
+        let src_ip: IpAddr = "1.1.1.1".parse().unwrap();
+        let ctx = || BalanceCtx {
+            src_ip: &src_ip,
+            active: &[],
+            health: &health,
+        };
+
+        let balancer = Balancer::new(Strategy::Failover, &[1, 1, 1]);
+        assert_eq!(balancer.next(ctx()), Some(Token(0)));
+
+        // the primary is ejected, move on in order
+        assert!(health.report_failure(Token(0), 1, Duration::from_secs(60)));
+        assert_eq!(balancer.next(ctx()), Some(Token(1)));
+        balancer.connected(Token(1));
+
+        // kept after the primary is back
+        health.reset();
+        for _ in 0..16 {
+            assert_eq!(balancer.next(ctx()), Some(Token(1)));
+        }
+
+        // until it fails
+        assert!(health.report_failure(Token(1), 1, Duration::from_secs(60)));
+        assert_eq!(balancer.next(ctx()), Some(Token(2)));
+    }
+
+    #[test]
+    fn skip_tried() {
+        use std::time::Duration;
+
+        let health = Health::new();
+        let src_ip: IpAddr = "1.1.1.1".parse().unwrap();
+        let ctx = || BalanceCtx {
+            src_ip: &src_ip,
+            active: &[],
+            health: &health,
+        };
+
+        for strategy in [
+            Strategy::IpHash,
+            Strategy::RoundRobin,
+            Strategy::LeastConn,
+            Strategy::Random,
+            Strategy::ConsistentHash,
+            Strategy::Failover,
+        ] {
+            health.reset();
+            let balancer = Balancer::new(strategy, &[1, 1, 1]);
+            let first = balancer.next(ctx()).unwrap();
+            let second = balancer.next_untried(ctx(), &[first]).unwrap();
+            assert_ne!(first, second);
+            let third = balancer.next_untried(ctx(), &[first, second]).unwrap();
+            assert!(third != first && third != second);
+            assert_eq!(balancer.next_untried(ctx(), &[first, second, third]), None);
+
+            // unhealthy and disabled peers are skipped
+            assert!(health.report_failure(Token(1), 1, Duration::from_secs(60)));
+            assert!(health.set_enabled(Token(2), false));
+            assert_eq!(balancer.next_untried(ctx(), &[Token(0)]), Some(Token(1)));
+            assert_eq!(balancer.next_untried(ctx(), &[Token(0), Token(1)]), None);
+            health.reset();
+            assert!(health.report_failure(Token(1), 1, Duration::from_secs(60)));
+            assert_eq!(balancer.next_untried(ctx(), &[Token(0)]), Some(Token(2)));
+        }
+
+        let balancer = Balancer::new(Strategy::Off, &[]);
+        assert_eq!(balancer.next_untried(ctx(), &[Token(0)]), None);
+    }
+
+    #[test]
+    fn serde_balancer() {
+        fn run(s: &str, expect: &str) {
+            let balancer: Balancer = serde_json::from_str(&format!("\"{}\"", s)).unwrap();
+            let serialized = serde_json::to_string(&balancer).unwrap();
+            assert_eq!(serialized, format!("\"{}\"", expect));
+
+            let deserialized: Balancer = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(deserialized, balancer);
+        }
+
+        run("off:", "off:");
+        run("iphash: 1,2,3", "iphash: 1, 2, 3");
+        run("roundrobin: 4, 2, 1", "roundrobin: 4, 2, 1");
+        run("leastconn: 0, 0", "leastconn: 0, 0");
+        run("random:1", "random: 1");
+        run("random:", "random:");
+
+        assert!(serde_json::from_str::<Balancer>("\"iphash\"").is_err());
+        assert!(serde_json::from_str::<Balancer>("\"iphsah: 1\"").is_err());
+        assert_ne!(
+            Balancer::new(Strategy::IpHash, &[1, 2]),
+            Balancer::new(Strategy::IpHash, &[2, 1])
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        assert_eq!(Strategy::try_from("roundrobin"), Ok(Strategy::RoundRobin));
+        assert_eq!(
+            Strategy::try_from("roundrobn"),
+            Err(ParseError::UnknownStrategy("roundrobn".to_string()))
+        );
+
+        assert_eq!(
+            Balancer::parse_from_str("iphash 1, 2"),
+            Err(ParseError::MissingColon("iphash 1, 2".to_string()))
+        );
+        assert_eq!(
+            Balancer::parse_from_str("ip_hash: 1, 2"),
+            Err(ParseError::UnknownStrategy("ip_hash".to_string()))
+        );
+        assert_eq!(
+            Balancer::parse_from_str(" iphash : 1, 2").map(|x| x.strategy()),
+            Ok(Strategy::IpHash)
+        );
     }
 }
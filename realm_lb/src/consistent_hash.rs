@@ -0,0 +1,169 @@
+use std::net::IpAddr;
+
+use super::{Balance, Token};
+use super::ip_hash::{chash, chash_for_ip};
+
+// virtual nodes for each unit of weight
+const REPLICA: usize = 160;
+
+/// Consistent hash node.
+#[derive(Debug)]
+struct Node {
+    hash: u32,
+    token: Token,
+}
+
+/// Consistent hash (ketama) balancer.
+///
+/// Peers are placed on a hash ring with virtual nodes in proportion to
+/// their weights, so that adding or removing a peer only remaps the
+/// source ips close to its virtual nodes.
+#[derive(Debug)]
+pub struct ConsistentHash {
+    ring: Vec<Node>,
+    weights: Vec<u8>,
+    total: u8,
+}
+
+impl Balance for ConsistentHash {
+    type State = IpAddr;
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn weights(&self) -> &[u8] {
+        &self.weights
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        let mut ring = Vec::with_capacity(weights.iter().map(|x| *x as usize * REPLICA).sum());
+        for (n, weight) in weights.iter().enumerate() {
+            let token = Token(n as u8);
+            for vidx in 0..*weight as usize * REPLICA {
+                let buf = format!("{}-{}", n, vidx);
+                let hash = fmix(chash(buf.as_bytes()));
+                ring.push(Node { hash, token });
+            }
+        }
+
+        ring.sort_unstable_by_key(|node| node.hash);
+
+        Self {
+            ring,
+            weights: weights.to_vec(),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, state: &Self::State) -> Option<Token> {
+        if self.total <= 1 || self.ring.is_empty() {
+            return Some(Token(0));
+        }
+
+        let hash = fmix(match state {
+            IpAddr::V4(x) => chash_for_ip(&x.octets()),
+            IpAddr::V6(x) => chash_for_ip(&x.octets()),
+        });
+
+        // the first node clockwise
+        let idx = self.ring.partition_point(|node| node.hash < hash);
+        let idx = if idx == self.ring.len() { 0 } else { idx };
+
+        Some(self.ring[idx].token)
+    }
+}
+
+// murmur3 finalizer, spread similar keys over the ring
+fn fmix(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ips() -> impl Iterator<Item = IpAddr> {
+        (0..=u32::MAX).step_by(104729).map(Ipv4Addr::from).map(IpAddr::from)
+    }
+
+    #[test]
+    fn ch_same_ip() {
+        let ch = ConsistentHash::new(&[1, 2, 3, 4]);
+        assert_eq!(ch.ring.len(), (1 + 2 + 3 + 4) * REPLICA);
+
+        for ip in ips().take(64) {
+            let token = ch.next(&ip);
+            assert_eq!(ch.next(&ip), token);
+        }
+    }
+
+    #[test]
+    fn ch_all_weights() {
+        let weights: Vec<u8> = (1..=8).collect();
+        let total_weight: f64 = weights.iter().map(|x| *x as f64).sum();
+        let ch = ConsistentHash::new(&weights);
+        let mut distro = [0f64; 8];
+
+        let mut total: usize = 0;
+        for ip in ips() {
+            let token = ch.next(&ip).unwrap();
+            distro[token.0 as usize] += 1.0;
+            total += 1;
+        }
+
+        println!("{:?}", distro);
+        for (i, x) in distro.iter().enumerate() {
+            let expect = (i as f64 + 1.0) / total_weight;
+            assert!((x / total as f64 - expect).abs() < expect * 0.25);
+        }
+    }
+
+    #[test]
+    fn ch_add_peer() {
+        const N: usize = 8;
+
+        let before = ConsistentHash::new(&[1; N]);
+        let after = ConsistentHash::new(&[1; N + 1]);
+
+        let mut total: usize = 0;
+        let mut changed: usize = 0;
+        for ip in ips() {
+            let (x, y) = (before.next(&ip).unwrap(), after.next(&ip).unwrap());
+            total += 1;
+            if x != y {
+                // only moved to the new peer
+                assert_eq!(y, Token(N as u8));
+                changed += 1;
+            }
+        }
+
+        println!("changed: {}/{}", changed, total);
+        assert!(changed > 0);
+        assert!(changed < total / N);
+    }
+
+    #[test]
+    fn ch_remove_peer() {
+        const N: usize = 8;
+
+        let before = ConsistentHash::new(&[1; N]);
+        // the last peer is removed
+        let after = ConsistentHash::new(&[1; N - 1]);
+
+        for ip in ips() {
+            let x = before.next(&ip).unwrap();
+            if x != Token(N as u8 - 1) {
+                assert_eq!(after.next(&ip), Some(x));
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::{Balance, Token};
+
+/// Failover balancer.
+///
+/// Always select the active peer, which is the first one at start.
+/// Once it fails, the next peer in order that connects becomes
+/// the active one, and is kept until it fails as well.
+#[derive(Debug)]
+pub struct Failover {
+    active: AtomicU8,
+    weights: Vec<u8>,
+    total: u8,
+}
+
+impl Failover {
+    /// Keep the peer as the active one.
+    pub fn stick(&self, token: Token) {
+        self.active.store(token.0, Ordering::Relaxed);
+    }
+}
+
+impl Balance for Failover {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn weights(&self) -> &[u8] {
+        &self.weights
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        Self {
+            active: AtomicU8::new(0),
+            weights: weights.to_vec(),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        Some(Token(self.active.load(Ordering::Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fo_stick() {
+        let failover = Failover::new(&[1, 1, 1]);
+        for _ in 0..16 {
+            assert_eq!(failover.next(&()), Some(Token(0)));
+        }
+
+        failover.stick(Token(2));
+        for _ in 0..16 {
+            assert_eq!(failover.next(&()), Some(Token(2)));
+        }
+    }
+}
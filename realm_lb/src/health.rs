@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::Token;
+
+/// Health state of a peer.
+#[derive(Debug, Default)]
+struct Node {
+    // consecutive connect failures
+    failures: AtomicUsize,
+    // ejected until, in millis since epoch, 0 if never ejected
+    until: AtomicU64,
+    // marked down by active checks
+    down: AtomicBool,
+    // disabled manually
+    disabled: AtomicBool,
+}
+
+/// Health state of peers, indexed by token.
+///
+/// Passively, a peer is ejected after `threshold` consecutive connect
+/// failures, and restored once `cooldown` has elapsed.
+/// Actively, a peer is marked up or down by periodic checks.
+/// Besides, a peer can be disabled manually, e.g. for maintenance.
+#[derive(Debug)]
+pub struct Health {
+    epoch: Instant,
+    nodes: Box<[Node]>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    /// Constructor, all peers are healthy.
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            nodes: (0..=u8::MAX).map(|_| Node::default()).collect(),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    /// Check if the peer is enabled, and neither ejected nor down.
+    pub fn is_healthy(&self, token: Token) -> bool {
+        self.is_enabled(token) && self.is_up(token) && !self.is_ejected(token)
+    }
+
+    /// Check if the peer is not disabled manually.
+    pub fn is_enabled(&self, token: Token) -> bool {
+        !self.nodes[token.0 as usize].disabled.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable the peer, return true if changed.
+    pub fn set_enabled(&self, token: Token, enabled: bool) -> bool {
+        self.nodes[token.0 as usize].disabled.swap(!enabled, Ordering::Relaxed) == enabled
+    }
+
+    /// Check if the peer is ejected by connect failures.
+    pub fn is_ejected(&self, token: Token) -> bool {
+        let until = self.nodes[token.0 as usize].until.load(Ordering::Relaxed);
+        until != 0 && self.now() < until
+    }
+
+    /// Check if the peer is not marked down by active checks.
+    pub fn is_up(&self, token: Token) -> bool {
+        !self.nodes[token.0 as usize].down.load(Ordering::Relaxed)
+    }
+
+    /// Mark the peer up or down, return true if changed.
+    pub fn set_up(&self, token: Token, up: bool) -> bool {
+        self.nodes[token.0 as usize].down.swap(!up, Ordering::Relaxed) == up
+    }
+
+    /// Record a successful connect.
+    pub fn report_success(&self, token: Token) {
+        self.nodes[token.0 as usize].failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Record a failed connect, return true if this ejects the peer.
+    ///
+    /// Threshold = 0 means never eject.
+    pub fn report_failure(&self, token: Token, threshold: usize, cooldown: Duration) -> bool {
+        if threshold == 0 || self.is_ejected(token) {
+            return false;
+        }
+
+        let node = &self.nodes[token.0 as usize];
+        if node.failures.fetch_add(1, Ordering::Relaxed) + 1 < threshold {
+            return false;
+        }
+
+        // ejected by another connection
+        if node.failures.swap(0, Ordering::Relaxed) == 0 {
+            return false;
+        }
+
+        let until = self.now() + cooldown.as_millis() as u64;
+        node.until.store(until.max(1), Ordering::Relaxed);
+        true
+    }
+
+    /// Restore all peers.
+    pub fn reset(&self) {
+        for node in self.nodes.iter() {
+            node.failures.store(0, Ordering::Relaxed);
+            node.until.store(0, Ordering::Relaxed);
+            node.down.store(false, Ordering::Relaxed);
+            node.disabled.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hc_eject() {
+        let health = Health::new();
+        let cooldown = Duration::from_secs(60);
+
+        assert!(!health.report_failure(Token(1), 3, cooldown));
+        assert!(!health.report_failure(Token(1), 3, cooldown));
+        assert!(health.report_failure(Token(1), 3, cooldown));
+        assert!(!health.is_healthy(Token(1)));
+        assert!(health.is_healthy(Token(0)));
+
+        // already ejected
+        assert!(!health.report_failure(Token(1), 3, cooldown));
+
+        health.reset();
+        assert!(health.is_healthy(Token(1)));
+    }
+
+    #[test]
+    fn hc_consecutive() {
+        let health = Health::new();
+        let cooldown = Duration::from_secs(60);
+
+        assert!(!health.report_failure(Token(2), 2, cooldown));
+        health.report_success(Token(2));
+        assert!(!health.report_failure(Token(2), 2, cooldown));
+        assert!(health.is_healthy(Token(2)));
+
+        // never eject
+        for _ in 0..16 {
+            assert!(!health.report_failure(Token(3), 0, cooldown));
+        }
+        assert!(health.is_healthy(Token(3)));
+    }
+
+    #[test]
+    fn hc_up_down() {
+        let health = Health::new();
+
+        assert!(health.is_up(Token(1)));
+        assert!(!health.set_up(Token(1), true));
+        assert!(health.set_up(Token(1), false));
+        assert!(!health.set_up(Token(1), false));
+        assert!(!health.is_healthy(Token(1)));
+        assert!(!health.is_ejected(Token(1)));
+
+        assert!(health.set_up(Token(1), true));
+        assert!(health.is_healthy(Token(1)));
+    }
+
+    #[test]
+    fn hc_disable() {
+        let health = Health::new();
+
+        assert!(health.set_enabled(Token(0), false));
+        assert!(!health.set_enabled(Token(0), false));
+        assert!(!health.is_healthy(Token(0)));
+        assert!(health.is_up(Token(0)));
+
+        assert!(health.set_enabled(Token(0), true));
+        assert!(health.is_healthy(Token(0)));
+    }
+
+    #[test]
+    fn hc_restore() {
+        let health = Health::new();
+
+        assert!(health.report_failure(Token(0), 1, Duration::from_millis(50)));
+        assert!(!health.is_healthy(Token(0)));
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(health.is_healthy(Token(0)));
+    }
+}
@@ -13,6 +13,7 @@ struct Node {
 #[derive(Debug)]
 pub struct IpHash {
     nodes: Vec<Node>,
+    weights: Vec<u8>,
     total: u8,
 }
 
@@ -23,12 +24,17 @@ impl Balance for IpHash {
         self.total
     }
 
+    fn weights(&self) -> &[u8] {
+        &self.weights
+    }
+
     fn new(weights: &[u8]) -> Self {
         assert!(weights.len() <= u8::MAX as usize);
 
         if weights.len() <= 1 {
             return Self {
                 nodes: Vec::new(),
+                weights: weights.to_vec(),
                 total: weights.len() as u8,
             };
         }
@@ -51,6 +57,7 @@ impl Balance for IpHash {
 
         Self {
             nodes,
+            weights: weights.to_vec(),
             total: weights.len() as u8,
         }
     }
@@ -67,7 +74,7 @@ impl Balance for IpHash {
 
         let idx = match self.nodes.binary_search_by_key(&hash, |node| node.hash) {
             Ok(idx) => idx,
-            Err(idx) if idx >= self.nodes.len() as usize => 0,
+            Err(idx) if idx >= self.nodes.len() => 0,
             Err(idx) => idx,
         };
 
@@ -75,20 +82,20 @@ impl Balance for IpHash {
     }
 }
 
-use chash::{chash, chash_for_ip};
+pub(crate) use chash::{chash, chash_for_ip};
 mod chash {
     const SEED: u32 = 0xbc9f1d34;
     const M: u32 = 0xc6a4a793;
 
     macro_rules! c_add {
         ($a:expr, $b:expr) => {
-            unsafe { $a.unchecked_add($b) }
+            $a.wrapping_add($b)
         };
     }
 
     macro_rules! c_mul {
         ($a:expr, $b:expr) => {
-            unsafe { $a.unchecked_mul($b) }
+            $a.wrapping_mul($b)
         };
     }
 
@@ -241,7 +248,7 @@ mod tests {
         let ip3 = "114.51.4.19".parse::<IpAddr>().unwrap();
         let ip4 = "2001:4860:4860::8888".parse::<IpAddr>().unwrap();
 
-        let iphash = IpHash::new(&vec![1, 2, 3, 4]);
+        let iphash = IpHash::new(&[1, 2, 3, 4]);
         assert_eq!(iphash.total, 4);
         assert!(iphash.nodes.len() >= (1 + 2 + 3 + 4) * 128 / 4);
 
@@ -260,13 +267,13 @@ mod tests {
 
     #[test]
     fn ih_same_weight() {
-        let iphash = IpHash::new(&vec![1; 16]);
+        let iphash = IpHash::new(&[1; 16]);
         let mut distro = [0f64; 16];
 
         let mut total: usize = 0;
         for ip in (0..=u32::MAX).map(Ipv4Addr::from).map(IpAddr::from).step_by(127) {
             let token = iphash.next(&ip).unwrap();
-            distro[token.0 as usize] += 1 as f64;
+            distro[token.0 as usize] += 1.0;
             total += 1;
         }
 
@@ -295,7 +302,7 @@ mod tests {
         let mut total: usize = 0;
         for ip in (0..=u32::MAX).map(Ipv4Addr::from).map(IpAddr::from).step_by(127) {
             let token = iphash.next(&ip).unwrap();
-            distro[token.0 as usize] += 1 as f64;
+            distro[token.0 as usize] += 1.0;
             total += 1;
         }
 
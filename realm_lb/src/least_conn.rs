@@ -0,0 +1,111 @@
+use super::{Balance, Token};
+
+/// Least-connections balancer.
+///
+/// Select the peer with the fewest active connections per weight,
+/// prefer the heavier one if tied. Peers with zero weight are never
+/// selected unless all of them are zero.
+#[derive(Debug)]
+pub struct LeastConn {
+    weights: Vec<u8>,
+    // all weights are zero, treat them as one
+    uniform: bool,
+    total: u8,
+}
+
+impl Balance for LeastConn {
+    /// Active connections of each peer, indexed by token.
+    /// Missing ones are treated as idle.
+    type State = [usize];
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn weights(&self) -> &[u8] {
+        &self.weights
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        Self {
+            weights: weights.to_vec(),
+            uniform: weights.iter().all(|x| *x == 0),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, state: &Self::State) -> Option<Token> {
+        if self.total <= 1 {
+            return Some(Token(0));
+        }
+
+        let active = |i: usize| state.get(i).copied().unwrap_or(0) as u128;
+        let mut best: Option<(usize, u8)> = None;
+        for (i, &w) in self.weights.iter().enumerate() {
+            let w = if self.uniform { 1 } else { w };
+            if w == 0 {
+                continue;
+            }
+            best = match best {
+                None => Some((i, w)),
+                Some((j, wj)) => {
+                    // active(i) / w < active(j) / wj
+                    let (x, y) = (active(i) * wj as u128, active(j) * w as u128);
+                    if x < y || (x == y && w > wj) {
+                        Some((i, w))
+                    } else {
+                        Some((j, wj))
+                    }
+                }
+            };
+        }
+
+        best.map(|(i, _)| Token(i as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lc_fewest_active() {
+        let lc = LeastConn::new(&[1, 1, 1]);
+        assert_eq!(lc.next(&[3, 1, 2]), Some(Token(1)));
+        assert_eq!(lc.next(&[0, 1, 2]), Some(Token(0)));
+        assert_eq!(lc.next(&[2, 2, 1]), Some(Token(2)));
+
+        // missing counts are idle
+        assert_eq!(lc.next(&[2, 2]), Some(Token(2)));
+        assert_eq!(lc.next(&[]), Some(Token(0)));
+    }
+
+    #[test]
+    fn lc_weighted() {
+        let lc = LeastConn::new(&[4, 2, 1]);
+        // 8/4 > 3/2 > 1/1
+        assert_eq!(lc.next(&[8, 3, 1]), Some(Token(2)));
+        // 4/4 = 2/2 = 1/1, the heavier one wins
+        assert_eq!(lc.next(&[4, 2, 1]), Some(Token(0)));
+        assert_eq!(lc.next(&[5, 2, 1]), Some(Token(1)));
+
+        // never select a peer without weight
+        let lc = LeastConn::new(&[0, 1]);
+        assert_eq!(lc.next(&[0, 100]), Some(Token(1)));
+        let lc = LeastConn::new(&[0, 0]);
+        assert_eq!(lc.next(&[1, 0]), Some(Token(1)));
+    }
+
+    #[test]
+    fn lc_spread() {
+        let lc = LeastConn::new(&[2, 1]);
+        let mut active = [0; 2];
+        for _ in 0..300 {
+            let Token(i) = lc.next(&active).unwrap();
+            active[i as usize] += 1;
+        }
+        assert_eq!(active, [200, 100]);
+    }
+}
@@ -4,7 +4,7 @@ pub struct Token(pub u8);
 
 /// Load balance traits.
 pub trait Balance {
-    type State;
+    type State: ?Sized;
 
     /// Constructor.
     fn new(weights: &[u8]) -> Self;
@@ -14,6 +14,9 @@ pub trait Balance {
 
     /// Total peers.
     fn total(&self) -> u8;
+
+    /// Weights of peers, as given to the constructor.
+    fn weights(&self) -> &[u8];
 }
 
 /// Iphash impl.
@@ -22,5 +25,21 @@ pub mod ip_hash;
 /// Round-robin impl.
 pub mod round_robin;
 
+/// Least-connections impl.
+pub mod least_conn;
+
+/// Random impl.
+pub mod random;
+
+/// Consistent hash impl.
+pub mod consistent_hash;
+
+/// Failover impl.
+pub mod failover;
+
+/// Passive health state.
+pub mod health;
+
 mod balancer;
-pub use balancer::{Balancer, BalanceCtx, Strategy};
+pub use balancer::{Balancer, BalanceCtx, ParseError, Strategy};
+pub use health::Health;
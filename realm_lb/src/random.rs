@@ -0,0 +1,142 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use super::{Balance, Token};
+
+/// Random balancer.
+///
+/// Select a peer at random, with a probability proportional
+/// to its weight. Peers are equally likely if all weights are zero.
+#[derive(Debug)]
+pub struct Random {
+    // cumulative weights
+    bounds: Vec<u32>,
+    weights: Vec<u8>,
+    total: u8,
+}
+
+impl Balance for Random {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn weights(&self) -> &[u8] {
+        &self.weights
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        let all_zero = weights.iter().all(|x| *x == 0);
+        let bounds = weights
+            .iter()
+            .map(|x| if all_zero { 1 } else { *x as u32 })
+            .scan(0, |acc, x| {
+                *acc += x;
+                Some(*acc)
+            })
+            .collect();
+
+        Self {
+            bounds,
+            weights: weights.to_vec(),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        if self.total <= 1 {
+            return Some(Token(0));
+        }
+
+        let sum = *self.bounds.last().unwrap();
+        let r = (rand_u64() % sum as u64) as u32;
+        let idx = self.bounds.partition_point(|x| *x <= r);
+
+        Some(Token(idx as u8))
+    }
+}
+
+/// Xorshift64* with a per-thread state.
+fn rand_u64() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545f4914f6cdd1d)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use average::{Max, Mean, Min};
+
+    fn distro(weights: &[u8], total_weight: f64) {
+        const N: usize = 1_000_000;
+
+        let random = Random::new(weights);
+        let mut distro = vec![0f64; weights.len()];
+
+        for _ in 0..N {
+            let token = random.next(&()).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+
+        let diffs: Vec<f64> = distro
+            .iter()
+            .zip(weights)
+            .map(|(x, w)| *x / N as f64 - *w as f64 / total_weight)
+            .map(f64::abs)
+            .inspect(|x| assert!(x < &5e-3))
+            .collect();
+
+        let min_diff: Min = diffs.iter().collect();
+        let max_diff: Max = diffs.iter().collect();
+        let mean_diff: Mean = diffs.iter().collect();
+
+        println!("{:?}", distro);
+        println!("min diff: {}", min_diff.min());
+        println!("max diff: {}", max_diff.max());
+        println!("mean diff: {}", mean_diff.mean());
+    }
+
+    #[test]
+    fn rd_same_weight() {
+        distro(&[1; 16], 16.0);
+    }
+
+    #[test]
+    fn rd_all_weights() {
+        let weights: Vec<u8> = (1..=16).collect();
+        let total_weight: f64 = weights.iter().map(|x| *x as f64).sum();
+        distro(&weights, total_weight);
+    }
+
+    #[test]
+    fn rd_zero_weight() {
+        let random = Random::new(&[0, 3, 0, 1]);
+        for _ in 0..10_000 {
+            let Token(i) = random.next(&()).unwrap();
+            assert!(i == 1 || i == 3);
+        }
+
+        // all zero, equally likely
+        let random = Random::new(&[0; 4]);
+        let mut seen = [false; 4];
+        for _ in 0..10_000 {
+            let Token(i) = random.next(&()).unwrap();
+            seen[i as usize] = true;
+        }
+        assert_eq!(seen, [true; 4]);
+    }
+}
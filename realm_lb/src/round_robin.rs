@@ -5,16 +5,21 @@ use super::{Balance, Token};
 /// Round-robin node.
 #[derive(Debug)]
 struct Node {
-    cw: i16,
-    ew: u8,
+    // current weight, which could exceed i16 with 255 nodes
+    cw: i32,
     weight: u8,
     token: Token,
 }
 
-/// Round robin balancer.
+/// Smooth weighted round robin balancer, like nginx.
+///
+/// Each time every node gains its weight, the one with the highest
+/// current weight is picked and loses the total weight. Nodes are
+/// interleaved, e.g. weights [3, 1, 1] give `A B A C A` in a cycle.
 #[derive(Debug)]
 pub struct RoundRobin {
     nodes: Mutex<Vec<Node>>,
+    weights: Vec<u8>,
     total: u8,
 }
 
@@ -25,12 +30,17 @@ impl Balance for RoundRobin {
         self.total
     }
 
+    fn weights(&self) -> &[u8] {
+        &self.weights
+    }
+
     fn new(weights: &[u8]) -> Self {
         assert!(weights.len() <= u8::MAX as usize);
 
         if weights.len() <= 1 {
             return Self {
                 nodes: Mutex::new(Vec::new()),
+                weights: weights.to_vec(),
                 total: weights.len() as u8,
             };
         }
@@ -39,7 +49,6 @@ impl Balance for RoundRobin {
             .iter()
             .enumerate()
             .map(|(i, w)| Node {
-                ew: *w,
                 cw: 0,
                 weight: *w,
                 token: Token(i as u8),
@@ -47,6 +56,7 @@ impl Balance for RoundRobin {
             .collect();
         Self {
             nodes: Mutex::new(nodes),
+            weights: weights.to_vec(),
             total: weights.len() as u8,
         }
     }
@@ -60,16 +70,13 @@ impl Balance for RoundRobin {
         // lock the whole list
         {
             let mut nodes = self.nodes.lock().unwrap();
-            let mut tw: i16 = 0;
+            let mut tw: i32 = 0;
             let mut best: Option<&mut Node> = None;
             for p in nodes.iter_mut() {
-                tw += p.ew as i16;
-                p.cw += p.ew as i16;
-
-                if p.ew < p.weight {
-                    p.ew += 1;
-                }
+                tw += p.weight as i32;
+                p.cw += p.weight as i32;
 
+                // the first one wins a tie
                 if let Some(ref x) = best {
                     if p.cw > x.cw {
                         best = Some(p);
@@ -94,12 +101,12 @@ mod tests {
 
     #[test]
     fn rr_same_weight() {
-        let rr = RoundRobin::new(&vec![1; 255]);
+        let rr = RoundRobin::new(&[1; 255]);
         let mut distro = [0f64; 255];
 
         for _ in 0..1_000_000 {
             let token = rr.next(&()).unwrap();
-            distro[token.0 as usize] += 1 as f64;
+            distro[token.0 as usize] += 1.0;
         }
 
         let diffs: Vec<f64> = distro
@@ -119,6 +126,19 @@ mod tests {
         println!("mean diff: {}", mean_diff.mean());
     }
 
+    #[test]
+    fn rr_smooth() {
+        let rr = RoundRobin::new(&[3, 1, 1]);
+        let seq: Vec<u8> = (0..10).map(|_| rr.next(&()).unwrap().0).collect();
+        // not [0, 0, 0, 1, 2], and the cycle repeats
+        assert_eq!(seq, [0, 1, 0, 2, 0, 0, 1, 0, 2, 0]);
+
+        // the total weight exceeds i16
+        let rr = RoundRobin::new(&[255; 255]);
+        let seq: Vec<u8> = (0..510).map(|_| rr.next(&()).unwrap().0).collect();
+        assert!(seq.iter().enumerate().all(|(i, x)| *x as usize == i % 255));
+    }
+
     #[test]
     fn rr_all_weights() {
         let weights: Vec<u8> = (1..=255).collect();
@@ -128,7 +148,7 @@ mod tests {
 
         for _ in 0..1_000_000 {
             let token = rr.next(&()).unwrap();
-            distro[token.0 as usize] += 1 as f64;
+            distro[token.0 as usize] += 1.0;
         }
 
         let diffs: Vec<f64> = distro
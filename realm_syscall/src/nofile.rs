@@ -26,6 +26,7 @@ pub fn set_nofile_limit(nofile: u64) -> Result<()> {
 ///
 /// Reference: [man](https://man7.org/linux/man-pages/man2/setrlimit.2.html).
 #[cfg(all(unix, not(target_os = "android")))]
+#[allow(clippy::unnecessary_cast)]
 pub fn get_nofile_limit() -> Result<(u64, u64)> {
     let mut lim = rlimit {
         rlim_cur: 0,
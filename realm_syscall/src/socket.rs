@@ -67,6 +67,28 @@ pub fn new_tcp_socket(addr: &SocketAddr) -> Result<Socket> {
     new_socket(domain, Type::STREAM)
 }
 
+/// Create a new non-blocking Multipath TCP socket.
+///
+/// It requires linux 5.6 or later with `net.mptcp.enabled` set,
+/// otherwise an error is returned.
+#[cfg(target_os = "linux")]
+pub fn new_mptcp_socket(addr: &SocketAddr) -> Result<Socket> {
+    use std::os::unix::prelude::FromRawFd;
+    use libc::{SOCK_STREAM, SOCK_NONBLOCK, SOCK_CLOEXEC, IPPROTO_MPTCP};
+
+    let domain = match addr {
+        SocketAddr::V4(..) => Domain::IPV4,
+        SocketAddr::V6(..) => Domain::IPV6,
+    };
+    let fd = unsafe { libc::socket(domain.into(), SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC, IPPROTO_MPTCP) };
+
+    if fd < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(unsafe { Socket::from_raw_fd(fd) })
+    }
+}
+
 /// Create a new non-blocking UDP socket.
 ///
 /// On unix-like platforms, [`SOCK_NONBLOCK`](libc::SOCK_NONBLOCK) and
@@ -110,3 +132,89 @@ pub fn bind_to_device<T: std::os::unix::io::AsRawFd>(socket: &T, iface: &str) ->
         Ok(())
     }
 }
+
+/// Get the index of a network interface by its name,
+/// e.g. to use it as the scope id of an ipv6 link-local address.
+#[cfg(unix)]
+pub fn interface_index(iface: &str) -> std::io::Result<u32> {
+    let name = std::ffi::CString::new(iface).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(std::io::Error::last_os_error()),
+        x => Ok(x),
+    }
+}
+
+/// Set the DSCP of outgoing packets, with `IP_TOS` or `IPV6_TCLASS`
+/// according to the family of `addr`. The ECN bits are left as 0.
+///
+/// An ipv6 socket also tries `IP_TOS`, which applies to ipv4-mapped peers.
+pub fn set_dscp(socket: &Socket, addr: &SocketAddr, dscp: u8) -> Result<()> {
+    let tos = (dscp as u32) << 2;
+    match addr {
+        SocketAddr::V4(_) => set_tos(socket, tos),
+        SocketAddr::V6(_) => {
+            let _ = set_tos(socket, tos);
+            set_tclass_v6(socket, tos)
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "haiku",
+)))]
+fn set_tos(socket: &Socket, tos: u32) -> Result<()> { socket.set_tos(tos) }
+
+#[cfg(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "haiku",
+))]
+fn set_tos(_: &Socket, _: u32) -> Result<()> { Err(std::io::ErrorKind::Unsupported.into()) }
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn set_tclass_v6(socket: &Socket, tclass: u32) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let tclass = tclass as libc::c_int;
+    if unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &tclass as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } < 0
+    {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn set_tclass_v6(_: &Socket, _: u32) -> Result<()> { Err(std::io::ErrorKind::Unsupported.into()) }
@@ -0,0 +1,2312 @@
+//! Management api served by the binary.
+//!
+//! Connection statistics are provided by [`realm_core::api`],
+//! while handlers here need to know about the whole build or config.
+
+use std::fs;
+use std::io::{Result, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, HashMap};
+
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::HttpMessage;
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use actix_web::http::header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::middleware::Next;
+use dashmap::DashMap;
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use realm::{VERSION, ENV_RULES_STORE, ENV_DYNAMIC_PORT_RANGE, ENV_API_LOG, ENV_API_LOG_HEADERS, ENV_API_LOG_HEADERS_DENY};
+use realm::{ENV_API_TOKENS, ENV_API_TOKENS_FILE, ENV_API_RATE_LIMIT, ENV_API_TLS_CERT, ENV_API_TLS_KEY};
+use realm::{GIT_COMMIT, ENV_API_ENABLED, ENV_API_UNIX_SOCKET};
+use realm::conf::{Config, EndpointConf, EndpointInfo, NetConf, NetInfo};
+use realm::consts::{Features, ALLOCATOR, FEATURES};
+use realm_core::endpoint::{ByteCount, UpstreamEof};
+use realm_core::monitor::{RuleMetrics, RULE_METRICS, CONNECT_STATS_WINDOW};
+use realm_core::shutdown::Stop;
+use realm_core::endpoint::Endpoint;
+use realm_core::tcp::{bind_tcp, run_tcp_with_listeners, TcpListeners};
+use realm_core::udp::{bind_udp, run_udp_with_listeners, UdpListeners};
+
+#[cfg(feature = "transport")]
+use realm_core::endpoint::SharedTransport;
+
+pub use realm_core::api::{list_tcp_connections, get_tcp_connection_stats, list_udp_associations, get_udp_association_stats};
+pub use realm_core::api::close_udp_association;
+pub use realm_core::api::{get_rule_sources, unquarantine_rule, get_shutdown_status, get_top_connections, get_metrics};
+pub use realm_core::api::{get_rule_stats, reset_rule, reset_rules, stream_stats, ApiError};
+
+#[cfg(feature = "balance")]
+pub use realm_core::api::{patch_balancer_weights, get_rule_health, disable_peer, enable_peer};
+
+/// What to log about each api request.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ApiLogMode {
+    Off,
+    /// method, path, status and elapsed time
+    #[default]
+    Concise,
+    /// plus request and response headers
+    Full,
+}
+
+impl std::str::FromStr for ApiLogMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "concise" => Ok(Self::Concise),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("unknown api log mode: {}", s)),
+        }
+    }
+}
+
+/// Request logging of the api server.
+///
+/// In full mode, a header is logged if it is in the allowlist (or the
+/// allowlist is empty) and not in the denylist. Authorization is never logged.
+#[derive(Debug, Default, Clone)]
+pub struct ApiLog {
+    pub mode: ApiLogMode,
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl ApiLog {
+    pub fn from_env() -> Self {
+        let names = |key: &str| -> Vec<String> {
+            std::env::var(key)
+                .map(|x| {
+                    x.split(',')
+                        .map(|x| x.trim().to_ascii_lowercase())
+                        .filter(|x| !x.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let mode = match std::env::var(ENV_API_LOG) {
+            Ok(x) if !x.is_empty() => x.parse().unwrap_or_else(|e| panic!("{}", e)),
+            _ => ApiLogMode::default(),
+        };
+        Self {
+            mode,
+            allow: names(ENV_API_LOG_HEADERS),
+            deny: names(ENV_API_LOG_HEADERS_DENY),
+        }
+    }
+
+    fn headers<'a>(&'a self, headers: &'a HeaderMap) -> Vec<(&'a str, &'a str)> {
+        headers
+            .iter()
+            .filter(|(k, _)| *k != AUTHORIZATION)
+            .filter(|(k, _)| self.allow.is_empty() || self.allow.iter().any(|x| x == k.as_str()))
+            .filter(|(k, _)| !self.deny.iter().any(|x| x == k.as_str()))
+            .map(|(k, v)| (k.as_str(), v.to_str().unwrap_or("<binary>")))
+            .collect()
+    }
+}
+
+/// Middleware which logs api requests according to [`ApiLog`].
+pub async fn log_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> std::result::Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let log = req.app_data::<web::Data<ApiLog>>().cloned().unwrap_or_default();
+    if log.mode == ApiLogMode::Off {
+        return next.call(req).await;
+    }
+
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    if log.mode == ApiLogMode::Full {
+        log::info!("[api]{} {} request headers: {:?}", method, path, log.headers(req.headers()));
+    }
+
+    let start = std::time::Instant::now();
+    let res = next.call(req).await?;
+    let key = res.request().extensions().get::<ApiKey>().map(|x| format!(" key={}", x.label));
+    log::info!(
+        "[api]{} {} {} {}ms{}",
+        method,
+        path,
+        res.status().as_u16(),
+        start.elapsed().as_millis(),
+        key.unwrap_or_default()
+    );
+    if log.mode == ApiLogMode::Full {
+        log::info!("[api]{} {} response headers: {:?}", method, path, log.headers(res.headers()));
+    }
+    Ok(res)
+}
+
+/// Requests allowed from a source ip in each window.
+#[derive(Debug)]
+pub struct ApiRateLimit {
+    limit: u32,
+    window: Duration,
+    // (window start, requests)
+    counters: DashMap<IpAddr, (Instant, u32)>,
+    last_prune: Mutex<Instant>,
+}
+
+impl ApiRateLimit {
+    /// Ips tracked at most, the others share one counter.
+    const MAX_IPS: usize = 65536;
+
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            counters: DashMap::new(),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Parse `requests/seconds` from [`ENV_API_RATE_LIMIT`], e.g. `120/60`,
+    /// the window is 60 seconds if omitted. None if not set or 0.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var(ENV_API_RATE_LIMIT) {
+            Ok(x) if !x.is_empty() => parse_rate_limit(&x).unwrap_or_else(|e| panic!("{}", e)),
+            _ => None,
+        }
+    }
+
+    /// Count a request, return false if the limit is exceeded.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+
+        let ip = match self.counters.len() < Self::MAX_IPS || self.counters.contains_key(&ip) {
+            true => ip,
+            false => IpAddr::from([0u8; 16]),
+        };
+        let mut counter = self.counters.entry(ip).or_insert((now, 0));
+        let (start, count) = counter.value_mut();
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        *count <= self.limit
+    }
+
+    // drop expired counters once per window
+    fn prune(&self, now: Instant) {
+        let Ok(mut last) = self.last_prune.try_lock() else {
+            return;
+        };
+        if now.duration_since(*last) < self.window {
+            return;
+        }
+        *last = now;
+        self.counters.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+    }
+}
+
+fn parse_rate_limit(s: &str) -> std::result::Result<Option<ApiRateLimit>, String> {
+    let err = || format!("invalid api rate limit, expect requests[/seconds]: {}", s);
+    let (limit, window) = s.split_once('/').unwrap_or((s, "60"));
+    let limit: u32 = limit.trim().parse().map_err(|_| err())?;
+    let window: u64 = window.trim().parse().map_err(|_| err())?;
+    if limit == 0 {
+        return Ok(None);
+    }
+    if window == 0 {
+        return Err(err());
+    }
+    Ok(Some(ApiRateLimit::new(limit, Duration::from_secs(window))))
+}
+
+/// Middleware which rejects a source ip with 429 once it exceeds [`ApiRateLimit`].
+///
+/// It runs ahead of [`authenticate`], so that guessing tokens is limited as well.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> std::result::Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<ApiRateLimit>>().cloned();
+    let ip = req.peer_addr().map(|x| x.ip());
+    if let (Some(limiter), Some(ip)) = (limiter, ip) {
+        if !limiter.check(ip) {
+            log::debug!("[api]too many requests from {}", ip);
+            let mut res =
+                ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", "Too many requests").error_response();
+            res.headers_mut().insert(
+                actix_web::http::header::RETRY_AFTER,
+                limiter.window.as_secs().into(),
+            );
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// What an api token is allowed to do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// only GET, HEAD and OPTIONS requests, e.g. for monitoring
+    Read,
+    #[default]
+    Write,
+}
+
+impl std::str::FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            _ => Err(format!("unknown api token permission: {}", s)),
+        }
+    }
+}
+
+impl Permission {
+    fn allow(&self, method: &actix_web::http::Method) -> bool {
+        use actix_web::http::Method;
+        *self == Permission::Write || [Method::GET, Method::HEAD, Method::OPTIONS].contains(method)
+    }
+}
+
+/// The token which an api request is authenticated with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub label: String,
+    pub permission: Permission,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ApiToken {
+    key: ApiKey,
+    token: String,
+}
+
+/// Certificate and key of the api server, which is served over
+/// https if they are set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiTls {
+    pub cert: String,
+    pub key: String,
+}
+
+impl ApiTls {
+    /// Load the paths from [`ENV_API_TLS_CERT`] and [`ENV_API_TLS_KEY`],
+    /// panic if only one of them is set.
+    pub fn from_env() -> Option<Self> {
+        let var = |x| std::env::var(x).ok().filter(|x: &String| !x.is_empty());
+        Self::new(var(ENV_API_TLS_CERT), var(ENV_API_TLS_KEY)).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn new(cert: Option<String>, key: Option<String>) -> std::result::Result<Option<Self>, String> {
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok(Some(Self { cert, key })),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(format!("{} is set without {}", ENV_API_TLS_CERT, ENV_API_TLS_KEY)),
+            (None, Some(_)) => Err(format!("{} is set without {}", ENV_API_TLS_KEY, ENV_API_TLS_CERT)),
+        }
+    }
+
+    /// Read the pem encoded certificate chain and private key.
+    #[cfg(feature = "api-tls")]
+    pub fn server_config(&self) -> std::result::Result<rustls::ServerConfig, String> {
+        use std::sync::Arc;
+        use rustls::crypto::ring::default_provider;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+        use rustls::pki_types::pem::PemObject;
+
+        let certs = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|x| x.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| format!("failed to read api certificate {}: {}", self.cert, e))?;
+        if certs.is_empty() {
+            return Err(format!("no certificate in {}", self.cert));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| format!("failed to read api key {}: {}", self.key, e))?;
+
+        rustls::ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("bad api certificate or key: {}", e))
+    }
+}
+
+/// Path of a unix socket from [`ENV_API_UNIX_SOCKET`], which the api
+/// server listens on instead of the tcp address if set.
+pub fn unix_socket_from_env() -> Option<PathBuf> {
+    std::env::var(ENV_API_UNIX_SOCKET).ok().filter(|x| !x.is_empty()).map(PathBuf::from)
+}
+
+/// Whether to serve the api from [`ENV_API_ENABLED`], true if not set.
+pub fn enabled_from_env() -> bool {
+    match std::env::var(ENV_API_ENABLED) {
+        Ok(x) if !x.is_empty() => parse_enabled(&x).unwrap_or_else(|e| panic!("{}", e)),
+        _ => true,
+    }
+}
+
+fn parse_enabled(s: &str) -> std::result::Result<bool, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "on" | "yes" => Ok(true),
+        "false" | "0" | "off" | "no" => Ok(false),
+        _ => Err(format!("invalid {}: {}", ENV_API_ENABLED, s)),
+    }
+}
+
+/// Listen on a unix socket only accessible by the owner,
+/// a stale socket file left by a previous run is removed first.
+#[cfg(unix)]
+pub fn bind_unix_socket(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+
+    if fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Bearer tokens of the api server, each with a label and permission.
+///
+/// If there is any token, a request must carry one of them in the
+/// `Authorization` header, otherwise the api is open to all.
+#[derive(Debug, Default)]
+pub struct ApiAuth {
+    tokens: Vec<ApiToken>,
+}
+
+impl ApiAuth {
+    /// Parse `label:token` or `label:token:permission` pairs.
+    pub fn new<'a>(tokens: impl IntoIterator<Item = &'a str>) -> std::result::Result<Self, String> {
+        let tokens = tokens.into_iter().filter_map(parse_token).collect::<std::result::Result<_, _>>()?;
+        Ok(Self { tokens })
+    }
+
+    /// Load tokens, comma separated from [`ENV_API_TOKENS`],
+    /// and one per line from the file at [`ENV_API_TOKENS_FILE`].
+    pub fn from_env() -> Self {
+        let mut tokens = Vec::new();
+        if let Ok(x) = std::env::var(ENV_API_TOKENS) {
+            tokens.extend(x.split(',').map(String::from));
+        }
+        if let Ok(path) = std::env::var(ENV_API_TOKENS_FILE) {
+            let s = fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to open api tokens {}: {}", path, e));
+            tokens.extend(s.lines().filter(|x| !x.trim_start().starts_with('#')).map(String::from));
+        }
+        Self::new(tokens.iter().map(String::as_str)).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Find the key of a token.
+    ///
+    /// Every token is compared in constant time, so that
+    /// neither the matched one nor the length leaks out.
+    pub fn check(&self, token: &str) -> Option<&ApiKey> {
+        let mut found = None;
+        for x in &self.tokens {
+            if constant_time_eq(x.token.as_bytes(), token.as_bytes()) && found.is_none() {
+                found = Some(&x.key);
+            }
+        }
+        found
+    }
+}
+
+// none if empty
+fn parse_token(s: &str) -> Option<std::result::Result<ApiToken, String>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    let err = || format!("invalid api token, expect label:token[:permission]: {}", s);
+    let mut parts = s.splitn(3, ':').map(str::trim);
+    let parsed = match (parts.next(), parts.next(), parts.next()) {
+        (Some(label), Some(token), permission) if !label.is_empty() && !token.is_empty() => {
+            permission.map_or(Ok(Permission::default()), str::parse).map(|permission| ApiToken {
+                key: ApiKey {
+                    label: label.to_string(),
+                    permission,
+                },
+                token: token.to_string(),
+            })
+        }
+        _ => Err(err()),
+    };
+    Some(parsed)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for (i, x) in a.iter().enumerate() {
+        // compare against itself if b is shorter
+        let y = b.get(i).unwrap_or(x);
+        diff |= (x ^ y) as usize;
+    }
+    std::hint::black_box(diff) == 0
+}
+
+/// Middleware which requires a bearer token from [`ApiAuth`].
+///
+/// The matched token is attached to the request as [`ApiKey`].
+/// 403 is returned if it is not allowed to make this request.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> std::result::Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let Some(auth) = req.app_data::<web::Data<ApiAuth>>().filter(|x| !x.is_empty()).cloned() else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    // probes of orchestrators carry no token
+    if PROBES.contains(&req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+    let Some(key) = token.and_then(|x| auth.check(x.trim())).cloned() else {
+        let mut res =
+            ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", "Invalid or missing api token").error_response();
+        res.headers_mut()
+            .insert(WWW_AUTHENTICATE, actix_web::http::header::HeaderValue::from_static("Bearer"));
+        return Ok(req.into_response(res).map_into_right_body());
+    };
+
+    let allowed = key.permission.allow(req.method());
+    req.extensions_mut().insert(key);
+    if !allowed {
+        let res = ApiError::new(StatusCode::FORBIDDEN, "forbidden", "Read-only api token").error_response();
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// Paths served without authentication.
+const PROBES: [&str; 4] = ["/healthz", "/readyz", "/version", "/features"];
+
+/// Configs of the running rules, keyed by endpoint id.
+#[derive(Debug, Default)]
+pub struct RuleConfigs {
+    confs: RwLock<BTreeMap<String, EndpointConf>>,
+}
+
+impl RuleConfigs {
+    pub fn new(confs: &[EndpointConf]) -> Self {
+        let confs = confs.iter().map(|x| (x.build_id(), x.clone())).collect();
+        Self {
+            confs: RwLock::new(confs),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<EndpointConf> {
+        self.confs.read().unwrap_or_else(PoisonError::into_inner).get(id).cloned()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.confs.read().unwrap_or_else(PoisonError::into_inner).contains_key(id)
+    }
+
+    pub fn insert(&self, id: String, conf: EndpointConf) {
+        self.confs.write().unwrap_or_else(PoisonError::into_inner).insert(id, conf);
+    }
+
+    /// Insert only if the id is not taken.
+    pub fn try_insert(&self, id: String, conf: EndpointConf) -> bool {
+        use std::collections::btree_map::Entry;
+        match self.confs.write().unwrap_or_else(PoisonError::into_inner).entry(id) {
+            Entry::Vacant(x) => {
+                x.insert(conf);
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+
+    pub fn remove(&self, id: &str) -> Option<EndpointConf> {
+        self.confs.write().unwrap_or_else(PoisonError::into_inner).remove(id)
+    }
+
+    pub fn all(&self) -> BTreeMap<String, EndpointConf> {
+        self.confs.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+/// Rules added at runtime, keyed by endpoint id.
+///
+/// They are saved to a json file if [`ENV_RULES_STORE`] is set,
+/// and loaded again at startup. Global options are not saved.
+#[derive(Debug, Default)]
+pub struct RuleStore {
+    path: Option<PathBuf>,
+    rules: Mutex<BTreeMap<String, EndpointConf>>,
+}
+
+impl RuleStore {
+    /// Load saved rules, a missing file is treated as empty.
+    pub fn open() -> Self {
+        match std::env::var_os(ENV_RULES_STORE) {
+            Some(path) => Self::load(PathBuf::from(path)),
+            None => Self::default(),
+        }
+    }
+
+    fn load(path: PathBuf) -> Self {
+        let rules: Vec<EndpointConf> = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s)
+                .unwrap_or_else(|e| panic!("failed to parse rule store {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => panic!("failed to open rule store {}: {}", path.display(), e),
+        };
+
+        Self {
+            path: Some(path),
+            rules: Mutex::new(rules.into_iter().map(|x| (x.build_id(), x)).collect()),
+        }
+    }
+
+    pub fn rules(&self) -> Vec<EndpointConf> {
+        self.rules.lock().unwrap_or_else(PoisonError::into_inner).values().cloned().collect()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.rules.lock().unwrap_or_else(PoisonError::into_inner).contains_key(id)
+    }
+
+    /// Add or replace a rule, then save all rules.
+    pub fn insert(&self, id: String, conf: EndpointConf) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap_or_else(PoisonError::into_inner);
+        let old = rules.insert(id.clone(), conf);
+        let result = self.save(&rules);
+        // keep in sync with the file
+        if result.is_err() {
+            match old {
+                Some(old) => rules.insert(id, old),
+                None => rules.remove(&id),
+            };
+        }
+        result
+    }
+
+    /// Add or replace rules at once, then save all rules.
+    ///
+    /// None of them is kept if failed to save.
+    pub fn insert_all(&self, new: impl IntoIterator<Item = (String, EndpointConf)>) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap_or_else(PoisonError::into_inner);
+        let old = rules.clone();
+        rules.extend(new);
+        let result = self.save(&rules);
+        if result.is_err() {
+            *rules = old;
+        }
+        result
+    }
+
+    /// Remove a rule if it exists, then save all rules.
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(old) = rules.remove(id) else {
+            return Ok(());
+        };
+        let result = self.save(&rules);
+        if result.is_err() {
+            rules.insert(id.to_string(), old);
+        }
+        result
+    }
+
+    /// Remove all rules, then save.
+    pub fn clear(&self) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap_or_else(PoisonError::into_inner);
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let old = std::mem::take(&mut *rules);
+        let result = self.save(&rules);
+        if result.is_err() {
+            *rules = old;
+        }
+        result
+    }
+
+    // write to a temporary file first,
+    // so that the store is never left half written
+    fn save(&self, rules: &BTreeMap<String, EndpointConf>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let rules: Vec<_> = rules.values().collect();
+        let s = serde_json::to_string_pretty(&rules)?;
+
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(s.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Ports for rules added with a listen port of 0, from [`ENV_DYNAMIC_PORT_RANGE`],
+/// e.g. `30000-30999`.
+///
+/// Port 0 is passed to the system as is if there is no range.
+#[derive(Debug, Default)]
+pub struct DynamicPorts {
+    range: Option<RangeInclusive<u16>>,
+    // held until the picked port is taken by a rule
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl DynamicPorts {
+    pub fn new(range: Option<RangeInclusive<u16>>) -> Self {
+        Self {
+            range,
+            lock: Default::default(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let range = match std::env::var(ENV_DYNAMIC_PORT_RANGE) {
+            Ok(x) if !x.is_empty() => Some(parse_port_range(&x).unwrap_or_else(|e| panic!("{}", e))),
+            _ => None,
+        };
+        Self::new(range)
+    }
+
+    /// Find the first port in the range which is free on this ip and
+    /// not listened by other rules, try udp as well if `udp` is set.
+    fn pick(&self, ip: IpAddr, tcp: bool, udp: bool, taken: impl Fn(u16) -> bool) -> Option<u16> {
+        let free = |port: u16| {
+            let addr = SocketAddr::new(ip, port);
+            (!tcp || TcpListener::bind(addr).is_ok()) && (!udp || UdpSocket::bind(addr).is_ok())
+        };
+        self.range.clone()?.find(|&port| !taken(port) && free(port))
+    }
+}
+
+fn parse_port_range(s: &str) -> std::result::Result<RangeInclusive<u16>, String> {
+    let err = || format!("invalid dynamic port range: {}", s);
+    let (start, end) = s.split_once('-').ok_or_else(err)?;
+    let start: u16 = start.trim().parse().map_err(|_| err())?;
+    let end: u16 = end.trim().parse().map_err(|_| err())?;
+    if start == 0 || start > end {
+        return Err(err());
+    }
+    Ok(start..=end)
+}
+
+/// Running relays, keyed by endpoint id.
+#[derive(Debug)]
+pub struct Relays {
+    runtime: Handle,
+    // global options for new rules
+    network: NetConf,
+    stops: tokio::sync::Mutex<HashMap<String, Running>>,
+    #[cfg(feature = "transport")]
+    transports: Mutex<BTreeMap<String, SharedTransport>>,
+}
+
+// relays of an endpoint, kept to be launched again
+// if its replacement could not be bound
+#[derive(Debug)]
+struct Running {
+    stop: Stop,
+    info: EndpointInfo,
+}
+
+// listeners of an endpoint, bound before its relays are launched
+enum Bound {
+    Tcp(Endpoint, TcpListeners),
+    Udp(Endpoint, UdpListeners),
+}
+
+impl Relays {
+    /// This must be called inside the relay runtime.
+    pub fn new(network: NetConf) -> Self {
+        Self {
+            runtime: Handle::current(),
+            network,
+            stops: Default::default(),
+            #[cfg(feature = "transport")]
+            transports: Default::default(),
+        }
+    }
+
+    // all listeners are closed if any could not be bound
+    fn bind(&self, info: &EndpointInfo, stop: &Stop) -> Result<Vec<Bound>> {
+        // registered to the relay runtime
+        let _runtime = self.runtime.enter();
+        let EndpointInfo { no_tcp, use_udp, .. } = *info;
+
+        // listeners of a port range share the stop
+        let mut bound = Vec::new();
+        for endpoint in info.expand() {
+            if use_udp {
+                if let Some(x) = bind_udp(&endpoint, stop)? {
+                    bound.push(Bound::Udp(endpoint.clone(), x));
+                }
+            }
+            if !no_tcp {
+                if let Some(x) = bind_tcp(&endpoint, stop)? {
+                    bound.push(Bound::Tcp(endpoint, x));
+                }
+            }
+        }
+        Ok(bound)
+    }
+
+    // a relay stopped to be replaced never finishes
+    fn spawn(&self, stop: &Stop, bound: Vec<Bound>) -> Vec<JoinHandle<Result<()>>> {
+        bound
+            .into_iter()
+            .map(|x| {
+                let stop = stop.clone();
+                self.runtime.spawn(async move {
+                    let result = match x {
+                        Bound::Tcp(endpoint, lis) => run_tcp_with_listeners(endpoint, lis, stop.clone()).await,
+                        Bound::Udp(endpoint, lis) => run_udp_with_listeners(endpoint, lis, stop.clone()).await,
+                    };
+                    if stop.is_stopped() {
+                        std::future::pending::<()>().await;
+                    }
+                    result
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "transport")]
+    fn track_transport(&self, endpoint: &Endpoint) {
+        let id = endpoint.id.clone();
+        let mut transports = self.transports.lock().unwrap_or_else(PoisonError::into_inner);
+        match &endpoint.conn_opts.transport {
+            Some(transport) => transports.insert(id, transport.clone()),
+            None => transports.remove(&id),
+        };
+    }
+
+    /// Resolve once the endpoints have bound the expected number of listeners.
+    ///
+    /// Endpoints removed in the meantime are skipped.
+    pub async fn wait_bound(&self, expected: &[(String, usize)]) {
+        for (id, n) in expected {
+            loop {
+                let stop = self.stops.lock().await.get(id).map(|x| x.stop.clone());
+                match stop {
+                    Some(stop) if stop.bound() < *n => tokio::time::sleep(Duration::from_millis(10)).await,
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    /// Bind the listeners of a new endpoint, then launch its relays.
+    pub async fn start(&self, info: EndpointInfo) -> Result<Vec<JoinHandle<Result<()>>>> {
+        let stop = Stop::new();
+        let bound = self.bind(&info, &stop)?;
+        let workers = self.spawn(&stop, bound);
+        #[cfg(feature = "transport")]
+        self.track_transport(&info.endpoint);
+        let id = info.endpoint.id.clone();
+        self.stops.lock().await.insert(id, Running { stop, info });
+        Ok(workers)
+    }
+
+    /// Stop the relays of an endpoint, then launch the new ones,
+    /// return the old endpoint if any.
+    ///
+    /// Established tcp connections of the old relay are left alone.
+    /// If the new listeners could not be bound, the old relay is
+    /// launched again.
+    pub async fn replace(&self, info: EndpointInfo) -> Result<Option<EndpointInfo>> {
+        let id = info.endpoint.id.clone();
+        let old = self.stops.lock().await.remove(&id);
+        if let Some(old) = &old {
+            old.stop.stop().await;
+        }
+
+        let stop = Stop::new();
+        let bound = match self.bind(&info, &stop) {
+            Ok(x) => x,
+            Err(e) => {
+                if let Some(Running { info: old, .. }) = old {
+                    if let Err(e) = self.start(old).await {
+                        log::error!("[api]failed to restore rule {}: {}", id, e);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        self.spawn(&stop, bound);
+        #[cfg(feature = "transport")]
+        self.track_transport(&info.endpoint);
+        self.stops.lock().await.insert(id, Running { stop, info });
+        Ok(old.map(|x| x.info))
+    }
+
+    /// Stop the relays of an endpoint, the listen address is freed once returned.
+    ///
+    /// Established tcp connections are left alone.
+    pub async fn remove(&self, id: &str) -> bool {
+        #[cfg(feature = "transport")]
+        self.transports.lock().unwrap_or_else(PoisonError::into_inner).remove(id);
+
+        let running = self.stops.lock().await.remove(id);
+        match running {
+            Some(Running { stop, .. }) => {
+                stop.stop().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Transports of the running endpoints.
+    #[cfg(feature = "transport")]
+    pub fn transports(&self) -> Vec<(String, SharedTransport)> {
+        let transports = self.transports.lock().unwrap_or_else(PoisonError::into_inner);
+        transports.iter().map(|(id, x)| (id.clone(), x.clone())).collect()
+    }
+}
+
+/// Current settings of features which could be toggled at runtime.
+///
+/// A field is omitted if the related feature is not compiled in.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RuntimeToggles {
+    pub worker_threads: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_loaded: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_endpoints: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance_endpoints: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport_endpoints: Option<usize>,
+}
+
+impl RuntimeToggles {
+    /// Collect settings from the running endpoints.
+    ///
+    /// This must be called inside the relay runtime.
+    #[allow(unused_variables)]
+    pub fn collect(endpoints: &[EndpointInfo]) -> Self {
+        #[allow(unused_mut)]
+        let mut toggles = RuntimeToggles {
+            worker_threads: tokio::runtime::Handle::current().metrics().num_workers(),
+            ..Default::default()
+        };
+
+        #[cfg(feature = "hook")]
+        {
+            toggles.hook_loaded = Some(realm_core::hook::pre_conn::is_loaded());
+        }
+
+        #[cfg(feature = "proxy")]
+        {
+            let n = endpoints
+                .iter()
+                .filter(|x| {
+                    let opts = &x.endpoint.conn_opts.proxy_opts;
+                    opts.send_proxy || opts.accept_proxy
+                })
+                .count();
+            toggles.proxy_endpoints = Some(n);
+        }
+
+        #[cfg(feature = "balance")]
+        {
+            use realm_core::balance::Strategy;
+            let n = endpoints
+                .iter()
+                .filter(|x| x.endpoint.conn_opts.balancer.load().strategy() != Strategy::Off)
+                .count();
+            toggles.balance_endpoints = Some(n);
+        }
+
+        #[cfg(feature = "transport")]
+        {
+            let n = endpoints
+                .iter()
+                .filter(|x| x.endpoint.conn_opts.transport.is_some())
+                .count();
+            toggles.transport_endpoints = Some(n);
+        }
+
+        toggles
+    }
+}
+
+#[derive(Serialize)]
+struct FeaturesResponse<'a> {
+    version: &'static str,
+    features: &'static Features,
+    runtime: &'a RuntimeToggles,
+}
+
+/// Compiled features and their runtime settings. No auth required.
+#[get("/features")]
+pub async fn get_features(toggles: web::Data<RuntimeToggles>) -> impl Responder {
+    HttpResponse::Ok().json(FeaturesResponse {
+        version: VERSION,
+        features: &FEATURES,
+        runtime: &toggles,
+    })
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    commit: Option<&'static str>,
+    features: Vec<&'static str>,
+    allocator: &'static str,
+}
+
+/// Build info, to tell which build is running. No auth required.
+#[get("/version")]
+pub async fn get_version() -> impl Responder {
+    HttpResponse::Ok().json(VersionResponse {
+        version: VERSION,
+        commit: GIT_COMMIT,
+        features: FEATURES.enabled(),
+        allocator: ALLOCATOR,
+    })
+}
+
+/// Liveness and readiness of the process.
+#[derive(Debug)]
+pub struct Readiness {
+    started: Instant,
+    ready: AtomicBool,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            ready: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Readiness {
+    /// Mark ready once the endpoints from the config are listening.
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    uptime_seconds: u64,
+    active_tcp: usize,
+    active_udp: usize,
+}
+
+/// Liveness probe, always 200 while the api is serving. No auth required.
+#[get("/healthz")]
+pub async fn healthz(readiness: web::Data<Readiness>) -> impl Responder {
+    use realm_core::monitor::{TCP_CONNECTION_METRICS, UDP_ASSOCIATION_METRICS};
+    use realm_core::shutdown::drain_state;
+
+    HttpResponse::Ok().json(HealthResponse {
+        status: if drain_state().is_some() { "draining" } else { "ok" },
+        uptime_seconds: readiness.started.elapsed().as_secs(),
+        active_tcp: TCP_CONNECTION_METRICS.len(),
+        active_udp: UDP_ASSOCIATION_METRICS.len(),
+    })
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    status: &'static str,
+}
+
+/// Readiness probe, 503 until the endpoints from the config are
+/// listening, or once draining. No auth required.
+#[get("/readyz")]
+pub async fn readyz(readiness: web::Data<Readiness>) -> impl Responder {
+    use realm_core::shutdown::drain_state;
+
+    if drain_state().is_some() {
+        HttpResponse::ServiceUnavailable().json(ReadyResponse { status: "draining" })
+    } else if !readiness.is_ready() {
+        HttpResponse::ServiceUnavailable().json(ReadyResponse { status: "starting" })
+    } else {
+        HttpResponse::Ok().json(ReadyResponse { status: "ready" })
+    }
+}
+
+/// Config of a rule, as a toml config file.
+#[get("/rules/{id}/config")]
+pub async fn get_rule_config(
+    id: web::Path<String>,
+    rules: web::Data<RuleConfigs>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    match rules.get(&id) {
+        Some(conf) => Ok(HttpResponse::Ok()
+            .content_type("application/toml")
+            .body(conf.to_redacted_string())),
+        None => Err(ApiError::not_found(format!("Rule not found: {}", id))),
+    }
+}
+
+/// Replace a running rule with a new config.
+///
+/// Traffic counters are kept, and established tcp connections
+/// of the old rule are left alone until they finish.
+#[put("/rules/{id}")]
+pub async fn update_rule(
+    id: web::Path<String>,
+    conf: web::Json<EndpointConf>,
+    rules: web::Data<RuleConfigs>,
+    relays: web::Data<Relays>,
+    store: web::Data<RuleStore>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let mut conf = conf.into_inner();
+    if !rules.contains(&id) {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    }
+    match &conf.id {
+        Some(x) if *x != id => {
+            return Err(ApiError::bad_request(
+                "id_mismatch",
+                format!("Rule id mismatch: {} != {}", x, id),
+            ));
+        }
+        _ => conf.id = Some(id.clone()),
+    }
+
+    let saved = conf.clone();
+    conf.network.take_field(&relays.network);
+    let info = build_rule(&id, conf.clone()).await?;
+    let endpoint = info.endpoint.to_string();
+
+    // the old rule is back if the new one could not listen
+    let old = relays.replace(info).await.map_err(|e| bind_error(&id, e))?;
+
+    // rules from the config file are not saved
+    if store.contains(&id) {
+        if let Err(e) = store.insert(id.clone(), saved) {
+            if let Some(old) = old {
+                if let Err(e) = relays.replace(old).await {
+                    log::error!("[api]failed to restore rule {}: {}", id, e);
+                }
+            }
+            return Err(store_error(format!("Failed to save rule {}: {}", id, e)));
+        }
+    }
+    log::info!("[api]rule {} updated: {}", id, endpoint);
+
+    rules.insert(id, conf.clone());
+    Ok(HttpResponse::Ok()
+        .content_type("application/toml")
+        .body(conf.to_redacted_string()))
+}
+
+fn invalid_config(id: &str, e: impl std::fmt::Display) -> ApiError {
+    ApiError::bad_request("invalid_config", format!("Invalid rule {}: {}", id, e))
+}
+
+// addresses are resolved while building, which blocks
+async fn build_rule(id: &str, conf: EndpointConf) -> std::result::Result<EndpointInfo, ApiError> {
+    match web::block(move || conf.try_build()).await {
+        Ok(Ok(x)) => Ok(x),
+        Ok(Err(e)) => Err(invalid_config(id, e)),
+        Err(e) => Err(ApiError::internal(e.to_string())),
+    }
+}
+
+// the listen address of a rule could not be bound
+fn bind_error(id: &str, e: std::io::Error) -> ApiError {
+    let detail = format!("Failed to listen for rule {}: {}", id, e);
+    match e.kind() {
+        std::io::ErrorKind::AddrInUse => ApiError::new(StatusCode::CONFLICT, "address_in_use", detail),
+        _ => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "bind_error", detail),
+    }
+}
+
+fn rule_exists(id: &str) -> ApiError {
+    ApiError::new(StatusCode::CONFLICT, "rule_exists", format!("Rule already exists: {}", id))
+}
+
+// the rule store is not writable
+fn store_error(detail: String) -> ApiError {
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "store_error", detail)
+}
+
+/// Json extractor config which replies a malformed body as [`ApiError`].
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .error_handler(|e, _| ApiError::bad_request("invalid_json", e.to_string()).into())
+}
+
+// A new rule checked but not launched yet.
+struct NewRule {
+    id: String,
+    // with global options applied
+    conf: EndpointConf,
+    // as saved to the rule store
+    saved: EndpointConf,
+    info: EndpointInfo,
+}
+
+/// Check a new rule, a listen port of 0 is replaced with a free one
+/// which is not listened by running rules or in `listens`.
+///
+/// The lock of [`DynamicPorts`] must be held until the rule is inserted.
+async fn check_rule(
+    mut conf: EndpointConf,
+    rules: &RuleConfigs,
+    relays: &Relays,
+    ports: &DynamicPorts,
+    listens: &[SocketAddr],
+) -> std::result::Result<NewRule, ApiError> {
+    let mut network = conf.network;
+    network.take_field(&relays.network);
+
+    let laddr = conf.listen.parse::<SocketAddr>().ok().filter(|x| x.port() == 0);
+    if let (Some(laddr), Some(range)) = (laddr, &ports.range) {
+        let tcp = !network.no_tcp.unwrap_or_default();
+        let udp = network.use_udp.unwrap_or_default();
+        let running: Vec<_> = rules
+            .all()
+            .into_values()
+            .filter_map(|x| x.listen.parse::<SocketAddr>().ok())
+            .collect();
+        let taken = |port| running.iter().chain(listens).any(|x| x.port() == port);
+        match ports.pick(laddr.ip(), tcp, udp, taken) {
+            Some(port) => conf.listen = SocketAddr::new(laddr.ip(), port).to_string(),
+            None => {
+                return Err(ApiError::new(
+                    StatusCode::INSUFFICIENT_STORAGE,
+                    "no_free_port",
+                    format!("No free port in {}-{}", range.start(), range.end()),
+                ))
+            }
+        }
+    }
+
+    let id = conf.build_id();
+    conf.id = Some(id.clone());
+
+    let saved = conf.clone();
+    conf.network = network;
+    let info = build_rule(&id, conf.clone()).await?;
+    Ok(NewRule { id, conf, saved, info })
+}
+
+/// Add and launch a new rule.
+///
+/// The rule is saved to the rule store if there is one.
+/// A listen port of 0 is replaced with a free one in [`DynamicPorts`].
+#[post("/rules")]
+pub async fn add_rule(
+    conf: web::Json<EndpointConf>,
+    rules: web::Data<RuleConfigs>,
+    relays: web::Data<Relays>,
+    store: web::Data<RuleStore>,
+    ports: web::Data<DynamicPorts>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let _picking = ports.lock.lock().await;
+    let NewRule { id, conf, saved, info } = check_rule(conf.into_inner(), &rules, &relays, &ports, &[]).await?;
+
+    if !rules.try_insert(id.clone(), conf.clone()) {
+        return Err(rule_exists(&id));
+    }
+    let endpoint = info.endpoint.to_string();
+    if let Err(e) = relays.start(info).await {
+        rules.remove(&id);
+        return Err(bind_error(&id, e));
+    }
+    if let Err(e) = store.insert(id.clone(), saved) {
+        relays.remove(&id).await;
+        rules.remove(&id);
+        return Err(store_error(format!("Failed to save rule {}: {}", id, e)));
+    }
+    log::info!("[api]rule {} added: {}", id, endpoint);
+    drop(_picking);
+
+    Ok(HttpResponse::Created()
+        .content_type("application/toml")
+        .body(conf.to_redacted_string()))
+}
+
+#[derive(Serialize)]
+struct BulkError {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    // as of ApiError
+    error: &'static str,
+    detail: String,
+}
+
+impl BulkError {
+    fn new(index: usize, id: Option<String>, e: ApiError) -> Self {
+        Self {
+            index,
+            id,
+            error: e.code(),
+            detail: e.detail().to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
+struct BulkSummary {
+    added: Vec<String>,
+    errors: Vec<BulkError>,
+}
+
+/// Add and launch new rules at once, none of them is added if any is invalid.
+///
+/// Each rule is checked as with [`add_rule`], and ids must differ from
+/// each other as well as the running rules.
+#[post("/rules/bulk")]
+pub async fn add_rules(
+    confs: web::Json<Vec<EndpointConf>>,
+    rules: web::Data<RuleConfigs>,
+    relays: web::Data<Relays>,
+    store: web::Data<RuleStore>,
+    ports: web::Data<DynamicPorts>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let _picking = ports.lock.lock().await;
+    let mut summary = BulkSummary::default();
+    let mut checked: Vec<NewRule> = Vec::new();
+    for (index, conf) in confs.into_inner().into_iter().enumerate() {
+        // ports picked for the previous ones are taken as well
+        let listens: Vec<_> = checked
+            .iter()
+            .filter_map(|x| x.conf.listen.parse::<SocketAddr>().ok())
+            .collect();
+        let rule = match check_rule(conf, &rules, &relays, &ports, &listens).await {
+            Ok(x) => x,
+            Err(e) => {
+                summary.errors.push(BulkError::new(index, None, e));
+                continue;
+            }
+        };
+        if rules.contains(&rule.id) || checked.iter().any(|x| x.id == rule.id) {
+            summary.errors.push(BulkError::new(index, Some(rule.id.clone()), rule_exists(&rule.id)));
+            continue;
+        }
+        checked.push(rule);
+    }
+    if !summary.errors.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(summary));
+    }
+
+    // the lock of ports keeps other rules from being added meanwhile
+    for (i, rule) in checked.iter().enumerate() {
+        if !rules.try_insert(rule.id.clone(), rule.conf.clone()) {
+            checked[..i].iter().for_each(|x| drop(rules.remove(&x.id)));
+            return Err(rule_exists(&rule.id));
+        }
+    }
+
+    // all of them are listening before saved, or none is kept
+    let mut started = Vec::with_capacity(checked.len());
+    for rule in &checked {
+        match relays.start(rule.info.clone()).await {
+            Ok(_) => started.push(rule.id.as_str()),
+            Err(e) => {
+                for id in started {
+                    relays.remove(id).await;
+                }
+                checked.iter().for_each(|x| drop(rules.remove(&x.id)));
+                return Err(bind_error(&rule.id, e));
+            }
+        }
+    }
+    if let Err(e) = store.insert_all(checked.iter().map(|x| (x.id.clone(), x.saved.clone()))) {
+        for rule in &checked {
+            relays.remove(&rule.id).await;
+            rules.remove(&rule.id);
+        }
+        return Err(store_error(format!("Failed to save rules: {}", e)));
+    }
+    drop(_picking);
+
+    for rule in checked {
+        log::info!("[api]rule {} added: {}", rule.id, rule.info.endpoint);
+        summary.added.push(rule.id);
+    }
+    Ok(HttpResponse::Created().json(summary))
+}
+
+/// Stop and remove a rule, including the saved one.
+///
+/// Established tcp connections are left alone until they finish.
+#[delete("/rules/{id}")]
+pub async fn delete_rule(
+    id: web::Path<String>,
+    rules: web::Data<RuleConfigs>,
+    relays: web::Data<Relays>,
+    store: web::Data<RuleStore>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    if !rules.contains(&id) {
+        return Err(ApiError::not_found(format!("Rule not found: {}", id)));
+    }
+    if let Err(e) = store.remove(&id) {
+        return Err(store_error(format!("Failed to save rules: {}", e)));
+    }
+
+    relays.remove(&id).await;
+    rules.remove(&id);
+    RULE_METRICS.remove(&id);
+    log::info!("[api]rule {} deleted", id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Serialize)]
+struct DeleteSummary {
+    deleted: Vec<String>,
+}
+
+/// Stop and remove all rules, including the saved ones.
+///
+/// Established tcp connections are left alone until they finish.
+#[delete("/rules")]
+pub async fn delete_rules(
+    rules: web::Data<RuleConfigs>,
+    relays: web::Data<Relays>,
+    store: web::Data<RuleStore>,
+) -> std::result::Result<HttpResponse, ApiError> {
+    if let Err(e) = store.clear() {
+        return Err(store_error(format!("Failed to save rules: {}", e)));
+    }
+
+    let deleted: Vec<_> = rules.all().into_keys().collect();
+    for id in &deleted {
+        relays.remove(id).await;
+        rules.remove(id);
+        RULE_METRICS.remove(id);
+        log::info!("[api]rule {} deleted", id);
+    }
+
+    Ok(HttpResponse::Ok().json(DeleteSummary { deleted }))
+}
+
+#[cfg(feature = "transport")]
+#[derive(Serialize)]
+struct TlsReloadInfo {
+    id: String,
+    reloaded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Read tls certificates and keys of all rules again.
+///
+/// New handshakes use the new certificate while established connections
+/// are left alone. A rule keeps the old certificate if it fails to reload.
+#[cfg(feature = "transport")]
+#[post("/tls/reload")]
+pub async fn reload_tls(rules: web::Data<RuleConfigs>, relays: web::Data<Relays>) -> impl Responder {
+    let mut reloads = Vec::new();
+    for (id, transport) in relays.transports() {
+        let Some(conf) = rules.get(&id) else {
+            continue;
+        };
+        let info = match conf.reload_transport() {
+            Ok(None) => continue,
+            Ok(Some(ac)) => {
+                let cc = transport.load().1.clone();
+                transport.store(std::sync::Arc::new((ac, cc)));
+                log::info!("[api]rule {} tls reloaded", id);
+                TlsReloadInfo {
+                    id,
+                    reloaded: true,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                log::warn!("[api]rule {} failed to reload tls: {}", id, e);
+                TlsReloadInfo {
+                    id,
+                    reloaded: false,
+                    error: Some(e),
+                }
+            }
+        };
+        reloads.push(info);
+    }
+
+    if reloads.iter().all(|x| x.reloaded) {
+        HttpResponse::Ok().json(reloads)
+    } else {
+        HttpResponse::InternalServerError().json(reloads)
+    }
+}
+
+#[derive(Serialize, Default)]
+struct QuarantineInfo {
+    quarantined: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_seconds: Option<u64>,
+    count: u64,
+}
+
+#[derive(Serialize, Default)]
+struct ConnectInfo {
+    window_seconds: u64,
+    attempts: u64,
+    successes: u64,
+    // none if there is no attempt
+    success_ratio: Option<f64>,
+    // of successful connects, none if there is no success
+    latency_avg_ms: Option<f64>,
+    latency_p95_ms: Option<u64>,
+    latency_max_ms: Option<u64>,
+}
+
+/// Effective options of a rule, with defaults filled in.
+#[derive(Serialize)]
+struct RuleOptions<'a> {
+    no_tcp: bool,
+    use_udp: bool,
+
+    // bind
+    ipv6_only: bool,
+    reuse_address: bool,
+    reuse_port_workers: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp_linger: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    send_buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recv_buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listen_backlog: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listen_interface: Option<&'a str>,
+
+    // connect
+    tcp_timeout: usize,
+    connect_retries: usize,
+    connect_retry_delay: usize,
+    udp_timeout: usize,
+    idle_timeout: usize,
+    tcp_keepalive: usize,
+    tcp_keepalive_probe: usize,
+    tcp_nodelay: bool,
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    udp_associate_concurrency: usize,
+    udp_batch_size: usize,
+    max_bytes: usize,
+    rate_limit_bps: u64,
+    write_coalesce_size: usize,
+    write_coalesce_delay: usize,
+    upstream_eof: UpstreamEof,
+    byte_count: ByteCount,
+    dscp: u8,
+    quarantine_threshold: usize,
+    quarantine_window: usize,
+    quarantine_cooldown: usize,
+    dns_round_robin: bool,
+    #[cfg(feature = "happy-eyeballs")]
+    happy_eyeballs_delay: usize,
+    #[cfg(feature = "mptcp")]
+    mptcp: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    through: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interface: Option<&'a str>,
+
+    #[cfg(feature = "proxy")]
+    send_proxy: bool,
+    #[cfg(feature = "proxy")]
+    send_proxy_version: usize,
+    #[cfg(feature = "proxy")]
+    accept_proxy: bool,
+    #[cfg(feature = "proxy")]
+    accept_proxy_timeout: usize,
+    #[cfg(feature = "proxy")]
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    send_proxy_tlvs: &'a [String],
+    #[cfg(feature = "proxy")]
+    accept_proxy_tlvs: bool,
+
+    #[cfg(feature = "hook")]
+    hook_fail_mode: realm_core::endpoint::HookFailMode,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<&'a str>,
+    #[cfg(feature = "balance")]
+    eject_threshold: usize,
+    #[cfg(feature = "balance")]
+    eject_cooldown: usize,
+    #[cfg(feature = "balance")]
+    health_check_interval: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listen_transport: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_transport: Option<&'a str>,
+}
+
+impl<'a> RuleOptions<'a> {
+    fn new(conf: &'a EndpointConf) -> Self {
+        let NetInfo {
+            bind_opts,
+            conn_opts,
+            no_tcp,
+            use_udp,
+        } = conf.network.build();
+
+        RuleOptions {
+            no_tcp,
+            use_udp,
+            ipv6_only: bind_opts.ipv6_only,
+            reuse_address: bind_opts.reuse_address,
+            reuse_port_workers: bind_opts.workers(),
+            tcp_linger: bind_opts.tcp_linger,
+            send_buffer_size: bind_opts.send_buffer_size,
+            recv_buffer_size: bind_opts.recv_buffer_size,
+            listen_backlog: bind_opts.listen_backlog,
+            listen_interface: conf.listen_interface.as_deref(),
+            tcp_timeout: conn_opts.connect_timeout,
+            connect_retries: conn_opts.connect_retries,
+            connect_retry_delay: conn_opts.connect_retry_delay,
+            udp_timeout: conn_opts.effective_associate_timeout(),
+            idle_timeout: conn_opts.idle_timeout,
+            tcp_keepalive: conn_opts.tcp_keepalive,
+            tcp_keepalive_probe: conn_opts.tcp_keepalive_probe,
+            tcp_nodelay: conn_opts.tcp_nodelay,
+            max_connections: conn_opts.max_connections,
+            max_connections_per_ip: conn_opts.max_connections_per_ip,
+            udp_associate_concurrency: conn_opts.associate_concurrency,
+            udp_batch_size: conn_opts.udp_batch_size,
+            max_bytes: conn_opts.max_bytes,
+            rate_limit_bps: conn_opts.rate_limit_bps,
+            write_coalesce_size: conn_opts.write_coalesce_size,
+            write_coalesce_delay: conn_opts.write_coalesce_delay,
+            upstream_eof: conn_opts.upstream_eof,
+            byte_count: conn_opts.byte_count,
+            dscp: conn_opts.dscp,
+            quarantine_threshold: conn_opts.quarantine.threshold,
+            quarantine_window: conn_opts.quarantine.window,
+            quarantine_cooldown: conn_opts.quarantine.cooldown,
+            dns_round_robin: conn_opts.dns_round_robin,
+            #[cfg(feature = "happy-eyeballs")]
+            happy_eyeballs_delay: conn_opts.happy_eyeballs_delay,
+            #[cfg(feature = "mptcp")]
+            mptcp: conn_opts.mptcp,
+            through: conf.through.as_deref(),
+            interface: conf.interface.as_deref(),
+            #[cfg(feature = "proxy")]
+            send_proxy: conn_opts.proxy_opts.send_proxy,
+            #[cfg(feature = "proxy")]
+            send_proxy_version: conn_opts.proxy_opts.send_proxy_version,
+            #[cfg(feature = "proxy")]
+            accept_proxy: conn_opts.proxy_opts.accept_proxy,
+            #[cfg(feature = "proxy")]
+            accept_proxy_timeout: conn_opts.proxy_opts.accept_proxy_timeout,
+            #[cfg(feature = "proxy")]
+            send_proxy_tlvs: &conf.send_proxy_tlvs,
+            #[cfg(feature = "proxy")]
+            accept_proxy_tlvs: conn_opts.proxy_opts.accept_proxy_tlvs,
+            #[cfg(feature = "hook")]
+            hook_fail_mode: conn_opts.hook_fail_mode,
+            balance: conf.balance.as_deref(),
+            #[cfg(feature = "balance")]
+            eject_threshold: conn_opts.eject.threshold,
+            #[cfg(feature = "balance")]
+            eject_cooldown: conn_opts.eject.cooldown,
+            #[cfg(feature = "balance")]
+            health_check_interval: conn_opts.health_check_interval,
+            listen_transport: conf.listen_transport.as_deref(),
+            remote_transport: conf.remote_transport.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RuleInfo<'a> {
+    id: &'a str,
+    listen: &'a str,
+    remote: &'a str,
+    extra_remotes: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp_remote: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    udp_remote: Option<&'a str>,
+    options: RuleOptions<'a>,
+    quarantine: QuarantineInfo,
+    connect: ConnectInfo,
+    // packets dropped by udp_associate_concurrency
+    udp_associate_dropped: u64,
+    // refused by acl
+    rejected_filtered: u64,
+    udp_filtered_dropped: u64,
+}
+
+fn quarantine_info(id: &str) -> QuarantineInfo {
+    let Some(rule) = RULE_METRICS.get(id).map(|x| x.value().clone()) else {
+        return QuarantineInfo::default();
+    };
+    let Ok(mut quarantine) = rule.quarantine.lock() else {
+        return QuarantineInfo::default();
+    };
+    let remaining = quarantine.remaining();
+    QuarantineInfo {
+        quarantined: remaining.is_some(),
+        remaining_seconds: remaining.map(|x| x.as_secs()),
+        count: quarantine.count,
+    }
+}
+
+fn connect_info(id: &str) -> ConnectInfo {
+    let window_seconds = CONNECT_STATS_WINDOW.as_secs();
+    let Some(rule) = RULE_METRICS.get(id).map(|x| x.value().clone()) else {
+        return ConnectInfo {
+            window_seconds,
+            ..Default::default()
+        };
+    };
+    let Ok(mut connects) = rule.connects.lock() else {
+        return ConnectInfo {
+            window_seconds,
+            ..Default::default()
+        };
+    };
+    let (attempts, successes) = connects.get();
+    let latency = connects.latency();
+    ConnectInfo {
+        window_seconds,
+        attempts,
+        successes,
+        success_ratio: connects.ratio(),
+        latency_avg_ms: latency.map(|x| x.avg_ms),
+        latency_p95_ms: latency.map(|x| x.p95_ms),
+        latency_max_ms: latency.map(|x| x.max_ms),
+    }
+}
+
+fn counter(id: &str, f: impl Fn(&RuleMetrics) -> &AtomicU64) -> u64 {
+    RULE_METRICS.get(id).map_or(0, |x| f(x.value()).load(Ordering::Relaxed))
+}
+
+/// All running rules.
+#[get("/rules")]
+pub async fn list_rules(rules: web::Data<RuleConfigs>) -> impl Responder {
+    let rules = rules.all();
+    let rules: Vec<_> = rules
+        .iter()
+        .map(|(id, conf)| RuleInfo {
+            id,
+            listen: &conf.listen,
+            remote: &conf.remote,
+            extra_remotes: &conf.extra_remotes,
+            tcp_remote: conf.tcp_remote.as_deref(),
+            udp_remote: conf.udp_remote.as_deref(),
+            options: RuleOptions::new(conf),
+            quarantine: quarantine_info(id),
+            connect: connect_info(id),
+            udp_associate_dropped: counter(id, |x| &x.associate_dropped),
+            rejected_filtered: counter(id, |x| &x.rejected_filtered),
+            udp_filtered_dropped: counter(id, |x| &x.filtered_dropped),
+        })
+        .collect();
+    HttpResponse::Ok().json(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_log_headers() {
+        use actix_web::http::header::{HeaderName, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        for (k, v) in [("authorization", "Bearer x"), ("user-agent", "curl"), ("cookie", "a=b")] {
+            headers.insert(HeaderName::from_static(k), HeaderValue::from_static(v));
+        }
+        let log = ApiLog::default();
+        let mut logged = log.headers(&headers);
+        logged.sort();
+        assert_eq!(logged, [("cookie", "a=b"), ("user-agent", "curl")]);
+
+        let log = ApiLog {
+            deny: vec!["cookie".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(log.headers(&headers), [("user-agent", "curl")]);
+
+        let log = ApiLog {
+            allow: vec!["cookie".to_string(), "authorization".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(log.headers(&headers), [("cookie", "a=b")]);
+    }
+
+    #[test]
+    fn api_tokens() {
+        let key = |label: &str, permission| ApiKey {
+            label: label.to_string(),
+            permission,
+        };
+
+        assert!(parse_token(" ").is_none());
+        assert_eq!(parse_token("ops: abc ").unwrap().unwrap().key, key("ops", Permission::Write));
+        assert_eq!(parse_token("mon:abc:read").unwrap().unwrap().key, key("mon", Permission::Read));
+        assert!(parse_token("abc").unwrap().is_err());
+        assert!(parse_token(":abc").unwrap().is_err());
+        assert!(parse_token("ops:abc:admin").unwrap().is_err());
+
+        let auth = ApiAuth::new(["ops:abc", "monitor:xyz:read", ""]).unwrap();
+        assert_eq!(auth.check("abc"), Some(&key("ops", Permission::Write)));
+        assert_eq!(auth.check("xyz"), Some(&key("monitor", Permission::Read)));
+        assert_eq!(auth.check("ab"), None);
+        assert_eq!(auth.check("abcd"), None);
+        assert_eq!(auth.check(""), None);
+        assert!(ApiAuth::new(["ops"]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn api_authenticate() {
+        use actix_web::{test, App};
+
+        let auth = ApiAuth::new(["ops:abc", "monitor:xyz:read"]).unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(actix_web::middleware::from_fn(authenticate))
+                .app_data(web::Data::new(auth))
+                .route("/rules/{id}", web::get().to(HttpResponse::Ok))
+                .route("/rules/{id}", web::delete().to(HttpResponse::NoContent)),
+        )
+        .await;
+
+        let call = |method: &str, token: Option<&str>| {
+            let req = test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri("/rules/a");
+            let req = match token {
+                Some(x) => req.insert_header((AUTHORIZATION, format!("Bearer {}", x))),
+                None => req,
+            };
+            test::call_service(&app, req.to_request())
+        };
+
+        assert_eq!(call("GET", None).await.status(), 401);
+        assert_eq!(call("GET", Some("abd")).await.status(), 401);
+
+        let res = call("GET", Some("abc")).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.request().extensions().get::<ApiKey>().unwrap().label, "ops");
+        assert_eq!(call("DELETE", Some("abc")).await.status(), 204);
+
+        // read-only
+        assert_eq!(call("GET", Some("xyz")).await.status(), 200);
+        let res = call("DELETE", Some("xyz")).await;
+        assert_eq!(res.status(), 403);
+        assert_eq!(res.request().extensions().get::<ApiKey>().unwrap().label, "monitor");
+    }
+
+    #[actix_web::test]
+    async fn api_probes() {
+        use actix_web::{test, App};
+
+        let readiness = web::Data::new(Readiness::default());
+        let app = test::init_service(
+            App::new()
+                .wrap(actix_web::middleware::from_fn(authenticate))
+                .app_data(web::Data::new(ApiAuth::new(["ops:abc"]).unwrap()))
+                .app_data(readiness.clone())
+                .service(healthz)
+                .service(readyz)
+                .service(get_version)
+                .service(get_features)
+                .app_data(web::Data::new(RuntimeToggles {
+                    worker_threads: 2,
+                    ..Default::default()
+                }))
+                .route("/rules", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let call = |uri: &str| test::call_service(&app, test::TestRequest::get().uri(uri).to_request());
+
+        assert_eq!(call("/rules").await.status(), 401);
+        let res = call("/healthz").await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], "ok");
+        assert!(body["active_tcp"].is_u64() && body["active_udp"].is_u64());
+
+        let res = call("/readyz").await;
+        assert_eq!(res.status(), 503);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], "starting");
+
+        readiness.set_ready();
+        assert_eq!(call("/readyz").await.status(), 200);
+
+        let res = call("/version").await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["version"], VERSION);
+        assert_eq!(body["commit"].as_str(), GIT_COMMIT);
+        assert_eq!(body["features"].as_array().unwrap().len(), FEATURES.enabled().len());
+        assert_eq!(body["allocator"], ALLOCATOR);
+
+        let res = call("/features").await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["version"], VERSION);
+        assert_eq!(body["runtime"]["worker_threads"], 2);
+    }
+
+    #[test]
+    fn api_rate_limit() {
+        assert!(parse_rate_limit("0").unwrap().is_none());
+        assert!(parse_rate_limit("10/0").is_err());
+        assert!(parse_rate_limit("x").is_err());
+        let limiter = parse_rate_limit(" 120 ").unwrap().unwrap();
+        assert_eq!((limiter.limit, limiter.window), (120, Duration::from_secs(60)));
+
+        let limiter = ApiRateLimit::new(3, Duration::from_millis(100));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        for _ in 0..3 {
+            assert!(limiter.check(a));
+        }
+        assert!(!limiter.check(a));
+        assert!(limiter.check(b));
+
+        // a new window, stale counters are dropped
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(limiter.check(a));
+        assert_eq!(limiter.counters.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn api_rate_limit_before_auth() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .wrap(actix_web::middleware::from_fn(authenticate))
+                .wrap(actix_web::middleware::from_fn(rate_limit))
+                .app_data(web::Data::new(ApiAuth::new(["ops:abc"]).unwrap()))
+                .app_data(web::Data::new(ApiRateLimit::new(2, Duration::from_secs(60))))
+                .route("/rules", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let peer: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let call = |token: &str| {
+            let req = test::TestRequest::get()
+                .uri("/rules")
+                .peer_addr(peer)
+                .insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+            test::call_service(&app, req.to_request())
+        };
+        assert_eq!(call("xyz").await.status(), 401);
+        assert_eq!(call("abc").await.status(), 200);
+        assert_eq!(call("abc").await.status(), 429);
+    }
+
+    #[test]
+    fn api_tls() {
+        let some = |x: &str| Some(x.to_string());
+        assert_eq!(ApiTls::new(None, None), Ok(None));
+        let tls = ApiTls::new(some("cert.pem"), some("key.pem")).unwrap().unwrap();
+        assert_eq!((tls.cert.as_str(), tls.key.as_str()), ("cert.pem", "key.pem"));
+        assert_eq!(ApiTls::new(some("cert.pem"), None).unwrap_err(), "API_TLS_CERT is set without API_TLS_KEY");
+        assert_eq!(ApiTls::new(None, some("key.pem")).unwrap_err(), "API_TLS_KEY is set without API_TLS_CERT");
+    }
+
+    #[test]
+    fn api_enabled() {
+        assert_eq!(parse_enabled("false"), Ok(false));
+        assert_eq!(parse_enabled(" OFF "), Ok(false));
+        assert_eq!(parse_enabled("1"), Ok(true));
+        assert_eq!(parse_enabled("disabled").unwrap_err(), "invalid API_ENABLED: disabled");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn api_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("realm-api-{}.sock", std::process::id()));
+        let stale = bind_unix_socket(&path).unwrap();
+        drop(stale);
+
+        // the stale socket file is replaced
+        let listener = bind_unix_socket(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        UnixStream::connect(&path).unwrap();
+        assert!(listener.accept().is_ok());
+
+        // other files are left alone
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "x").unwrap();
+        assert!(bind_unix_socket(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dynamic_ports() {
+        assert_eq!(parse_port_range("30000-30999"), Ok(30000..=30999));
+        assert_eq!(parse_port_range(" 8080 - 8080 "), Ok(8080..=8080));
+        assert!(parse_port_range("30999-30000").is_err());
+        assert!(parse_port_range("0-100").is_err());
+        assert!(parse_port_range("30000").is_err());
+
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(DynamicPorts::default().pick(ip, true, true, |_| false), None);
+
+        let start = free_ports(3);
+        let _lis = TcpListener::bind(("127.0.0.1", start)).unwrap();
+        let ports = DynamicPorts::new(Some(start..=start + 2));
+        assert_eq!(ports.pick(ip, true, false, |_| false), Some(start + 1));
+        assert_eq!(ports.pick(ip, true, false, |x| x == start + 1), Some(start + 2));
+        // udp only
+        assert_eq!(ports.pick(ip, false, true, |_| false), Some(start));
+        assert_eq!(ports.pick(ip, true, false, |x| x != start), None);
+    }
+
+    #[test]
+    fn rule_store() {
+        let path = std::env::temp_dir().join(format!("realm-rules-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let store = RuleStore::load(path.clone());
+        assert!(store.rules().is_empty());
+
+        let conf: EndpointConf = serde_json::from_str(r#"{"listen":"127.0.0.1:5000","remote":"127.0.0.1:443"}"#).unwrap();
+        store.insert(conf.build_id(), conf).unwrap();
+        assert!(store.contains("127.0.0.1:5000"));
+
+        // saved
+        let store = RuleStore::load(path.clone());
+        let rules = store.rules();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].remote, "127.0.0.1:443");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn bulk_rules() {
+        use actix_web::{test, App};
+
+        let relays = web::Data::new(Relays::new(NetConf::default()));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RuleConfigs::default()))
+                .app_data(relays)
+                .app_data(web::Data::new(RuleStore::default()))
+                .app_data(web::Data::new(DynamicPorts::default()))
+                .service(add_rules)
+                .service(delete_rules)
+                .service(list_rules),
+        )
+        .await;
+        let bulk = |body: serde_json::Value| test::TestRequest::post().uri("/rules/bulk").set_json(body);
+        let rule = |listen: &str| serde_json::json!({"listen": listen, "remote": "127.0.0.1:20390"});
+        let list = || async {
+            let res = test::call_service(&app, test::TestRequest::get().uri("/rules").to_request()).await;
+            let list: serde_json::Value = test::read_body_json(res).await;
+            list.as_array().unwrap().len()
+        };
+        let (a, b) = (free_addr().to_string(), free_addr().to_string());
+
+        // none is added if any is invalid
+        let body = serde_json::json!([rule(&a), rule("127.0.0.1:x"), rule(&a)]);
+        let res = test::call_service(&app, bulk(body).to_request()).await;
+        assert_eq!(res.status(), 400);
+        let summary: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(summary["added"].as_array().unwrap().len(), 0);
+        assert_eq!(summary["errors"][0]["index"], 1);
+        assert_eq!(summary["errors"][1]["index"], 2);
+        assert_eq!(summary["errors"][1]["id"], a.as_str());
+        assert_eq!(summary["errors"][1]["error"], "rule_exists");
+        assert_eq!(list().await, 0);
+
+        // or could not listen, the others are stopped
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let body = serde_json::json!([rule(&a), rule(&taken.local_addr().unwrap().to_string())]);
+        let res = test::call_service(&app, bulk(body).to_request()).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "address_in_use");
+        assert_eq!(list().await, 0);
+        TcpListener::bind(&a).unwrap();
+
+        let body = serde_json::json!([rule(&a), rule(&b)]);
+        let res = test::call_service(&app, bulk(body).to_request()).await;
+        assert_eq!(res.status(), 201);
+        let summary: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(summary["added"], serde_json::json!([a, b]));
+        assert!(std::net::TcpStream::connect(&b).is_ok());
+
+        // taken by running rules
+        let body = serde_json::json!([rule(&b)]);
+        assert_eq!(test::call_service(&app, bulk(body).to_request()).await.status(), 400);
+
+        let res = test::call_service(&app, test::TestRequest::delete().uri("/rules").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let summary: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(summary["deleted"].as_array().unwrap().len(), 2);
+        TcpListener::bind(&a).unwrap();
+        TcpListener::bind(&b).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn api_errors() {
+        use actix_web::{test, App};
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RuleConfigs::default()))
+                .app_data(web::Data::new(Relays::new(NetConf::default())))
+                .app_data(web::Data::new(RuleStore::default()))
+                .app_data(web::Data::new(DynamicPorts::default()))
+                .app_data(json_config())
+                .service(add_rule)
+                .service(delete_rule),
+        )
+        .await;
+        let call = |req: test::TestRequest| test::call_service(&app, req.to_request());
+        let add = |body: serde_json::Value| test::TestRequest::post().uri("/rules").set_json(body);
+        let (laddr, raddr) = (free_addr(), free_addr());
+        let rule = serde_json::json!({"listen": laddr.to_string(), "remote": raddr.to_string()});
+
+        let res = call(add(serde_json::json!({"listen": "127.0.0.1:x", "remote": raddr.to_string()}))).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "invalid_config");
+        assert!(body["detail"].as_str().unwrap().starts_with("Invalid rule 127.0.0.1:x"));
+
+        let req = test::TestRequest::post()
+            .uri("/rules")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{");
+        let res = call(req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "invalid_json");
+
+        // taken by someone else, the rule is not added
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let in_use = serde_json::json!({"listen": taken.local_addr().unwrap().to_string(), "remote": raddr.to_string()});
+        let res = call(add(in_use)).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "address_in_use");
+        let uri = format!("/rules/{}", taken.local_addr().unwrap());
+        assert_eq!(call(test::TestRequest::delete().uri(&uri)).await.status(), 404);
+
+        // listening once added
+        assert_eq!(call(add(rule.clone())).await.status(), 201);
+        assert!(std::net::TcpStream::connect(laddr).is_ok());
+        let res = call(add(rule)).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "rule_exists");
+        assert_eq!(body["detail"], format!("Rule already exists: {}", laddr));
+
+        let uri = format!("/rules/{}", laddr);
+        assert_eq!(call(test::TestRequest::delete().uri(&uri)).await.status(), 204);
+        let res = call(test::TestRequest::delete().uri(&uri)).await;
+        assert_eq!(res.status(), 404);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        let detail = format!("Rule not found: {}", laddr);
+        assert_eq!(body, serde_json::json!({"error": "not_found", "detail": detail}));
+    }
+
+    #[tokio::test]
+    async fn remove_relay() {
+        use tokio::net::{TcpListener, TcpStream};
+
+        let laddr = free_addr();
+        let conf = EndpointConf {
+            id: Some("remove".to_string()),
+            ..endpoint(laddr, free_addr())
+        };
+        let relays = Relays::new(NetConf::default());
+        relays.start(conf.try_build().unwrap()).await.unwrap();
+        assert!(TcpStream::connect(laddr).await.is_ok());
+
+        assert!(relays.remove("remove").await);
+        assert!(!relays.remove("remove").await);
+
+        // the listen address is freed
+        TcpListener::bind(laddr).await.unwrap();
+    }
+
+    // an unused local address
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    // the first of n contiguous local ports, unused by tcp and udp
+    fn free_ports(n: u16) -> u16 {
+        let free = |port| TcpListener::bind(("127.0.0.1", port)).is_ok() && UdpSocket::bind(("127.0.0.1", port)).is_ok();
+        loop {
+            let start = free_addr().port();
+            if start.checked_add(n - 1).is_some() && (start..start + n).all(free) {
+                return start;
+            }
+        }
+    }
+
+    fn endpoint(listen: SocketAddr, remote: SocketAddr) -> EndpointConf {
+        let conf = serde_json::json!({"listen": listen.to_string(), "remote": remote.to_string()});
+        serde_json::from_value(conf).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn rule_config_redacted() {
+        use actix_web::{test, App};
+
+        let (laddr, raddr) = (free_addr(), free_addr());
+        let conf = EndpointConf {
+            id: Some("socks5".to_string()),
+            socks5_proxy: Some("u:secret@127.0.0.1:1080".to_string()),
+            ..endpoint(laddr, raddr)
+        };
+        let relays = web::Data::new(Relays::new(NetConf::default()));
+        relays.start(conf.clone().try_build().unwrap()).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RuleConfigs::new(std::slice::from_ref(&conf))))
+                .app_data(relays.clone())
+                .app_data(web::Data::new(RuleStore::default()))
+                .app_data(json_config())
+                .service(get_rule_config)
+                .service(update_rule),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/rules/socks5/config");
+        let body = test::call_and_read_body(&app, req.to_request()).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("u:***@127.0.0.1:1080"));
+        assert!(!body.contains("secret"));
+
+        let req = test::TestRequest::put().uri("/rules/socks5").set_json(&conf);
+        let body = test::call_and_read_body(&app, req.to_request()).await;
+        assert!(!String::from_utf8_lossy(&body).contains("secret"));
+        assert!(relays.remove("socks5").await);
+    }
+
+    #[actix_web::test]
+    async fn update_rule_in_use() {
+        use actix_web::{test, App};
+
+        let (laddr, raddr) = (free_addr(), free_addr());
+        let conf = EndpointConf {
+            id: Some("in-use".to_string()),
+            ..endpoint(laddr, raddr)
+        };
+        let relays = web::Data::new(Relays::new(NetConf::default()));
+        relays.start(conf.clone().try_build().unwrap()).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RuleConfigs::new(&[conf])))
+                .app_data(relays.clone())
+                .app_data(web::Data::new(RuleStore::default()))
+                .app_data(json_config())
+                .service(update_rule),
+        )
+        .await;
+
+        // taken by someone else
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let body = endpoint(taken.local_addr().unwrap(), raddr);
+        let req = test::TestRequest::put().uri("/rules/in-use").set_json(&body);
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "address_in_use");
+
+        // the old one keeps running
+        assert!(std::net::TcpStream::connect(laddr).is_ok());
+        assert!(relays.remove("in-use").await);
+    }
+
+    #[tokio::test]
+    async fn port_range_relay() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listen = free_ports(3);
+        let remote = loop {
+            let remote = free_ports(3);
+            if remote.abs_diff(listen) >= 3 {
+                break remote;
+            }
+        };
+        let conf = serde_json::json!({
+            "id": "range",
+            "listen": format!("127.0.0.1:{}-{}", listen, listen + 2),
+            "remote": format!("127.0.0.1:{}-{}", remote, remote + 2),
+        });
+        let conf: EndpointConf = serde_json::from_value(conf).unwrap();
+        let relays = Relays::new(NetConf::default());
+        let started = relays.start(conf.try_build().unwrap()).await.unwrap();
+        assert_eq!(started.len(), 3);
+        relays.wait_bound(&[("range".to_string(), 3)]).await;
+
+        // each port to its mirrored remote port
+        for i in 0..3 {
+            let lis = TcpListener::bind(("127.0.0.1", remote + i)).await.unwrap();
+            let mut client = TcpStream::connect(("127.0.0.1", listen + i)).await.unwrap();
+            let (mut server, _) = lis.accept().await.unwrap();
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            server.read_exact(&mut buf).await.unwrap();
+        }
+
+        // under the one rule
+        let rule = RULE_METRICS.get("range").map(|x| x.value().clone()).unwrap();
+        assert_eq!(rule.connects.lock().unwrap().get(), (3, 3));
+
+        // all of them are stopped together
+        assert!(relays.remove("range").await);
+        for port in listen..=listen + 2 {
+            TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        }
+    }
+}
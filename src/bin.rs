@@ -2,9 +2,12 @@ use std::env;
 use cfg_if::cfg_if;
 
 mod api;
+mod reload;
+mod selftest;
 use realm::cmd;
-use realm::conf::{Config, FullConf, LogConf, DnsConf, EndpointInfo};
-use realm::ENV_CONFIG;
+use realm::conf::{CmdOverride, Config, FullConf, LogConf, DnsConf, NetConf, MetricsConf, EndpointConf, EndpointInfo};
+use realm::{ENV_CONFIG, ENV_LOG_FORMAT, ENV_API_WORKERS, ENV_CONNECT_TIMEOUT, ENV_DRAIN_TIMEOUT, ENV_SPEED_INTERVAL};
+use realm::consts::{API_WORKERS, DRAIN_TIMEOUT, SPEED_INTERVAL};
 
 cfg_if! {
     if #[cfg(feature = "mi-malloc")] {
@@ -23,10 +26,11 @@ cfg_if! {
 }
 
 fn main() {
-    let conf = 'blk: {
+    // where to reload endpoints from
+    let (conf, source) = 'blk: {
         if let Ok(conf_str) = env::var(ENV_CONFIG) {
             if let Ok(conf) = FullConf::from_conf_str(&conf_str) {
-                break 'blk conf;
+                break 'blk (conf, Some(reload::Source::Env(conf_str)));
             }
         };
 
@@ -35,75 +39,175 @@ fn main() {
             CmdInput::Endpoint(ep, opts) => {
                 let mut conf = FullConf::default();
                 conf.add_endpoint(ep).apply_global_opts().apply_cmd_opts(opts);
-                conf
+                (conf, None)
             }
-            CmdInput::Config(conf, opts) => {
-                let mut conf = FullConf::from_conf_file(&conf);
-                conf.apply_global_opts().apply_cmd_opts(opts);
-                conf
+            CmdInput::Config(file, opts) => {
+                let mut conf = FullConf::from_conf_file(&file);
+                conf.apply_global_opts().apply_cmd_opts(opts.clone());
+                (conf, Some(reload::Source::File(file, Box::new(opts))))
             }
+            CmdInput::SelfTest(domain, opts) => self_test(&domain, opts),
+            CmdInput::Check(file, opts) => check(&file, opts),
             CmdInput::None => std::process::exit(0),
         }
     };
 
-    start_from_conf(conf);
+    start_from_conf(conf, source);
 }
 
-fn start_from_conf(full: FullConf) {
+fn self_test(domain: &str, opts: CmdOverride) -> ! {
+    let mut conf = FullConf::default();
+    conf.apply_cmd_opts(opts);
+    setup_dns(conf.dns);
+
+    let passed = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(selftest::run(domain));
+    std::process::exit(if passed { 0 } else { 1 })
+}
+
+// build every endpoint without listening or resolving
+fn check(file: &str, opts: CmdOverride) -> ! {
+    let mut conf = match FullConf::try_from_conf_file(file) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    conf.apply_global_opts().apply_cmd_opts(opts);
+
+    let total = conf.endpoints.len();
+    let mut failed = 0;
+    for endpoint in conf.endpoints {
+        let id = endpoint.build_id();
+        match endpoint.try_check() {
+            Ok(info) => println!("ok: {}", info.endpoint),
+            Err(e) => {
+                eprintln!("error: {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} endpoints checked, {} failed", total, failed);
+    std::process::exit(if failed == 0 { 0 } else { 1 })
+}
+
+fn start_from_conf(full: FullConf, source: Option<reload::Source>) {
     let FullConf {
         log: log_conf,
         dns: dns_conf,
+        network: network_conf,
+        metrics: metrics_conf,
         endpoints: endpoints_conf,
-        ..
     } = full;
 
     setup_log(log_conf);
     setup_dns(dns_conf);
+    setup_metrics(metrics_conf);
+
+    // fallback for endpoints with tcp_timeout = 0
+    if let Some(timeout) = env::var(ENV_CONNECT_TIMEOUT).ok().and_then(|x| x.parse::<usize>().ok()) {
+        realm::core::time::set_connect_timeout(timeout);
+    }
+
+    let reloader = source.map(|x| reload::Reloader::new(x, &endpoints_conf));
+
+    let store = api::RuleStore::open();
+    let mut endpoints_conf = endpoints_conf;
+    endpoints_conf.extend(load_stored_rules(&store, &endpoints_conf, &network_conf));
+
+    let rules = api::RuleConfigs::new(&endpoints_conf);
 
     let endpoints: Vec<EndpointInfo> = endpoints_conf
         .into_iter()
         .map(Config::build)
-        .inspect(|x| println!("inited: {}", x.endpoint))
+        .inspect(|x| match &x.ports {
+            Some(ports) => println!("inited: {}, {}", x.endpoint, ports),
+            None => println!("inited: {}", x.endpoint),
+        })
         .collect();
 
-    execute(endpoints);
+    execute(endpoints, rules, store, network_conf, reloader);
+}
+
+// rules from the config file take precedence
+fn load_stored_rules(store: &api::RuleStore, confs: &[EndpointConf], network: &NetConf) -> Vec<EndpointConf> {
+    let mut rules = store.rules();
+    rules.retain_mut(|conf| {
+        let id = conf.build_id();
+        if confs.iter().any(|x| x.build_id() == id) {
+            log::warn!("[store]rule {} is overridden by the config file", id);
+            return false;
+        }
+        conf.network.take_field(network);
+        if let Err(e) = conf.clone().try_build() {
+            log::warn!("[store]rule {} is invalid, skipped: {}", id, e);
+            return false;
+        }
+        true
+    });
+    rules
 }
 
-fn setup_log(log: LogConf) {
-    println!("log: {}", &log);
+fn setup_log(mut log: LogConf) {
+    if let Ok(format) = env::var(ENV_LOG_FORMAT) {
+        log.format = Some(format.into());
+    }
+    println!("log: {}", log);
 
-    let (level, output) = log.build();
+    let (level, format, output, access) = log.build();
     fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}]{}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
+        .format(move |out, message, record| out.finish(format_args!("{}", format.line(message, record))))
         .level(level)
         .chain(output)
         .apply()
-        .unwrap_or_else(|e| panic!("failed to setup logger: {}", &e))
+        .unwrap_or_else(|e| panic!("failed to setup logger: {}", e));
+
+    if let Some(access) = access {
+        realm::core::access::set_format(access);
+    }
 }
 
 fn setup_dns(dns: DnsConf) {
-    println!("dns: {}", &dns);
+    println!("dns: {}", dns);
 
-    let (conf, opts) = dns.build();
+    let (conf, opts, cache_ttl) = dns.build();
     realm::core::dns::build_lazy(conf, opts);
+    if let Some(ttl) = cache_ttl {
+        realm::core::dns::set_cache_ttl(ttl);
+    }
+}
+
+fn setup_metrics(metrics: MetricsConf) {
+    println!("metrics: {}", metrics);
+
+    let (bytes_buckets, duration_buckets, flow_collector) = metrics.build();
+    realm::core::monitor::set_histogram_buckets(bytes_buckets, duration_buckets);
+
+    if let Some(collector) = flow_collector {
+        realm::core::flow::set_collector(collector.as_str())
+            .unwrap_or_else(|e| panic!("failed to set flow collector {}: {}", collector, e));
+    }
 }
 
-fn execute(eps: Vec<EndpointInfo>) {
+fn execute(
+    eps: Vec<EndpointInfo>,
+    rules: api::RuleConfigs,
+    store: api::RuleStore,
+    network: NetConf,
+    reloader: Option<reload::Reloader>,
+) {
     #[cfg(feature = "multi-thread")]
     {
         tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap()
-            .block_on(run(eps))
+            .block_on(run(eps, rules, store, network, reloader))
     }
 
     #[cfg(not(feature = "multi-thread"))]
@@ -112,56 +216,256 @@ fn execute(eps: Vec<EndpointInfo>) {
             .enable_all()
             .build()
             .unwrap()
-            .block_on(run(eps))
+            .block_on(run(eps, rules, store, network, reloader))
     }
 }
 
-async fn run(endpoints: Vec<EndpointInfo>) {
-    use realm::core::tcp::run_tcp;
-    use realm::core::udp::run_udp;
+async fn run(
+    endpoints: Vec<EndpointInfo>,
+    rules: api::RuleConfigs,
+    store: api::RuleStore,
+    network: NetConf,
+    reloader: Option<reload::Reloader>,
+) {
     use realm_core::monitor::periodically_calculate_speeds;
-    use futures::future::join_all;
-    use actix_web::{App, HttpServer}; // HttpServer might be implicitly used via api.rs, but App is needed
-    use crate::api::{list_tcp_connections, get_tcp_connection_stats, list_udp_associations, get_udp_association_stats};
+    use futures::future::{join_all, select, Either};
+    use realm_core::shutdown;
+    use std::pin::pin;
+    use std::time::Duration;
+    use actix_web::web;
+    use crate::api::{RuntimeToggles, Relays};
+
+    let speed_interval = env::var(ENV_SPEED_INTERVAL)
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(SPEED_INTERVAL);
+    tokio::spawn(periodically_calculate_speeds(Duration::from_secs(speed_interval as u64)));
+
+    let toggles = web::Data::new(RuntimeToggles::collect(&endpoints));
+    let rules = web::Data::new(rules);
+    let relays = web::Data::new(Relays::new(network));
+    let store = web::Data::new(store);
+
+    let readiness = web::Data::new(api::Readiness::default());
+
+    // headless, rules could not be changed at runtime
+    if api::enabled_from_env() {
+        serve_api(toggles, rules.clone(), relays.clone(), store, readiness.clone());
+    } else {
+        log::info!("API server is disabled");
+    }
 
-    tokio::spawn(periodically_calculate_speeds());
+    let mut workers = Vec::with_capacity(2 * endpoints.len());
+    let mut listeners = Vec::with_capacity(endpoints.len());
+
+    for info in endpoints {
+        let id = info.endpoint.id.clone();
+        let started = relays.start(info).await.unwrap_or_else(|e| panic!("{}", e));
+        listeners.push((id, started.len()));
+        workers.extend(started);
+    }
+
+    // ready once all endpoints from the config are listening
+    tokio::spawn({
+        let relays = relays.clone();
+        async move {
+            relays.wait_bound(&listeners).await;
+            readiness.set_ready();
+            log::info!("all endpoints are listening, ready");
+        }
+    });
+
+    workers.shrink_to_fit();
+
+    // relays are started, so that a reload never races with them
+    #[cfg(unix)]
+    if let Some(reloader) = reloader {
+        tokio::spawn(reloader.run(rules.clone(), relays.clone()));
+    }
+    #[cfg(not(unix))]
+    drop(reloader);
+
+    // run until all relays exit, or drain on signal
+    if let Either::Left(_) = select(join_all(workers), pin!(shutdown_signal())).await {
+        return;
+    }
+
+    let drain_timeout = env::var(ENV_DRAIN_TIMEOUT)
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(DRAIN_TIMEOUT);
+    shutdown::begin_drain(Duration::from_secs(drain_timeout as u64));
+
+    let (conns, assocs) = shutdown::active();
+    log::info!(
+        "shutting down, draining {} connections and {} associations in {}s",
+        conns,
+        assocs,
+        drain_timeout
+    );
+
+    // a second signal forces shutdown
+    match select(pin!(shutdown::wait_drained()), pin!(shutdown_signal())).await {
+        Either::Left((true, _)) => log::info!("drained, exit"),
+        Either::Left((false, _)) => {
+            let (conns, assocs) = shutdown::active();
+            log::warn!("drain timeout, {} connections and {} associations left", conns, assocs);
+        }
+        Either::Right(_) => log::warn!("forced shutdown"),
+    }
+}
+
+/// Start the api server in the background, panic if it could not be bound.
+fn serve_api(
+    toggles: actix_web::web::Data<api::RuntimeToggles>,
+    rules: actix_web::web::Data<api::RuleConfigs>,
+    relays: actix_web::web::Data<api::Relays>,
+    store: actix_web::web::Data<api::RuleStore>,
+    readiness: actix_web::web::Data<api::Readiness>,
+) {
+    use actix_web::{web, App, HttpServer}; // HttpServer might be implicitly used via api.rs, but App is needed
+    use crate::api::{list_tcp_connections, get_tcp_connection_stats, list_udp_associations, get_udp_association_stats};
+    use crate::api::close_udp_association;
+    use crate::api::{get_features, list_rules, get_rule_sources, get_rule_config, unquarantine_rule};
+    use crate::api::{get_shutdown_status, get_top_connections, get_metrics, get_rule_stats, reset_rule, reset_rules, add_rule, add_rules, update_rule, delete_rule, delete_rules};
+    use crate::api::{stream_stats, healthz, readyz, get_version};
 
-    // API Server Setup
     let api_host = "127.0.0.1"; // Should be configurable
     let api_port = 8080;       // Should be configurable
 
+    // the api server runs its own threads beside the relay runtime,
+    // keep it small since it is not the data plane.
+    let api_workers = env::var(ENV_API_WORKERS)
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(API_WORKERS);
+
+    // fail before any relay starts
+    let api_tls = api::ApiTls::from_env();
+    #[cfg(feature = "api-tls")]
+    let api_tls = api_tls.map(|x| x.server_config().unwrap_or_else(|e| panic!("{}", e)));
+    #[cfg(not(feature = "api-tls"))]
+    if api_tls.is_some() {
+        panic!("{} requires the api-tls feature", realm::ENV_API_TLS_CERT);
+    }
+
+    let api_log = web::Data::new(api::ApiLog::from_env());
+    let api_auth = web::Data::new(api::ApiAuth::from_env());
+    let api_rate_limit = api::ApiRateLimit::from_env().map(web::Data::new);
+    let ports = web::Data::new(api::DynamicPorts::from_env());
+
     let server = HttpServer::new(move || {
-        App::new()
+        // the last wrapped runs first
+        let app = App::new()
+            .wrap(actix_web::middleware::from_fn(api::authenticate))
+            .wrap(actix_web::middleware::from_fn(api::rate_limit))
+            .wrap(actix_web::middleware::from_fn(api::log_request))
+            .app_data(api_log.clone())
+            .app_data(api_auth.clone())
+            .app_data(toggles.clone())
+            .app_data(rules.clone())
+            .app_data(relays.clone())
+            .app_data(store.clone())
+            .app_data(ports.clone())
+            .app_data(readiness.clone())
+            .app_data(api::json_config())
+            .service(healthz)
+            .service(readyz)
+            .service(get_version)
+            .service(get_features)
+            .service(stream_stats)
             .service(list_tcp_connections)
             .service(get_tcp_connection_stats)
             .service(list_udp_associations)
             .service(get_udp_association_stats)
+            .service(close_udp_association)
+            .service(get_rule_sources)
+            .service(get_rule_stats)
+            .service(get_rule_config)
+            .service(list_rules)
+            .service(add_rule)
+            .service(add_rules)
+            .service(update_rule)
+            .service(delete_rule)
+            .service(delete_rules)
+            .service(unquarantine_rule)
+            .service(reset_rules)
+            .service(reset_rule)
+            .service(get_shutdown_status)
+            .service(get_top_connections)
+            .service(get_metrics);
+
+        let app = match &api_rate_limit {
+            Some(x) => app.app_data(x.clone()),
+            None => app,
+        };
+
+        #[cfg(feature = "balance")]
+        let app = app
+            .service(crate::api::patch_balancer_weights)
+            .service(crate::api::get_rule_health)
+            .service(crate::api::disable_peer)
+            .service(crate::api::enable_peer);
+
+        #[cfg(feature = "transport")]
+        let app = app.service(crate::api::reload_tls);
+
+        app
     })
-    .bind((api_host, api_port))
-    .unwrap_or_else(|e| panic!("Failed to bind API server to {}:{}: {}", api_host, api_port, e))
-    .run();
-    
-    tokio::spawn(server);
-    log::info!("API server started at http://{}:{}", api_host, api_port);
+    .workers(api_workers)
+    // keep serving during graceful shutdown
+    .disable_signals();
 
-    let mut workers = Vec::with_capacity(2 * endpoints.len());
+    // a unix socket takes the place of the tcp address
+    let api_unix = api::unix_socket_from_env();
+    #[cfg(not(unix))]
+    if api_unix.is_some() {
+        panic!("{} is not supported on this platform", realm::ENV_API_UNIX_SOCKET);
+    }
 
-    for EndpointInfo {
-        endpoint,
-        no_tcp,
-        use_udp,
-    } in endpoints
-    {
-        if use_udp {
-            workers.push(tokio::spawn(run_udp(endpoint.clone())));
+    let (server, api_addr) = match api_unix {
+        #[cfg(unix)]
+        Some(path) => {
+            if api_tls.is_some() {
+                log::warn!("API server on unix socket is served over plain http, tls is ignored");
+            }
+            let server = api::bind_unix_socket(&path).and_then(|x| server.listen_uds(x));
+            (server, format!("unix:{}", path.display()))
         }
-
-        if !no_tcp {
-            workers.push(tokio::spawn(run_tcp(endpoint)));
+        _ => {
+            #[cfg(feature = "api-tls")]
+            let (server, scheme) = match api_tls {
+                Some(tls) => (server.bind_rustls_0_23((api_host, api_port), tls), "https"),
+                None => (server.bind((api_host, api_port)), "http"),
+            };
+            #[cfg(not(feature = "api-tls"))]
+            let (server, scheme) = (server.bind((api_host, api_port)), "http");
+            (server, format!("{}://{}:{}", scheme, api_host, api_port))
         }
-    }
+    };
 
-    workers.shrink_to_fit();
+    let server = server
+        .unwrap_or_else(|e| panic!("Failed to bind API server to {}: {}", api_addr, e))
+        .run();
+
+    tokio::spawn(server);
+    log::info!("API server started at {} with {} workers", api_addr, api_workers);
+}
 
-    join_all(workers).await;
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use futures::future::select;
+        use std::pin::pin;
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("failed to listen to SIGTERM");
+        select(pin!(term.recv()), pin!(tokio::signal::ctrl_c())).await;
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
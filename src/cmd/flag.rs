@@ -58,6 +58,11 @@ pub fn add_flags(app: Command) -> Command {
             .help("force enable tcp zero copy -- deprecated")
             .action(ArgAction::SetTrue)
             .display_order(7),
+        Arg::new("check")
+            .long("check")
+            .help("check the config file, then exit")
+            .action(ArgAction::SetTrue)
+            .display_order(8),
     ])
 }
 
@@ -111,6 +116,18 @@ pub fn add_options(app: Command) -> Command {
             .help("remote transport")
             .value_name("options")
             .display_order(7),
+        Arg::new("trace_header")
+            .long("trace-header")
+            .help("propagate trace id via ws header")
+            .value_name("name")
+            .display_order(8),
+        Arg::new("self_test")
+            .long("self-test")
+            .help("relay in-process and resolve a domain, then exit")
+            .value_name("domain")
+            .num_args(0..=1)
+            .default_missing_value("localhost")
+            .display_order(9),
     ])
 }
 
@@ -135,6 +152,11 @@ pub fn add_global_options(app: Command) -> Command {
             .help("set pre-connect hook")
             .value_name("path")
             .display_order(2),
+        Arg::new("hook_fail_mode")
+            .long("hook-fail-mode")
+            .help("closed or open on hook error")
+            .value_name("mode")
+            .display_order(3),
     ]);
 
     // log
@@ -149,6 +171,16 @@ pub fn add_global_options(app: Command) -> Command {
             .help("override log output")
             .value_name("path")
             .display_order(1),
+        Arg::new("log_format")
+            .long("log-format")
+            .help("override log format")
+            .value_name("text|json")
+            .display_order(2),
+        Arg::new("log_access")
+            .long("log-access")
+            .help("log closed connections")
+            .value_name("plain|json")
+            .display_order(3),
     ]);
 
     // dns
@@ -173,16 +205,21 @@ pub fn add_global_options(app: Command) -> Command {
             .help("override dns cache size")
             .value_name("number")
             .display_order(3),
+        Arg::new("dns_cache_ttl")
+            .long("dns-cache-ttl")
+            .help("override ttl of resolved remotes")
+            .value_name("second")
+            .display_order(4),
         Arg::new("dns_protocol")
             .long("dns-protocol")
             .help("override dns protocol")
             .value_name("protocol")
-            .display_order(4),
+            .display_order(5),
         Arg::new("dns_servers")
             .long("dns-servers")
             .help("override dns servers")
             .value_name("servers")
-            .display_order(5),
+            .display_order(6),
     ]);
 
     // proxy-protocol belogs to network
@@ -229,6 +266,16 @@ pub fn add_global_options(app: Command) -> Command {
             .help("override default tcp keepalive count(3)")
             .value_name("count")
             .display_order(3),
+        Arg::new("tcp_linger")
+            .long("tcp-linger")
+            .help("set tcp linger, 0 for abortive close")
+            .value_name("second")
+            .display_order(4),
+        Arg::new("upstream_eof")
+            .long("upstream-eof")
+            .help("drain or close on upstream eof")
+            .value_name("mode")
+            .display_order(5),
     ]);
 
     app
@@ -17,6 +17,8 @@ mod flag;
 pub enum CmdInput {
     Config(String, CmdOverride),
     Endpoint(EndpointConf, CmdOverride),
+    SelfTest(String, CmdOverride),
+    Check(String, CmdOverride),
     None,
 }
 
@@ -57,6 +59,18 @@ pub fn scan() -> CmdInput {
         _ => {}
     };
 
+    if let Some(domain) = matches.get_one::<String>("self_test").cloned() {
+        return CmdInput::SelfTest(domain, parse_global_opts(&matches));
+    }
+
+    if matches.get_flag("check") {
+        let Some(config) = matches.get_one::<String>("config").cloned() else {
+            eprintln!("--check requires a config file");
+            std::process::exit(2);
+        };
+        return CmdInput::Check(config, parse_global_opts(&matches));
+    }
+
     // start
     handle_matches(matches)
 }
@@ -1,5 +1,6 @@
 use std::fmt::{Formatter, Display};
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
 use serde::{Serialize, Deserialize};
 use realm_core::dns::config;
@@ -126,6 +127,11 @@ pub struct DnsConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_size: Option<usize>,
 
+    // override ttl of resolved remote addresses
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u32>,
+
     // ResolverConfig
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -157,6 +163,7 @@ impl Display for DnsConf {
             min_ttl,
             max_ttl,
             cache_size,
+            cache_ttl,
             protocol,
             nameservers,
         } = self;
@@ -176,23 +183,26 @@ impl Display for DnsConf {
             None => String::from("system"),
         };
 
-        write!(f, "mode={}, protocol={}, ", &mode, &protocol).unwrap();
+        write!(f, "mode={}, protocol={}, ", mode, protocol).unwrap();
         write!(
             f,
             "min-ttl={}, max-ttl={}, cache-size={}, ",
             min_ttl, max_ttl, cache_size
         )
         .unwrap();
-        write!(f, "servers={}", &nameservers)
+        if let Some(ttl) = cache_ttl {
+            write!(f, "cache-ttl={}, ", ttl).unwrap();
+        }
+        write!(f, "servers={}", nameservers)
     }
 }
 
 impl Config for DnsConf {
-    type Output = (Option<ResolverConfig>, Option<ResolverOpts>);
+    // resolver config, resolver opts, cache ttl
+    type Output = (Option<ResolverConfig>, Option<ResolverOpts>, Option<Duration>);
 
     fn build(self) -> Self::Output {
         use crate::empty;
-        use std::time::Duration;
 
         let DnsConf {
             mode,
@@ -201,8 +211,11 @@ impl Config for DnsConf {
             min_ttl,
             max_ttl,
             cache_size,
+            cache_ttl,
         } = self;
 
+        let cache_ttl = cache_ttl.map(|x| Duration::from_secs(x as u64));
+
         // parse into ResolverOpts
         // default value:
         // https://docs.rs/trust-dns-resolver/latest/src/trust_dns_resolver/config.rs.html#681-737
@@ -239,7 +252,7 @@ impl Config for DnsConf {
         // parse into ResolverConfig
         let protocol = protocol.unwrap_or_default();
         if nameservers.is_none() && (protocol == DnsProtocol::default()) {
-            return (None, opts);
+            return (None, opts, cache_ttl);
         }
 
         let mut conf = ResolverConfig::new();
@@ -270,7 +283,7 @@ impl Config for DnsConf {
             }
         }
 
-        (Some(conf), opts)
+        (Some(conf), opts, cache_ttl)
     }
 
     fn rst_field(&mut self, other: &Self) -> &mut Self {
@@ -280,6 +293,7 @@ impl Config for DnsConf {
         rst!(self, min_ttl, other);
         rst!(self, max_ttl, other);
         rst!(self, cache_size, other);
+        rst!(self, cache_ttl, other);
         rst!(self, protocol, other);
         rst!(self, nameservers, other);
         self
@@ -292,6 +306,7 @@ impl Config for DnsConf {
         take!(self, min_ttl, other);
         take!(self, max_ttl, other);
         take!(self, cache_size, other);
+        take!(self, cache_ttl, other);
         take!(self, protocol, other);
         take!(self, nameservers, other);
         self
@@ -309,6 +324,9 @@ impl Config for DnsConf {
         let cache_size = matches
             .get_one::<String>("dns_cache_size")
             .and_then(|x| x.parse::<usize>().ok());
+        let cache_ttl = matches
+            .get_one::<String>("dns_cache_ttl")
+            .and_then(|x| x.parse::<u32>().ok());
 
         let protocol = matches
             .get_one::<String>("dns_protocol")
@@ -324,6 +342,7 @@ impl Config for DnsConf {
             min_ttl,
             max_ttl,
             cache_size,
+            cache_ttl,
             protocol,
             nameservers,
         }
@@ -1,18 +1,101 @@
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::ops::RangeInclusive;
 
-use realm_core::endpoint::{Endpoint, RemoteAddr};
+use realm_core::acl::Acl;
+#[cfg(feature = "alert")]
+use realm_core::alert::Alert;
+use realm_core::endpoint::{Endpoint, LocalAddr, RemoteAddr, SharedAcceptFilter, Socks5Proxy, UNIX_SOCKET_PREFIX};
+
+#[cfg(any(feature = "balance", feature = "transport"))]
+use std::sync::Arc;
 
 #[cfg(feature = "balance")]
 use realm_core::balance::Balancer;
 
+#[cfg(feature = "balance")]
+use realm_core::endpoint::SharedBalancer;
+
 #[cfg(feature = "transport")]
 use realm_core::kaminari::mix::{MixAccept, MixConnect};
 
+#[cfg(feature = "transport")]
+use realm_core::endpoint::TlsPolicy;
+
+#[cfg(feature = "geoip")]
+use realm_core::geoip::GeoRouter;
+
+#[cfg(feature = "proxy")]
+use realm_core::endpoint::{ProxyOpts, ProxyTlv};
+
 use super::{Config, NetConf, NetInfo};
+use crate::consts::ALERT_COOLDOWN;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeoConf {
+    pub database: String,
+
+    // region => remote or extra remote
+    #[serde(default)]
+    pub regions: BTreeMap<String, String>,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AclConf {
+    // cidrs
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlertConf {
+    pub webhook_url: String,
+
+    // tx + rx bytes
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+
+    // upload + download speed
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_bps: Option<u64>,
+
+    // seconds
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsPolicyConf {
+    // 1.2 or 1.3
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ciphers: Vec<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EndpointConf {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
     pub listen: String,
 
     pub remote: String,
@@ -21,10 +104,31 @@ pub struct EndpointConf {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub extra_remotes: Vec<String>,
 
+    // replace remote for tcp or udp only
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_remote: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub udp_remote: Option<String>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub balance: Option<String>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geoip: Option<GeoConf>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acl: Option<AclConf>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<AlertConf>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub through: Option<String>,
@@ -33,6 +137,10 @@ pub struct EndpointConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub interface: Option<String>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socks5_proxy: Option<String>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub listen_interface: Option<String>,
@@ -45,62 +153,392 @@ pub struct EndpointConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub remote_transport: Option<String>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_header: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_policy: Option<TlsPolicyConf>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub send_proxy_tlvs: Vec<String>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Config::is_empty")]
     pub network: NetConf,
 }
 
 impl EndpointConf {
-    fn build_local(&self) -> SocketAddr {
-        self.listen
+    // the listen address is used if id is not specified
+    pub fn build_id(&self) -> String {
+        self.id.clone().unwrap_or_else(|| self.listen.clone())
+    }
+
+    /// Serialize as a toml config file with only this endpoint,
+    /// which could be loaded by [`FullConf::from_conf_str`](super::FullConf::from_conf_str).
+    pub fn to_config_string(&self) -> String {
+        #[derive(Serialize)]
+        struct Wrapper<'a> {
+            endpoints: [&'a EndpointConf; 1],
+        }
+
+        toml::to_string(&Wrapper { endpoints: [self] })
+            .unwrap_or_else(|e| panic!("failed to serialize endpoint {}: {}", self.build_id(), e))
+    }
+
+    /// Same as [`EndpointConf::to_config_string`], but the password of
+    /// `socks5_proxy` is masked, so that it is safe to be shown by the api.
+    pub fn to_redacted_string(&self) -> String {
+        let mut conf = self.clone();
+        if let Some(proxy) = conf.socks5_proxy.as_mut() {
+            // the password may contain '@'
+            if let Some((auth, addr)) = proxy.rsplit_once('@') {
+                if let Some((user, _)) = auth.split_once(':') {
+                    *proxy = format!("{}:***@{}", user, addr);
+                }
+            }
+        }
+        conf.to_config_string()
+    }
+
+    /// Same as [`Config::build`], but return an error instead of
+    /// panicking on an invalid config, e.g. one received by the api.
+    pub fn try_build(self) -> Result<EndpointInfo, String> {
+        let id = self.build_id();
+        let laddr = self.build_local()?;
+        let raddr = self.build_remote()?;
+        let ports = self.build_ports()?;
+
+        let extra_raddrs = self.extra_remotes.iter().map(|r| Self::build_remote_x(r)).collect::<Result<_, _>>()?;
+        let tcp_raddr = self.tcp_remote.as_deref().map(Self::build_remote_x).transpose()?;
+        let udp_raddr = self.udp_remote.as_deref().map(Self::build_remote_x).transpose()?;
+
+        if let Some(dscp @ 64..) = self.network.dscp {
+            return Err(format!("dscp: {} is out of range 0-63", dscp));
+        }
+
+        // build partial conn_opts from netconf
+        let NetInfo {
+            mut bind_opts,
+            mut conn_opts,
+            no_tcp,
+            use_udp,
+        } = self.network.build();
+
+        // unix sockets only carry tcp
+        let udp_unix = laddr.is_unix() || udp_raddr.as_ref().unwrap_or(&raddr).is_unix();
+        if use_udp && udp_unix {
+            return Err("use_udp: unix socket does not support udp".to_string());
+        }
+
+        #[cfg(feature = "balance")]
+        {
+            conn_opts.balancer = self.build_balancer()?;
+        }
+
+        #[cfg(feature = "transport")]
+        {
+            let transport = self.build_transport()?;
+            let tcp_unix = laddr.is_unix() || tcp_raddr.as_ref().unwrap_or(&raddr).is_unix();
+            if transport.is_some() && tcp_unix {
+                return Err("transport: unix socket is not supported".to_string());
+            }
+            conn_opts.tls_policy = self.build_tls_policy(&transport)?;
+            conn_opts.transport = transport.map(|x| Arc::new(Arc::new(x).into()));
+        }
+
+        #[cfg(feature = "geoip")]
+        {
+            conn_opts.geoip = self.build_geoip()?;
+        }
+
+        #[cfg(feature = "proxy")]
+        {
+            let ProxyOpts {
+                send_proxy,
+                accept_proxy,
+                ..
+            } = conn_opts.proxy_opts;
+            if (send_proxy || accept_proxy) && laddr.is_unix() {
+                return Err("proxy protocol: unix socket listener has no client address".to_string());
+            }
+            conn_opts.proxy_opts.send_proxy_tlvs = self.build_proxy_tlvs(&conn_opts.proxy_opts)?;
+        }
+
+        // build left fields of bind_opts and conn_opts
+        // an existing filter is kept along with the acl
+        if let Some(acl) = self.build_acl(&laddr)? {
+            conn_opts.accept_filter = Some(match conn_opts.accept_filter.take() {
+                Some(filter) => filter.and(acl),
+                None => acl,
+            });
+        }
+        #[cfg(feature = "alert")]
+        {
+            conn_opts.alert = self.build_alert()?;
+        }
+        conn_opts.bind_address = self.build_send_through()?;
+        conn_opts.socks5_proxy = self.build_socks5_proxy(tcp_raddr.as_ref().unwrap_or(&raddr))?;
+        conn_opts.bind_interface = self.interface;
+        bind_opts.bind_interface = self.listen_interface;
+
+        #[cfg(feature = "transport")]
+        {
+            conn_opts.trace_header = self.trace_header;
+        }
+
+        Ok(EndpointInfo {
+            no_tcp,
+            use_udp,
+            endpoint: Endpoint {
+                id,
+                laddr,
+                raddr,
+                bind_opts,
+                conn_opts,
+                extra_raddrs,
+                tcp_raddr,
+                udp_raddr,
+            },
+            ports,
+        })
+    }
+
+    /// Same as [`try_build`](Self::try_build), but domain names are only
+    /// checked by their form instead of resolved, e.g. to check a config
+    /// before deploying it.
+    pub fn try_check(mut self) -> Result<EndpointInfo, String> {
+        let remotes = std::iter::once(&self.remote)
+            .chain(self.extra_remotes.iter())
+            .chain(self.tcp_remote.iter())
+            .chain(self.udp_remote.iter());
+        for remote in remotes {
+            if let Ok(RemoteAddr::DomainName(host, _)) = first_of_range(remote).parse() {
+                if !is_domain_name(&host) {
+                    return Err(format!("remote: invalid domain name: {}", remote));
+                }
+            }
+        }
+
+        // resolved by build, replaced with an ip of the same port
+        self.id = Some(self.build_id());
+        self.listen = unresolved(&self.listen).map_err(|e| format!("listen: {}", e))?;
+        if let Some(through) = self.through.take() {
+            self.through = Some(unresolved(&through).map_err(|e| format!("through: {}", e))?);
+        }
+        self.try_build()
+    }
+
+    /// Read the tls certificate and key of listen_transport again.
+    ///
+    /// Return none if the listen transport does not use tls.
+    #[cfg(feature = "transport")]
+    pub fn reload_transport(&self) -> Result<Option<MixAccept>, String> {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use realm_core::kaminari::mix::MixServerConf;
+        use realm_core::kaminari::opt::{get_ws_conf, get_tls_server_conf};
+
+        let Some(listen_transport) = &self.listen_transport else {
+            return Ok(None);
+        };
+
+        let tls = catch_unwind(|| get_tls_server_conf(listen_transport)).map_err(|_| "invalid tls config")?;
+        let Some(tls) = tls else {
+            return Ok(None);
+        };
+        if !tls.crt.is_empty() && !tls.key.is_empty() {
+            check_cert_key(&tls.crt, &tls.key)?;
+        }
+
+        catch_unwind(AssertUnwindSafe(|| {
+            let ws = get_ws_conf(listen_transport);
+            // unlike new_shared, files are not cached
+            Some(MixAccept::new(MixServerConf { ws, tls: Some(tls) }))
+        }))
+        .map_err(|e| {
+            e.downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|x| x.to_string()))
+                .unwrap_or_else(|| "invalid certificate or key".to_string())
+        })
+    }
+
+    fn build_local(&self) -> Result<LocalAddr, String> {
+        if self.listen.starts_with(UNIX_SOCKET_PREFIX) {
+            return self.listen.parse().map_err(|e| format!("listen: {}", e));
+        }
+        first_of_range(&self.listen)
             .to_socket_addrs()
-            .expect("invalid local address")
+            .map_err(|e| format!("invalid local address: {}", e))?
             .next()
-            .unwrap()
+            .map(LocalAddr::from)
+            .ok_or_else(|| format!("invalid local address: {}", self.listen))
     }
 
-    fn build_remote(&self) -> RemoteAddr {
-        Self::build_remote_x(&self.remote)
+    fn build_remote(&self) -> Result<RemoteAddr, String> {
+        Self::build_remote_x(&first_of_range(&self.remote))
     }
 
-    fn build_remote_x(remote: &str) -> RemoteAddr {
-        if let Ok(sockaddr) = remote.parse::<SocketAddr>() {
-            RemoteAddr::SocketAddr(sockaddr)
-        } else {
-            let mut iter = remote.rsplitn(2, ':');
-            let port = iter.next().unwrap().parse::<u16>().unwrap();
-            let addr = iter.next().unwrap().to_string();
-            RemoteAddr::DomainName(addr, port)
+    fn build_ports(&self) -> Result<Option<PortRange>, String> {
+        let remote = split_port_range(&self.remote).map(|(_, x)| x);
+        let Some((_, listen)) = split_port_range(&self.listen) else {
+            if remote.is_some() {
+                return Err("remote: port range requires a listen port range".to_string());
+            }
+            return Ok(None);
+        };
+        if listen.is_empty() || *listen.start() == 0 {
+            return Err(format!("listen: invalid port range: {}", self.listen));
         }
+
+        // mirrored port by port
+        let remote = match remote {
+            Some(x) if x.is_empty() || x.end() - x.start() != listen.end() - listen.start() => {
+                return Err(format!("remote: port range does not match listen: {}", self.remote));
+            }
+            x => x.map(|x| *x.start()),
+        };
+        Ok(Some(PortRange { listen, remote }))
+    }
+
+    fn build_remote_x(remote: &str) -> Result<RemoteAddr, String> {
+        remote.parse().map_err(|e| format!("remote: {}", e))
     }
 
-    fn build_send_through(&self) -> Option<SocketAddr> {
+    fn build_send_through(&self) -> Result<Option<SocketAddr>, String> {
         let Self { through, .. } = self;
         let through = match through {
             Some(x) => x,
-            None => return None,
+            None => return Ok(None),
         };
         match through.to_socket_addrs() {
-            Ok(mut x) => Some(x.next().unwrap()),
+            Ok(mut x) => x.next().map(Some).ok_or_else(|| format!("through: no address: {}", through)),
             Err(_) => {
                 let mut ipstr = String::from(through);
                 ipstr.retain(|c| c != '[' && c != ']');
-                ipstr.parse::<IpAddr>().map_or(None, |ip| Some(SocketAddr::new(ip, 0)))
+                Ok(ipstr.parse::<IpAddr>().map_or(None, |ip| Some(SocketAddr::new(ip, 0))))
             }
         }
     }
 
     #[cfg(feature = "balance")]
-    fn build_balancer(&self) -> Balancer {
-        if let Some(s) = &self.balance {
-            Balancer::parse_from_str(s)
-        } else {
-            Balancer::default()
+    fn build_balancer(&self) -> Result<SharedBalancer, String> {
+        let balancer = match &self.balance {
+            Some(s) => Balancer::parse_from_str(s).map_err(|e| format!("balance: {}", e))?,
+            None => Balancer::default(),
+        };
+        Ok(Arc::new(Arc::new(balancer).into()))
+    }
+
+    #[cfg(feature = "geoip")]
+    fn build_geoip(&self) -> Result<Option<std::sync::Arc<GeoRouter>>, String> {
+        let Some(GeoConf { database, regions }) = self.geoip.as_ref() else {
+            return Ok(None);
+        };
+
+        // 0 is the default remote peer
+        let regions = regions
+            .iter()
+            .map(|(region, remote)| {
+                std::iter::once(&self.remote)
+                    .chain(self.extra_remotes.iter())
+                    .position(|x| x == remote)
+                    .map(|idx| (region.clone(), idx))
+                    .ok_or_else(|| format!("geoip: {} is neither remote nor extra remote", remote))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let router = GeoRouter::open(database, regions).map_err(|e| format!("failed to open {}: {}", database, e))?;
+        Ok(Some(std::sync::Arc::new(router)))
+    }
+
+    fn build_acl(&self, laddr: &LocalAddr) -> Result<Option<SharedAcceptFilter>, String> {
+        let Some(AclConf { allow, deny }) = self.acl.as_ref() else {
+            return Ok(None);
+        };
+        if laddr.is_unix() {
+            return Err("acl: unix socket listener has no client address".to_string());
+        }
+        let acl = Acl::new(allow, deny).map_err(|e| format!("acl: {}", e))?;
+        Ok(Some(SharedAcceptFilter::new(acl)))
+    }
+
+    #[cfg(feature = "alert")]
+    fn build_alert(&self) -> Result<Option<std::sync::Arc<Alert>>, String> {
+        let Some(AlertConf {
+            webhook_url,
+            bytes,
+            speed_bps,
+            cooldown,
+        }) = self.alert.as_ref()
+        else {
+            return Ok(None);
+        };
+
+        let webhook = webhook_url.parse().map_err(|e| format!("alert: {}", e))?;
+        let bytes = bytes.unwrap_or_default();
+        let speed_bps = speed_bps.unwrap_or_default();
+        if bytes == 0 && speed_bps == 0 {
+            return Err("alert: neither bytes nor speed_bps is set".to_string());
+        }
+        let cooldown = std::time::Duration::from_secs(cooldown.unwrap_or(ALERT_COOLDOWN) as u64);
+        Ok(Some(std::sync::Arc::new(Alert {
+            webhook,
+            bytes,
+            speed_bps,
+            cooldown,
+        })))
+    }
+
+    fn build_socks5_proxy(&self, tcp_raddr: &RemoteAddr) -> Result<Option<Socks5Proxy>, String> {
+        let Some(proxy) = self.socks5_proxy.as_ref() else {
+            return Ok(None);
+        };
+        if tcp_raddr.is_unix() {
+            return Err("socks5_proxy: unix socket is not supported".to_string());
         }
+        proxy.parse().map(Some).map_err(|e| format!("socks5_proxy: {}", e))
+    }
+
+    #[cfg(feature = "proxy")]
+    fn build_proxy_tlvs(&self, opts: &ProxyOpts) -> Result<Vec<ProxyTlv>, String> {
+        if !self.send_proxy_tlvs.is_empty() && opts.send_proxy_version != 2 {
+            return Err("send_proxy_tlvs: requires send_proxy_version = 2".to_string());
+        }
+        self.send_proxy_tlvs
+            .iter()
+            .map(|x| x.parse().map_err(|e| format!("send_proxy_tlvs: {}", e)))
+            .collect()
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_tls_policy(&self, transport: &Option<(MixAccept, MixConnect)>) -> Result<Option<Arc<TlsPolicy>>, String> {
+        let Some(TlsPolicyConf {
+            min_version,
+            ciphers,
+            groups,
+        }) = self.tls_policy.as_ref()
+        else {
+            return Ok(None);
+        };
+
+        let use_tls = transport.as_ref().is_some_and(|(ac, cc)| {
+            ac.as_tls().is_some() || ac.as_wss().is_some() || cc.as_tls().is_some() || cc.as_wss().is_some()
+        });
+        if !use_tls {
+            return Err("tls_policy: neither listen_transport nor remote_transport uses tls".to_string());
+        }
+
+        let policy = TlsPolicy::new(min_version.as_deref().unwrap_or("1.2"), ciphers, groups)
+            .map_err(|e| format!("tls_policy: {}", e))?;
+        Ok(Some(Arc::new(policy)))
     }
 
     #[cfg(feature = "transport")]
-    fn build_transport(&self) -> Option<(MixAccept, MixConnect)> {
+    fn build_transport(&self) -> Result<Option<(MixAccept, MixConnect)>, String> {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
         use realm_core::kaminari::mix::{MixClientConf, MixServerConf};
         use realm_core::kaminari::opt::get_ws_conf;
         use realm_core::kaminari::opt::get_tls_client_conf;
@@ -112,36 +550,154 @@ impl EndpointConf {
             ..
         } = self;
 
-        let listen_ws = listen_transport.as_ref().and_then(|s| get_ws_conf(s));
-        let listen_tls = listen_transport.as_ref().and_then(|s| get_tls_server_conf(s));
-
-        let remote_ws = remote_transport.as_ref().and_then(|s| get_ws_conf(s));
-        let remote_tls = remote_transport.as_ref().and_then(|s| get_tls_client_conf(s));
-
-        if matches!(
-            (&listen_ws, &listen_tls, &remote_ws, &remote_tls),
-            (None, None, None, None)
-        ) {
-            None
-        } else {
-            let ac = MixAccept::new_shared(MixServerConf {
-                ws: listen_ws,
-                tls: listen_tls,
-            });
-            let cc = MixConnect::new_shared(MixClientConf {
-                ws: remote_ws,
-                tls: remote_tls,
-            });
-            Some((ac, cc))
+        // kaminari panics on an invalid option, certificate or key
+        catch_unwind(AssertUnwindSafe(|| {
+            let listen_ws = listen_transport.as_ref().and_then(|s| get_ws_conf(s));
+            let listen_tls = listen_transport.as_ref().and_then(|s| get_tls_server_conf(s));
+
+            let remote_ws = remote_transport.as_ref().and_then(|s| get_ws_conf(s));
+            let remote_tls = remote_transport.as_ref().and_then(|s| get_tls_client_conf(s));
+
+            if matches!(
+                (&listen_ws, &listen_tls, &remote_ws, &remote_tls),
+                (None, None, None, None)
+            ) {
+                None
+            } else {
+                let ac = MixAccept::new_shared(MixServerConf {
+                    ws: listen_ws,
+                    tls: listen_tls,
+                });
+                let cc = MixConnect::new_shared(MixClientConf {
+                    ws: remote_ws,
+                    tls: remote_tls,
+                });
+                Some((ac, cc))
+            }
+        }))
+        .map_err(|_| "transport: invalid transport config".to_string())
+    }
+}
+
+// the tls transport does not check if the key matches the certificate
+#[cfg(feature = "transport")]
+fn check_cert_key(crt: &str, key: &str) -> Result<(), String> {
+    use rustls::crypto::ring::default_provider;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::sign::CertifiedKey;
+
+    // pem or der, same as the tls transport
+    let read = |path: &str| std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e));
+    let crt_data = read(crt)?;
+    let mut certs: Vec<_> = CertificateDer::pem_slice_iter(&crt_data).filter_map(|x| x.ok()).collect();
+    if certs.is_empty() {
+        certs.push(CertificateDer::from(crt_data));
+    }
+    let key_data = read(key)?;
+    let key = PrivateKeyDer::from_pem_slice(&key_data).or_else(|_| PrivateKeyDer::try_from(key_data))?;
+
+    CertifiedKey::from_der(certs, key, &default_provider())
+        .map(|_| ())
+        .map_err(|e| format!("bad certificate or key: {}", e))
+}
+
+// `host:first-last`, e.g. `0.0.0.0:20000-20100`
+fn split_port_range(addr: &str) -> Option<(&str, RangeInclusive<u16>)> {
+    if addr.starts_with(UNIX_SOCKET_PREFIX) {
+        return None;
+    }
+    let (host, ports) = addr.rsplit_once(':')?;
+    let (first, last) = ports.split_once('-')?;
+    Some((host, first.parse().ok()?..=last.parse().ok()?))
+}
+
+// the endpoint is built with the first port of a range
+fn first_of_range(addr: &str) -> String {
+    match split_port_range(addr) {
+        Some((host, ports)) => format!("{}:{}", host, ports.start()),
+        None => addr.to_string(),
+    }
+}
+
+// a domain name with a port is replaced with an unspecified ip,
+// while an ip or other forms are left alone
+fn unresolved(addr: &str) -> Result<String, String> {
+    let plain = first_of_range(addr);
+    if addr.starts_with(UNIX_SOCKET_PREFIX) || plain.parse::<SocketAddr>().is_ok() {
+        return Ok(addr.to_string());
+    }
+    let Some((host, port)) = addr.rsplit_once(':') else {
+        return Ok(addr.to_string());
+    };
+    if !is_domain_name(host) {
+        return Err(format!("invalid domain name: {}", addr));
+    }
+    Ok(format!("0.0.0.0:{}", port))
+}
+
+// letters, digits and hyphens, in labels of at most 63 characters
+fn is_domain_name(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|x| {
+            !x.is_empty()
+                && x.len() <= 63
+                && !x.starts_with('-')
+                && !x.ends_with('-')
+                && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Listen ports of an endpoint listening on a port range,
+/// and the remote port of the first one if mirrored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRange {
+    pub listen: RangeInclusive<u16>,
+    pub remote: Option<u16>,
+}
+
+impl Display for PortRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (first, last) = (*self.listen.start(), *self.listen.end());
+        match self.remote {
+            Some(x) => write!(f, "ports={}-{} => {}-{}", first, last, x, x + (last - first)),
+            None => write!(f, "ports={}-{}", first, last),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EndpointInfo {
     pub no_tcp: bool,
     pub use_udp: bool,
     pub endpoint: Endpoint,
+    // the endpoint is the first port of the range
+    pub ports: Option<PortRange>,
+}
+
+impl EndpointInfo {
+    /// Endpoints to launch, one for each port of the range,
+    /// all sharing the same id.
+    pub fn expand(&self) -> Vec<Endpoint> {
+        let Some(PortRange { listen, remote }) = &self.ports else {
+            return vec![self.endpoint.clone()];
+        };
+        listen
+            .clone()
+            .map(|port| {
+                let mut endpoint = self.endpoint.clone();
+                if let LocalAddr::SocketAddr(addr) = &mut endpoint.laddr {
+                    addr.set_port(port);
+                }
+                if let Some(first) = remote {
+                    endpoint.raddr.set_port(first + (port - listen.start()));
+                }
+                endpoint
+            })
+            .collect()
+    }
 }
 
 impl Config for EndpointConf {
@@ -152,45 +708,7 @@ impl Config for EndpointConf {
     }
 
     fn build(self) -> Self::Output {
-        let laddr = self.build_local();
-        let raddr = self.build_remote();
-
-        let extra_raddrs = self.extra_remotes.iter().map(|r| Self::build_remote_x(r)).collect();
-
-        // build partial conn_opts from netconf
-        let NetInfo {
-            mut bind_opts,
-            mut conn_opts,
-            no_tcp,
-            use_udp,
-        } = self.network.build();
-
-        #[cfg(feature = "balance")]
-        {
-            conn_opts.balancer = self.build_balancer();
-        }
-
-        #[cfg(feature = "transport")]
-        {
-            conn_opts.transport = self.build_transport();
-        }
-
-        // build left fields of bind_opts and conn_opts
-        conn_opts.bind_address = self.build_send_through();
-        conn_opts.bind_interface = self.interface;
-        bind_opts.bind_interface = self.listen_interface;
-
-        EndpointInfo {
-            no_tcp,
-            use_udp,
-            endpoint: Endpoint {
-                laddr,
-                raddr,
-                bind_opts,
-                conn_opts,
-                extra_raddrs,
-            },
-        }
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
     }
 
     fn rst_field(&mut self, _: &Self) -> &mut Self {
@@ -209,18 +727,380 @@ impl Config for EndpointConf {
         let listen_interface = matches.get_one("listen_interface").cloned();
         let listen_transport = matches.get_one("listen_transport").cloned();
         let remote_transport = matches.get_one("remote_transport").cloned();
+        let trace_header = matches.get_one("trace_header").cloned();
 
         EndpointConf {
+            id: None,
             listen,
             remote,
             through,
             interface,
+            socks5_proxy: None,
             listen_interface,
             listen_transport,
             remote_transport,
+            trace_header,
+            tls_policy: None,
+            send_proxy_tlvs: Vec::new(),
             network: Default::default(),
             extra_remotes: Vec::new(),
+            tcp_remote: None,
+            udp_remote: None,
             balance: None,
+            geoip: None,
+            acl: None,
+            alert: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::FullConf;
+    use super::{EndpointConf, EndpointInfo};
+
+    #[test]
+    fn config_string_round_trip() {
+        let conf = r#"
+            [[endpoints]]
+            id = "web"
+            listen = "0.0.0.0:5000"
+            remote = "a:443"
+            extra_remotes = ["b:443", "c:443"]
+            balance = "roundrobin: 4, 2, 1"
+            remote_transport = "tls;sni=example.com"
+
+            [endpoints.network]
+            send_proxy = true
+            tcp_timeout = 10
+        "#;
+        let full = FullConf::from_conf_str(conf).unwrap();
+        let s = full.endpoints[0].to_config_string();
+
+        let full2 = FullConf::from_conf_str(&s).unwrap();
+        assert_eq!(full2.endpoints.len(), 1);
+        assert_eq!(full2.endpoints[0].to_config_string(), s);
+
+        let ep = &full2.endpoints[0];
+        assert_eq!(ep.id.as_deref(), Some("web"));
+        assert_eq!(ep.extra_remotes, ["b:443", "c:443"]);
+        assert_eq!(ep.balance.as_deref(), Some("roundrobin: 4, 2, 1"));
+        assert_eq!(ep.remote_transport.as_deref(), Some("tls;sni=example.com"));
+        assert_eq!(ep.network.send_proxy, Some(true));
+        assert_eq!(ep.network.tcp_timeout, Some(10));
+    }
+
+    #[test]
+    fn max_connections() {
+        let conf = r#"
+            [[endpoints]]
+            listen = "0.0.0.0:5000"
+            remote = "1.1.1.1:443"
+
+            [[endpoints]]
+            listen = "0.0.0.0:5001"
+            remote = "1.1.1.1:443"
+
+            [endpoints.network]
+            max_connections = 100
+        "#;
+        let full = FullConf::from_conf_str(conf).unwrap();
+
+        // unlimited if absent
+        let ep = &full.endpoints[0];
+        assert_eq!(ep.network.max_connections, None);
+        assert!(!ep.to_config_string().contains("max_connections"));
+        let info = ep.clone().try_build().unwrap();
+        assert_eq!(info.endpoint.conn_opts.max_connections, 0);
+
+        let ep = &full.endpoints[1];
+        assert_eq!(ep.network.max_connections, Some(100));
+        assert!(ep.to_config_string().contains("max_connections = 100"));
+        let info = ep.clone().try_build().unwrap();
+        assert_eq!(info.endpoint.conn_opts.max_connections, 100);
+        assert!(info.endpoint.to_string().contains("max-connections=100; "));
+    }
+
+    #[test]
+    #[cfg(feature = "proxy")]
+    fn send_proxy_tlvs() {
+        let conf = r#"
+            [[endpoints]]
+            listen = "0.0.0.0:5000"
+            remote = "1.1.1.1:443"
+            send_proxy_tlvs = ["0xe0=tenant-a", "225=hex:00ff"]
+
+            [endpoints.network]
+            send_proxy = true
+            accept_proxy_tlvs = true
+        "#;
+        let full = FullConf::from_conf_str(conf).unwrap();
+        let ep = &full.endpoints[0];
+        assert!(ep.to_config_string().contains("send_proxy_tlvs = ["));
+
+        let info = ep.clone().try_build().unwrap();
+        let opts = &info.endpoint.conn_opts.proxy_opts;
+        assert_eq!(opts.send_proxy_tlvs.len(), 2);
+        assert_eq!((opts.send_proxy_tlvs[0].kind, &opts.send_proxy_tlvs[0].value[..]), (0xe0, &b"tenant-a"[..]));
+        assert_eq!((opts.send_proxy_tlvs[1].kind, &opts.send_proxy_tlvs[1].value[..]), (0xe1, &[0, 0xff][..]));
+        assert!(opts.accept_proxy_tlvs);
+        assert!(info
+            .endpoint
+            .to_string()
+            .contains("send-proxy-tlvs=[0xe0=tenant-a, 0xe1=hex:00ff]; accept-proxy-tlvs; "));
+
+        // invalid entries, or sent with v1
+        let mut ep = ep.clone();
+        ep.send_proxy_tlvs = vec!["0x100=x".to_string()];
+        assert!(ep.clone().try_build().is_err());
+        ep.send_proxy_tlvs = vec!["0xe0=hex:0".to_string()];
+        assert!(ep.clone().try_build().is_err());
+        ep.send_proxy_tlvs = vec!["0xe0=x".to_string()];
+        ep.network.send_proxy_version = Some(1);
+        assert!(ep.try_build().unwrap_err().contains("send_proxy_version"));
+    }
+
+    #[test]
+    fn build_protocol_remotes() {
+        let conf: EndpointConf = serde_json::from_str(
+            r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","udp_remote":"2.2.2.2:443"}"#,
+        )
+        .unwrap();
+        let endpoint = conf.try_build().unwrap().endpoint;
+        assert!(endpoint.tcp_raddr.is_none());
+        assert_eq!(endpoint.udp_raddr.unwrap().to_string(), "2.2.2.2:443");
+
+        let conf: EndpointConf = serde_json::from_str(
+            r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","tcp_remote":"a.b.c:80"}"#,
+        )
+        .unwrap();
+        let endpoint = conf.try_build().unwrap().endpoint;
+        assert!(endpoint.to_string().starts_with("0.0.0.0:5000 -> [1.1.1.1:443], tcp -> a.b.c:80;"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn build_unix_socket() {
+        use realm_core::endpoint::{LocalAddr, RemoteAddr};
+        let build = |x: &str| serde_json::from_str::<EndpointConf>(x).unwrap().try_build();
+
+        let endpoint = build(r#"{"listen":"unix:/tmp/a.sock","remote":"unix:/tmp/b.sock"}"#)
+            .unwrap()
+            .endpoint;
+        assert_eq!(endpoint.laddr, LocalAddr::UnixSocket("/tmp/a.sock".into()));
+        assert_eq!(endpoint.raddr, RemoteAddr::UnixSocket("/tmp/b.sock".into()));
+        assert!(endpoint.to_string().starts_with("unix:/tmp/a.sock -> [unix:/tmp/b.sock]"));
+
+        // udp is not carried by unix sockets
+        let e = build(r#"{"listen":"unix:/tmp/a.sock","remote":"1.1.1.1:443","network":{"use_udp":true}}"#);
+        assert!(e.unwrap_err().contains("use_udp"));
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"unix:/tmp/b.sock","network":{"use_udp":true}}"#);
+        assert!(e.unwrap_err().contains("use_udp"));
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"unix:","network":{}}"#);
+        assert!(e.unwrap_err().contains("empty"));
+    }
+
+    #[test]
+    fn build_port_range() {
+        use realm_core::endpoint::RemoteAddr;
+        let build = |listen: &str, remote: &str| {
+            let conf = format!(r#"{{"listen":"{}","remote":"{}"}}"#, listen, remote);
+            serde_json::from_str::<EndpointConf>(&conf).unwrap().try_build()
+        };
+        let ports = |x: &EndpointInfo| -> Vec<(String, String)> {
+            x.expand()
+                .iter()
+                .map(|x| (x.laddr.to_string(), x.raddr.to_string()))
+                .collect()
+        };
+
+        // the same remote
+        let info = build("127.0.0.1:5000-5002", "a.b.c:443").unwrap();
+        assert_eq!(info.endpoint.id, "127.0.0.1:5000-5002");
+        assert_eq!(info.endpoint.raddr, RemoteAddr::DomainName("a.b.c".into(), 443));
+        assert_eq!(
+            ports(&info),
+            [
+                ("127.0.0.1:5000".into(), "a.b.c:443".into()),
+                ("127.0.0.1:5001".into(), "a.b.c:443".into()),
+                ("127.0.0.1:5002".into(), "a.b.c:443".into()),
+            ]
+        );
+        assert_eq!(info.ports.unwrap().to_string(), "ports=5000-5002");
+
+        // mirrored
+        let info = build("127.0.0.1:5000-5001", "10.0.0.1:6000-6001").unwrap();
+        assert_eq!(
+            ports(&info),
+            [
+                ("127.0.0.1:5000".into(), "10.0.0.1:6000".into()),
+                ("127.0.0.1:5001".into(), "10.0.0.1:6001".into()),
+            ]
+        );
+        assert_eq!(info.ports.unwrap().to_string(), "ports=5000-5001 => 6000-6001");
+
+        // a single port
+        let info = build("127.0.0.1:5000", "10.0.0.1:6000").unwrap();
+        assert!(info.ports.is_none());
+        assert_eq!(info.expand().len(), 1);
+
+        assert!(build("127.0.0.1:5002-5000", "a.b.c:443").unwrap_err().contains("invalid port range"));
+        assert!(build("127.0.0.1:5000-5001", "a.b.c:443-445").unwrap_err().contains("does not match"));
+        assert!(build("127.0.0.1:5000", "a.b.c:443-445").unwrap_err().contains("requires a listen"));
+    }
+
+    #[test]
+    fn check_unresolved() {
+        let check = |listen: &str, remote: &str, through: &str| {
+            let conf = format!(
+                r#"{{"listen":"{}","remote":"{}","through":"{}"}}"#,
+                listen, remote, through
+            );
+            serde_json::from_str::<EndpointConf>(&conf).unwrap().try_check()
+        };
+
+        // never resolved
+        let info = check("no-such-host.invalid:5000", "no-such-host.invalid:443", "no-such-host.invalid:0").unwrap();
+        assert_eq!(info.endpoint.laddr.to_string(), "0.0.0.0:5000");
+        let info = check("no-such-host.invalid:5000-5001", "a.b.c:443", "1.1.1.1").unwrap();
+        assert_eq!(info.ports.unwrap().listen, 5000..=5001);
+        assert_eq!(info.endpoint.id, "no-such-host.invalid:5000-5001");
+
+        assert!(check("a_b:5000", "a.b.c:443", "1.1.1.1").unwrap_err().starts_with("listen: invalid domain"));
+        assert!(check("0.0.0.0:5000", "a..b:443", "1.1.1.1").unwrap_err().starts_with("remote: invalid domain"));
+        assert!(check("0.0.0.0:5000", "a.b.c:443", "-a:0").unwrap_err().starts_with("through: invalid domain"));
+        assert!(check("0.0.0.0:x", "a.b.c:443", "1.1.1.1").is_err());
+    }
+
+    #[test]
+    fn build_scoped_remote() {
+        use std::net::SocketAddrV6;
+        use realm_core::endpoint::RemoteAddr;
+        let build = |x: &str| {
+            let conf = format!(r#"{{"listen":"0.0.0.0:5000","remote":"{}"}}"#, x);
+            serde_json::from_str::<EndpointConf>(&conf).unwrap().try_build()
+        };
+        let scoped = |scope_id| {
+            let addr = SocketAddrV6::new("fe80::1".parse().unwrap(), 443, 0, scope_id);
+            RemoteAddr::SocketAddr(addr.into())
+        };
+
+        let endpoint = build("[fe80::1%2]:443").unwrap().endpoint;
+        assert_eq!(endpoint.raddr, scoped(2));
+        // shown with the index, which parses back
+        assert_eq!(endpoint.raddr.to_string(), "[fe80::1%2]:443");
+        assert_eq!("[fe80::1%2]:443".parse::<RemoteAddr>().unwrap(), scoped(2));
+
+        #[cfg(target_os = "linux")]
+        assert_eq!(build("[fe80::1%lo]:443").unwrap().endpoint.raddr, scoped(1));
+        assert!(build("[fe80::1%no-such-if]:443").unwrap_err().contains("invalid zone"));
+        assert!(build("[fe80::1%2]:x").unwrap_err().contains("invalid port"));
+
+        // not scoped
+        assert_eq!(build("[fe80::1]:443").unwrap().endpoint.raddr, scoped(0));
+        assert_eq!(build("a.b.c:443").unwrap().endpoint.raddr, RemoteAddr::DomainName("a.b.c".into(), 443));
+    }
+
+    #[test]
+    fn try_build_invalid() {
+        let conf: EndpointConf = serde_json::from_str(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443"}"#).unwrap();
+        assert!(conf.try_build().is_ok());
+
+        let conf: EndpointConf = serde_json::from_str(r#"{"listen":"0.0.0.0","remote":"1.1.1.1:443"}"#).unwrap();
+        assert!(conf.try_build().unwrap_err().starts_with("invalid local address"));
+
+        #[cfg(feature = "balance")]
+        {
+            let conf: EndpointConf =
+                serde_json::from_str(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","balance":"roundrobn: 1"}"#)
+                    .unwrap();
+            assert_eq!(conf.try_build().unwrap_err(), "balance: unknown strategy: roundrobn");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alert")]
+    fn build_alert() {
+        let build = |x: &str| serde_json::from_str::<EndpointConf>(x).unwrap().try_build();
+
+        let endpoint =
+            build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","alert":{"webhook_url":"http://a.b.c/x","bytes":100}}"#)
+                .unwrap()
+                .endpoint;
+        let alert = endpoint.conn_opts.alert.unwrap();
+        assert_eq!((alert.bytes, alert.speed_bps), (100, 0));
+        assert_eq!(alert.cooldown.as_secs(), 300);
+
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","alert":{"webhook_url":"http://a.b.c/x"}}"#);
+        assert_eq!(e.unwrap_err(), "alert: neither bytes nor speed_bps is set");
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","alert":{"webhook_url":"https://a.b.c","bytes":1}}"#);
+        assert!(e.is_ok());
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","alert":{"webhook_url":"ftp://a.b.c","bytes":1}}"#);
+        assert_eq!(e.unwrap_err(), "alert: only http or https webhook is supported: ftp://a.b.c");
+    }
+
+    #[test]
+    fn build_socks5_proxy() {
+        let build = |x: &str| serde_json::from_str::<EndpointConf>(x).unwrap().try_build();
+
+        let endpoint = build(r#"{"listen":"0.0.0.0:5000","remote":"a.b.c:443","socks5_proxy":"u:p@127.0.0.1:1080"}"#)
+            .unwrap()
+            .endpoint;
+        let proxy = endpoint.conn_opts.socks5_proxy.unwrap();
+        assert_eq!(proxy.addr, "127.0.0.1:1080".parse().unwrap());
+        assert_eq!(proxy.auth, Some(("u".to_string(), "p".to_string())));
+
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","socks5_proxy":"localhost:1080"}"#);
+        assert_eq!(e.unwrap_err(), "socks5_proxy: invalid socks5 proxy address: localhost:1080");
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"unix:/tmp/a.sock","socks5_proxy":"127.0.0.1:1080"}"#);
+        assert_eq!(e.unwrap_err(), "socks5_proxy: unix socket is not supported");
+    }
+
+    #[test]
+    fn redact_socks5_proxy() {
+        let conf = |x: &str| serde_json::from_str::<EndpointConf>(x).unwrap();
+
+        let ep = conf(r#"{"listen":"0.0.0.0:5000","remote":"a.b.c:443","socks5_proxy":"u:p@ss@127.0.0.1:1080"}"#);
+        let s = ep.to_redacted_string();
+        assert!(s.contains(r#"socks5_proxy = "u:***@127.0.0.1:1080""#));
+        assert!(!s.contains("p@ss"));
+        // kept as is for reloading
+        assert!(ep.to_config_string().contains("u:p@ss@127.0.0.1:1080"));
+
+        let ep = conf(r#"{"listen":"0.0.0.0:5000","remote":"a.b.c:443","socks5_proxy":"127.0.0.1:1080"}"#);
+        assert_eq!(ep.to_redacted_string(), ep.to_config_string());
+    }
+
+    #[test]
+    fn build_acl() {
+        let build = |x: &str| serde_json::from_str::<EndpointConf>(x).unwrap().try_build();
+
+        let endpoint = build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","acl":{"allow":["10.0.0.0/8"]}}"#)
+            .unwrap()
+            .endpoint;
+        let filter = endpoint.conn_opts.accept_filter.unwrap();
+        assert!(filter.allow("10.0.0.1:1000".parse().unwrap()));
+        assert!(!filter.allow("11.0.0.1:1000".parse().unwrap()));
+
+        // fail fast
+        let e = build(r#"{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","acl":{"deny":["10.0.0.0/x"]}}"#);
+        assert_eq!(e.unwrap_err(), "acl: invalid cidr: 10.0.0.0/x");
+    }
+
+    #[test]
+    #[cfg(feature = "transport")]
+    fn reload_transport() {
+        let conf = |transport: &str| -> EndpointConf {
+            serde_json::from_str(&format!(
+                r#"{{"listen":"0.0.0.0:5000","remote":"1.1.1.1:443","listen_transport":"{}"}}"#,
+                transport
+            ))
+            .unwrap()
+        };
+        assert!(conf("ws;host=a.b.c;path=/").reload_transport().unwrap().is_none());
+        assert!(conf("tls;servername=a.b.c").reload_transport().unwrap().is_some());
+
+        let invalid = conf("tls;cert=/nonexistent/cert.pem;key=/nonexistent/key.pem").reload_transport();
+        assert!(invalid.unwrap_err().starts_with("failed to read /nonexistent/cert.pem"));
+    }
+}
@@ -68,16 +68,26 @@ impl From<LegacyConf> for FullConf {
             .into_iter()
             .zip(remote)
             .map(|(listen, remote)| EndpointConf {
+                id: None,
                 listen,
                 remote,
                 through: None,
                 interface: None,
+                socks5_proxy: None,
                 listen_interface: None,
                 listen_transport: None,
                 remote_transport: None,
+                trace_header: None,
+                tls_policy: None,
+                send_proxy_tlvs: Vec::new(),
                 network: Default::default(),
                 extra_remotes: Vec::new(),
+                tcp_remote: None,
+                udp_remote: None,
                 balance: None,
+                geoip: None,
+                acl: None,
+                alert: None,
             })
             .collect();
 
@@ -121,7 +131,7 @@ mod tests {
 
         let addrs = strvec!["a.com", "b.com", "c.com"];
         let ports = vec![1, 2, 3];
-        let result = vec!["a.com:1", "b.com:2", "c.com:3"];
+        let result = ["a.com:1", "b.com:2", "c.com:3"];
         assert_eq!(super::join_addr_port(addrs, ports, 2), result[..2]);
 
         let addrs = strvec!["a.com", "b.com", "c.com"];
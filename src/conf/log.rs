@@ -3,6 +3,7 @@ use serde::{Serialize, Deserialize};
 use log::LevelFilter;
 use super::Config;
 use crate::consts::DEFAULT_LOG_FILE;
+use realm_core::access::{self, Format as AccessFormat};
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
@@ -60,6 +61,66 @@ impl Display for LogLevel {
     }
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// `[date][time][target][level]message`
+    #[default]
+    Text,
+    /// one json object per line
+    Json,
+}
+
+impl From<String> for LogFormat {
+    fn from(x: String) -> Self {
+        use LogFormat::*;
+        match x.to_ascii_lowercase().as_str() {
+            "text" => Text,
+            "json" => Json,
+            _ => Self::default(),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use LogFormat::*;
+        let s = match self {
+            Text => "text",
+            Json => "json",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl LogFormat {
+    /// Format a record as a line, without the trailing newline.
+    pub fn line(&self, message: &std::fmt::Arguments, record: &log::Record) -> String {
+        use chrono::{Local, SecondsFormat};
+        // access lines are formatted by themselves
+        if record.target() == access::TARGET {
+            return message.to_string();
+        }
+        match self {
+            LogFormat::Text => format!(
+                "{}[{}][{}]{}",
+                Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ),
+            // newlines and quotes are escaped
+            LogFormat::Json => serde_json::json!({
+                "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": message.to_string(),
+            })
+            .to_string(),
+        }
+    }
+}
+
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct LogConf {
     #[serde(default)]
@@ -69,20 +130,34 @@ pub struct LogConf {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub output: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<AccessFormat>,
 }
 
 impl Config for LogConf {
-    type Output = (LevelFilter, fern::Output);
+    type Output = (LevelFilter, LogFormat, fern::Output, Option<AccessFormat>);
 
     fn is_empty(&self) -> bool {
-        crate::empty![self => level, output]
+        crate::empty![self => level, output, format, access]
     }
 
     fn build(self) -> Self::Output {
         use std::io;
         use std::fs::OpenOptions;
-        let LogConf { level, output } = self;
+        let LogConf {
+            level,
+            output,
+            format,
+            access,
+        } = self;
         let level = level.unwrap_or_default();
+        let format = format.unwrap_or_default();
         let output = output.unwrap_or_else(|| String::from(DEFAULT_LOG_FILE));
 
         let output: fern::Output = match output.as_str() {
@@ -92,11 +167,11 @@ impl Config for LogConf {
                 .append(true)
                 .create(true)
                 .open(output)
-                .unwrap_or_else(|e| panic!("failed to open {}: {}", output, &e))
+                .unwrap_or_else(|e| panic!("failed to open {}: {}", output, e))
                 .into(),
         };
 
-        (level.into(), output)
+        (level.into(), format, output, access)
     }
 
     fn rst_field(&mut self, other: &Self) -> &mut Self {
@@ -105,6 +180,8 @@ impl Config for LogConf {
 
         rst!(self, level, other);
         rst!(self, output, other);
+        rst!(self, format, other);
+        rst!(self, access, other);
         self
     }
 
@@ -114,6 +191,8 @@ impl Config for LogConf {
 
         take!(self, level, other);
         take!(self, output, other);
+        take!(self, format, other);
+        take!(self, access, other);
         self
     }
 
@@ -122,16 +201,76 @@ impl Config for LogConf {
 
         let output = matches.get_one("log_output").cloned();
 
-        Self { level, output }
+        let format = matches.get_one::<String>("log_format").cloned().map(LogFormat::from);
+
+        let access = matches
+            .get_one::<String>("log_access")
+            .map(|x| x.parse().unwrap_or_else(|e| panic!("{}", e)));
+
+        Self {
+            level,
+            output,
+            format,
+            access,
+        }
     }
 }
 
 impl Display for LogConf {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let LogConf { level, output } = self.clone();
+        let LogConf {
+            level,
+            output,
+            format,
+            access,
+        } = self.clone();
         let level = level.unwrap_or_default();
         let output = output.unwrap_or_else(|| String::from("stdout"));
+        let format = format.unwrap_or_default();
+
+        write!(f, "level={}, output={}, format={}", level, output, format)?;
+        if let Some(access) = access {
+            write!(f, ", access={}", access)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_line() {
+        let record = log::Record::builder().level(log::Level::Warn).target("realm").build();
+        let line = LogFormat::Json.line(&format_args!("a \"quoted\"\nmulti-line\tmessage"), &record);
+        assert!(!line.contains('\n'));
+
+        let v: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["level"], "WARN");
+        assert_eq!(v["target"], "realm");
+        assert_eq!(v["message"], "a \"quoted\"\nmulti-line\tmessage");
+        assert!(chrono::DateTime::parse_from_rfc3339(v["timestamp"].as_str().unwrap()).is_ok());
+
+        let line = LogFormat::Text.line(&format_args!("message"), &record);
+        assert!(line.ends_with("[realm][WARN]message"));
+
+        // access lines as they are
+        let record = log::Record::builder().level(log::Level::Info).target("access").build();
+        assert_eq!(LogFormat::Json.line(&format_args!("line"), &record), "line");
+    }
+
+    #[test]
+    fn log_format() {
+        let conf: LogConf = toml::from_str(r#"format = "json""#).unwrap();
+        assert_eq!(conf.format, Some(LogFormat::Json));
+        assert_eq!(LogFormat::from("JSON".to_string()), LogFormat::Json);
+        assert_eq!(LogFormat::from("x".to_string()), LogFormat::Text);
+        assert_eq!(LogConf::default().build().1, LogFormat::Text);
 
-        write!(f, "level={}, output={}", level, output)
+        let conf: LogConf = toml::from_str(r#"access = "json""#).unwrap();
+        assert_eq!(conf.access, Some(AccessFormat::Json));
+        assert!(toml::from_str::<LogConf>(r#"access = "clf""#).is_err());
+        assert_eq!(LogConf::default().build().3, None);
     }
 }
@@ -0,0 +1,81 @@
+use std::fmt::{Formatter, Display};
+use serde::{Serialize, Deserialize};
+use realm_core::monitor::{DEFAULT_BYTES_BUCKETS, DEFAULT_DURATION_BUCKETS};
+use super::Config;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct MetricsConf {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_buckets: Option<Vec<f64>>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_buckets: Option<Vec<f64>>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_collector: Option<String>,
+}
+
+impl Config for MetricsConf {
+    type Output = (Vec<f64>, Vec<f64>, Option<String>);
+
+    fn is_empty(&self) -> bool {
+        crate::empty![self => bytes_buckets, duration_buckets, flow_collector]
+    }
+
+    fn build(self) -> Self::Output {
+        let MetricsConf {
+            bytes_buckets,
+            duration_buckets,
+            flow_collector,
+        } = self;
+        let bytes_buckets = bytes_buckets.unwrap_or_else(|| DEFAULT_BYTES_BUCKETS.to_vec());
+        let duration_buckets = duration_buckets.unwrap_or_else(|| DEFAULT_DURATION_BUCKETS.to_vec());
+
+        (bytes_buckets, duration_buckets, flow_collector)
+    }
+
+    fn rst_field(&mut self, other: &Self) -> &mut Self {
+        use crate::rst;
+        let other = other.clone();
+
+        rst!(self, bytes_buckets, other);
+        rst!(self, duration_buckets, other);
+        rst!(self, flow_collector, other);
+        self
+    }
+
+    fn take_field(&mut self, other: &Self) -> &mut Self {
+        use crate::take;
+        let other = other.clone();
+
+        take!(self, bytes_buckets, other);
+        take!(self, duration_buckets, other);
+        take!(self, flow_collector, other);
+        self
+    }
+
+    // no cmd args
+    fn from_cmd_args(_: &clap::ArgMatches) -> Self {
+        Self::default()
+    }
+}
+
+impl Display for MetricsConf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (bytes_buckets, duration_buckets, flow_collector) = self.clone().build();
+
+        write!(
+            f,
+            "bytes-buckets={:?}, duration-buckets={:?}",
+            bytes_buckets, duration_buckets
+        )?;
+
+        if let Some(collector) = flow_collector {
+            write!(f, ", flow-collector={}", collector)?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use std::fs;
-use std::io::{Result, Error, ErrorKind};
+use std::io::{Result, Error};
 
 use walkdir::WalkDir;
 use clap::ArgMatches;
@@ -14,12 +14,17 @@ pub use dns::{DnsMode, DnsProtocol, DnsConf};
 mod net;
 pub use net::{NetConf, NetInfo};
 
+mod metrics;
+pub use metrics::MetricsConf;
+
 mod endpoint;
-pub use endpoint::{EndpointConf, EndpointInfo};
+pub use endpoint::{EndpointConf, EndpointInfo, GeoConf, PortRange, TlsPolicyConf};
 
 mod legacy;
 pub use legacy::LegacyConf;
 
+mod remote;
+
 #[allow(clippy::too_long_first_doc_paragraph)]
 /// Conig Architecture
 /// cmd | file => LogConf => { level, output }
@@ -45,7 +50,7 @@ pub trait Config {
     fn from_cmd_args(matches: &ArgMatches) -> Self;
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct CmdOverride {
     pub log: LogConf,
     pub dns: DnsConf,
@@ -66,6 +71,10 @@ pub struct FullConf {
     #[serde(skip_serializing_if = "Config::is_empty")]
     pub network: NetConf,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Config::is_empty")]
+    pub metrics: MetricsConf,
+
     pub endpoints: Vec<EndpointConf>,
 }
 
@@ -76,19 +85,30 @@ impl FullConf {
             log,
             dns,
             network,
+            metrics: MetricsConf::default(),
             endpoints,
         }
     }
 
     pub fn from_conf_file(file: &str) -> Self {
-        let mtd = fs::metadata(file).unwrap_or_else(|e| panic!("failed to open {}: {}", file, e));
+        Self::try_from_conf_file(file).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as [`from_conf_file`](Self::from_conf_file), but return an error instead of panicking,
+    /// e.g. when the config is reloaded.
+    pub fn try_from_conf_file(file: &str) -> Result<Self> {
+        if remote::is_url(file) {
+            return Self::from_conf_str(file);
+        }
+
+        let open_err = |path: &str, e: Error| Error::new(e.kind(), format!("failed to open {}: {}", path, e));
+        let parse_err = |path: &str, e: Error| Error::new(e.kind(), format!("failed to parse {}: {}", path, e));
+
+        let mtd = fs::metadata(file).map_err(|e| open_err(file, e))?;
 
         if mtd.is_file() {
-            let conf = fs::read_to_string(file).unwrap_or_else(|e| panic!("failed to open {}: {}", file, e));
-            match Self::from_conf_str(&conf) {
-                Ok(x) => return x,
-                Err(e) => panic!("failed to parse {}: {}", file, &e),
-            }
+            let conf = fs::read_to_string(file).map_err(|e| open_err(file, e))?;
+            return Self::from_conf_str(&conf).map_err(|e| parse_err(file, e));
         }
 
         let mut full_conf = FullConf::default();
@@ -100,17 +120,23 @@ impl FullConf {
             .filter(|e| e.file_type().is_file())
             .filter(|e| e.path().extension().is_some_and(|s| s == "toml" || s == "json"))
         {
-            let conf_part = fs::read_to_string(entry.path())
-                .unwrap_or_else(|e| panic!("failed to open {}: {}", entry.path().to_string_lossy(), e));
-
-            let conf_part = Self::from_conf_str(&conf_part)
-                .unwrap_or_else(|e| panic!("failed to parse {}: {}", entry.path().to_string_lossy(), e));
+            let path = entry.path().to_string_lossy();
+            let conf_part = fs::read_to_string(entry.path()).map_err(|e| open_err(&path, e))?;
+            let conf_part = Self::from_conf_str(&conf_part).map_err(|e| parse_err(&path, e))?;
             full_conf.take_fields(conf_part);
         }
-        full_conf
+        Ok(full_conf)
     }
 
+    /// Parse a toml or json config, or fetch it from a http(s) url.
     pub fn from_conf_str(s: &str) -> Result<Self> {
+        if remote::is_url(s) {
+            return remote::load(s, Self::parse_conf_str);
+        }
+        Self::parse_conf_str(s)
+    }
+
+    fn parse_conf_str(s: &str) -> Result<Self> {
         let toml_err = match toml::from_str(s) {
             Ok(x) => return Ok(x),
             Err(e) => e,
@@ -130,19 +156,17 @@ impl FullConf {
             Err(e) => e,
         };
 
-        Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "parse as toml: {0}; parse as json: {1}; parse as legacy: {2}",
-                toml_err, json_err, legacy_err
-            ),
-        ))
+        Err(Error::other(format!(
+            "parse as toml: {0}; parse as json: {1}; parse as legacy: {2}",
+            toml_err, json_err, legacy_err
+        )))
     }
 
     fn take_fields(&mut self, other: Self) {
         self.log.take_field(&other.log);
         self.dns.take_field(&other.dns);
         self.network.take_field(&other.network);
+        self.metrics.take_field(&other.metrics);
         self.endpoints.extend(other.endpoints);
     }
 
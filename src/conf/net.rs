@@ -1,9 +1,15 @@
 use serde::{Serialize, Deserialize};
-use realm_core::endpoint::{BindOpts, ConnectOpts};
+use realm_core::endpoint::{BindOpts, ByteCount, ConnectOpts, HookFailMode, QuarantineOpts, UpstreamEof};
 
 use super::Config;
 use crate::consts::{TCP_TIMEOUT, UDP_TIMEOUT};
 use crate::consts::{TCP_KEEPALIVE, TCP_KEEPALIVE_PROBE};
+use crate::consts::{WRITE_COALESCE_SIZE, UDP_BATCH_SIZE};
+use crate::consts::{QUARANTINE_WINDOW, QUARANTINE_COOLDOWN};
+use crate::consts::EJECT_COOLDOWN;
+use crate::consts::CONNECT_RETRY_DELAY;
+#[cfg(feature = "happy-eyeballs")]
+use crate::consts::HAPPY_EYEBALLS_DELAY;
 use crate::consts::PROXY_PROTOCOL_VERSION;
 use crate::consts::PROXY_PROTOCOL_TIMEOUT;
 
@@ -21,6 +27,14 @@ pub struct NetConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ipv6_only: Option<bool>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reuse_address: Option<bool>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reuse_port_workers: Option<usize>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub send_proxy: Option<bool>,
@@ -36,6 +50,10 @@ pub struct NetConf {
     #[serde(default)]
     pub accept_proxy_timeout: Option<usize>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_proxy_tlvs: Option<bool>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tcp_keepalive: Option<usize>,
@@ -44,13 +62,129 @@ pub struct NetConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tcp_keepalive_probe: Option<usize>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_linger: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_nodelay: Option<bool>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_buffer_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recv_buffer_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_backlog: Option<u32>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_eof: Option<UpstreamEof>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_count: Option<ByteCount>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dscp: Option<u8>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_coalesce_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_coalesce_delay: Option<usize>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tcp_timeout: Option<usize>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_retries: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_retry_delay: Option<usize>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub udp_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_fail_mode: Option<HookFailMode>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections_per_ip: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub udp_associate_concurrency: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub udp_batch_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_bps: Option<u64>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine_threshold: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine_window: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine_cooldown: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eject_threshold: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eject_cooldown: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check_interval: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_round_robin: Option<bool>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub happy_eyeballs_delay: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mptcp: Option<bool>,
 }
 
 #[derive(Debug)]
@@ -66,9 +200,16 @@ impl Config for NetConf {
 
     fn is_empty(&self) -> bool {
         crate::empty![self =>
-            no_tcp, use_udp, ipv6_only,
-            send_proxy, accept_proxy, send_proxy_version, accept_proxy_timeout,
-            tcp_keepalive, tcp_keepalive_probe, tcp_timeout, udp_timeout
+            no_tcp, use_udp, ipv6_only, reuse_address, reuse_port_workers,
+            send_proxy, accept_proxy, send_proxy_version, accept_proxy_timeout, accept_proxy_tlvs,
+            tcp_keepalive, tcp_keepalive_probe, tcp_linger, tcp_nodelay, send_buffer_size, recv_buffer_size, listen_backlog, upstream_eof, byte_count, dscp, tcp_timeout, udp_timeout, idle_timeout,
+            connect_retries, connect_retry_delay,
+            hook_fail_mode, max_connections, max_connections_per_ip, udp_associate_concurrency, max_bytes, rate_limit_bps,
+            udp_batch_size,
+            write_coalesce_size, write_coalesce_delay,
+            quarantine_threshold, quarantine_window, quarantine_cooldown,
+            eject_threshold, eject_cooldown, health_check_interval,
+            dns_round_robin, happy_eyeballs_delay, mptcp
         ]
     }
 
@@ -85,31 +226,123 @@ impl Config for NetConf {
         let no_tcp = unbox!(no_tcp);
         let use_udp = unbox!(use_udp);
         let ipv6_only = unbox!(ipv6_only);
+        let reuse_address = unbox!(reuse_address, true);
+        let reuse_port_workers = unbox!(reuse_port_workers);
+        let tcp_linger = self.tcp_linger;
+        let tcp_nodelay = unbox!(tcp_nodelay, true);
+        let send_buffer_size = self.send_buffer_size;
+        let recv_buffer_size = self.recv_buffer_size;
+        let listen_backlog = self.listen_backlog;
+        let upstream_eof = unbox!(upstream_eof);
+        let byte_count = unbox!(byte_count);
+        let dscp = unbox!(dscp);
+        if dscp > 63 {
+            panic!("dscp: {} is out of range 0-63", dscp);
+        }
         let tcp_kpa = unbox!(tcp_keepalive, TCP_KEEPALIVE);
         let tcp_kpa_probe = unbox!(tcp_keepalive_probe, TCP_KEEPALIVE_PROBE);
         let tcp_timeout = unbox!(tcp_timeout, TCP_TIMEOUT);
         let udp_timeout = unbox!(udp_timeout, UDP_TIMEOUT);
+        let connect_retries = unbox!(connect_retries);
+        let connect_retry_delay = unbox!(connect_retry_delay, CONNECT_RETRY_DELAY);
+        let idle_timeout = unbox!(idle_timeout);
+        let max_conns = unbox!(max_connections);
+        let max_conns_per_ip = unbox!(max_connections_per_ip);
+        let associate_concurrency = unbox!(udp_associate_concurrency);
+        let udp_batch_size = unbox!(udp_batch_size, UDP_BATCH_SIZE).clamp(1, UDP_BATCH_SIZE);
+        let max_bytes = unbox!(max_bytes);
+        let rate_limit_bps = unbox!(rate_limit_bps);
+        let coalesce_size = unbox!(write_coalesce_size, WRITE_COALESCE_SIZE);
+        let coalesce_delay = unbox!(write_coalesce_delay);
+        let dns_round_robin = unbox!(dns_round_robin);
+        let quarantine = QuarantineOpts {
+            threshold: unbox!(quarantine_threshold),
+            window: unbox!(quarantine_window, QUARANTINE_WINDOW),
+            cooldown: unbox!(quarantine_cooldown, QUARANTINE_COOLDOWN),
+        };
 
         let bind_opts = BindOpts {
             ipv6_only,
+            reuse_address,
+            tcp_linger,
+            send_buffer_size,
+            recv_buffer_size,
+            listen_backlog,
             bind_interface: None,
+            reuse_port_workers,
+            dscp,
+            #[cfg(feature = "mptcp")]
+            mptcp: unbox!(mptcp),
         };
         let conn_opts = ConnectOpts {
             tcp_keepalive: tcp_kpa,
             tcp_keepalive_probe: tcp_kpa_probe,
+            tcp_linger,
+            tcp_nodelay,
+            send_buffer_size,
+            recv_buffer_size,
+            max_connections: max_conns,
+            max_connections_per_ip: max_conns_per_ip,
+            associate_concurrency,
+            udp_batch_size,
+            max_bytes,
+            rate_limit_bps,
+            write_coalesce_size: coalesce_size,
+            write_coalesce_delay: coalesce_delay,
+            upstream_eof,
+            byte_count,
+            dscp,
+            quarantine,
+            dns_round_robin,
             connect_timeout: tcp_timeout,
+            connect_retries,
+            connect_retry_delay,
+
+            #[cfg(feature = "happy-eyeballs")]
+            happy_eyeballs_delay: unbox!(happy_eyeballs_delay, HAPPY_EYEBALLS_DELAY),
+
+            #[cfg(feature = "mptcp")]
+            mptcp: unbox!(mptcp),
             associate_timeout: udp_timeout,
+            idle_timeout,
 
             // from endpoint
             bind_address: None,
             bind_interface: None,
+            socks5_proxy: None,
+
+            // only set by embedders
+            accept_filter: None,
+            #[cfg(feature = "alert")]
+            alert: None,
 
             #[cfg(feature = "balance")]
             balancer: Default::default(),
 
+            #[cfg(feature = "balance")]
+            eject: realm_core::endpoint::EjectOpts {
+                threshold: unbox!(eject_threshold),
+                cooldown: unbox!(eject_cooldown, EJECT_COOLDOWN),
+            },
+
+            #[cfg(feature = "balance")]
+            health_check_interval: unbox!(health_check_interval),
+
             #[cfg(feature = "transport")]
             transport: None,
 
+            #[cfg(feature = "transport")]
+            trace_header: None,
+
+            #[cfg(feature = "transport")]
+            tls_policy: None,
+
+            #[cfg(feature = "geoip")]
+            geoip: None,
+
+            #[cfg(feature = "hook")]
+            hook_fail_mode: unbox!(hook_fail_mode),
+
             #[cfg(feature = "proxy")]
             proxy_opts: {
                 use realm_core::endpoint::ProxyOpts;
@@ -117,11 +350,15 @@ impl Config for NetConf {
                 let send_proxy_version = unbox!(send_proxy_version, PROXY_PROTOCOL_VERSION);
                 let accept_proxy = unbox!(accept_proxy);
                 let accept_proxy_timeout = unbox!(accept_proxy_timeout, PROXY_PROTOCOL_TIMEOUT);
+                let accept_proxy_tlvs = unbox!(accept_proxy_tlvs);
                 ProxyOpts {
                     send_proxy,
                     accept_proxy,
                     send_proxy_version,
                     accept_proxy_timeout,
+                    accept_proxy_tlvs,
+                    // from endpoint
+                    send_proxy_tlvs: Vec::new(),
                 }
             },
         };
@@ -141,14 +378,46 @@ impl Config for NetConf {
         rst!(self, no_tcp, other);
         rst!(self, use_udp, other);
         rst!(self, ipv6_only, other);
+        rst!(self, reuse_address, other);
+        rst!(self, reuse_port_workers, other);
         rst!(self, tcp_keepalive, other);
         rst!(self, tcp_keepalive_probe, other);
+        rst!(self, tcp_linger, other);
+        rst!(self, tcp_nodelay, other);
+        rst!(self, send_buffer_size, other);
+        rst!(self, recv_buffer_size, other);
+        rst!(self, listen_backlog, other);
+        rst!(self, upstream_eof, other);
+        rst!(self, byte_count, other);
+        rst!(self, dscp, other);
         rst!(self, tcp_timeout, other);
         rst!(self, udp_timeout, other);
+        rst!(self, connect_retries, other);
+        rst!(self, connect_retry_delay, other);
+        rst!(self, idle_timeout, other);
         rst!(self, send_proxy, other);
         rst!(self, accept_proxy, other);
         rst!(self, send_proxy_version, other);
         rst!(self, accept_proxy_timeout, other);
+        rst!(self, accept_proxy_tlvs, other);
+        rst!(self, hook_fail_mode, other);
+        rst!(self, max_connections, other);
+        rst!(self, max_connections_per_ip, other);
+        rst!(self, udp_associate_concurrency, other);
+        rst!(self, udp_batch_size, other);
+        rst!(self, max_bytes, other);
+        rst!(self, rate_limit_bps, other);
+        rst!(self, write_coalesce_size, other);
+        rst!(self, write_coalesce_delay, other);
+        rst!(self, quarantine_threshold, other);
+        rst!(self, quarantine_window, other);
+        rst!(self, quarantine_cooldown, other);
+        rst!(self, eject_threshold, other);
+        rst!(self, eject_cooldown, other);
+        rst!(self, health_check_interval, other);
+        rst!(self, dns_round_robin, other);
+        rst!(self, happy_eyeballs_delay, other);
+        rst!(self, mptcp, other);
         self
     }
 
@@ -159,14 +428,46 @@ impl Config for NetConf {
         take!(self, no_tcp, other);
         take!(self, use_udp, other);
         take!(self, ipv6_only, other);
+        take!(self, reuse_address, other);
+        take!(self, reuse_port_workers, other);
         take!(self, tcp_keepalive, other);
         take!(self, tcp_keepalive_probe, other);
+        take!(self, tcp_linger, other);
+        take!(self, tcp_nodelay, other);
+        take!(self, send_buffer_size, other);
+        take!(self, recv_buffer_size, other);
+        take!(self, listen_backlog, other);
+        take!(self, upstream_eof, other);
+        take!(self, byte_count, other);
+        take!(self, dscp, other);
         take!(self, tcp_timeout, other);
         take!(self, udp_timeout, other);
+        take!(self, connect_retries, other);
+        take!(self, connect_retry_delay, other);
+        take!(self, idle_timeout, other);
         take!(self, send_proxy, other);
         take!(self, accept_proxy, other);
         take!(self, send_proxy_version, other);
         take!(self, accept_proxy_timeout, other);
+        take!(self, accept_proxy_tlvs, other);
+        take!(self, hook_fail_mode, other);
+        take!(self, max_connections, other);
+        take!(self, max_connections_per_ip, other);
+        take!(self, udp_associate_concurrency, other);
+        take!(self, udp_batch_size, other);
+        take!(self, max_bytes, other);
+        take!(self, rate_limit_bps, other);
+        take!(self, write_coalesce_size, other);
+        take!(self, write_coalesce_delay, other);
+        take!(self, quarantine_threshold, other);
+        take!(self, quarantine_window, other);
+        take!(self, quarantine_cooldown, other);
+        take!(self, eject_threshold, other);
+        take!(self, eject_cooldown, other);
+        take!(self, health_check_interval, other);
+        take!(self, dns_round_robin, other);
+        take!(self, happy_eyeballs_delay, other);
+        take!(self, mptcp, other);
         self
     }
 
@@ -190,6 +491,8 @@ impl Config for NetConf {
 
         let tcp_keepalive = unpack!("tcp_keepalive", usize);
         let tcp_keepalive_probe = unpack!("tcp_keepalive", usize);
+        let tcp_linger = unpack!("tcp_linger", usize);
+        let upstream_eof = unpack!("upstream_eof", UpstreamEof);
         let tcp_timeout = unpack!("tcp_timeout", usize);
         let udp_timeout = unpack!("udp_timeout", usize);
 
@@ -199,18 +502,52 @@ impl Config for NetConf {
         let accept_proxy = unpack!("accept_proxy", bool);
         let accept_proxy_timeout = unpack!("accept_proxy_timeout", usize);
 
+        let hook_fail_mode = unpack!("hook_fail_mode", HookFailMode);
+
         Self {
             no_tcp,
             use_udp,
             ipv6_only,
+            reuse_address: None,
+            reuse_port_workers: None,
             tcp_keepalive,
             tcp_keepalive_probe,
+            tcp_linger,
+            tcp_nodelay: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            listen_backlog: None,
+            upstream_eof,
+            byte_count: None,
+            dscp: None,
             tcp_timeout,
             udp_timeout,
+            connect_retries: None,
+            connect_retry_delay: None,
+            idle_timeout: None,
             send_proxy,
             accept_proxy,
             send_proxy_version,
             accept_proxy_timeout,
+            accept_proxy_tlvs: None,
+            hook_fail_mode,
+            max_connections: None,
+            max_connections_per_ip: None,
+            udp_associate_concurrency: None,
+            udp_batch_size: None,
+            max_bytes: None,
+            rate_limit_bps: None,
+            write_coalesce_size: None,
+            write_coalesce_delay: None,
+            quarantine_threshold: None,
+            quarantine_window: None,
+            quarantine_cooldown: None,
+            eject_threshold: None,
+            eject_cooldown: None,
+            health_check_interval: None,
+            dns_round_robin: None,
+            happy_eyeballs_delay: None,
+            mptcp: None,
         }
     }
 }
@@ -0,0 +1,85 @@
+//! Fetch config from a http(s) url.
+
+use std::env;
+use std::fs;
+use std::io::{Result, Error};
+
+use crate::ENV_CONFIG_CACHE;
+
+/// Check if the location is a http(s) url.
+pub fn is_url(s: &str) -> bool {
+    let s = s.trim();
+    !s.contains('\n') && (s.starts_with("http://") || s.starts_with("https://"))
+}
+
+#[cfg(feature = "remote-config")]
+fn fetch(url: &str) -> Result<String> {
+    use std::time::Duration;
+    use crate::ENV_CONFIG_TIMEOUT;
+    use crate::consts::CONFIG_FETCH_TIMEOUT;
+
+    let timeout = env::var(ENV_CONFIG_TIMEOUT)
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(CONFIG_FETCH_TIMEOUT as u64);
+
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(timeout))
+        .build()
+        .get(url)
+        .call()
+        .map_err(Error::other)?
+        .into_string()
+}
+
+#[cfg(not(feature = "remote-config"))]
+fn fetch(_: &str) -> Result<String> {
+    use std::io::ErrorKind;
+    Err(Error::new(ErrorKind::Unsupported, "require remote-config feature"))
+}
+
+/// Fetch and parse the config from a url.
+///
+/// A successfully parsed config is saved to the cache file,
+/// which is loaded instead if the config is not available.
+pub fn load<T, F>(url: &str, parse: F) -> Result<T>
+where
+    F: Fn(&str) -> Result<T>,
+{
+    let url = url.trim();
+    let cache = env::var(ENV_CONFIG_CACHE).ok();
+
+    let err = match fetch(url).and_then(|s| parse(&s).map(|x| (s, x))) {
+        Ok((s, conf)) => {
+            if let Some(cache) = &cache {
+                if let Err(e) = fs::write(cache, s) {
+                    log::warn!("failed to save config cache {}: {}", cache, e);
+                }
+            }
+            return Ok(conf);
+        }
+        Err(e) => e,
+    };
+
+    let Some(cache) = cache else {
+        return Err(Error::new(err.kind(), format!("failed to load {}: {}", url, err)));
+    };
+
+    log::warn!("failed to load {}: {}, fallback to {}", url, err, cache);
+    let s = fs::read_to_string(&cache)
+        .map_err(|e| Error::new(e.kind(), format!("failed to load {}: {}; cache {}: {}", url, err, cache, e)))?;
+    parse(&s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_url() {
+        assert!(is_url("http://127.0.0.1/realm.toml"));
+        assert!(is_url(" https://example.com/realm.json\n"));
+        assert!(!is_url("realm.toml"));
+        assert!(!is_url("[[endpoints]]\nremote = \"http://127.0.0.1\""));
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt::{Display, Formatter};
+use serde::Serialize;
 
 // default logfile
 pub const DEFAULT_LOG_FILE: &str = "stdout";
@@ -9,12 +10,46 @@ pub const TCP_KEEPALIVE: usize = 15;
 pub const TCP_KEEPALIVE_PROBE: usize = 3;
 pub const UDP_TIMEOUT: usize = 30;
 
+// default quarantine window and cooldown
+pub const QUARANTINE_WINDOW: usize = 60;
+pub const QUARANTINE_COOLDOWN: usize = 30;
+
+// default cooldown of an ejected remote peer
+pub const EJECT_COOLDOWN: usize = 30;
+
+// default udp batch size, also the max
+pub const UDP_BATCH_SIZE: usize = realm_core::udp::MAX_PACKETS;
+
+// default write coalescing buffer size
+pub const WRITE_COALESCE_SIZE: usize = 4096;
+
 // default haproxy proxy-protocol version
 pub const PROXY_PROTOCOL_VERSION: usize = 2;
 
 // default haproxy proxy-protocol version
 pub const PROXY_PROTOCOL_TIMEOUT: usize = 5;
 
+// default api server worker threads
+pub const API_WORKERS: usize = 2;
+
+// default graceful shutdown timeout
+pub const DRAIN_TIMEOUT: usize = 30;
+
+// default speed calculation interval
+pub const SPEED_INTERVAL: usize = 5;
+
+// default delay before the first connect retry, in milliseconds
+pub const CONNECT_RETRY_DELAY: usize = 100;
+
+// default delay before racing the next address, as recommended by RFC 8305
+pub const HAPPY_EYEBALLS_DELAY: usize = 250;
+
+// default seconds before the same alert fires again
+pub const ALERT_COOLDOWN: usize = 300;
+
+// default remote config fetch timeout
+pub const CONFIG_FETCH_TIMEOUT: usize = 10;
+
 // features
 macro_rules! def_feat {
     ($fet: ident, $name: expr) => {
@@ -25,12 +60,19 @@ macro_rules! def_feat {
 def_feat!(FEATURE_HOOK, "hook");
 def_feat!(FEATURE_PROXY, "proxy");
 def_feat!(FEATURE_BALANCE, "balance");
-def_feat!(FEATURE_MIMALLOC, "mimalloc");
+def_feat!(FEATURE_MIMALLOC, "mi-malloc");
 def_feat!(FEATURE_JEMALLOC, "jemalloc");
 def_feat!(FEATURE_MULTI_THREAD, "multi-thread");
 def_feat!(FEATURE_TRANSPORT, "transport");
 def_feat!(FEATURE_BRUTAL_SHUTDOWN, "brutal-shutdown");
+def_feat!(FEATURE_GEOIP, "geoip");
+def_feat!(FEATURE_REMOTE_CONFIG, "remote-config");
+def_feat!(FEATURE_HAPPY_EYEBALLS, "happy-eyeballs");
+def_feat!(FEATURE_MPTCP, "mptcp");
+def_feat!(FEATURE_API_TLS, "api-tls");
+def_feat!(FEATURE_ALERT, "alert");
 
+#[derive(Serialize)]
 pub struct Features {
     pub mimalloc: bool,
     pub jemalloc: bool,
@@ -40,6 +82,12 @@ pub struct Features {
     pub balance: bool,
     pub transport: bool,
     pub brutal_shutdown: bool,
+    pub geoip: bool,
+    pub remote_config: bool,
+    pub happy_eyeballs: bool,
+    pub mptcp: bool,
+    pub api_tls: bool,
+    pub alert: bool,
 }
 
 pub const FEATURES: Features = Features {
@@ -51,6 +99,48 @@ pub const FEATURES: Features = Features {
     balance: FEATURE_BALANCE,
     transport: FEATURE_TRANSPORT,
     brutal_shutdown: FEATURE_BRUTAL_SHUTDOWN,
+    geoip: FEATURE_GEOIP,
+    remote_config: FEATURE_REMOTE_CONFIG,
+    happy_eyeballs: FEATURE_HAPPY_EYEBALLS,
+    mptcp: FEATURE_MPTCP,
+    api_tls: FEATURE_API_TLS,
+    alert: FEATURE_ALERT,
+};
+
+impl Features {
+    /// Names of the enabled cargo features.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        [
+            (self.hook, "hook"),
+            (self.proxy, "proxy"),
+            (self.balance, "balance"),
+            (self.brutal_shutdown, "brutal-shutdown"),
+            (self.transport, "transport"),
+            (self.geoip, "geoip"),
+            (self.remote_config, "remote-config"),
+            (self.happy_eyeballs, "happy-eyeballs"),
+            (self.mptcp, "mptcp"),
+            (self.api_tls, "api-tls"),
+            (self.alert, "alert"),
+            (self.multi_thread, "multi-thread"),
+            (self.mimalloc, "mi-malloc"),
+            (self.jemalloc, "jemalloc"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect()
+    }
+}
+
+// the global allocator picked in bin.rs
+pub const ALLOCATOR: &str = if cfg!(feature = "mi-malloc") {
+    "mimalloc"
+} else if cfg!(all(feature = "jemalloc", not(target_env = "msvc"))) {
+    "jemalloc"
+} else if cfg!(all(feature = "page-alloc", unix)) {
+    "mmap"
+} else {
+    "system"
 };
 
 impl Display for Features {
@@ -68,6 +158,12 @@ impl Display for Features {
         disp_feat!(balance, "balance");
         disp_feat!(brutal_shutdown, "brutal");
         disp_feat!(transport, "transport");
+        disp_feat!(geoip, "geoip");
+        disp_feat!(remote_config, "remote-config");
+        disp_feat!(happy_eyeballs, "happy-eyeballs");
+        disp_feat!(mptcp, "mptcp");
+        disp_feat!(api_tls, "api-tls");
+        disp_feat!(alert, "alert");
         disp_feat!(multi_thread, "multi-thread");
         disp_feat!(mimalloc, "mimalloc");
         disp_feat!(jemalloc, "jemalloc");
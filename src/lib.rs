@@ -4,4 +4,25 @@ pub mod consts;
 pub use realm_core as core;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Set by the build script, unless built without git.
+pub const GIT_COMMIT: Option<&str> = option_env!("REALM_GIT_COMMIT");
 pub const ENV_CONFIG: &str = "REALM_CONF";
+pub const ENV_CONFIG_TIMEOUT: &str = "REALM_CONF_TIMEOUT";
+pub const ENV_CONFIG_CACHE: &str = "REALM_CONF_CACHE";
+pub const ENV_API_ENABLED: &str = "API_ENABLED";
+pub const ENV_API_WORKERS: &str = "API_WORKERS";
+pub const ENV_API_LOG: &str = "API_LOG";
+pub const ENV_API_LOG_HEADERS: &str = "API_LOG_HEADERS";
+pub const ENV_API_LOG_HEADERS_DENY: &str = "API_LOG_HEADERS_DENY";
+pub const ENV_API_TOKENS: &str = "API_TOKENS";
+pub const ENV_API_TOKENS_FILE: &str = "API_TOKENS_FILE";
+pub const ENV_API_RATE_LIMIT: &str = "API_RATE_LIMIT";
+pub const ENV_API_TLS_CERT: &str = "API_TLS_CERT";
+pub const ENV_API_TLS_KEY: &str = "API_TLS_KEY";
+pub const ENV_API_UNIX_SOCKET: &str = "API_UNIX_SOCKET";
+pub const ENV_LOG_FORMAT: &str = "LOG_FORMAT";
+pub const ENV_CONNECT_TIMEOUT: &str = "REALM_CONNECT_TIMEOUT";
+pub const ENV_DRAIN_TIMEOUT: &str = "REALM_DRAIN_TIMEOUT";
+pub const ENV_SPEED_INTERVAL: &str = "SPEED_INTERVAL_SECS";
+pub const ENV_RULES_STORE: &str = "REALM_RULES_STORE";
+pub const ENV_DYNAMIC_PORT_RANGE: &str = "REALM_DYNAMIC_PORT_RANGE";
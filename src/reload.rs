@@ -0,0 +1,220 @@
+use std::io::Result;
+use std::collections::BTreeMap;
+
+use actix_web::web;
+
+use realm::conf::{CmdOverride, FullConf, EndpointConf};
+use realm_core::monitor::RULE_METRICS;
+
+use crate::api::{RuleConfigs, Relays};
+
+/// Where the config comes from.
+#[derive(Debug)]
+pub enum Source {
+    /// A config file or directory, with overrides from the command line.
+    File(String, Box<CmdOverride>),
+    /// A config string or url from [`ENV_CONFIG`](realm::ENV_CONFIG).
+    Env(String),
+}
+
+impl Source {
+    /// Load endpoints the same way as they are loaded at startup.
+    fn load(&self) -> Result<Vec<EndpointConf>> {
+        match self {
+            Source::File(file, opts) => {
+                let mut conf = FullConf::try_from_conf_file(file)?;
+                conf.apply_global_opts().apply_cmd_opts((**opts).clone());
+                Ok(conf.endpoints)
+            }
+            Source::Env(s) => FullConf::from_conf_str(s).map(|x| x.endpoints),
+        }
+    }
+}
+
+/// Rules changed by a reload.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+}
+
+/// Reload endpoints from the config source.
+///
+/// Endpoints are compared with the ones loaded last time, so rules
+/// added or modified with the api are left alone unless the config
+/// of the same endpoint changes. Other sections are not reloaded.
+#[derive(Debug)]
+pub struct Reloader {
+    source: Source,
+    // endpoints from the source, keyed by id
+    confs: BTreeMap<String, EndpointConf>,
+}
+
+impl Reloader {
+    pub fn new(source: Source, confs: &[EndpointConf]) -> Self {
+        let confs = confs.iter().map(|x| (x.build_id(), x.clone())).collect();
+        Self { source, confs }
+    }
+
+    /// Apply the changes of endpoints, the running rules are kept as is
+    /// if the config could not be loaded.
+    pub async fn reload(&mut self, rules: &RuleConfigs, relays: &Relays) -> Result<Summary> {
+        let confs: BTreeMap<_, _> = self.source.load()?.into_iter().map(|x| (x.build_id(), x)).collect();
+        let mut summary = Summary::default();
+
+        // free listen addresses first
+        for id in self.confs.keys().filter(|id| !confs.contains_key(*id)) {
+            // may be deleted with the api
+            if relays.remove(id).await {
+                rules.remove(id);
+                RULE_METRICS.remove(id);
+                log::info!("[reload]rule {} removed", id);
+                summary.removed += 1;
+            }
+        }
+
+        let mut loaded = BTreeMap::new();
+        for (id, conf) in confs {
+            let old = self.confs.remove(&id);
+            if old.as_ref().is_some_and(|x| x.to_config_string() == conf.to_config_string()) {
+                summary.unchanged += 1;
+                loaded.insert(id, conf);
+                continue;
+            }
+
+            let info = match conf.clone().try_build() {
+                Ok(x) => x,
+                Err(e) => {
+                    log::error!("[reload]rule {} is invalid, skipped: {}", id, e);
+                    summary.failed += 1;
+                    // keep the old one, if any
+                    if let Some(old) = old {
+                        loaded.insert(id, old);
+                    }
+                    continue;
+                }
+            };
+
+            // also overrides a rule added with the api, as what is done at startup
+            let updated = rules.contains(&id);
+            let endpoint = info.endpoint.to_string();
+            let launched = match updated {
+                true => relays.replace(info).await.map(drop),
+                false => relays.start(info).await.map(drop),
+            };
+            if let Err(e) = launched {
+                log::error!("[reload]rule {} could not listen, skipped: {}", id, e);
+                summary.failed += 1;
+                // the old one is launched again, if any
+                if let Some(old) = old {
+                    loaded.insert(id, old);
+                }
+                continue;
+            }
+            if updated {
+                log::info!("[reload]rule {} updated: {}", id, endpoint);
+                summary.updated += 1;
+            } else {
+                log::info!("[reload]rule {} added: {}", id, endpoint);
+                summary.added += 1;
+            }
+            rules.insert(id.clone(), conf.clone());
+            loaded.insert(id, conf);
+        }
+
+        self.confs = loaded;
+        Ok(summary)
+    }
+
+    /// Reload on each SIGHUP.
+    #[cfg(unix)]
+    pub async fn run(mut self, rules: web::Data<RuleConfigs>, relays: web::Data<Relays>) {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to listen to SIGHUP");
+
+        while hangup.recv().await.is_some() {
+            log::info!("[reload]SIGHUP received, reload endpoints");
+            match self.reload(&rules, &relays).await {
+                Ok(x) => log::info!(
+                    "[reload]{} added, {} updated, {} removed, {} unchanged, {} failed",
+                    x.added,
+                    x.updated,
+                    x.removed,
+                    x.unchanged,
+                    x.failed
+                ),
+                Err(e) => log::error!("[reload]failed to load config, keep running: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use realm::conf::NetConf;
+
+    fn endpoint(id: &str, listen: u16, remote: u16) -> String {
+        format!(
+            "[[endpoints]]\nid = \"{}\"\nlisten = \"127.0.0.1:{}\"\nremote = \"127.0.0.1:{}\"\n",
+            id, listen, remote
+        )
+    }
+
+    // an unused local port
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn reload_endpoints() {
+        use tokio::net::TcpStream;
+
+        let path = std::env::temp_dir().join(format!("realm-reload-{}.toml", std::process::id()));
+        let file = path.to_string_lossy().to_string();
+        let (a, b, c) = (free_port(), free_port(), free_port());
+        fs::write(&path, endpoint("a", a, 20370) + &endpoint("b", b, 20371)).unwrap();
+
+        let source = Source::File(file, Box::default());
+        let confs = source.load().unwrap();
+        let rules = RuleConfigs::new(&confs);
+        let relays = Relays::new(NetConf::default());
+        for conf in &confs {
+            relays.start(conf.clone().try_build().unwrap()).await.unwrap();
+        }
+        let mut reloader = Reloader::new(source, &confs);
+
+        // a is removed, b is changed, c is added
+        fs::write(&path, endpoint("b", b, 20381) + &endpoint("c", c, 20372)).unwrap();
+        let summary = reloader.reload(&rules, &relays).await.unwrap();
+        assert_eq!((summary.added, summary.updated, summary.removed, summary.unchanged), (1, 1, 1, 0));
+        assert!(!rules.contains("a"));
+        assert_eq!(rules.get("b").unwrap().remote, "127.0.0.1:20381");
+        assert!(rules.contains("c"));
+
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        assert!(TcpStream::connect(("127.0.0.1", a)).await.is_err());
+        assert!(TcpStream::connect(("127.0.0.1", c)).await.is_ok());
+
+        // nothing changed
+        let summary = reloader.reload(&rules, &relays).await.unwrap();
+        assert_eq!((summary.added, summary.updated, summary.removed, summary.unchanged), (0, 0, 0, 2));
+
+        // keep running if the config is broken
+        fs::write(&path, "endpoints = [").unwrap();
+        assert!(reloader.reload(&rules, &relays).await.is_err());
+        assert!(rules.contains("b") && rules.contains("c"));
+
+        // an invalid endpoint is skipped
+        fs::write(&path, endpoint("b", b, 20381) + &endpoint("c", c, 0).replace(":0", ":x")).unwrap();
+        let summary = reloader.reload(&rules, &relays).await.unwrap();
+        assert_eq!((summary.failed, summary.unchanged), (1, 1));
+        assert_eq!(rules.get("c").unwrap().remote, "127.0.0.1:20372");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
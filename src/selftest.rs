@@ -0,0 +1,189 @@
+//! Relay a known payload in-process, then report each subsystem.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+use realm::conf::EndpointConf;
+use realm::core::dns::resolve_ip;
+use realm::core::monitor::TCP_CONNECTION_HISTOGRAMS;
+use realm::core::tcp::run_tcp;
+
+const PAYLOAD_SIZE: usize = 256 * 1024;
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run all checks, return false if any of them failed.
+pub async fn run(domain: &str) -> bool {
+    let mut passed = true;
+
+    passed &= report("tcp plain", relay_plain()).await;
+
+    #[cfg(feature = "transport")]
+    {
+        passed &= report("tcp transport", relay_transport()).await;
+    }
+
+    passed &= report(&format!("dns {}", domain), resolve(domain)).await;
+
+    println!("self-test: {}", if passed { "pass" } else { "fail" });
+    passed
+}
+
+async fn report(name: &str, check: impl Future<Output = Result<String, String>>) -> bool {
+    let result = match timeout(STEP_TIMEOUT, check).await {
+        Ok(x) => x,
+        Err(_) => Err("timeout".to_string()),
+    };
+    match result {
+        Ok(detail) => {
+            println!("self-test: {} ... ok, {}", name, detail);
+            true
+        }
+        Err(e) => {
+            println!("self-test: {} ... failed, {}", name, e);
+            false
+        }
+    }
+}
+
+async fn relay_plain() -> Result<String, String> {
+    let echo = Echo::start().await?;
+    let listen = free_addr()?;
+    let relay = start(&format!(r#"{{"listen": "{}", "remote": "{}"}}"#, listen, echo.addr))?;
+
+    let result = relay_payload(listen, 1).await;
+    relay.abort();
+    result
+}
+
+#[cfg(feature = "transport")]
+async fn relay_transport() -> Result<String, String> {
+    const WS: &str = "ws;host=realm.self-test;path=/self-test";
+
+    let echo = Echo::start().await?;
+    let (listen, middle) = (free_addr()?, free_addr()?);
+    let client = start(&format!(
+        r#"{{"listen": "{}", "remote": "{}", "remote_transport": "{}"}}"#,
+        listen, middle, WS
+    ))?;
+    let server = start(&format!(
+        r#"{{"listen": "{}", "remote": "{}", "listen_transport": "{}"}}"#,
+        middle, echo.addr, WS
+    ))?;
+
+    let result = relay_payload(listen, 2).await;
+    client.abort();
+    server.abort();
+    result
+}
+
+async fn resolve(domain: &str) -> Result<String, String> {
+    let ips = resolve_ip(domain).await.map_err(|e| e.to_string())?;
+    let ips: Vec<String> = ips.iter().map(|x| x.to_string()).collect();
+    match ips.is_empty() {
+        true => Err("no address".to_string()),
+        false => Ok(ips.join(", ")),
+    }
+}
+
+/// Send the payload through `hops` relays to an echo server,
+/// then check the bytes recorded by each relay once it is closed.
+async fn relay_payload(listen: SocketAddr, hops: u64) -> Result<String, String> {
+    let payload: Vec<u8> = (0..PAYLOAD_SIZE).map(|x| (x % 251) as u8).collect();
+    let (count, sum) = recorded();
+
+    // read back before closing, a half close could end the relay
+    // early with brutal shutdown
+    let mut stream = connect(listen).await?;
+    let (mut rd, mut wr) = stream.split();
+    let mut echoed = vec![0; PAYLOAD_SIZE];
+    futures::try_join!(wr.write_all(&payload), rd.read_exact(&mut echoed)).map_err(|e| e.to_string())?;
+    drop(stream);
+    if echoed != payload {
+        return Err("echoed bytes mismatched".to_string());
+    }
+
+    // both directions of each hop
+    let expected = 2 * PAYLOAD_SIZE as u64 * hops;
+    loop {
+        let (count2, sum2) = recorded();
+        if count2 >= count + hops {
+            let bytes = (sum2 - sum) as u64;
+            return match bytes == expected {
+                true => Ok(format!("{} bytes relayed and counted", bytes)),
+                false => Err(format!("{} bytes counted, expect {}", bytes, expected)),
+            };
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// closed connections, and their total bytes
+fn recorded() -> (u64, f64) {
+    let histograms = TCP_CONNECTION_HISTOGRAMS.lock().unwrap();
+    (histograms.bytes.count, histograms.bytes.sum)
+}
+
+fn start(conf: &str) -> Result<JoinHandle<()>, String> {
+    let conf: EndpointConf = serde_json::from_str(conf).map_err(|e| e.to_string())?;
+    let endpoint = conf.try_build()?.endpoint;
+    Ok(tokio::spawn(async move {
+        if let Err(e) = run_tcp(endpoint).await {
+            println!("self-test: relay exited: {}", e);
+        }
+    }))
+}
+
+// a throwaway port on loopback
+fn free_addr() -> Result<SocketAddr, String> {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|x| x.local_addr())
+        .map_err(|e| e.to_string())
+}
+
+// wait for the relay to listen
+async fn connect(addr: SocketAddr) -> Result<TcpStream, String> {
+    let mut retry = 0;
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(x) => return Ok(x),
+            Err(e) if retry >= 50 => return Err(e.to_string()),
+            Err(_) => retry += 1,
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+struct Echo {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Echo {
+    async fn start() -> Result<Self, String> {
+        let lis = TcpListener::bind("127.0.0.1:0").await.map_err(|e| e.to_string())?;
+        let addr = lis.local_addr().map_err(|e| e.to_string())?;
+        let task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = lis.accept().await {
+                tokio::spawn(async move {
+                    let (mut rd, mut wr) = stream.split();
+                    if tokio::io::copy(&mut rd, &mut wr).await.is_ok() {
+                        let _ = wr.shutdown().await;
+                    }
+                });
+            }
+        });
+        Ok(Self { addr, task })
+    }
+}
+
+impl Drop for Echo {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}